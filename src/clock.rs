@@ -0,0 +1,155 @@
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Where time comes from for retries, cache TTLs and rate limits, so tests can use a
+/// [`MockClock`] instead of waiting.
+#[async_trait]
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Wall clock time, e.g. for expiry timestamps stored elsewhere.
+    fn system_now(&self) -> SystemTime;
+
+    async fn sleep(&self, duration: Duration);
+
+    /// Blocks the current thread.
+    fn sleep_blocking(&self, duration: Duration);
+}
+
+/// The real time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+
+    fn sleep_blocking(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// The default [`Clock`].
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+#[derive(Debug)]
+struct MockTime {
+    elapsed: Duration,
+    sleeps: Vec<Duration>,
+}
+
+/// Time that only moves when told to, or when slept on. Sleeping returns straight away after
+/// moving the time forward, so code waiting on backoffs or TTLs runs instantly. Clones share
+/// the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start: Instant,
+    system_start: SystemTime,
+    time: Arc<Mutex<MockTime>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        MockClock {
+            start: Instant::now(),
+            system_start: SystemTime::now(),
+            time: Arc::new(Mutex::new(MockTime {
+                elapsed: Duration::ZERO,
+                sleeps: vec![],
+            })),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.time.lock().elapsed += duration;
+    }
+
+    /// How far the time moved since creating the clock.
+    pub fn elapsed(&self) -> Duration {
+        self.time.lock().elapsed
+    }
+
+    /// The durations slept so far, in order.
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.time.lock().sleeps.clone()
+    }
+
+    /// For passing to the utilities taking a clock.
+    pub fn shared(&self) -> Arc<dyn Clock> {
+        Arc::new(self.clone())
+    }
+
+    fn record_sleep(&self, duration: Duration) {
+        let mut time = self.time.lock();
+        time.elapsed += duration;
+        time.sleeps.push(duration);
+    }
+}
+
+#[async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.system_start + self.elapsed()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.record_sleep(duration);
+        // Still lets other tasks run, like a real sleep would:
+        tokio::task::yield_now().await;
+    }
+
+    fn sleep_blocking(&self, duration: Duration) {
+        self.record_sleep(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn mock_clock_moves_when_slept() {
+        let clock = MockClock::new();
+        let shared = clock.shared();
+        let (start, system_start) = (shared.now(), shared.system_now());
+        shared.sleep(Duration::from_secs(60)).await;
+        clock.advance(Duration::from_secs(5));
+        shared.sleep_blocking(Duration::from_secs(1));
+
+        assert_eq!(shared.now() - start, Duration::from_secs(66));
+        assert_eq!(
+            shared.system_now().duration_since(system_start).unwrap(),
+            Duration::from_secs(66)
+        );
+        assert_eq!(
+            clock.sleeps(),
+            vec![Duration::from_secs(60), Duration::from_secs(1)]
+        );
+    }
+}
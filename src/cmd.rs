@@ -1,13 +1,54 @@
 use crate::err;
+use parking_lot::RwLock;
 use std::{
     io::{BufRead, BufReader},
-    process::{Command, Stdio},
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    sync::Arc,
+    time::Instant,
 };
-use tokio::io::{AsyncBufReadExt, BufReader as AsyncBufReader};
+use tokio::io::AsyncBufReadExt;
 use tokio::process::Command as TokioCommand;
+use tokio::task::JoinHandle;
 pub use tracing::{debug, error, info};
 
 use super::errors::{AnyErr, RResult};
+use super::python::{PyArgs, PythonEnv};
+
+/// Runs the commands of [`run_command`] and [`run_async_command`] instead of spawning them, see
+/// [`crate::testing::cmd::CmdHarness`].
+pub trait CommandExecutor: Send + Sync {
+    fn execute(&self, command: &str, args: &[&str]) -> RResult<(), AnyErr>;
+}
+
+/// Process wide, so commands run from other threads, e.g. in `spawn_blocking`, don't escape it.
+static EXECUTOR: RwLock<Option<Arc<dyn CommandExecutor>>> = RwLock::new(None);
+
+/// Makes `executor` run the commands of every thread, returning the one it replaces.
+pub(crate) fn set_executor(
+    executor: Option<Arc<dyn CommandExecutor>>,
+) -> Option<Arc<dyn CommandExecutor>> {
+    std::mem::replace(&mut *EXECUTOR.write(), executor)
+}
+
+/// What the installed executor made of the command, None when none is installed.
+fn execute_mocked(command: &str, args: &[&str]) -> Option<RResult<(), AnyErr>> {
+    let executor = EXECUTOR.read().clone()?;
+    Some(executor.execute(command, args))
+}
+
+/// The first executable called `name` on the `PATH`.
+pub(crate) fn which(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).find_map(|dir| {
+        let candidates = if cfg!(windows) {
+            vec![dir.join(format!("{}.exe", name)), dir.join(name)]
+        } else {
+            vec![dir.join(name)]
+        };
+        candidates.into_iter().find(|candidate| candidate.is_file())
+    })
+}
 
 fn stream_output(child: &mut std::process::Child) -> RResult<(), AnyErr> {
     let stdout = child
@@ -50,7 +91,29 @@ fn stream_output(child: &mut std::process::Child) -> RResult<(), AnyErr> {
     Ok(())
 }
 
+/// Counts and times the command in [`crate::metrics::global`].
+fn record_run(command: &str, started: Instant, result: &RResult<(), AnyErr>) {
+    let program = std::path::Path::new(command)
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    crate::counter!("cmd_runs_total", "program" => program, "outcome" => outcome).increment(1);
+    crate::histogram!("cmd_duration_seconds", "program" => program)
+        .record_duration(started.elapsed());
+}
+
 pub fn run_command(command: &str, args: &[&str]) -> RResult<(), AnyErr> {
+    let started = Instant::now();
+    let result = run_command_inner(command, args);
+    record_run(command, started, &result);
+    result
+}
+
+fn run_command_inner(command: &str, args: &[&str]) -> RResult<(), AnyErr> {
+    if let Some(result) = execute_mocked(command, args) {
+        return result;
+    }
     let mut child = Command::new(command)
         .args(args)
         .stdout(Stdio::piped())
@@ -77,6 +140,16 @@ pub fn run_command(command: &str, args: &[&str]) -> RResult<(), AnyErr> {
 }
 
 pub async fn run_async_command(command: &str, args: &[&str]) -> RResult<(), AnyErr> {
+    let started = Instant::now();
+    let result = run_async_command_inner(command, args).await;
+    record_run(command, started, &result);
+    result
+}
+
+async fn run_async_command_inner(command: &str, args: &[&str]) -> RResult<(), AnyErr> {
+    if let Some(result) = execute_mocked(command, args) {
+        return result;
+    }
     let mut child = TokioCommand::new(command)
         .args(args)
         .stdout(Stdio::piped())
@@ -131,91 +204,27 @@ pub async fn run_async_command(command: &str, args: &[&str]) -> RResult<(), AnyE
     Ok(())
 }
 
-pub fn run_python_script(file: &str, args: Option<&[&str]>) {
-    let dummy = vec![""];
-    let args = args.unwrap_or_else(|| &dummy);
-
-    let mut cmd = Command::new("pdm")
-        .arg("run")
-        .arg(file)
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to start pdm run script");
-
-    let stdout = cmd.stdout.take().expect("Failed to capture stdout");
-    let stderr = cmd.stderr.take().expect("Failed to capture stderr");
-
-    let stdout_reader = BufReader::new(stdout);
-    let stderr_reader = BufReader::new(stderr);
-
-    // Process both stdout and stderr
-    for line in stdout_reader.lines().chain(stderr_reader.lines()) {
-        match line {
-            Ok(line) => {
-                info!("{}", line);
-            }
-            Err(e) => error!("Error reading line: {}", e),
-        }
-    }
-
-    let status = cmd.wait().expect("Failed to wait on child process");
+/// Runs `file` in the python environment of the current directory, see
+/// [`crate::python::run_python_script_with_args`].
+pub fn run_python_script(file: &str, args: Option<&[&str]>) -> RResult<ExitStatus, AnyErr> {
+    crate::python::run_python_script_with_args(file, args)
+}
 
-    if !status.success() {
-        info!("Python script failed with status: {}", status);
-    }
+/// Like [`run_python_script`], logging failures instead of returning them.
+pub fn run_python_script_lenient(file: &str, args: Option<&[&str]>) {
+    crate::python::run_python_script_with_args_lenient(file, args)
 }
 
-pub fn run_background_python_script(file: &str, args: Option<&[&str]>) {
+/// Runs `file` in a new task, the handle resolves to its exit status or failure.
+///
+/// Failures are only reported through the handle, await it to find out about them.
+pub fn run_background_python_script(
+    file: &str,
+    args: Option<&[&str]>,
+) -> JoinHandle<RResult<ExitStatus, AnyErr>> {
     let file = file.to_string();
-    let args = args
-        .unwrap_or(&[])
-        .iter()
-        .map(|&s| s.to_string())
-        .collect::<Vec<String>>(); // Convert `args` to owned `Vec<String>`
+    let args = PyArgs::from(args.unwrap_or_default());
 
     // Spawn the command asynchronously in a new task
-    tokio::spawn(async move {
-        let mut cmd = TokioCommand::new("pdm")
-            .arg("run")
-            .arg(file)
-            .args(&args)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .expect("Failed to start pdm run script");
-
-        // Take stdout and stderr streams
-        let stdout = cmd.stdout.take().expect("Failed to capture stdout");
-        let stderr = cmd.stderr.take().expect("Failed to capture stderr");
-
-        let stdout_reader = AsyncBufReader::new(stdout);
-        let stderr_reader = AsyncBufReader::new(stderr);
-
-        // Process stdout and stderr asynchronously in separate tasks
-        let stdout_task = tokio::task::spawn(async move {
-            let mut lines = stdout_reader.lines();
-            while let Ok(Some(line)) = lines.next_line().await {
-                info!("stdout: {}", line);
-            }
-        });
-
-        let stderr_task = tokio::task::spawn(async move {
-            let mut lines = stderr_reader.lines();
-            while let Ok(Some(line)) = lines.next_line().await {
-                error!("stderr: {}", line);
-            }
-        });
-
-        // Wait for the Python script to complete and ensure logs are processed
-        let status = cmd.wait().await.expect("Failed to wait on child process");
-
-        // Ensure both stdout and stderr tasks are finished
-        let _ = tokio::join!(stdout_task, stderr_task);
-
-        if !status.success() {
-            info!("Python script failed with status: {}", status);
-        }
-    });
+    tokio::spawn(async move { PythonEnv::detect(".")?.run_script_async(&file, args).await })
 }
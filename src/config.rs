@@ -0,0 +1,408 @@
+use futures::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::files::{read_file, ConfigLoader, Watcher};
+use crate::prelude::*;
+use crate::redis_manager::RedisLike;
+
+/// A setting that shouldn't end up in logs, its Debug output is redacted.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T = String>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> std::fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret([redacted])")
+    }
+}
+
+enum Source {
+    /// The defaults as json, or why they failed to serialize.
+    Defaults(Result<Value, String>),
+    File {
+        path: PathBuf,
+        required: bool,
+    },
+    Env {
+        prefix: String,
+    },
+    Remote {
+        redis: Arc<dyn RedisLike>,
+        key: String,
+    },
+}
+
+/// Typed application settings merged from layers, later ones overriding earlier ones:
+/// usually defaults, then TOML/YAML files, then env vars, then json stored in redis, see
+/// [`ConfigLoader`] for how layers merge.
+///
+/// Whole string values of the defaults and files referencing a secret are resolved before
+/// deserializing: `"${env:DB_PASSWORD}"` reads an env var and `"${file:/run/secrets/db}"` a
+/// file, without its trailing newline. Deserialize them into a [`Secret`] to keep them out of
+/// logs. References in env vars and remote values are rejected, as whoever sets those could
+/// otherwise read any file or env var of the process.
+pub struct Settings {
+    sources: Vec<Source>,
+    reload_interval: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings::new()
+    }
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Settings {
+            sources: vec![],
+            reload_interval: Duration::from_secs(1),
+        }
+    }
+
+    pub fn defaults(mut self, defaults: impl Serialize) -> Self {
+        let value = serde_json::to_value(defaults).map_err(|e| e.to_string());
+        self.sources.push(Source::Defaults(value));
+        self
+    }
+
+    /// A file that must exist, by extension TOML, YAML or JSON.
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sources.push(Source::File {
+            path: path.into(),
+            required: true,
+        });
+        self
+    }
+
+    pub fn optional_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sources.push(Source::File {
+            path: path.into(),
+            required: false,
+        });
+        self
+    }
+
+    /// Env vars like `{prefix}__DATABASE__PORT`, see [`ConfigLoader::env_prefix`].
+    pub fn env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.sources.push(Source::Env {
+            prefix: prefix.into(),
+        });
+        self
+    }
+
+    /// A json object stored at `key`, e.g. by an admin tool, skipped while the key isn't set.
+    pub fn remote(mut self, redis: impl RedisLike + 'static, key: impl Into<String>) -> Self {
+        self.sources.push(Source::Remote {
+            redis: Arc::new(redis),
+            key: key.into(),
+        });
+        self
+    }
+
+    /// How often [`Self::watch`] checks the files for changes.
+    pub fn reload_interval(mut self, reload_interval: Duration) -> Self {
+        self.reload_interval = reload_interval;
+        self
+    }
+
+    pub async fn load<T: DeserializeOwned>(&self) -> RResult<T, AnyErr2> {
+        let mut loader = ConfigLoader::new();
+        for source in &self.sources {
+            let resolve = matches!(source, Source::Defaults(_) | Source::File { .. });
+            let (name, value) = match source {
+                Source::Defaults(value) => {
+                    let value = value.clone().map_err(|e| {
+                        Report::new(err2!("Failed to serialize default settings"))
+                            .attach_printable(e)
+                    })?;
+                    ("defaults".to_string(), value)
+                }
+                Source::File { path, required } => {
+                    if !required && !path.exists() {
+                        debug!("Optional settings file {} not found", path.display());
+                        continue;
+                    }
+                    (path.display().to_string(), read_file(path)?)
+                }
+                Source::Env { prefix } => (
+                    format!("env {}__*", prefix),
+                    ConfigLoader::new().env_prefix(prefix).merged()?,
+                ),
+                Source::Remote { redis, key } => {
+                    let stored = redis
+                        .get(key)
+                        .await
+                        .change_context(err2!("Failed to read remote settings"))
+                        .attach_printable_lazy(|| format!("Key: {}", key))?;
+                    let Some(stored) = stored else {
+                        debug!("Remote settings {} not set", key);
+                        continue;
+                    };
+                    let value = serde_json::from_str(&stored)
+                        .change_context(err2!("Invalid remote settings"))
+                        .attach_printable_lazy(|| format!("Key: {}", key))?;
+                    (format!("redis {}", key), value)
+                }
+            };
+            let value = resolve_secrets(value, "", resolve)
+                .attach_printable_lazy(|| format!("Set by: {}", name))?;
+            loader = loader.layer(name, value);
+        }
+        loader.load()
+    }
+
+    /// Loads now and again whenever one of the files changes, e.g. to apply new log levels
+    /// without restarting. Remote values are only reloaded along with the files.
+    pub fn watch<T: DeserializeOwned>(
+        self,
+    ) -> RResult<impl Stream<Item = RResult<T, AnyErr2>>, AnyErr2> {
+        let files = self
+            .sources
+            .iter()
+            .filter_map(|source| match source {
+                Source::File { path, .. } => Some(path.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let changes = Watcher::new(files)
+            .poll_interval(self.reload_interval)
+            .stream()?
+            // The events of one change arrive together:
+            .ready_chunks(64);
+        let settings = Arc::new(self);
+        let initial = {
+            let settings = settings.clone();
+            futures::stream::once(async move { settings.load().await })
+        };
+        let reloads = changes.then(move |events| {
+            let settings = settings.clone();
+            async move {
+                debug!("Reloading settings after {} file changes", events.len());
+                settings.load().await
+            }
+        });
+        Ok(initial.chain(reloads))
+    }
+}
+
+/// Replaces strings referencing secrets with their values, or errors on them unless `resolve`.
+fn resolve_secrets(value: Value, path: &str, resolve: bool) -> RResult<Value, AnyErr2> {
+    Ok(match value {
+        Value::String(string) => match secret_reference(&string) {
+            Some(_) if !resolve => {
+                return Err(Report::new(err2!(
+                    "Secret references are only resolved in defaults and files"
+                ))
+                .attach_printable(format!("Key: {}", path)))
+            }
+            Some((kind, name)) => Value::String(
+                read_secret(kind, name).attach_printable_lazy(|| format!("Key: {}", path))?,
+            ),
+            None => Value::String(string),
+        },
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let nested = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    Ok((key, resolve_secrets(value, &nested, resolve)?))
+                })
+                .collect::<RResult<_, AnyErr2>>()?,
+        ),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| resolve_secrets(value, &format!("{}[]", path), resolve))
+                .collect::<RResult<_, AnyErr2>>()?,
+        ),
+        value => value,
+    })
+}
+
+/// The kind and name of `${kind:name}`.
+fn secret_reference(value: &str) -> Option<(&str, &str)> {
+    value
+        .trim()
+        .strip_prefix("${")?
+        .strip_suffix('}')?
+        .split_once(':')
+}
+
+fn read_secret(kind: &str, name: &str) -> RResult<String, AnyErr2> {
+    match kind {
+        "env" => std::env::var(name)
+            .change_context(err2!("Secret env var isn't set"))
+            .attach_printable_lazy(|| format!("Env var: {}", name)),
+        "file" => std::fs::read_to_string(name)
+            .map(|contents| contents.trim_end_matches(['\n', '\r']).to_string())
+            .change_context(err2!("Failed to read secret file"))
+            .attach_printable_lazy(|| format!("Path: {}", name)),
+        _ => Err(Report::new(err2!(format!(
+            "Unknown secret reference ${{{}:..}}, expected env or file",
+            kind
+        )))),
+    }
+}
+
+/// The settings items, also part of [`crate::prelude`].
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{Secret, Settings};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempWorkspace;
+    use crate::testing::prelude::*;
+
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    struct Database {
+        host: String,
+        password: Secret,
+    }
+
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    struct AppSettings {
+        log_level: String,
+        workers: u32,
+        database: Database,
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn merges_sources(temp_dir: TempWorkspace, memory_redis: MemoryRedis) {
+        let password = temp_dir.write("secrets/db", "hunter2\n").unwrap();
+        let toml = temp_dir
+            .write(
+                "app.toml",
+                format!(
+                    "log_level = \"info\"\n\n[database]\nhost = \"db\"\npassword = \"${{file:{}}}\"\n",
+                    password.display()
+                ),
+            )
+            .unwrap();
+        std::env::set_var("RUTILS_TEST_SETTINGS__WORKERS", "4");
+        memory_redis
+            .set("settings:api", r#"{"log_level": "debug"}"#, None)
+            .await
+            .unwrap();
+
+        let settings: AppSettings = Settings::new()
+            .defaults(AppSettings::default())
+            .file(&toml)
+            .optional_file(temp_dir.path().join("local.yaml"))
+            .env_prefix("RUTILS_TEST_SETTINGS")
+            .remote(memory_redis, "settings:api")
+            .load()
+            .await
+            .unwrap();
+        assert_eq!(settings.log_level, "debug");
+        assert_eq!(settings.workers, 4);
+        assert_eq!(settings.database.password.expose(), "hunter2");
+        assert!(!format!("{:?}", settings).contains("hunter2"));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reports_missing_secrets(temp_dir: TempWorkspace) {
+        let yaml = temp_dir
+            .write(
+                "app.yaml",
+                "database:\n  password: ${env:RUTILS_TEST_SETTINGS_UNSET}\n",
+            )
+            .unwrap();
+        let report = Settings::new()
+            .file(&yaml)
+            .load::<Value>()
+            .await
+            .unwrap_err();
+        let debug = format!("{:?}", report);
+        assert!(debug.contains("Key: database.password"), "{}", debug);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reports_invalid_defaults() {
+        // Maps need string keys to become json:
+        let defaults = std::collections::BTreeMap::from([(vec![1u8], 1)]);
+        let report = Settings::new()
+            .defaults(defaults)
+            .load::<Value>()
+            .await
+            .unwrap_err();
+        let debug = format!("{:?}", report);
+        assert!(
+            debug.contains("Failed to serialize default settings"),
+            "{}",
+            debug
+        );
+        assert!(debug.contains("key must be a string"), "{}", debug);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejects_remote_secret_references(memory_redis: MemoryRedis) {
+        memory_redis
+            .set(
+                "settings:api",
+                r#"{"database": {"password": "${file:/etc/passwd}"}}"#,
+                None,
+            )
+            .await
+            .unwrap();
+        let report = Settings::new()
+            .remote(memory_redis, "settings:api")
+            .load::<Value>()
+            .await
+            .unwrap_err();
+        let debug = format!("{:?}", report);
+        assert!(
+            debug.contains("only resolved in defaults and files"),
+            "{}",
+            debug
+        );
+        assert!(debug.contains("Set by: redis settings:api"), "{}", debug);
+        assert!(!debug.contains("root:"), "{}", debug);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reloads_on_change(temp_dir: TempWorkspace) {
+        let toml = temp_dir.write("app.toml", "workers = 1\n").unwrap();
+        let mut settings = Box::pin(
+            Settings::new()
+                .file(&toml)
+                .reload_interval(Duration::from_millis(20))
+                .watch::<Value>()
+                .unwrap(),
+        );
+        assert_eq!(settings.next().await.unwrap().unwrap()["workers"], 1);
+        // Past the modification time granularity of some filesystems:
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        std::fs::write(&toml, "workers = 22\n").unwrap();
+        let reloaded = tokio::time::timeout(Duration::from_secs(5), settings.next())
+            .await
+            .unwrap();
+        assert_eq!(reloaded.unwrap().unwrap()["workers"], 22);
+    }
+}
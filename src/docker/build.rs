@@ -0,0 +1,406 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+use super::client::describe;
+use super::{ContainerEngine, DockerCli, DockerCommandFailed};
+use crate::prelude::*;
+use crate::secrets::SecretStore;
+
+/// How many output lines build and push errors include.
+const OUTPUT_TAIL_LINES: usize = 30;
+
+/// Credentials for a registry, the token can be a password.
+#[derive(Clone)]
+pub struct RegistryAuth {
+    /// e.g. `ghcr.io`, empty for docker hub.
+    pub registry: String,
+    pub username: String,
+    pub token: String,
+}
+
+impl std::fmt::Debug for RegistryAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegistryAuth")
+            .field("registry", &self.registry)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RegistryAuth {
+    /// Credentials with the token read from `secrets`, for [`DockerCli::push`] and
+    /// [`DockerCli::registry_login`] without passing the token around.
+    pub async fn from_secret(
+        registry: impl Into<String>,
+        username: impl Into<String>,
+        secrets: &dyn SecretStore,
+        token_secret: &str,
+    ) -> RResult<Self, AnyErr> {
+        let token = secrets.get(token_secret).await?;
+        Ok(RegistryAuth {
+            registry: registry.into(),
+            username: username.into(),
+            token: token.expose().clone(),
+        })
+    }
+}
+
+/// An image built by [`super::build`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuiltImage {
+    pub tag: String,
+    /// The local image id, `sha256:...`.
+    pub id: String,
+}
+
+/// An image pushed by [`super::push`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushedImage {
+    pub tag: String,
+    /// The manifest digest in the registry, `sha256:...`.
+    pub digest: String,
+}
+
+/// The context below the `AnyErr` of a failed build, get it with
+/// `report.frames().find_map(|f| f.downcast_ref::<DockerBuildFailed>())`.
+#[derive(Debug, Clone)]
+pub struct DockerBuildFailed {
+    pub tag: String,
+    /// The dockerfile instruction that failed, e.g. `[3/5] RUN pip install -r requirements.txt`.
+    pub step: Option<String>,
+    /// Why it failed, e.g. `process "/bin/sh -c pip install" did not complete successfully`.
+    pub error: Option<String>,
+    /// The last lines of the build output.
+    pub output_tail: String,
+}
+
+impl std::fmt::Display for DockerBuildFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Docker build of {} failed", self.tag)?;
+        if let Some(step) = &self.step {
+            write!(f, " at {}", step)?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, ": {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl error_stack::Context for DockerBuildFailed {}
+
+impl DockerCli {
+    /// Builds `context_dir` as `tag`, logging the build output.
+    ///
+    /// Failures are errors with a [`DockerBuildFailed`] naming the step that failed.
+    pub fn build(
+        &self,
+        context_dir: impl AsRef<Path>,
+        tag: &str,
+        build_args: &BTreeMap<String, String>,
+    ) -> RResult<BuiltImage, AnyErr> {
+        let id_file = tempfile::NamedTempFile::new()
+            .change_context(AnyErr)
+            .attach_printable("Failed to create image id file")?;
+        let mut command = self.command();
+        command.arg("build");
+        // Podman has no buildkit, its output is always plain:
+        if self.get_engine() == ContainerEngine::Docker {
+            command.arg("--progress=plain");
+        }
+        command
+            .args(["--tag", tag, "--iidfile"])
+            .arg(id_file.path());
+        for (key, value) in build_args {
+            command.arg("--build-arg").arg(format!("{}={}", key, value));
+        }
+        command.arg(context_dir.as_ref());
+
+        let span = tracing::info_span!("docker_build", tag = %tag);
+        let _entered = span.enter();
+        let (status, progress) = stream_lines(&mut command, BuildProgress::default())?;
+        if !status.success() {
+            return Err(Report::new(DockerBuildFailed {
+                tag: tag.to_string(),
+                step: progress.failed_step(),
+                error: progress.error,
+                output_tail: Vec::from(progress.tail).join("\n"),
+            })
+            .change_context(AnyErr));
+        }
+
+        let id = std::fs::read_to_string(id_file.path())
+            .change_context(AnyErr)
+            .attach_printable("Failed to read the built image id")?;
+        info!("Built {} as {}", tag, id.trim());
+        Ok(BuiltImage {
+            tag: tag.to_string(),
+            id: id.trim().to_string(),
+        })
+    }
+
+    /// Pushes `tag`, logging in with `auth` first if given.
+    pub fn push(&self, tag: &str, auth: Option<&RegistryAuth>) -> RResult<PushedImage, AnyErr> {
+        if let Some(auth) = auth {
+            self.login(auth)?;
+        }
+        // Podman doesn't print the digest:
+        let digest_file = tempfile::NamedTempFile::new()
+            .change_context(AnyErr)
+            .attach_printable("Failed to create digest file")?;
+        let mut command = self.command();
+        command.args(["push", tag]);
+        if self.get_engine() == ContainerEngine::Podman {
+            command.arg("--digestfile").arg(digest_file.path());
+        }
+
+        let span = tracing::info_span!("docker_push", tag = %tag);
+        let _entered = span.enter();
+        let (status, progress) = stream_lines(&mut command, PushProgress::default())?;
+        if !status.success() {
+            return Err(Report::new(DockerCommandFailed {
+                command: describe(&command),
+                code: status.code(),
+                stderr: Vec::from(progress.tail).join("\n"),
+            })
+            .change_context(AnyErr));
+        }
+        let written = std::fs::read_to_string(digest_file.path()).unwrap_or_default();
+        let digest = progress
+            .digest
+            .or_else(|| Some(written.trim().to_string()).filter(|digest| !digest.is_empty()))
+            .ok_or_else(|| err!(AnyErr, "No digest in the output of pushing {}", tag))?;
+        info!("Pushed {} as {}", tag, digest);
+        Ok(PushedImage {
+            tag: tag.to_string(),
+            digest,
+        })
+    }
+
+    /// `docker login`, passing the token on stdin so it isn't visible in the process list.
+    pub(super) fn login(&self, auth: &RegistryAuth) -> RResult<(), AnyErr> {
+        let mut command = self.command();
+        command.args(["login", "--username", &auth.username, "--password-stdin"]);
+        if !auth.registry.is_empty() {
+            command.arg(&auth.registry);
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .change_context(AnyErr)
+            .attach_printable("Failed to start docker login")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(auth.token.as_bytes())
+                .change_context(AnyErr)
+                .attach_printable("Failed to pass the token to docker login")?;
+        }
+        let output = child
+            .wait_with_output()
+            .change_context(AnyErr)
+            .attach_printable("Failed to wait on docker login")?;
+        if !output.status.success() {
+            return Err(Report::new(DockerCommandFailed {
+                command: describe(&command),
+                code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            })
+            .change_context(AnyErr));
+        }
+        Ok(())
+    }
+}
+
+/// Follows command output line by line.
+trait Progress: Send + 'static {
+    fn line(&mut self, line: &str);
+}
+
+/// Runs the command logging both streams and following them with `progress`.
+fn stream_lines<P: Progress>(
+    command: &mut Command,
+    progress: P,
+) -> RResult<(ExitStatus, P), AnyErr> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .change_context(AnyErr)
+        .attach_printable_lazy(|| format!("Failed to start {}", describe(command)))?;
+    let stdout = BufReader::new(child.stdout.take().expect("Failed to capture stdout"));
+    let stderr = BufReader::new(child.stderr.take().expect("Failed to capture stderr"));
+
+    // Buildkit writes its progress to stderr, the legacy builder to stdout:
+    let progress = std::sync::Arc::new(std::sync::Mutex::new(progress));
+    let span = tracing::Span::current();
+    let stderr_progress = progress.clone();
+    let stderr_handle = std::thread::spawn(move || {
+        let _entered = span.enter();
+        for line in stderr.lines().map_while(Result::ok) {
+            info!("{}", line);
+            if let Ok(mut progress) = stderr_progress.lock() {
+                progress.line(&line);
+            }
+        }
+    });
+    for line in stdout.lines().map_while(Result::ok) {
+        info!("{}", line);
+        if let Ok(mut progress) = progress.lock() {
+            progress.line(&line);
+        }
+    }
+    let _ = stderr_handle.join();
+
+    let status = child
+        .wait()
+        .change_context(AnyErr)
+        .attach_printable("Failed to wait on docker")?;
+    let progress = std::sync::Arc::try_unwrap(progress)
+        .ok()
+        .and_then(|progress| progress.into_inner().ok())
+        .ok_or_else(|| err!(AnyErr, "Docker output is still being read"))?;
+    Ok((status, progress))
+}
+
+/// Runs the command logging its output, failures are a [`DockerCommandFailed`] with its end.
+pub(super) fn run_streamed(command: &mut Command) -> RResult<(), AnyErr> {
+    let (status, tail) = stream_lines(command, VecDeque::new())?;
+    if !status.success() {
+        return Err(Report::new(DockerCommandFailed {
+            command: describe(command),
+            code: status.code(),
+            stderr: Vec::from(tail).join("\n"),
+        })
+        .change_context(AnyErr));
+    }
+    Ok(())
+}
+
+impl Progress for VecDeque<String> {
+    fn line(&mut self, line: &str) {
+        push_tail(self, line);
+    }
+}
+
+fn push_tail(tail: &mut VecDeque<String>, line: &str) {
+    if tail.len() == OUTPUT_TAIL_LINES {
+        tail.pop_front();
+    }
+    tail.push_back(line.to_string());
+}
+
+/// Tracks which step is running in `--progress=plain` buildkit, legacy builder or podman output.
+#[derive(Debug, Default)]
+struct BuildProgress {
+    /// Buildkit step names by their `#n` vertex.
+    steps: BTreeMap<String, String>,
+    failed_vertex: Option<String>,
+    /// The last `Step 3/5 : ...` of the legacy builder or `STEP 3/5: ...` of podman.
+    legacy_step: Option<String>,
+    error: Option<String>,
+    tail: VecDeque<String>,
+}
+
+impl BuildProgress {
+    fn failed_step(&self) -> Option<String> {
+        match &self.failed_vertex {
+            Some(vertex) => self.steps.get(vertex).cloned(),
+            None => self.legacy_step.clone(),
+        }
+    }
+}
+
+impl Progress for BuildProgress {
+    fn line(&mut self, line: &str) {
+        push_tail(&mut self.tail, line);
+        if let Some(step) = line.strip_prefix("Step ").or(line.strip_prefix("STEP ")) {
+            self.legacy_step = Some(step.to_string());
+        } else if line.starts_with("The command '") && line.contains("returned a non-zero code") {
+            self.error = Some(line.to_string());
+        } else if let Some(error) = line.strip_prefix("Error: ") {
+            self.error = Some(error.to_string());
+        } else if let Some((vertex, rest)) = line.split_once(' ') {
+            if !vertex.starts_with('#') {
+                if let Some(error) = line.strip_prefix("ERROR: ") {
+                    self.error.get_or_insert_with(|| error.to_string());
+                }
+                return;
+            }
+            if rest.starts_with('[') {
+                self.steps
+                    .entry(vertex.to_string())
+                    .or_insert_with(|| rest.to_string());
+            } else if let Some(error) = rest.strip_prefix("ERROR: ") {
+                // The first failing step caused the rest to be cancelled:
+                if self.failed_vertex.is_none() {
+                    self.failed_vertex = Some(vertex.to_string());
+                    self.error = Some(error.to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Finds the digest in `latest: digest: sha256:... size: 1234`.
+#[derive(Debug, Default)]
+struct PushProgress {
+    digest: Option<String>,
+    tail: VecDeque<String>,
+}
+
+impl Progress for PushProgress {
+    fn line(&mut self, line: &str) {
+        push_tail(&mut self.tail, line);
+        if let Some((_, rest)) = line.split_once("digest: ") {
+            if let Some(digest) = rest.split_whitespace().next() {
+                self.digest = Some(digest.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn follow<P: Progress>(mut progress: P, output: &str) -> P {
+        output.lines().for_each(|line| progress.line(line));
+        progress
+    }
+
+    #[rstest]
+    #[case::buildkit(
+        "#5 [1/3] FROM docker.io/library/python:3.11\n#5 DONE 0.1s\n#6 [2/3] COPY requirements.txt .\n#6 DONE 0.0s\n#7 [3/3] RUN pip install -r requirements.txt\n#7 1.2 ERROR: No matching distribution found for nump\n#7 ERROR: process \"/bin/sh -c pip install -r requirements.txt\" did not complete successfully: exit code: 1\n------\nERROR: failed to solve: process \"/bin/sh -c pip install -r requirements.txt\" did not complete successfully: exit code: 1",
+        "[3/3] RUN pip install -r requirements.txt",
+        "process \"/bin/sh -c pip install -r requirements.txt\" did not complete successfully: exit code: 1"
+    )]
+    #[case::legacy(
+        "Step 1/2 : FROM python:3.11\n ---> 1a2b3c\nStep 2/2 : RUN exit 3\n ---> Running in 4d5e6f\nThe command '/bin/sh -c exit 3' returned a non-zero code: 3",
+        "2/2 : RUN exit 3",
+        "The command '/bin/sh -c exit 3' returned a non-zero code: 3"
+    )]
+    #[case::podman(
+        "STEP 1/2: FROM python:3.11\nSTEP 2/2: RUN exit 3\nError: building at STEP \"RUN exit 3\": while running runtime: exit status 3",
+        "2/2: RUN exit 3",
+        "building at STEP \"RUN exit 3\": while running runtime: exit status 3"
+    )]
+    fn finds_failed_step(#[case] output: &str, #[case] step: &str, #[case] error: &str) {
+        let progress = follow(BuildProgress::default(), output);
+        assert_eq!(progress.failed_step().as_deref(), Some(step));
+        assert_eq!(progress.error.as_deref(), Some(error));
+    }
+
+    #[rstest]
+    fn finds_push_digest() {
+        let progress = follow(
+            PushProgress::default(),
+            "The push refers to repository [ghcr.io/acme/app]\n5f70bf18a086: Pushed\nv1: digest: sha256:0123abcd size: 1573",
+        );
+        assert_eq!(progress.digest.as_deref(), Some("sha256:0123abcd"));
+    }
+}
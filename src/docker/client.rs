@@ -0,0 +1,232 @@
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use super::{ContainerEngine, ContainerInfo, ContainerLogs, ContainerSpec, DockerCommandFailed};
+use crate::prelude::*;
+
+/// The operations of a docker daemon, implemented by [`DockerCli`] and by fakes in tests.
+pub trait DockerClient: Send + Sync {
+    /// Whether the daemon answers.
+    fn is_running(&self) -> bool;
+
+    fn pull(&self, image: &str) -> RResult<(), AnyErr>;
+
+    /// Starts a detached container, returning its id.
+    fn run_container(&self, spec: &ContainerSpec) -> RResult<String, AnyErr>;
+
+    fn stop(&self, container: &str) -> RResult<(), AnyErr>;
+
+    /// Force removes the container and its anonymous volumes.
+    fn remove(&self, container: &str) -> RResult<(), AnyErr>;
+
+    fn logs(&self, container: &str) -> RResult<ContainerLogs, AnyErr>;
+
+    fn inspect(&self, container: &str) -> RResult<ContainerInfo, AnyErr>;
+}
+
+/// A [`DockerClient`] shelling out to the `docker` or `podman` CLI.
+#[derive(Debug, Clone)]
+pub struct DockerCli {
+    program: PathBuf,
+    engine: ContainerEngine,
+}
+
+/// The cli of [`ContainerEngine::detect`].
+impl Default for DockerCli {
+    fn default() -> Self {
+        ContainerEngine::detect().cli()
+    }
+}
+
+impl DockerCli {
+    /// Uses `program` instead of `docker`, for a cli with the same interface.
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        DockerCli {
+            program: program.into(),
+            engine: ContainerEngine::Docker,
+        }
+    }
+
+    /// Which engine's flavour of the interface `program` has, docker by default.
+    pub fn engine(mut self, engine: ContainerEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn program(&self) -> &std::path::Path {
+        &self.program
+    }
+
+    pub fn get_engine(&self) -> ContainerEngine {
+        self.engine
+    }
+
+    /// A command running the cli, add the subcommand and its args to it.
+    pub fn command(&self) -> Command {
+        Command::new(&self.program)
+    }
+
+    /// Runs the cli with `args`, returning its stdout.
+    pub fn output<S: AsRef<OsStr>>(&self, args: &[S]) -> RResult<String, AnyErr> {
+        let output = self.raw_output(args)?;
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string())
+    }
+
+    fn raw_output<S: AsRef<OsStr>>(&self, args: &[S]) -> RResult<Output, AnyErr> {
+        let mut command = self.command();
+        command.args(args);
+        let output = command
+            .output()
+            .change_context(AnyErr)
+            .attach_printable_lazy(|| format!("Failed to run {}", self.program.display()))?;
+        if !output.status.success() {
+            return Err(Report::new(DockerCommandFailed {
+                command: describe(&command),
+                code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            })
+            .change_context(AnyErr));
+        }
+        Ok(output)
+    }
+}
+
+/// Flags taking a `KEY=VALUE` whose value may be a secret.
+const VALUE_FLAGS: [&str; 3] = ["-e", "--env", "--build-arg"];
+
+/// The command as it would be typed, with the values of env vars and build args redacted.
+pub(super) fn describe(command: &Command) -> String {
+    let mut redact_next = false;
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            let redact = std::mem::replace(&mut redact_next, VALUE_FLAGS.contains(&arg.as_ref()));
+            match arg.split_once('=') {
+                Some((key, _)) if redact => format!("{}=***", key),
+                // `--env=KEY=VALUE`:
+                Some((flag, value)) if VALUE_FLAGS.contains(&flag) => match value.split_once('=') {
+                    Some((key, _)) => format!("{}={}=***", flag, key),
+                    None => arg.into_owned(),
+                },
+                _ => arg.into_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl DockerClient for DockerCli {
+    fn is_running(&self) -> bool {
+        let format = match self.engine {
+            ContainerEngine::Docker => "{{.ServerVersion}}",
+            ContainerEngine::Podman => "{{.Version.Version}}",
+        };
+        self.raw_output(&["info", "--format", format]).is_ok()
+    }
+
+    fn pull(&self, image: &str) -> RResult<(), AnyErr> {
+        self.output(&["pull", "--quiet", image])?;
+        Ok(())
+    }
+
+    fn run_container(&self, spec: &ContainerSpec) -> RResult<String, AnyErr> {
+        self.output(&spec.run_args())
+    }
+
+    fn stop(&self, container: &str) -> RResult<(), AnyErr> {
+        self.output(&["stop", container])?;
+        Ok(())
+    }
+
+    fn remove(&self, container: &str) -> RResult<(), AnyErr> {
+        self.output(&["rm", "--force", "--volumes", container])?;
+        Ok(())
+    }
+
+    fn logs(&self, container: &str) -> RResult<ContainerLogs, AnyErr> {
+        let output = self.raw_output(&["logs", container])?;
+        Ok(ContainerLogs {
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+
+    fn inspect(&self, container: &str) -> RResult<ContainerInfo, AnyErr> {
+        let json = self.output(&["inspect", "--type", "container", container])?;
+        ContainerInfo::parse(&json).attach_printable_lazy(|| format!("Container: {}", container))
+    }
+}
+
+/// A cli running the shell `script` in `dir`.
+#[cfg(all(test, unix))]
+pub(super) fn fake_cli(dir: &std::path::Path, script: &str) -> DockerCli {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join("docker");
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    DockerCli::new(path)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn redacts_env_values() {
+        let mut command = Command::new("docker");
+        command.args([
+            "run",
+            "-e",
+            "TOKEN=hunter2",
+            "--env=DB_URL=postgres://u:p@db",
+            "--env",
+            "HOME",
+            "--build-arg",
+            "NPM_TOKEN=abc",
+            "--name",
+            "a=b",
+        ]);
+        assert_eq!(
+            describe(&command),
+            "docker run -e TOKEN=*** --env=DB_URL=*** --env HOME --build-arg NPM_TOKEN=*** --name a=b"
+        );
+    }
+
+    #[rstest]
+    fn runs_cli() {
+        let dir = tempfile::tempdir().unwrap();
+        // Prints its args, failing for pull:
+        let cli = fake_cli(
+            dir.path(),
+            "if [ \"$1\" = pull ]; then echo \"manifest for $3 not found\" >&2; exit 1; fi\necho \"$@\"\necho to-stderr >&2",
+        );
+
+        let id = cli
+            .run_container(&ContainerSpec::new("redis:7").name("cache"))
+            .unwrap();
+        assert_eq!(id, "run --detach --name cache redis:7");
+        let logs = cli.logs("cache").unwrap();
+        assert_eq!(
+            logs,
+            ContainerLogs {
+                stdout: "logs cache\n".to_string(),
+                stderr: "to-stderr\n".to_string(),
+            }
+        );
+
+        let report = cli.pull("nope:1").unwrap_err();
+        let failed = report
+            .frames()
+            .find_map(|f| f.downcast_ref::<DockerCommandFailed>())
+            .unwrap();
+        assert_eq!(failed.code, Some(1));
+        assert_eq!(failed.stderr, "manifest for nope:1 not found");
+        assert!(failed.command.ends_with("docker pull --quiet nope:1"));
+    }
+}
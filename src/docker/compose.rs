@@ -0,0 +1,233 @@
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Deserializer};
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use super::build::run_streamed;
+use super::{ContainerStatus, DockerCli, HealthStatus};
+use crate::prelude::*;
+
+/// How often [`DockerCli::compose_wait_healthy`] checks the containers.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A container of a compose project, from `docker compose ps --format json`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ComposeContainer {
+    pub name: String,
+    pub service: String,
+    pub state: ContainerStatus,
+    /// None without a healthcheck.
+    #[serde(default, deserialize_with = "health")]
+    pub health: Option<HealthStatus>,
+    #[serde(default)]
+    pub exit_code: i64,
+    #[serde(default)]
+    pub publishers: Vec<ComposePublisher>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ComposePublisher {
+    pub target_port: u16,
+    /// 0 when not published.
+    pub published_port: u16,
+    pub protocol: String,
+}
+
+impl ComposeContainer {
+    /// Running and healthy, containers without a healthcheck count as healthy, as do one-off
+    /// containers that exited successfully.
+    pub fn is_ready(&self) -> bool {
+        match self.state {
+            ContainerStatus::Running => matches!(self.health, None | Some(HealthStatus::Healthy)),
+            ContainerStatus::Exited => self.exit_code == 0,
+            _ => false,
+        }
+    }
+
+    /// Whether it won't become ready without intervention.
+    pub fn has_failed(&self) -> bool {
+        match self.state {
+            ContainerStatus::Exited => self.exit_code != 0,
+            ContainerStatus::Dead => true,
+            _ => self.health == Some(HealthStatus::Unhealthy),
+        }
+    }
+
+    /// The host port publishing tcp `target_port`.
+    pub fn host_port(&self, target_port: u16) -> Option<u16> {
+        self.publishers
+            .iter()
+            .find(|p| p.target_port == target_port && p.protocol == "tcp" && p.published_port != 0)
+            .map(|p| p.published_port)
+    }
+}
+
+/// Compose writes an empty string without a healthcheck.
+fn health<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<HealthStatus>, D::Error> {
+    let health = Option::<String>::deserialize(deserializer)?.unwrap_or_default();
+    if health.is_empty() {
+        return Ok(None);
+    }
+    HealthStatus::deserialize(IntoDeserializer::<D::Error>::into_deserializer(
+        health.as_str(),
+    ))
+    .map(Some)
+}
+
+/// Compose v2.21+ writes a json object per line, older versions one array.
+fn parse_ps(output: &str) -> RResult<Vec<ComposeContainer>, AnyErr> {
+    let output = output.trim();
+    let parsed = if output.starts_with('[') {
+        serde_json::from_str(output)
+    } else {
+        output
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect()
+    };
+    parsed
+        .change_context(AnyErr)
+        .attach_printable("Invalid docker compose ps output")
+        .attach_printable_lazy(|| format!("Output: {}", output))
+}
+
+impl DockerCli {
+    /// A `docker compose --file <file>` command, add the subcommand and its args to it.
+    pub fn compose_command(&self, file: impl AsRef<Path>) -> Command {
+        let mut command = self.command();
+        command.arg("compose").arg("--file").arg(file.as_ref());
+        command
+    }
+
+    /// Starts `services`, all of them if empty, logging the output. Without `detach` this blocks
+    /// until they exit.
+    pub fn compose_up(
+        &self,
+        file: impl AsRef<Path>,
+        services: &[&str],
+        detach: bool,
+    ) -> RResult<(), AnyErr> {
+        let mut command = self.compose_command(file);
+        command.arg("up");
+        if detach {
+            command.arg("--detach");
+        }
+        run_streamed(command.args(services))
+    }
+
+    /// Stops and removes the project's containers and networks, volumes are kept.
+    pub fn compose_down(&self, file: impl AsRef<Path>) -> RResult<(), AnyErr> {
+        run_streamed(
+            self.compose_command(file)
+                .args(["down", "--remove-orphans"]),
+        )
+    }
+
+    /// All of the project's containers, including stopped ones.
+    pub fn compose_ps(&self, file: impl AsRef<Path>) -> RResult<Vec<ComposeContainer>, AnyErr> {
+        let mut command = self.compose_command(file);
+        command.args(["ps", "--all", "--format", "json"]);
+        let args = command
+            .get_args()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        parse_ps(&self.output(&args)?)
+    }
+
+    /// Waits until all the project's containers are [`ComposeContainer::is_ready`], failing early
+    /// when one [`ComposeContainer::has_failed`] and with [`ErrorClass::Timeout`] after `timeout`.
+    pub fn compose_wait_healthy(
+        &self,
+        file: impl AsRef<Path>,
+        timeout: Duration,
+    ) -> RResult<Vec<ComposeContainer>, AnyErr> {
+        let file = file.as_ref();
+        let started = Instant::now();
+        loop {
+            let containers = self.compose_ps(file)?;
+            if let Some(failed) = containers.iter().find(|c| c.has_failed()) {
+                return Err(err!(
+                    AnyErr,
+                    "Compose service {} failed: {:?}, health {:?}, exit code {}",
+                    failed.service,
+                    failed.state,
+                    failed.health,
+                    failed.exit_code
+                ));
+            }
+            if !containers.is_empty() && containers.iter().all(ComposeContainer::is_ready) {
+                return Ok(containers);
+            }
+            if started.elapsed() >= timeout {
+                let waiting = containers
+                    .iter()
+                    .filter(|c| !c.is_ready())
+                    .map(|c| format!("{} ({:?}, health {:?})", c.service, c.state, c.health))
+                    .collect::<Vec<_>>();
+                return Err(err!(
+                    AnyErr,
+                    "Timed out waiting for compose services to become healthy"
+                ))
+                .attach_printable(format!("Compose file: {}", file.display()))
+                .attach_printable(format!("Waiting for: {}", waiting.join(", ")))
+                .classify(ErrorClass::Timeout);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    const REDIS: &str = r#"{"Name":"app-redis-1","Service":"redis","State":"running","Health":"healthy","ExitCode":0,"Publishers":[{"URL":"0.0.0.0","TargetPort":6379,"PublishedPort":32768,"Protocol":"tcp"}]}"#;
+    const MIGRATE: &str = r#"{"Name":"app-migrate-1","Service":"migrate","State":"exited","Health":"","ExitCode":0,"Publishers":[]}"#;
+
+    #[rstest]
+    #[case::lines(format!("{}\n{}\n", REDIS, MIGRATE))]
+    #[case::array(format!("[{},{}]", REDIS, MIGRATE))]
+    fn parses_ps(#[case] output: String) {
+        let containers = parse_ps(&output).unwrap();
+        assert_eq!(containers.len(), 2);
+        assert_eq!(containers[0].health, Some(HealthStatus::Healthy));
+        assert_eq!(containers[0].host_port(6379), Some(32768));
+        assert_eq!(containers[1].health, None);
+        assert!(containers.iter().all(ComposeContainer::is_ready));
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    fn waits_for_healthy() {
+        let dir = tempfile::tempdir().unwrap();
+        // Starting on the first ps, healthy after:
+        let starting = REDIS.replace("healthy", "starting");
+        let cli = super::super::client::fake_cli(
+            dir.path(),
+            &format!(
+                "if [ -f {0}/polled ]; then echo '{1}'; else touch {0}/polled; echo '{2}'; fi",
+                dir.path().display(),
+                REDIS,
+                starting
+            ),
+        );
+        let containers = cli
+            .compose_wait_healthy("compose.yml", Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(containers[0].service, "redis");
+
+        let cli = super::super::client::fake_cli(
+            dir.path(),
+            &format!("echo '{}'", REDIS.replace("healthy", "unhealthy")),
+        );
+        let report = cli
+            .compose_wait_healthy("compose.yml", Duration::from_secs(5))
+            .unwrap_err();
+        assert!(format!("{:?}", report).contains("Compose service redis failed"));
+    }
+}
@@ -0,0 +1,235 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use super::{ContainerEngine, DockerCli, DockerClient};
+use crate::cmd::{run_command, which};
+use crate::prelude::*;
+
+/// How long [`ensure_docker_running`] waits for a started daemon.
+pub const DAEMON_START_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How often the daemon is checked while starting.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const DOCKER_DESKTOP_APP: &str = "/Applications/Docker.app";
+
+/// What runs the docker daemon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DockerBackend {
+    DockerDesktop,
+    /// The colima vm on macOS.
+    Colima,
+    /// Any other daemon, e.g. dockerd as a linux service.
+    Engine,
+    /// No daemon, or a podman machine on macOS.
+    Podman,
+}
+
+impl DockerCli {
+    /// What runs the daemon, None if it isn't answering.
+    pub fn backend(&self) -> Option<DockerBackend> {
+        if self.get_engine() == ContainerEngine::Podman {
+            return self.is_running().then_some(DockerBackend::Podman);
+        }
+        let info = self
+            .output(&["info", "--format", "{{.OperatingSystem}}|{{.Name}}"])
+            .ok()?;
+        Some(parse_backend(&info))
+    }
+
+    /// Waits up to `timeout` for the daemon to answer, failing with [`ErrorClass::Timeout`].
+    pub fn wait_for_daemon(&self, timeout: Duration) -> RResult<DockerBackend, AnyErr> {
+        let started = Instant::now();
+        loop {
+            if let Some(backend) = self.backend() {
+                return Ok(backend);
+            }
+            if started.elapsed() >= timeout {
+                return Err(err!(AnyErr, "Timed out waiting for the docker daemon"))
+                    .attach_printable(format!("Timeout: {:?}", timeout))
+                    .classify(ErrorClass::Timeout);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+fn parse_backend(info: &str) -> DockerBackend {
+    let (os, name) = info.trim().split_once('|').unwrap_or((info, ""));
+    if os.contains("Docker Desktop") {
+        DockerBackend::DockerDesktop
+    } else if name.contains("colima") {
+        DockerBackend::Colima
+    } else {
+        DockerBackend::Engine
+    }
+}
+
+/// Like [`ensure_docker_running_with_timeout`] waiting up to [`DAEMON_START_TIMEOUT`].
+pub fn ensure_docker_running() -> RResult<DockerBackend, AnyErr> {
+    ensure_docker_running_with_timeout(DAEMON_START_TIMEOUT)
+}
+
+/// Starts the docker daemon if it isn't answering, installing docker if needed, and waits up to
+/// `timeout` for it.
+///
+/// On macOS this starts colima or Docker Desktop, installing colima with brew if neither is.
+/// With [`ContainerEngine::Podman`] nothing is installed, on macOS its machine is started.
+pub fn ensure_docker_running_with_timeout(timeout: Duration) -> RResult<DockerBackend, AnyErr> {
+    let cli = DockerCli::default();
+    if let Some(backend) = cli.backend() {
+        debug!("Docker is running on {:?}.", backend);
+        return Ok(backend);
+    }
+    debug!("Docker is not installed or not running. Attempting to install and start...");
+
+    let os = std::env::consts::OS;
+    match (cli.get_engine(), os) {
+        (ContainerEngine::Podman, "macos") => start_podman_machine(),
+        (ContainerEngine::Podman, _) => {
+            Err(err!(AnyErr, "Podman isn't working, check `podman info`"))
+        }
+        (ContainerEngine::Docker, "macos") => start_docker_macos(),
+        (ContainerEngine::Docker, "linux") => install_and_start_docker_linux(),
+        _ => Err(err!(AnyErr, "Unsupported operating system: {}", os)),
+    }?;
+    let backend = cli.wait_for_daemon(timeout)?;
+    debug!("Docker is running on {:?}.", backend);
+    Ok(backend)
+}
+
+/// The docker cli from brew has no daemon, so start colima or Docker Desktop.
+fn start_docker_macos() -> RResult<(), AnyErr> {
+    if which("colima").is_some() {
+        return run_command("colima", &["start"]).attach_printable("Failed to start colima");
+    }
+    if Path::new(DOCKER_DESKTOP_APP).exists() {
+        return run_command("open", &["-a", "Docker"])
+            .attach_printable("Failed to start Docker Desktop");
+    }
+
+    // Check if Homebrew is installed
+    if Command::new("brew").arg("--version").output().is_err() {
+        return Err(err!(
+            AnyErr,
+            "Neither colima nor Docker Desktop is installed, and Homebrew isn't either to install colima."
+        ));
+    }
+    run_command("brew", &["install", "docker", "colima"])
+        .attach_printable("Failed to install docker and colima")?;
+    run_command("colima", &["start"]).attach_printable("Failed to start colima")?;
+
+    debug!("Docker has been installed and started with colima on macOS.");
+    Ok(())
+}
+
+/// Podman runs containers in a vm on macOS, created on first use.
+fn start_podman_machine() -> RResult<(), AnyErr> {
+    if run_command("podman", &["machine", "start"]).is_ok() {
+        return Ok(());
+    }
+    run_command("podman", &["machine", "init"])
+        .attach_printable("Failed to create the podman machine")?;
+    run_command("podman", &["machine", "start"])
+        .attach_printable("Failed to start the podman machine")
+}
+
+fn install_and_start_docker_linux() -> RResult<(), AnyErr> {
+    // An installed but stopped daemon only needs starting:
+    if which("docker").is_none() {
+        // Detect the Linux distribution
+        let os_release = std::fs::read_to_string("/etc/os-release")
+            .change_context(AnyErr)
+            .attach_printable("Failed to detect Linux distribution")?;
+
+        if os_release.contains("Ubuntu") || os_release.contains("Debian") {
+            // Update package list
+            run_command("sudo", &["apt-get", "update"])
+                .attach_printable("Failed to update package list")?;
+
+            // Install Docker on Ubuntu/Debian
+            run_command("sudo", &["apt-get", "install", "-y", "docker"])
+                .attach_printable("Failed to install Docker")?;
+        } else if os_release.contains("CentOS") || os_release.contains("Fedora") {
+            // Install Docker on CentOS/Fedora
+            run_command("sudo", &["yum", "install", "-y", "docker"])
+                .attach_printable("Failed to install Docker")?;
+        } else {
+            return Err(err!(AnyErr, "Unsupported Linux distribution"));
+        }
+    }
+
+    // Start Docker service
+    run_command("sudo", &["systemctl", "start", "docker"])
+        .attach_printable("Failed to start Docker service")?;
+
+    // Enable Docker service to start on boot
+    run_command("sudo", &["systemctl", "enable", "docker"])
+        .attach_printable("Failed to enable Docker service")?;
+
+    debug!("Docker has been installed and started on Linux.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::prelude::*;
+
+    #[rstest]
+    #[case::desktop("Docker Desktop|docker-desktop", DockerBackend::DockerDesktop)]
+    #[case::colima("Ubuntu 24.04 LTS|colima", DockerBackend::Colima)]
+    #[case::engine("Ubuntu 22.04.4 LTS|build-agent-3", DockerBackend::Engine)]
+    fn detects_backend(#[case] info: &str, #[case] backend: DockerBackend) {
+        assert_eq!(parse_backend(info), backend);
+    }
+
+    #[rstest]
+    fn creates_missing_podman_machine(cmd_harness: CmdHarness) {
+        cmd_harness.fail("podman", ["machine", "start"], 1);
+        start_podman_machine().unwrap();
+        assert_eq!(
+            cmd_harness
+                .ran()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "podman machine start",
+                "podman machine init",
+                "podman machine start"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    fn waits_for_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        // Answers from the 3rd call on:
+        let cli = super::super::client::fake_cli(
+            dir.path(),
+            &format!(
+                "echo x >> {0}/calls\nif [ $(wc -l < {0}/calls) -lt 3 ]; then exit 1; fi\necho 'Ubuntu|colima'",
+                dir.path().display()
+            ),
+        );
+        assert_eq!(
+            cli.wait_for_daemon(Duration::from_secs(10)).unwrap(),
+            DockerBackend::Colima
+        );
+        assert!(cli.is_running());
+        assert_eq!(
+            cli.clone().engine(ContainerEngine::Podman).backend(),
+            Some(DockerBackend::Podman)
+        );
+
+        let cli = super::super::client::fake_cli(dir.path(), "exit 1");
+        let report = cli.wait_for_daemon(Duration::from_millis(100)).unwrap_err();
+        assert_eq!(
+            crate::errors::error_class(&report),
+            Some(ErrorClass::Timeout)
+        );
+    }
+}
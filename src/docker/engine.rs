@@ -0,0 +1,115 @@
+use once_cell::sync::Lazy;
+use std::process::Command;
+use std::str::FromStr;
+
+use super::DockerCli;
+use crate::cmd::which;
+use crate::prelude::*;
+
+/// Set to `docker` or `podman` to skip detecting the [`ContainerEngine`].
+pub const CONTAINER_ENGINE_ENV: &str = "CONTAINER_ENGINE";
+
+static DETECTED: Lazy<ContainerEngine> = Lazy::new(|| {
+    let chosen = std::env::var(CONTAINER_ENGINE_ENV)
+        .ok()
+        .and_then(|value| match value.parse() {
+            Ok(engine) => Some(engine),
+            Err(report) => {
+                warn!("Ignoring {}: {:?}", CONTAINER_ENGINE_ENV, report);
+                None
+            }
+        });
+    let engine = chosen.unwrap_or_else(|| {
+        let docker = which("docker").is_some();
+        choose(
+            docker,
+            docker && docker_is_podman(),
+            which("podman").is_some(),
+        )
+    });
+    debug!("Using {:?} to run containers", engine);
+    engine
+});
+
+/// What runs containers, the helpers of this module use [`ContainerEngine::detect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerEngine {
+    Docker,
+    /// Daemonless and usually rootless, with a docker compatible cli.
+    Podman,
+}
+
+impl ContainerEngine {
+    /// [`CONTAINER_ENGINE_ENV`] if set, otherwise docker unless only podman is installed or
+    /// `docker` is podman's emulation script. Detected once per process.
+    pub fn detect() -> Self {
+        *DETECTED
+    }
+
+    pub fn program(self) -> &'static str {
+        match self {
+            ContainerEngine::Docker => "docker",
+            ContainerEngine::Podman => "podman",
+        }
+    }
+
+    pub fn cli(self) -> DockerCli {
+        DockerCli::new(self.program()).engine(self)
+    }
+}
+
+impl FromStr for ContainerEngine {
+    type Err = Report<AnyErr>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "docker" => Ok(ContainerEngine::Docker),
+            "podman" => Ok(ContainerEngine::Podman),
+            _ => Err(err!(AnyErr, "Unknown container engine: {}", s)),
+        }
+    }
+}
+
+/// `podman-docker` installs a `docker` script forwarding to podman.
+fn docker_is_podman() -> bool {
+    Command::new("docker")
+        .arg("--version")
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .to_lowercase()
+                .contains("podman")
+        })
+        .unwrap_or(false)
+}
+
+/// Without either installed docker is chosen, so errors name the usual cli.
+fn choose(has_docker: bool, docker_is_podman: bool, has_podman: bool) -> ContainerEngine {
+    if (has_docker && !docker_is_podman) || !has_podman {
+        ContainerEngine::Docker
+    } else {
+        ContainerEngine::Podman
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case::docker(true, false, false, ContainerEngine::Docker)]
+    #[case::both(true, false, true, ContainerEngine::Docker)]
+    #[case::podman(false, false, true, ContainerEngine::Podman)]
+    #[case::emulated(true, true, true, ContainerEngine::Podman)]
+    #[case::neither(false, false, false, ContainerEngine::Docker)]
+    fn chooses_engine(
+        #[case] has_docker: bool,
+        #[case] docker_is_podman: bool,
+        #[case] has_podman: bool,
+        #[case] engine: ContainerEngine,
+    ) {
+        assert_eq!(choose(has_docker, docker_is_podman, has_podman), engine);
+        assert_eq!(engine.program().parse::<ContainerEngine>().unwrap(), engine);
+    }
+}
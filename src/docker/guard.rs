@@ -0,0 +1,283 @@
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use super::{
+    ContainerInfo, ContainerLogs, ContainerSpec, ContainerStatus, DockerCli, DockerClient,
+    HealthStatus,
+};
+use crate::prelude::*;
+
+/// How often a [`ContainerGuard`] checks its [`Readiness`].
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// When a container started by a [`ContainerGuard`] counts as ready.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Readiness {
+    /// As soon as it's running.
+    Running,
+    /// Once the host port publishing this container port accepts connections.
+    Tcp(u16),
+    /// Once its stdout or stderr contains this.
+    LogLine(String),
+    /// Once its healthcheck passes.
+    Healthcheck,
+}
+
+/// A container removed when dropped, for tests needing a real redis, postgres etc.
+///
+/// ```ignore
+/// let redis = ContainerGuard::start(
+///     ContainerSpec::new("redis:7").port(6379),
+///     Readiness::LogLine("Ready to accept connections".into()),
+///     Duration::from_secs(30),
+/// )?;
+/// let url = format!("redis://127.0.0.1:{}", redis.host_port(6379).unwrap());
+/// ```
+pub struct ContainerGuard {
+    client: Box<dyn DockerClient>,
+    id: String,
+    info: ContainerInfo,
+}
+
+impl std::fmt::Debug for ContainerGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ContainerGuard")
+            .field("id", &self.id)
+            .field("info", &self.info)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ContainerGuard {
+    /// Starts `spec` with the docker cli and waits up to `timeout` for it to be ready.
+    pub fn start(
+        spec: ContainerSpec,
+        readiness: Readiness,
+        timeout: Duration,
+    ) -> RResult<Self, AnyErr> {
+        Self::start_with(DockerCli::default(), spec, readiness, timeout)
+    }
+
+    /// Like [`ContainerGuard::start`] with another client.
+    pub fn start_with(
+        client: impl DockerClient + 'static,
+        spec: ContainerSpec,
+        readiness: Readiness,
+        timeout: Duration,
+    ) -> RResult<Self, AnyErr> {
+        let id = client.run_container(&spec)?;
+        let info = match client.inspect(&id) {
+            Ok(info) => info,
+            Err(report) => {
+                remove(&client, &id);
+                return Err(report);
+            }
+        };
+        // From here on dropping the guard removes the container, also when it never gets ready:
+        let mut guard = ContainerGuard {
+            client: Box::new(client),
+            id,
+            info,
+        };
+        guard
+            .wait_ready(&readiness, timeout)
+            .attach_printable_lazy(|| format!("Image: {}", spec.image()))?;
+        debug!("Container {} of {} is ready", guard.info.name, spec.image());
+        Ok(guard)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// What the container looked like once ready.
+    pub fn info(&self) -> &ContainerInfo {
+        &self.info
+    }
+
+    /// The host port publishing tcp `container_port`.
+    pub fn host_port(&self, container_port: u16) -> Option<u16> {
+        self.info.host_port(container_port)
+    }
+
+    pub fn logs(&self) -> RResult<ContainerLogs, AnyErr> {
+        self.client.logs(&self.id)
+    }
+
+    fn wait_ready(&mut self, readiness: &Readiness, timeout: Duration) -> RResult<(), AnyErr> {
+        let started = Instant::now();
+        loop {
+            self.info = self.client.inspect(&self.id)?;
+            if matches!(
+                self.info.status,
+                ContainerStatus::Exited | ContainerStatus::Dead
+            ) {
+                let logs = self.client.logs(&self.id).unwrap_or_default();
+                return Err(err!(
+                    AnyErr,
+                    "Container {} exited with code {} before getting ready",
+                    self.info.name,
+                    self.info.exit_code
+                ))
+                .attach_printable(format!("Stdout: {}", logs.stdout))
+                .attach_printable(format!("Stderr: {}", logs.stderr));
+            }
+            if self.info.is_running() && self.is_ready(readiness)? {
+                return Ok(());
+            }
+            if started.elapsed() >= timeout {
+                return Err(err!(
+                    AnyErr,
+                    "Timed out waiting for container {} to be ready",
+                    self.info.name
+                ))
+                .attach_printable(format!("Readiness: {:?}", readiness))
+                .classify(ErrorClass::Timeout);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn is_ready(&self, readiness: &Readiness) -> RResult<bool, AnyErr> {
+        Ok(match readiness {
+            Readiness::Running => true,
+            Readiness::Tcp(container_port) => {
+                let host_port = self.host_port(*container_port).ok_or_else(|| {
+                    err!(AnyErr, "Container port {} isn't published", container_port)
+                })?;
+                let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, host_port));
+                TcpStream::connect_timeout(&addr, POLL_INTERVAL).is_ok()
+            }
+            Readiness::LogLine(line) => {
+                let logs = self.client.logs(&self.id)?;
+                logs.stdout.contains(line.as_str()) || logs.stderr.contains(line.as_str())
+            }
+            Readiness::Healthcheck => match self.info.health {
+                Some(HealthStatus::Healthy) => true,
+                Some(HealthStatus::Starting) => false,
+                Some(HealthStatus::Unhealthy) => {
+                    return Err(err!(AnyErr, "Container {} is unhealthy", self.info.name))
+                }
+                None => {
+                    return Err(err!(
+                        AnyErr,
+                        "Container {} has no healthcheck",
+                        self.info.name
+                    ))
+                }
+            },
+        })
+    }
+}
+
+impl Drop for ContainerGuard {
+    fn drop(&mut self) {
+        remove(self.client.as_ref(), &self.id);
+    }
+}
+
+fn remove(client: &dyn DockerClient, id: &str) {
+    if let Err(report) = client.remove(id) {
+        warn!("Failed to remove container {}: {:?}", id, report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    /// A container logging "ready" on the 3rd check, or exiting right away.
+    #[derive(Clone, Default)]
+    struct FakeDocker {
+        exits: bool,
+        log_checks: Arc<Mutex<usize>>,
+        removed: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DockerClient for FakeDocker {
+        fn is_running(&self) -> bool {
+            true
+        }
+
+        fn pull(&self, _image: &str) -> RResult<(), AnyErr> {
+            Ok(())
+        }
+
+        fn run_container(&self, _spec: &ContainerSpec) -> RResult<String, AnyErr> {
+            Ok("c0ffee".to_string())
+        }
+
+        fn stop(&self, _container: &str) -> RResult<(), AnyErr> {
+            Ok(())
+        }
+
+        fn remove(&self, container: &str) -> RResult<(), AnyErr> {
+            self.removed.lock().unwrap().push(container.to_string());
+            Ok(())
+        }
+
+        fn logs(&self, _container: &str) -> RResult<ContainerLogs, AnyErr> {
+            let mut checks = self.log_checks.lock().unwrap();
+            *checks += 1;
+            Ok(ContainerLogs {
+                stdout: if *checks >= 3 {
+                    "ready\n"
+                } else {
+                    "starting\n"
+                }
+                .to_string(),
+                stderr: String::new(),
+            })
+        }
+
+        fn inspect(&self, container: &str) -> RResult<ContainerInfo, AnyErr> {
+            Ok(ContainerInfo {
+                id: container.to_string(),
+                name: "fake".to_string(),
+                image: "fake:1".to_string(),
+                status: if self.exits {
+                    ContainerStatus::Exited
+                } else {
+                    ContainerStatus::Running
+                },
+                exit_code: if self.exits { 1 } else { 0 },
+                health: None,
+                ports: BTreeMap::from([("6379/tcp".to_string(), vec![32768])]),
+            })
+        }
+    }
+
+    #[rstest]
+    fn removes_on_drop() {
+        let docker = FakeDocker::default();
+        let guard = ContainerGuard::start_with(
+            docker.clone(),
+            ContainerSpec::new("fake:1").port(6379),
+            Readiness::LogLine("ready".to_string()),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(*docker.log_checks.lock().unwrap(), 3);
+        assert_eq!(guard.host_port(6379), Some(32768));
+        assert!(docker.removed.lock().unwrap().is_empty());
+        drop(guard);
+        assert_eq!(*docker.removed.lock().unwrap(), ["c0ffee"]);
+
+        let docker = FakeDocker {
+            exits: true,
+            ..Default::default()
+        };
+        let report = ContainerGuard::start_with(
+            docker.clone(),
+            ContainerSpec::new("fake:1"),
+            Readiness::Running,
+            Duration::from_secs(5),
+        )
+        .unwrap_err();
+        assert!(format!("{:?}", report).contains("exited with code 1"));
+        assert_eq!(*docker.removed.lock().unwrap(), ["c0ffee"]);
+    }
+}
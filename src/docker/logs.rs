@@ -0,0 +1,168 @@
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use super::client::describe;
+use super::{DockerCli, DockerCommandFailed};
+use crate::prelude::*;
+
+/// A container's logs being forwarded to tracing, see [`DockerCli::stream_container_logs`].
+#[derive(Debug)]
+pub struct ContainerLogStream {
+    cancel: CancellationToken,
+    task: JoinHandle<RResult<(), AnyErr>>,
+}
+
+impl ContainerLogStream {
+    /// Stops following the logs, the container keeps running.
+    pub fn stop(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Waits until the container's logs end, i.e. it's removed, or until stopped.
+    pub async fn wait(self) -> RResult<(), AnyErr> {
+        self.task
+            .await
+            .change_context(AnyErr)
+            .attach_printable("Container log task panicked")?
+    }
+}
+
+impl DockerCli {
+    /// Follows `container`'s logs from its start, logging stdout lines as info and stderr as
+    /// warn with the container as their `service_name`, in a `container_logs` span of
+    /// `app_name`. With [`crate::redis_tracing`] set up they are stored with the app's logs.
+    ///
+    /// Needs a tokio runtime.
+    pub fn stream_container_logs(&self, container: &str, app_name: &str) -> ContainerLogStream {
+        let mut command = tokio::process::Command::from(self.command());
+        command
+            .args(["logs", "--follow", container])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let cancel = CancellationToken::new();
+        let span =
+            tracing::info_span!("container_logs", container = %container, app_name = %app_name);
+        let task =
+            tokio::spawn(follow(command, container.to_string(), cancel.clone()).instrument(span));
+        ContainerLogStream { cancel, task }
+    }
+}
+
+async fn follow(
+    mut command: tokio::process::Command,
+    container: String,
+    cancel: CancellationToken,
+) -> RResult<(), AnyErr> {
+    let mut child = command
+        .spawn()
+        .change_context(AnyErr)
+        .attach_printable_lazy(|| format!("Failed to follow the logs of {}", container))?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("Failed to capture stdout")).lines();
+    let mut stderr = BufReader::new(child.stderr.take().expect("Failed to capture stderr")).lines();
+    let (mut stdout_open, mut stderr_open) = (true, true);
+    let mut last_stderr = String::new();
+    while stdout_open || stderr_open {
+        tokio::select! {
+            _ = cancel.cancelled() => {
+                let _ = child.kill().await;
+                return Ok(());
+            }
+            line = stdout.next_line(), if stdout_open => match line {
+                Ok(Some(line)) => info!(service_name = %container, stream = "stdout", "{}", line),
+                _ => stdout_open = false,
+            },
+            line = stderr.next_line(), if stderr_open => match line {
+                Ok(Some(line)) => {
+                    warn!(service_name = %container, stream = "stderr", "{}", line);
+                    last_stderr = line;
+                }
+                _ => stderr_open = false,
+            },
+        }
+    }
+
+    let status = child
+        .wait()
+        .await
+        .change_context(AnyErr)
+        .attach_printable("Failed to wait on docker logs")?;
+    if !status.success() {
+        return Err(Report::new(DockerCommandFailed {
+            command: describe(command.as_std()),
+            code: status.code(),
+            stderr: last_stderr,
+        })
+        .change_context(AnyErr));
+    }
+    debug!("The logs of {} ended", container);
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use rstest::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn forwards_lines_to_tracing() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let _default = tracing::subscriber::set_default(
+            tracing_subscriber::fmt()
+                .with_writer(move || writer.clone())
+                .with_ansi(false)
+                .finish(),
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let cli = super::super::client::fake_cli(
+            dir.path(),
+            "echo \"listening on $3\"\necho 'slow query' >&2",
+        );
+        cli.stream_container_logs("redis", "api")
+            .wait()
+            .await
+            .unwrap();
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let stdout = lines
+            .iter()
+            .find(|l| l.contains("listening on redis"))
+            .unwrap();
+        assert!(stdout.contains("INFO container_logs{container=redis app_name=api}"));
+        assert!(stdout.contains("service_name=redis"));
+        assert!(lines
+            .iter()
+            .any(|l| l.contains("WARN") && l.contains("slow query")));
+
+        let cli = super::super::client::fake_cli(dir.path(), "exec sleep 30");
+        let stream = cli.stream_container_logs("redis", "api");
+        stream.stop();
+        stream.wait().await.unwrap();
+    }
+}
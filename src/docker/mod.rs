@@ -0,0 +1,139 @@
+use crate::prelude::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+mod build;
+mod client;
+mod compose;
+mod daemon;
+mod engine;
+mod guard;
+mod logs;
+mod prune;
+mod registry;
+mod types;
+mod wait;
+
+pub use build::{BuiltImage, DockerBuildFailed, PushedImage, RegistryAuth};
+pub use client::{DockerCli, DockerClient};
+pub use compose::{ComposeContainer, ComposePublisher};
+pub use daemon::{
+    ensure_docker_running, ensure_docker_running_with_timeout, DockerBackend, DAEMON_START_TIMEOUT,
+};
+pub use engine::{ContainerEngine, CONTAINER_ENGINE_ENV};
+pub use guard::{ContainerGuard, Readiness};
+pub use logs::ContainerLogStream;
+pub use prune::{DiskUsage, PrunePolicy, PruneReport, UsageEntry};
+pub use registry::image_exists;
+pub use types::{ContainerInfo, ContainerLogs, ContainerSpec, ContainerStatus, HealthStatus};
+pub use wait::{wait_for_container_healthy, wait_for_http_ok, wait_for_tcp};
+
+/// The docker items, part of [`crate::prelude`] with the `docker` feature.
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{ContainerGuard, ContainerSpec, DockerCli, DockerClient, Readiness};
+}
+
+/// The context below the `AnyErr` of a docker command exiting unsuccessfully, get it with
+/// `report.frames().find_map(|f| f.downcast_ref::<DockerCommandFailed>())`.
+#[derive(Debug, Clone)]
+pub struct DockerCommandFailed {
+    pub command: String,
+    /// None when killed by a signal.
+    pub code: Option<i32>,
+    pub stderr: String,
+}
+
+impl std::fmt::Display for DockerCommandFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "`{}` failed with exit code {}", self.command, code),
+            None => write!(f, "`{}` was killed by a signal", self.command),
+        }?;
+        if !self.stderr.is_empty() {
+            write!(f, ": {}", self.stderr)?;
+        }
+        Ok(())
+    }
+}
+
+impl error_stack::Context for DockerCommandFailed {}
+
+/// Makes sure the docker daemon is running, see [`ensure_docker_running`].
+pub fn ensure_running() -> RResult<DockerBackend, AnyErr> {
+    ensure_docker_running()
+}
+
+/// Pulls `image` with the docker cli.
+pub fn pull(image: &str) -> RResult<(), AnyErr> {
+    DockerCli::default().pull(image)
+}
+
+/// Starts a detached container with the docker cli, returning its id.
+pub fn run_container(spec: &ContainerSpec) -> RResult<String, AnyErr> {
+    DockerCli::default().run_container(spec)
+}
+
+pub fn stop(container: &str) -> RResult<(), AnyErr> {
+    DockerCli::default().stop(container)
+}
+
+pub fn logs(container: &str) -> RResult<ContainerLogs, AnyErr> {
+    DockerCli::default().logs(container)
+}
+
+pub fn inspect(container: &str) -> RResult<ContainerInfo, AnyErr> {
+    DockerCli::default().inspect(container)
+}
+
+/// Forwards a container's logs to tracing, see [`DockerCli::stream_container_logs`].
+pub fn stream_container_logs(container: &str, app_name: &str) -> ContainerLogStream {
+    DockerCli::default().stream_container_logs(container, app_name)
+}
+
+/// What docker's storage is used by, see [`DockerCli::disk_usage`].
+pub fn disk_usage() -> RResult<DiskUsage, AnyErr> {
+    DockerCli::default().disk_usage()
+}
+
+/// Removes unused images, see [`DockerCli::prune`].
+pub fn prune(policy: &PrunePolicy) -> RResult<PruneReport, AnyErr> {
+    DockerCli::default().prune(policy)
+}
+
+/// Builds `context_dir` as `tag` with the docker cli, see [`DockerCli::build`].
+pub fn build(
+    context_dir: impl AsRef<Path>,
+    tag: &str,
+    build_args: &BTreeMap<String, String>,
+) -> RResult<BuiltImage, AnyErr> {
+    DockerCli::default().build(context_dir, tag, build_args)
+}
+
+/// Pushes `tag` with the docker cli, see [`DockerCli::push`].
+pub fn push(tag: &str, registry_auth: Option<&RegistryAuth>) -> RResult<PushedImage, AnyErr> {
+    DockerCli::default().push(tag, registry_auth)
+}
+
+/// Logs in to `registry` with the docker cli, see [`DockerCli::registry_login`].
+pub fn registry_login(registry: &str, user: &str, token: &str) -> RResult<(), AnyErr> {
+    DockerCli::default().registry_login(registry, user, token)
+}
+
+/// Starts `services` of a compose file, all of them if empty, see [`DockerCli::compose_up`].
+pub fn compose_up(file: impl AsRef<Path>, services: &[&str], detach: bool) -> RResult<(), AnyErr> {
+    DockerCli::default().compose_up(file, services, detach)
+}
+
+pub fn compose_down(file: impl AsRef<Path>) -> RResult<(), AnyErr> {
+    DockerCli::default().compose_down(file)
+}
+
+/// Waits for the containers of a compose file, see [`DockerCli::compose_wait_healthy`].
+pub fn compose_wait_healthy(
+    file: impl AsRef<Path>,
+    timeout: Duration,
+) -> RResult<Vec<ComposeContainer>, AnyErr> {
+    DockerCli::default().compose_wait_healthy(file, timeout)
+}
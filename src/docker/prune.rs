@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::{ContainerEngine, DockerCli, DockerCommandFailed};
+use crate::prelude::*;
+
+/// Space used by one kind of object, from `docker system df`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UsageEntry {
+    pub total: u64,
+    /// How many are in use by a container.
+    pub active: u64,
+    pub size_bytes: u64,
+    /// What pruning unused ones would free.
+    pub reclaimable_bytes: u64,
+}
+
+/// What docker's storage is used by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub images: UsageEntry,
+    pub containers: UsageEntry,
+    pub volumes: UsageEntry,
+    pub build_cache: UsageEntry,
+}
+
+impl DiskUsage {
+    pub fn size_bytes(&self) -> u64 {
+        self.entries().map(|entry| entry.size_bytes).sum()
+    }
+
+    pub fn reclaimable_bytes(&self) -> u64 {
+        self.entries().map(|entry| entry.reclaimable_bytes).sum()
+    }
+
+    fn entries(&self) -> impl Iterator<Item = &UsageEntry> {
+        [
+            &self.images,
+            &self.containers,
+            &self.volumes,
+            &self.build_cache,
+        ]
+        .into_iter()
+    }
+}
+
+/// Which images [`DockerCli::prune`] removes, images used by a container are always kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrunePolicy {
+    /// Only untagged images, otherwise all unused ones.
+    pub dangling_only: bool,
+    /// Only images created longer ago than this.
+    pub older_than: Option<Duration>,
+    /// `repository:tag` images never to remove, a repository without a tag keeps all its tags.
+    pub keep_tags: Vec<String>,
+}
+
+/// What [`DockerCli::prune`] removed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    /// `repository:tag` or, when untagged, the image id.
+    pub removed: Vec<String>,
+    /// The sizes of the images removed completely, shared layers make this an estimate.
+    pub reclaimed_bytes: u64,
+}
+
+/// A row of `docker image ls`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ImageRow {
+    id: String,
+    /// None when dangling.
+    reference: Option<String>,
+    repository: String,
+    created: Option<chrono::DateTime<chrono::FixedOffset>>,
+    size_bytes: u64,
+}
+
+const IMAGE_FORMAT: &str = "{{.ID}}\t{{.Repository}}\t{{.Tag}}\t{{.CreatedAt}}\t{{.Size}}";
+
+impl DockerCli {
+    pub fn disk_usage(&self) -> RResult<DiskUsage, AnyErr> {
+        let total = match self.get_engine() {
+            ContainerEngine::Docker => "{{.TotalCount}}",
+            ContainerEngine::Podman => "{{.Total}}",
+        };
+        let format = format!(
+            "{{{{.Type}}}}\t{}\t{{{{.Active}}}}\t{{{{.Size}}}}\t{{{{.Reclaimable}}}}",
+            total
+        );
+        parse_disk_usage(&self.output(&["system", "df", "--format", &format])?)
+    }
+
+    /// Removes the unused images matching `policy`, one by one so images that are in use or
+    /// were removed concurrently are skipped rather than failing the prune.
+    pub fn prune(&self, policy: &PrunePolicy) -> RResult<PruneReport, AnyErr> {
+        let images = parse_images(&self.output(&["image", "ls", "--format", IMAGE_FORMAT])?)?;
+        let now = chrono::Utc::now();
+        let candidates = images
+            .iter()
+            .filter(|image| policy.matches(image, now))
+            .collect::<Vec<_>>();
+
+        let mut report = PruneReport::default();
+        let mut removed_refs: BTreeMap<&str, usize> = BTreeMap::new();
+        for image in &candidates {
+            let target = image.reference.as_deref().unwrap_or(&image.id);
+            match self.output(&["image", "rm", target]) {
+                Ok(_) => {
+                    report.removed.push(target.to_string());
+                    *removed_refs.entry(&image.id).or_default() += 1;
+                }
+                Err(report) => {
+                    let stderr = report
+                        .frames()
+                        .find_map(|f| f.downcast_ref::<DockerCommandFailed>())
+                        .map(|failed| failed.stderr.clone())
+                        .unwrap_or_default();
+                    debug!("Not removing image {}: {}", target, stderr);
+                }
+            }
+        }
+        // An image is only gone once all its tags are:
+        let mut sizes = BTreeMap::new();
+        for image in &images {
+            let (refs, size) = sizes.entry(image.id.as_str()).or_insert((0, 0));
+            *refs += 1;
+            *size = image.size_bytes;
+        }
+        report.reclaimed_bytes = sizes
+            .iter()
+            .filter(|(id, (refs, _))| removed_refs.get(*id) == Some(refs))
+            .map(|(_, (_, size))| size)
+            .sum();
+        info!(
+            "Pruned {} images, reclaiming about {} bytes",
+            report.removed.len(),
+            report.reclaimed_bytes
+        );
+        Ok(report)
+    }
+}
+
+impl PrunePolicy {
+    fn matches(&self, image: &ImageRow, now: chrono::DateTime<chrono::Utc>) -> bool {
+        if let Some(reference) = &image.reference {
+            if self.dangling_only {
+                return false;
+            }
+            let kept = self
+                .keep_tags
+                .iter()
+                .any(|keep| keep == reference || *keep == image.repository);
+            if kept {
+                return false;
+            }
+        }
+        match (self.older_than, image.created) {
+            (Some(older_than), Some(created)) => {
+                now.signed_duration_since(created)
+                    .to_std()
+                    .unwrap_or_default()
+                    > older_than
+            }
+            // Without a creation time it can't be known to be old enough:
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+}
+
+/// Parses docker's `1.2GB`, `12.3kB`, `0B` and podman's `1.2 GB`, which are decimal units.
+fn parse_size(size: &str) -> RResult<u64, AnyErr> {
+    // Reclaimable sizes end with a percentage, `1.2GB (50%)`:
+    let size = size.split(" (").next().unwrap_or_default();
+    let size = size.replace(' ', "");
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number = number
+        .parse::<f64>()
+        .change_context(AnyErr)
+        .attach_printable_lazy(|| format!("Invalid size: {}", size))?;
+    let multiplier = match unit.to_lowercase().as_str() {
+        "" | "b" => 1e0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        _ => return Err(err!(AnyErr, "Unknown size unit: {}", size)),
+    };
+    Ok((number * multiplier).round() as u64)
+}
+
+fn parse_disk_usage(output: &str) -> RResult<DiskUsage, AnyErr> {
+    let mut usage = DiskUsage::default();
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        let fields = line.split('\t').map(str::trim).collect::<Vec<_>>();
+        let [kind, total, active, size, reclaimable] = fields[..] else {
+            return Err(err!(AnyErr, "Invalid docker system df line: {}", line));
+        };
+        let entry = UsageEntry {
+            total: total.parse().unwrap_or_default(),
+            active: active.parse().unwrap_or_default(),
+            size_bytes: parse_size(size)?,
+            reclaimable_bytes: parse_size(reclaimable)?,
+        };
+        match kind.to_lowercase().as_str() {
+            "images" => usage.images = entry,
+            "containers" => usage.containers = entry,
+            "local volumes" => usage.volumes = entry,
+            "build cache" => usage.build_cache = entry,
+            _ => debug!("Ignoring docker disk usage of {}", kind),
+        }
+    }
+    Ok(usage)
+}
+
+fn parse_images(output: &str) -> RResult<Vec<ImageRow>, AnyErr> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields = line.split('\t').map(str::trim).collect::<Vec<_>>();
+            let [id, repository, tag, created, size] = fields[..] else {
+                return Err(err!(AnyErr, "Invalid docker image ls line: {}", line));
+            };
+            let reference = (repository != "<none>" && tag != "<none>")
+                .then(|| format!("{}:{}", repository, tag));
+            // `2024-05-01 10:00:00 +0200 CEST`, the zone name isn't parseable:
+            let created = created.splitn(4, ' ').take(3).collect::<Vec<_>>().join(" ");
+            Ok(ImageRow {
+                id: id.to_string(),
+                reference,
+                repository: repository.to_string(),
+                created: chrono::DateTime::parse_from_str(&created, "%Y-%m-%d %H:%M:%S %z").ok(),
+                size_bytes: parse_size(size)?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("0B", 0)]
+    #[case("12.3kB", 12_300)]
+    #[case("72.5 MB", 72_500_000)]
+    #[case("1.2GB (50%)", 1_200_000_000)]
+    fn parses_sizes(#[case] size: &str, #[case] bytes: u64) {
+        assert_eq!(parse_size(size).unwrap(), bytes);
+    }
+
+    #[rstest]
+    fn parses_disk_usage() {
+        let usage = parse_disk_usage(
+            "Images\t5\t2\t2.4GB\t1.2GB (50%)\nContainers\t3\t1\t10MB\t5MB (50%)\nLocal Volumes\t1\t1\t1GB\t0B (0%)\nBuild Cache\t12\t0\t500MB\t500MB",
+        )
+        .unwrap();
+        assert_eq!(
+            usage.images,
+            UsageEntry {
+                total: 5,
+                active: 2,
+                size_bytes: 2_400_000_000,
+                reclaimable_bytes: 1_200_000_000,
+            }
+        );
+        assert_eq!(usage.volumes.size_bytes, 1_000_000_000);
+        assert_eq!(usage.reclaimable_bytes(), 1_705_000_000);
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    fn prunes_per_policy() {
+        let now = chrono::Utc::now();
+        let old = (now - chrono::Duration::days(30)).format("%Y-%m-%d %H:%M:%S +0000 UTC");
+        let new = now.format("%Y-%m-%d %H:%M:%S +0000 UTC");
+        let dir = tempfile::tempdir().unwrap();
+        let images = [
+            format!("aaa\tapp\tv1\t{}\t100MB", old),
+            format!("aaa\tapp\tlatest\t{}\t100MB", old),
+            format!("bbb\t<none>\t<none>\t{}\t50MB", old),
+            format!("ccc\tredis\t7\t{}\t30MB", old),
+            format!("ddd\tapp\tv2\t{}\t100MB", new),
+            format!("eee\tpostgres\t16\t{}\t200MB", old),
+        ];
+        // redis is used by a container:
+        let cli = super::super::client::fake_cli(
+            dir.path(),
+            &format!(
+                "if [ \"$2\" = ls ]; then printf '{}\\n'; elif [ \"$3\" = redis:7 ]; then echo 'image is being used' >&2; exit 1; fi",
+                images.join("\\n")
+            ),
+        );
+
+        let report = cli
+            .prune(&PrunePolicy {
+                dangling_only: false,
+                older_than: Some(Duration::from_secs(7 * 24 * 3600)),
+                keep_tags: vec!["postgres".to_string(), "app:latest".to_string()],
+            })
+            .unwrap();
+        assert_eq!(report.removed, ["app:v1", "bbb"]);
+        // app:latest still holds aaa:
+        assert_eq!(report.reclaimed_bytes, 50_000_000);
+
+        let report = cli
+            .prune(&PrunePolicy {
+                dangling_only: true,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(report.removed, ["bbb"]);
+    }
+}
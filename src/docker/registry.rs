@@ -0,0 +1,333 @@
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use super::{DockerCli, RegistryAuth};
+use crate::endpoints::{Endpoint, EndpointBuilder, HttpError, Method};
+use crate::prelude::*;
+
+/// Docker hub's registry, for images without a registry in their name.
+const DOCKER_HUB: &str = "registry-1.docker.io";
+
+/// The manifest types a registry may store a tag as.
+const MANIFEST_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+
+/// An image name split into where its manifest lives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct ImageRef {
+    pub registry: String,
+    pub repository: String,
+    /// A tag or `sha256:...` digest.
+    pub reference: String,
+}
+
+impl ImageRef {
+    /// Parses `[registry/]repository[:tag|@digest]` like docker does, defaulting to docker hub
+    /// and `latest`.
+    pub fn parse(image: &str) -> RResult<Self, AnyErr> {
+        let (name, reference) = match image.split_once('@') {
+            Some((name, digest)) => (name, digest.to_string()),
+            None => match image.rsplit_once(':') {
+                // A colon after the last slash is a tag, before it a registry port:
+                Some((name, tag)) if !tag.contains('/') => (name, tag.to_string()),
+                _ => (image, "latest".to_string()),
+            },
+        };
+        let (registry, repository) = match name.split_once('/') {
+            Some((first, rest))
+                if first.contains('.') || first.contains(':') || first == "localhost" =>
+            {
+                (first.to_string(), rest.to_string())
+            }
+            Some(_) => (DOCKER_HUB.to_string(), name.to_string()),
+            None => (DOCKER_HUB.to_string(), format!("library/{}", name)),
+        };
+        if repository.is_empty() || reference.is_empty() {
+            return Err(err!(AnyErr, "Invalid image name: {}", image));
+        }
+        Ok(ImageRef {
+            registry,
+            repository,
+            reference,
+        })
+    }
+
+    /// Local registries are usually plain http.
+    fn base_url(&self) -> String {
+        let host = self.registry.split(':').next().unwrap_or_default();
+        let scheme = if is_local(host) { "http" } else { "https" };
+        format!("{}://{}", scheme, self.registry)
+    }
+
+    fn manifest_request(&self) -> EndpointBuilder {
+        Endpoint::builder()
+            .base_url(&self.base_url())
+            .endpoint(&format!(
+                "/v2/{}/manifests/{}",
+                self.repository, self.reference
+            ))
+            .method(Method::HEAD)
+            .header("Accept", MANIFEST_TYPES)
+    }
+}
+
+impl DockerCli {
+    /// `docker login`, the token is passed on stdin so it isn't visible in the process list.
+    pub fn registry_login(&self, registry: &str, user: &str, token: &str) -> RResult<(), AnyErr> {
+        self.login(&RegistryAuth {
+            registry: registry.to_string(),
+            username: user.to_string(),
+            token: token.to_string(),
+        })
+    }
+}
+
+/// Whether `image` is published, asking its registry for the manifest without pulling it.
+///
+/// Public images need no `auth`, the token is only sent to the registry and its token service.
+pub async fn image_exists(image: &str, auth: Option<&RegistryAuth>) -> RResult<bool, AnyErr> {
+    let image_ref = ImageRef::parse(image)?;
+    let report = match image_ref.manifest_request().send_bytes().await {
+        Ok(_) => return Ok(true),
+        Err(report) => report,
+    };
+    let Some(http) = HttpError::from_report(&report) else {
+        return Err(report.change_context(AnyErr));
+    };
+    match http.status {
+        StatusCode::NOT_FOUND => return Ok(false),
+        StatusCode::UNAUTHORIZED => {}
+        _ => return Err(report.change_context(AnyErr)),
+    }
+
+    // Registries answer anonymous requests with how to authenticate:
+    let challenge = http
+        .headers
+        .get(reqwest::header::WWW_AUTHENTICATE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let request = match parse_challenge(&challenge) {
+        Challenge::Bearer { realm, params } => {
+            let http_registry = image_ref.base_url().starts_with("http:");
+            let token = bearer_token(&realm, &params, auth, http_registry).await?;
+            image_ref.manifest_request().bearer_token(&token)
+        }
+        Challenge::Basic => match auth {
+            Some(auth) => image_ref
+                .manifest_request()
+                .basic_auth(&auth.username, Some(&auth.token)),
+            None => return Err(report.change_context(AnyErr)),
+        },
+    };
+    match request.send_bytes().await {
+        Ok(_) => Ok(true),
+        Err(report) => match HttpError::from_report(&report).map(|http| http.status) {
+            Some(StatusCode::NOT_FOUND) => Ok(false),
+            _ => Err(report
+                .change_context(AnyErr)
+                .attach_printable(format!("Image: {}", image))),
+        },
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Challenge {
+    /// A token service at `realm`, with `service` and `scope` to ask it for.
+    Bearer {
+        realm: String,
+        params: Vec<(String, String)>,
+    },
+    Basic,
+}
+
+/// Parses `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`.
+fn parse_challenge(header: &str) -> Challenge {
+    let Some(rest) = header
+        .strip_prefix("Bearer ")
+        .or_else(|| header.strip_prefix("bearer "))
+    else {
+        return Challenge::Basic;
+    };
+    let mut realm = String::new();
+    let mut params = vec![];
+    // Values are quoted and scopes can contain commas, so split on `",` rather than `,`:
+    for pair in rest.split("\",") {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"').to_string();
+        match key.trim() {
+            "realm" => realm = value,
+            key => params.push((key.to_string(), value)),
+        }
+    }
+    Challenge::Bearer { realm, params }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+fn is_local(host: &str) -> bool {
+    host == "localhost" || host == "127.0.0.1"
+}
+
+/// Asks the token service at `realm` for a token. It has to be https, so the credentials aren't
+/// sent in the clear, unless it's local or the registry itself is plain http.
+async fn bearer_token(
+    realm: &str,
+    params: &[(String, String)],
+    auth: Option<&RegistryAuth>,
+    http_registry: bool,
+) -> RResult<String, AnyErr> {
+    let url = reqwest::Url::parse(realm)
+        .change_context(AnyErr)
+        .attach_printable_lazy(|| format!("Invalid token realm: {}", realm))?;
+    let local = url.host_str().is_some_and(is_local);
+    if url.scheme() != "https" && !(local || (http_registry && url.scheme() == "http")) {
+        return Err(err!(AnyErr, "Refusing the insecure token realm {}", realm));
+    }
+    let mut request = Endpoint::builder()
+        .base_url(url.origin().ascii_serialization().as_str())
+        .endpoint(url.path())
+        .method(Method::GET);
+    for (key, value) in params {
+        request = request.query_param(key, value);
+    }
+    if let Some(auth) = auth {
+        request = request.basic_auth(&auth.username, Some(&auth.token));
+    }
+    let response: TokenResponse = request
+        .send_typed()
+        .await
+        .change_context(AnyErr)
+        .attach_printable("Failed to get a registry token")?;
+    response
+        .token
+        .or(response.access_token)
+        .ok_or_else(|| err!(AnyErr, "No token from {}", realm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[rstest]
+    #[case::official("redis", DOCKER_HUB, "library/redis", "latest")]
+    #[case::hub_user("acme/app:v1", DOCKER_HUB, "acme/app", "v1")]
+    #[case::registry("ghcr.io/acme/app:v1", "ghcr.io", "acme/app", "v1")]
+    #[case::port("localhost:5000/app", "localhost:5000", "app", "latest")]
+    #[case::digest("ghcr.io/acme/app@sha256:abc", "ghcr.io", "acme/app", "sha256:abc")]
+    fn parses_image_names(
+        #[case] image: &str,
+        #[case] registry: &str,
+        #[case] repository: &str,
+        #[case] reference: &str,
+    ) {
+        assert_eq!(
+            ImageRef::parse(image).unwrap(),
+            ImageRef {
+                registry: registry.to_string(),
+                repository: repository.to_string(),
+                reference: reference.to_string(),
+            }
+        );
+    }
+
+    #[rstest]
+    fn parses_challenges() {
+        assert_eq!(
+            parse_challenge(
+                r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/redis:pull,push""#
+            ),
+            Challenge::Bearer {
+                realm: "https://auth.docker.io/token".to_string(),
+                params: vec![
+                    ("service".to_string(), "registry.docker.io".to_string()),
+                    (
+                        "scope".to_string(),
+                        "repository:library/redis:pull,push".to_string()
+                    ),
+                ],
+            }
+        );
+        assert_eq!(
+            parse_challenge(r#"Basic realm="registry""#),
+            Challenge::Basic
+        );
+    }
+
+    /// A registry with only `app:v1`, behind a token service.
+    async fn fake_registry() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut buf = vec![0; 8192];
+                let read = conn.read(&mut buf).await.unwrap_or_default();
+                let request = String::from_utf8_lossy(&buf[..read]).to_lowercase();
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                let (status, headers, body) = if path.starts_with("/token") {
+                    ("200 OK", String::new(), r#"{"token": "secret"}"#)
+                } else if !request.contains("authorization: bearer secret") {
+                    let challenge = format!(
+                        "www-authenticate: Bearer realm=\"http://{}/token\",service=\"fake\"\r\n",
+                        addr
+                    );
+                    ("401 Unauthorized", challenge, "")
+                } else if path == "/v2/app/manifests/v1" {
+                    ("200 OK", String::new(), "")
+                } else {
+                    ("404 Not Found", String::new(), "")
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\n{}content-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    headers,
+                    body.len(),
+                    body
+                );
+                let _ = conn.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("127.0.0.1:{}", addr.port())
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn refuses_insecure_realms() {
+        let auth = RegistryAuth {
+            registry: "ghcr.io".to_string(),
+            username: "ci".to_string(),
+            token: "hunter2".to_string(),
+        };
+        let report = bearer_token("http://auth.example.com/token", &[], Some(&auth), false)
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", report).contains("insecure token realm"));
+        let report = bearer_token("ftp://auth.example.com/token", &[], Some(&auth), true)
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", report).contains("insecure token realm"));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn checks_published_tags() {
+        let registry = fake_registry().await;
+        assert!(image_exists(&format!("{}/app:v1", registry), None)
+            .await
+            .unwrap());
+        assert!(!image_exists(&format!("{}/app:v2", registry), None)
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,321 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::prelude::*;
+
+/// What to run with [`super::run_container`], always detached.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContainerSpec {
+    image: String,
+    name: Option<String>,
+    /// Host port (None for a random one) and container port.
+    ports: Vec<(Option<u16>, u16)>,
+    env: Vec<(String, String)>,
+    volumes: Vec<(String, String)>,
+    network: Option<String>,
+    auto_remove: bool,
+    extra_args: Vec<String>,
+    command: Vec<String>,
+}
+
+impl ContainerSpec {
+    pub fn new(image: impl Into<String>) -> Self {
+        ContainerSpec {
+            image: image.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Publishes `container_port` on a random host port, see [`ContainerInfo::host_port`].
+    pub fn port(mut self, container_port: u16) -> Self {
+        self.ports.push((None, container_port));
+        self
+    }
+
+    pub fn port_mapping(mut self, host_port: u16, container_port: u16) -> Self {
+        self.ports.push((Some(host_port), container_port));
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Mounts a host path or named volume at `container_path`.
+    pub fn volume(mut self, source: impl Into<String>, container_path: impl Into<String>) -> Self {
+        self.volumes.push((source.into(), container_path.into()));
+        self
+    }
+
+    pub fn network(mut self, network: impl Into<String>) -> Self {
+        self.network = Some(network.into());
+        self
+    }
+
+    /// Removes the container once it stops, like `--rm`.
+    pub fn auto_remove(mut self) -> Self {
+        self.auto_remove = true;
+        self
+    }
+
+    /// An option for `docker run` without a builder method, e.g. `--memory=512m`.
+    pub fn extra_arg(mut self, arg: impl Into<String>) -> Self {
+        self.extra_args.push(arg.into());
+        self
+    }
+
+    /// Overrides the image's command.
+    pub fn command(mut self, command: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.command = command.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The arguments for `docker run`.
+    pub fn run_args(&self) -> Vec<String> {
+        let mut args = vec!["run".to_string(), "--detach".to_string()];
+        let mut option = |flag: &str, value: String| {
+            args.push(flag.to_string());
+            args.push(value);
+        };
+        if let Some(name) = &self.name {
+            option("--name", name.clone());
+        }
+        for (host_port, container_port) in &self.ports {
+            match host_port {
+                Some(host_port) => option("--publish", format!("{}:{}", host_port, container_port)),
+                None => option("--publish", container_port.to_string()),
+            }
+        }
+        for (key, value) in &self.env {
+            option("--env", format!("{}={}", key, value));
+        }
+        for (source, container_path) in &self.volumes {
+            option("--volume", format!("{}:{}", source, container_path));
+        }
+        if let Some(network) = &self.network {
+            option("--network", network.clone());
+        }
+        if self.auto_remove {
+            args.push("--rm".to_string());
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args.push(self.image.clone());
+        args.extend(self.command.iter().cloned());
+        args
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerStatus {
+    Created,
+    Running,
+    Paused,
+    Restarting,
+    Removing,
+    Exited,
+    Dead,
+}
+
+/// The state of a container's healthcheck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+/// The parts of `docker inspect` worth typing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerInfo {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub status: ContainerStatus,
+    pub exit_code: i64,
+    /// None without a healthcheck.
+    pub health: Option<HealthStatus>,
+    /// Published host ports by container port, e.g. `"6379/tcp"`.
+    pub ports: BTreeMap<String, Vec<u16>>,
+}
+
+impl ContainerInfo {
+    /// The first host port publishing tcp `container_port`.
+    pub fn host_port(&self, container_port: u16) -> Option<u16> {
+        self.ports
+            .get(&format!("{}/tcp", container_port))
+            .and_then(|ports| ports.first().copied())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status == ContainerStatus::Running
+    }
+
+    /// Parses the output of `docker inspect` for one container.
+    pub(super) fn parse(json: &str) -> RResult<Self, AnyErr> {
+        let mut inspected: Vec<Inspect> = serde_json::from_str(json)
+            .change_context(AnyErr)
+            .attach_printable("Invalid docker inspect output")?;
+        if inspected.len() != 1 {
+            return Err(err!(
+                AnyErr,
+                "Expected 1 container from docker inspect, got {}",
+                inspected.len()
+            ));
+        }
+        let inspect = inspected.remove(0);
+
+        let mut ports = BTreeMap::new();
+        for (port, bindings) in inspect.network_settings.ports {
+            let host_ports = bindings
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|binding| binding.host_port.parse().ok())
+                .collect::<Vec<u16>>();
+            // Docker lists ipv4 and ipv6 bindings to the same port separately:
+            let mut unique = Vec::with_capacity(host_ports.len());
+            for port in host_ports {
+                if !unique.contains(&port) {
+                    unique.push(port);
+                }
+            }
+            ports.insert(port, unique);
+        }
+        Ok(ContainerInfo {
+            id: inspect.id,
+            name: inspect.name.trim_start_matches('/').to_string(),
+            image: inspect.config.image,
+            status: inspect.state.status,
+            exit_code: inspect.state.exit_code,
+            health: inspect.state.health.map(|health| health.status),
+            ports,
+        })
+    }
+}
+
+/// What a container wrote to each stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContainerLogs {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Inspect {
+    id: String,
+    name: String,
+    config: InspectConfig,
+    state: InspectState,
+    network_settings: InspectNetwork,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectConfig {
+    image: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectState {
+    status: ContainerStatus,
+    exit_code: i64,
+    health: Option<InspectHealth>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectHealth {
+    status: HealthStatus,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectNetwork {
+    #[serde(default)]
+    ports: BTreeMap<String, Option<Vec<InspectBinding>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectBinding {
+    host_port: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn builds_run_args() {
+        let spec = ContainerSpec::new("redis:7")
+            .name("cache")
+            .port(6379)
+            .port_mapping(8080, 80)
+            .env("MODE", "test")
+            .volume("/tmp/data", "/data")
+            .auto_remove()
+            .command(["redis-server", "--appendonly", "yes"]);
+        assert_eq!(
+            spec.run_args(),
+            [
+                "run",
+                "--detach",
+                "--name",
+                "cache",
+                "--publish",
+                "6379",
+                "--publish",
+                "8080:80",
+                "--env",
+                "MODE=test",
+                "--volume",
+                "/tmp/data:/data",
+                "--rm",
+                "redis:7",
+                "redis-server",
+                "--appendonly",
+                "yes"
+            ]
+        );
+    }
+
+    #[rstest]
+    fn parses_inspect() {
+        let json = r#"[{
+            "Id": "4f1c2b",
+            "Name": "/cache",
+            "Config": {"Image": "redis:7"},
+            "State": {"Status": "running", "Running": true, "ExitCode": 0, "Health": {"Status": "healthy"}},
+            "NetworkSettings": {"Ports": {
+                "6379/tcp": [{"HostIp": "0.0.0.0", "HostPort": "32768"}, {"HostIp": "::", "HostPort": "32768"}],
+                "6380/tcp": null
+            }}
+        }]"#;
+        let info = ContainerInfo::parse(json).unwrap();
+        assert_eq!(info.name, "cache");
+        assert!(info.is_running());
+        assert_eq!(info.health, Some(HealthStatus::Healthy));
+        assert_eq!(info.host_port(6379), Some(32768));
+        assert_eq!(info.host_port(6380), None);
+
+        assert!(ContainerInfo::parse("[]").is_err());
+    }
+}
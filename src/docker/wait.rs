@@ -0,0 +1,205 @@
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::{ContainerInfo, ContainerStatus, DockerCli, DockerClient, HealthStatus};
+use crate::prelude::*;
+
+/// How often the probes check again.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+enum Probe<T> {
+    Ready(T),
+    /// Why it isn't ready yet, included in the timeout error.
+    Waiting(String),
+}
+
+/// Checks until ready, failing with [`ErrorClass::Timeout`] after `timeout`.
+async fn poll<T, F, Fut>(what: &str, timeout: Duration, mut check: F) -> RResult<T, AnyErr>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = RResult<Probe<T>, AnyErr>>,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        let waiting = match check().await? {
+            Probe::Ready(ready) => return Ok(ready),
+            Probe::Waiting(waiting) => waiting,
+        };
+        if Instant::now() + POLL_INTERVAL > deadline {
+            return Err(err!(AnyErr, "Timed out waiting for {}", what))
+                .attach_printable(format!("Timeout: {:?}", timeout))
+                .attach_printable(format!("Last check: {}", waiting))
+                .classify(ErrorClass::Timeout);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Waits until `addr`, e.g. `127.0.0.1:6379`, accepts tcp connections.
+pub async fn wait_for_tcp(addr: &str, timeout: Duration) -> RResult<(), AnyErr> {
+    poll(addr, timeout, || async {
+        Ok(
+            match tokio::time::timeout(POLL_INTERVAL, tokio::net::TcpStream::connect(addr)).await {
+                Ok(Ok(_)) => Probe::Ready(()),
+                Ok(Err(e)) => Probe::Waiting(e.to_string()),
+                Err(_) => Probe::Waiting("Connecting timed out".to_string()),
+            },
+        )
+    })
+    .await
+}
+
+/// Waits until a GET of `url` answers with a 2xx status.
+pub async fn wait_for_http_ok(url: &str, timeout: Duration) -> RResult<(), AnyErr> {
+    let client = reqwest::Client::builder()
+        .timeout(POLL_INTERVAL.max(Duration::from_secs(1)))
+        .build()
+        .change_context(AnyErr)?;
+    poll(url, timeout, || async {
+        Ok(match client.get(url).send().await {
+            Ok(response) if response.status().is_success() => Probe::Ready(()),
+            Ok(response) => Probe::Waiting(format!("Status {}", response.status())),
+            Err(e) => Probe::Waiting(e.to_string()),
+        })
+    })
+    .await
+}
+
+/// Waits until `container` passes its healthcheck, see [`DockerCli::wait_for_container_healthy`].
+pub async fn wait_for_container_healthy(
+    container: &str,
+    timeout: Duration,
+) -> RResult<ContainerInfo, AnyErr> {
+    DockerCli::default()
+        .wait_for_container_healthy(container, timeout)
+        .await
+}
+
+impl DockerCli {
+    /// Waits until `container` passes its healthcheck, or is running when it has none, failing
+    /// early when it exits or becomes unhealthy.
+    pub async fn wait_for_container_healthy(
+        &self,
+        container: &str,
+        timeout: Duration,
+    ) -> RResult<ContainerInfo, AnyErr> {
+        poll(container, timeout, || async {
+            let (cli, name) = (self.clone(), container.to_string());
+            let info = tokio::task::spawn_blocking(move || cli.inspect(&name))
+                .await
+                .change_context(AnyErr)??;
+            if matches!(info.status, ContainerStatus::Exited | ContainerStatus::Dead) {
+                return Err(err!(
+                    AnyErr,
+                    "Container {} exited with code {}",
+                    info.name,
+                    info.exit_code
+                ));
+            }
+            if !info.is_running() {
+                return Ok(Probe::Waiting(format!("Status {:?}", info.status)));
+            }
+            Ok(match info.health {
+                Some(HealthStatus::Healthy) | None => Probe::Ready(info),
+                Some(HealthStatus::Starting) => Probe::Waiting("Health starting".to_string()),
+                Some(HealthStatus::Unhealthy) => {
+                    return Err(err!(AnyErr, "Container {} is unhealthy", info.name))
+                }
+            })
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[rstest]
+    #[tokio::test]
+    async fn waits_for_tcp_and_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Unavailable on the first request:
+        tokio::spawn(async move {
+            let mut requests = 0;
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut buf = vec![0; 4096];
+                if conn.read(&mut buf).await.unwrap_or_default() == 0 {
+                    continue;
+                }
+                requests += 1;
+                let status = if requests == 1 {
+                    "503 Service Unavailable"
+                } else {
+                    "200 OK"
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                let _ = conn.write_all(response.as_bytes()).await;
+            }
+        });
+
+        wait_for_tcp(&addr.to_string(), Duration::from_secs(5))
+            .await
+            .unwrap();
+        wait_for_http_ok(&format!("http://{}/health", addr), Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        // Nothing listens here once dropped:
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let report = wait_for_tcp(&closed.to_string(), Duration::from_millis(300))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            crate::errors::error_class(&report),
+            Some(ErrorClass::Timeout)
+        );
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[tokio::test]
+    async fn waits_for_container_health() {
+        let inspect = |health: &str| {
+            format!(
+                r#"[{{"Id": "4f1c2b", "Name": "/db", "Config": {{"Image": "postgres:16"}}, "State": {{"Status": "running", "ExitCode": 0, "Health": {{"Status": "{}"}}}}, "NetworkSettings": {{"Ports": {{}}}}}}]"#,
+                health
+            )
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let cli = super::super::client::fake_cli(
+            dir.path(),
+            &format!(
+                "if [ -f {0}/inspected ]; then echo '{1}'; else touch {0}/inspected; echo '{2}'; fi",
+                dir.path().display(),
+                inspect("healthy"),
+                inspect("starting")
+            ),
+        );
+        let info = cli
+            .wait_for_container_healthy("db", Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(info.health, Some(HealthStatus::Healthy));
+
+        let cli =
+            super::super::client::fake_cli(dir.path(), &format!("echo '{}'", inspect("unhealthy")));
+        let report = cli
+            .wait_for_container_healthy("db", Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", report).contains("Container db is unhealthy"));
+    }
+}
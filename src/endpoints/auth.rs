@@ -0,0 +1,150 @@
+use reqwest::{RequestBuilder, Url};
+
+use crate::prelude::*;
+use crate::secrets::SecretStore;
+
+/// Where an API key is sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiKeyLocation {
+    Header,
+    Query,
+}
+
+/// Authentication applied to every attempt of a request.
+#[derive(Clone)]
+pub enum Auth {
+    Bearer(String),
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    ApiKey {
+        name: String,
+        value: String,
+        location: ApiKeyLocation,
+    },
+}
+
+impl Auth {
+    /// A bearer token read from `secrets`, e.g. `Auth::bearer_secret(&secrets, "github_token")`.
+    pub async fn bearer_secret(secrets: &dyn SecretStore, name: &str) -> RResult<Self, AnyErr2> {
+        Ok(Auth::Bearer(read_secret(secrets, name).await?))
+    }
+
+    /// An api key called `key_name` with its value read from `secrets`.
+    pub async fn api_key_secret(
+        key_name: &str,
+        location: ApiKeyLocation,
+        secrets: &dyn SecretStore,
+        name: &str,
+    ) -> RResult<Self, AnyErr2> {
+        Ok(Auth::ApiKey {
+            name: key_name.to_string(),
+            value: read_secret(secrets, name).await?,
+            location,
+        })
+    }
+
+    /// Query-located API keys have to go on the url before the request is created.
+    pub(crate) fn apply_to_url(&self, url: &mut Url) {
+        if let Auth::ApiKey {
+            name,
+            value,
+            location: ApiKeyLocation::Query,
+        } = self
+        {
+            url.query_pairs_mut().append_pair(name, value);
+        }
+    }
+
+    /// The name of the query param the key is sent in, to redact it.
+    pub(crate) fn query_key(&self) -> Option<&str> {
+        match self {
+            Auth::ApiKey {
+                name,
+                location: ApiKeyLocation::Query,
+                ..
+            } => Some(name),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn apply_to_request(&self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            Auth::Bearer(token) => request.bearer_auth(token),
+            Auth::Basic { username, password } => request.basic_auth(username, password.as_ref()),
+            Auth::ApiKey {
+                name,
+                value,
+                location: ApiKeyLocation::Header,
+            } => request.header(name.as_str(), value.as_str()),
+            Auth::ApiKey { .. } => request,
+        }
+    }
+}
+
+async fn read_secret(secrets: &dyn SecretStore, name: &str) -> RResult<String, AnyErr2> {
+    let secret = secrets
+        .get(name)
+        .await
+        .change_context(err2!("Failed to read the credentials"))?;
+    Ok(secret.expose().clone())
+}
+
+// Credentials must never end up in logs:
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Auth::Bearer(_) => write!(f, "Bearer(***)"),
+            Auth::Basic { username, .. } => write!(f, "Basic({}:***)", username),
+            Auth::ApiKey { name, location, .. } => {
+                write!(f, "ApiKey({}=*** in {:?})", name, location)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::EnvSecrets;
+    use rstest::*;
+
+    #[rstest]
+    fn query_api_key_goes_on_url() {
+        let mut url = Url::parse("https://example.com/items?page=2").unwrap();
+        Auth::ApiKey {
+            name: "key".to_string(),
+            value: "secret".to_string(),
+            location: ApiKeyLocation::Query,
+        }
+        .apply_to_url(&mut url);
+        assert_eq!(url.query(), Some("page=2&key=secret"));
+
+        let mut url = Url::parse("https://example.com/items").unwrap();
+        Auth::Bearer("token".to_string()).apply_to_url(&mut url);
+        assert_eq!(url.query(), None);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reads_credentials_from_secrets() {
+        std::env::set_var("RUTILS_TEST_AUTH_API_TOKEN", "t0ken");
+        let secrets = EnvSecrets::new().prefix("RUTILS_TEST_AUTH_");
+        let auth = Auth::api_key_secret("X-Api-Key", ApiKeyLocation::Header, &secrets, "api_token")
+            .await
+            .unwrap();
+        assert!(matches!(auth, Auth::ApiKey { value, .. } if value == "t0ken"));
+        assert!(Auth::bearer_secret(&secrets, "missing").await.is_err());
+    }
+
+    #[rstest]
+    fn debug_redacts_secrets() {
+        let auth = Auth::Basic {
+            username: "bob".to_string(),
+            password: Some("hunter2".to_string()),
+        };
+        assert_eq!(format!("{:?}", auth), "Basic(bob:***)");
+        assert!(!format!("{:?}", Auth::Bearer("abc".to_string())).contains("abc"));
+    }
+}
@@ -0,0 +1,106 @@
+use futures::stream::{self, StreamExt};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+use super::{ApiClient, EndpointBuilder};
+use crate::errors::MultiReport;
+use crate::prelude::*;
+
+/// Counts over all requests of a [`ApiClient::send_all`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Wall clock time for the whole batch.
+    pub elapsed: Duration,
+    /// The longest single request.
+    pub slowest: Duration,
+}
+
+/// The outcome of every request of a batch, in the order the endpoints were given.
+#[derive(Debug)]
+pub struct BatchResults<T> {
+    pub results: Vec<RResult<T, AnyErr2>>,
+    pub stats: BatchStats,
+}
+
+impl<T> BatchResults<T> {
+    /// The successful results, dropping any failures.
+    pub fn successes(self) -> Vec<T> {
+        self.results.into_iter().filter_map(Result::ok).collect()
+    }
+
+    /// All results in order if every request succeeded, otherwise one report with every failure.
+    pub fn into_result(self) -> RResult<Vec<T>, AnyErr2> {
+        let mut errors = MultiReport::new();
+        let values = self
+            .results
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, result)| {
+                errors.push_result(result.attach_printable_lazy(|| format!("Request {}", index)))
+            })
+            .collect();
+        errors.into_result(err2!(format!(
+            "{}/{} batch requests failed",
+            self.stats.failed, self.stats.total
+        )))?;
+        Ok(values)
+    }
+}
+
+impl ApiClient {
+    /// Sends all `endpoints` with at most `max_concurrent` in flight at a time.
+    ///
+    /// A failing request doesn't affect the others, its error is in its position of the results.
+    pub async fn send_all(
+        &self,
+        endpoints: impl IntoIterator<Item = EndpointBuilder>,
+        max_concurrent: usize,
+    ) -> BatchResults<Value> {
+        self.send_all_typed(endpoints, max_concurrent).await
+    }
+
+    /// Like [`Self::send_all`] deserializing each response into `T`.
+    pub async fn send_all_typed<T: DeserializeOwned>(
+        &self,
+        endpoints: impl IntoIterator<Item = EndpointBuilder>,
+        max_concurrent: usize,
+    ) -> BatchResults<T> {
+        let start = Instant::now();
+        let timed: Vec<(RResult<T, AnyErr2>, Duration)> = stream::iter(endpoints)
+            .map(|endpoint| async move {
+                let started = Instant::now();
+                let result = endpoint.send_typed::<T>().await;
+                (result, started.elapsed())
+            })
+            .buffered(max_concurrent.max(1))
+            .collect()
+            .await;
+
+        let mut stats = BatchStats {
+            total: timed.len(),
+            ..Default::default()
+        };
+        let results = timed
+            .into_iter()
+            .map(|(result, elapsed)| {
+                match &result {
+                    Ok(_) => stats.succeeded += 1,
+                    Err(_) => stats.failed += 1,
+                }
+                stats.slowest = stats.slowest.max(elapsed);
+                result
+            })
+            .collect();
+        stats.elapsed = start.elapsed();
+
+        debug!(
+            "Batch of {} requests finished in {:?}, {} failed",
+            stats.total, stats.elapsed, stats.failed
+        );
+        BatchResults { results, stats }
+    }
+}
@@ -0,0 +1,122 @@
+use reqwest::multipart::{Form, Part};
+use reqwest::RequestBuilder;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio_util::io::ReaderStream;
+
+use crate::prelude::*;
+
+/// The body of a request, kept as a description so it can be rebuilt for each retry attempt.
+#[derive(Debug, Clone)]
+pub(crate) enum Body {
+    Json(Value),
+    Form(HashMap<String, String>),
+    Multipart(Vec<MultipartPart>),
+}
+
+impl Body {
+    pub(crate) async fn apply(&self, request: RequestBuilder) -> RResult<RequestBuilder, AnyErr2> {
+        Ok(match self {
+            Body::Json(json) => request.json(json),
+            Body::Form(form) => request.form(form),
+            Body::Multipart(parts) => {
+                let mut form = Form::new();
+                for part in parts {
+                    form = form.part(part.name.clone(), part.to_part().await?);
+                }
+                request.multipart(form)
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PartData {
+    Text(String),
+    Bytes(Vec<u8>),
+    File(PathBuf),
+}
+
+/// One field of a `multipart/form-data` body.
+#[derive(Debug, Clone)]
+pub struct MultipartPart {
+    name: String,
+    data: PartData,
+    file_name: Option<String>,
+    mime: Option<String>,
+}
+
+impl MultipartPart {
+    pub fn text(name: &str, value: &str) -> Self {
+        Self::new(name, PartData::Text(value.to_string()))
+    }
+
+    pub fn bytes(name: &str, data: Vec<u8>) -> Self {
+        Self::new(name, PartData::Bytes(data))
+    }
+
+    /// Streams the file from disk when sent rather than loading it into memory.
+    ///
+    /// The file name defaults to the file's name on disk.
+    pub fn file(name: &str, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+        MultipartPart {
+            file_name,
+            ..Self::new(name, PartData::File(path))
+        }
+    }
+
+    pub fn file_name(mut self, file_name: &str) -> Self {
+        self.file_name = Some(file_name.to_string());
+        self
+    }
+
+    pub fn mime(mut self, mime: &str) -> Self {
+        self.mime = Some(mime.to_string());
+        self
+    }
+
+    fn new(name: &str, data: PartData) -> Self {
+        MultipartPart {
+            name: name.to_string(),
+            data,
+            file_name: None,
+            mime: None,
+        }
+    }
+
+    async fn to_part(&self) -> RResult<Part, AnyErr2> {
+        let mut part = match &self.data {
+            PartData::Text(text) => Part::text(text.clone()),
+            PartData::Bytes(bytes) => Part::bytes(bytes.clone()),
+            PartData::File(path) => {
+                let file = tokio::fs::File::open(path)
+                    .await
+                    .change_context(err2!(format!(
+                        "Failed to open multipart file: {}",
+                        path.display()
+                    )))?;
+                let len = file
+                    .metadata()
+                    .await
+                    .change_context(err2!("Failed to read multipart file metadata"))?
+                    .len();
+                Part::stream_with_length(reqwest::Body::wrap_stream(ReaderStream::new(file)), len)
+            }
+        };
+
+        if let Some(file_name) = &self.file_name {
+            part = part.file_name(file_name.clone());
+        }
+        if let Some(mime) = &self.mime {
+            part = part
+                .mime_str(mime)
+                .change_context(err2!(format!("Invalid mime type: {}", mime)))?;
+        }
+        Ok(part)
+    }
+}
@@ -0,0 +1,406 @@
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use reqwest::header::{HeaderMap, CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+use reqwest::{Method, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{Auth, Endpoint, RecordedResponse};
+use crate::prelude::*;
+use crate::redis_manager::RedisLike;
+
+/// A cached response and what is needed to decide whether it can be reused.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub response: RecordedResponse,
+    pub etag: Option<String>,
+    /// Unix time in milliseconds until which the response can be used without revalidating.
+    pub fresh_until: u64,
+}
+
+impl CacheEntry {
+    pub fn is_fresh(&self) -> bool {
+        self.is_fresh_at(SystemTime::now())
+    }
+
+    pub fn is_fresh_at(&self, now: SystemTime) -> bool {
+        unix_millis(now) < self.fresh_until
+    }
+}
+
+/// Where [`super::ApiClientBuilder::cache`] keeps responses.
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    async fn get(&self, key: &str) -> RResult<Option<CacheEntry>, AnyErr2>;
+    async fn set(&self, key: &str, entry: CacheEntry) -> RResult<(), AnyErr2>;
+}
+
+/// Keeps responses in the memory of the current process.
+#[derive(Default)]
+pub struct MemoryCache {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        MemoryCache::default()
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryCache {
+    async fn get(&self, key: &str) -> RResult<Option<CacheEntry>, AnyErr2> {
+        Ok(self.entries.lock().get(key).cloned())
+    }
+
+    async fn set(&self, key: &str, entry: CacheEntry) -> RResult<(), AnyErr2> {
+        self.entries.lock().insert(key.to_string(), entry);
+        Ok(())
+    }
+}
+
+/// Keeps responses in redis so they are shared between processes.
+pub struct RedisCache {
+    manager: Arc<dyn RedisLike>,
+    prefix: String,
+    ttl: Duration,
+}
+
+impl RedisCache {
+    /// Usually over a [`crate::redis_manager::RedisManager`].
+    pub fn new(manager: impl RedisLike + 'static) -> Self {
+        RedisCache {
+            manager: Arc::new(manager),
+            prefix: "http_cache:".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// How long redis keeps an entry, stale entries with an ETag are still useful for revalidating.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+}
+
+#[async_trait]
+impl CacheStore for RedisCache {
+    async fn get(&self, key: &str) -> RResult<Option<CacheEntry>, AnyErr2> {
+        let value = self
+            .manager
+            .get(&format!("{}{}", self.prefix, key))
+            .await
+            .change_context(err2!("Failed to read cached response"))?;
+        value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .change_context(err2!("Failed to parse cached response"))
+    }
+
+    async fn set(&self, key: &str, entry: CacheEntry) -> RResult<(), AnyErr2> {
+        let value = serde_json::to_string(&entry)
+            .change_context(err2!("Failed to serialize cached response"))?;
+        self.manager
+            .set(&format!("{}{}", self.prefix, key), &value, Some(self.ttl))
+            .await
+            .change_context(err2!("Failed to write cached response"))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<u64>,
+}
+
+fn cache_control(headers: &HeaderMap) -> CacheControl {
+    let mut control = CacheControl::default();
+    for directive in headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let directive = directive.trim().to_ascii_lowercase();
+        match directive.split_once('=') {
+            Some(("max-age", secs)) => control.max_age = secs.trim_matches('"').parse().ok(),
+            _ if directive == "no-store" || directive == "private" => control.no_store = true,
+            _ if directive == "no-cache" => control.no_cache = true,
+            _ => {}
+        }
+    }
+    control
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn fresh_until(control: &CacheControl, now: SystemTime) -> u64 {
+    match control.max_age {
+        Some(max_age) if !control.no_cache => unix_millis(now) + max_age * 1000,
+        _ => 0,
+    }
+}
+
+pub(crate) fn is_cacheable_method(method: &Method) -> bool {
+    method == Method::GET || method == Method::HEAD
+}
+
+/// Headers that identify who is asking, so responses to them aren't shared.
+fn is_credential_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name == "authorization"
+        || name == "cookie"
+        || ["key", "token", "auth", "session"]
+            .iter()
+            .any(|part| name.contains(part))
+}
+
+impl Endpoint {
+    /// A hash of the credentials sent, so a shared cache never serves one user's response to
+    /// another. `None` for anonymous requests.
+    fn credentials_hash(&self, url: &Url) -> Option<String> {
+        let mut credentials = vec![];
+        match &self.auth {
+            Some(Auth::Bearer(token)) => credentials.push(format!("bearer:{}", token)),
+            Some(Auth::Basic { username, password }) => credentials.push(format!(
+                "basic:{}:{}",
+                username,
+                password.as_deref().unwrap_or_default()
+            )),
+            Some(Auth::ApiKey { name, value, .. }) => {
+                credentials.push(format!("key:{}={}", name, value))
+            }
+            None => {}
+        }
+        let mut headers = self
+            .headers
+            .iter()
+            .flatten()
+            .filter(|(name, _)| is_credential_header(name))
+            .map(|(name, value)| format!("header:{}={}", name.to_ascii_lowercase(), value))
+            .collect::<Vec<_>>();
+        headers.sort();
+        credentials.extend(headers);
+        if let Some(cookies) = self
+            .cookie_jar
+            .as_ref()
+            .and_then(|jar| reqwest::cookie::CookieStore::cookies(&**jar, url))
+        {
+            credentials.push(format!(
+                "cookies:{}",
+                String::from_utf8_lossy(cookies.as_bytes())
+            ));
+        }
+        if credentials.is_empty() {
+            return None;
+        }
+        let digest = Sha256::digest(credentials.join("\n"));
+        Some(hex::encode(&digest[..8]))
+    }
+
+    /// Serves from `cache` while fresh, otherwise revalidates with `If-None-Match` when there is an ETag.
+    pub(crate) async fn execute_cached(
+        mut self,
+        cache: &dyn CacheStore,
+        url: Url,
+    ) -> RResult<Response, AnyErr2> {
+        let key = match self.credentials_hash(&url) {
+            Some(hash) => format!("{} {} {}", self.method, self.redact_api_key(&url), hash),
+            None => format!("{} {}", self.method, self.redact_api_key(&url)),
+        };
+        // The cache is only an optimization, a broken one shouldn't fail the request:
+        let cached = cache.get(&key).await.unwrap_or_else(|report| {
+            warn!("Failed to read response cache: {:?}", report);
+            None
+        });
+
+        if let Some(entry) = &cached {
+            if entry.is_fresh_at(self.clock.system_now()) {
+                debug!("Serving {} from cache", key);
+                return entry.response.to_response(&url);
+            }
+            if let Some(etag) = &entry.etag {
+                self.headers
+                    .get_or_insert_with(HashMap::new)
+                    .insert(IF_NONE_MATCH.to_string(), etag.clone());
+            }
+        }
+
+        let clock = self.clock.clone();
+        let response = self.execute_url(url.clone()).await?;
+        let control = cache_control(response.headers());
+
+        let entry = match (cached, response.status()) {
+            (Some(mut entry), StatusCode::NOT_MODIFIED) => {
+                debug!("{} not modified, serving from cache", key);
+                entry.fresh_until = fresh_until(&control, clock.system_now());
+                entry
+            }
+            (_, StatusCode::OK) if !control.no_store => {
+                let etag = response
+                    .headers()
+                    .get(ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(|etag| etag.to_string());
+                if etag.is_none() && control.max_age.is_none() {
+                    return Ok(response);
+                }
+                CacheEntry {
+                    response: RecordedResponse::read(response).await?,
+                    etag,
+                    fresh_until: fresh_until(&control, clock.system_now()),
+                }
+            }
+            _ => return Ok(response),
+        };
+
+        let response = entry.response.to_response(&url)?;
+        if let Err(report) = cache.set(&key, entry).await {
+            warn!("Failed to write response cache: {:?}", report);
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::endpoints::{ApiClient, EndpointBuilder};
+    use rstest::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers with the given cache headers, or a 304 when the request has `If-None-Match`.
+    async fn caching_server(cache_headers: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut buf = [0; 4096];
+                let n = conn.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_ascii_lowercase();
+                let response = if request.contains("if-none-match: \"v1\"") {
+                    format!(
+                        "HTTP/1.1 304 Not Modified\r\n{}\r\nconnection: close\r\n\r\n",
+                        cache_headers
+                    )
+                } else {
+                    let body = r#"{"version": 1}"#;
+                    format!(
+                        "HTTP/1.1 200 OK\r\n{}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        cache_headers,
+                        body.len(),
+                        body
+                    )
+                };
+                let _ = conn.write_all(response.as_bytes()).await;
+            }
+        });
+        (format!("http://{}", addr), hits)
+    }
+
+    #[rstest]
+    #[case::fresh("cache-control: max-age=60", 1)]
+    #[case::revalidated("etag: \"v1\"\r\ncache-control: no-cache", 2)]
+    #[tokio::test]
+    async fn caches_responses(#[case] cache_headers: &'static str, #[case] expected_hits: usize) {
+        let (base_url, hits) = caching_server(cache_headers).await;
+        let client = ApiClient::builder()
+            .base_url(&base_url)
+            .cache(MemoryCache::new())
+            .build()
+            .unwrap();
+
+        for _ in 0..2 {
+            let body = client
+                .endpoint("/config")
+                .method(Method::GET)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(body["version"], 1);
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), expected_hits);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn keeps_users_apart() {
+        let (base_url, hits) = caching_server("cache-control: max-age=60").await;
+        let client = ApiClient::builder()
+            .base_url(&base_url)
+            .cache(MemoryCache::new())
+            .build()
+            .unwrap();
+        let send = |endpoint: EndpointBuilder| async move {
+            endpoint.method(Method::GET).send().await.unwrap();
+        };
+
+        send(client.endpoint("/config").bearer_token("alice")).await;
+        send(client.endpoint("/config").bearer_token("alice")).await;
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        send(client.endpoint("/config").bearer_token("bob")).await;
+        send(client.endpoint("/config").header("X-Api-Key", "carol")).await;
+        send(client.endpoint("/config")).await;
+        assert_eq!(hits.load(Ordering::SeqCst), 4);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn expires_after_max_age() {
+        let (base_url, hits) = caching_server("cache-control: max-age=60").await;
+        let clock = MockClock::new();
+        let client = ApiClient::builder()
+            .base_url(&base_url)
+            .cache(MemoryCache::new())
+            .clock(clock.shared())
+            .build()
+            .unwrap();
+
+        for advance in [0, 59, 2] {
+            clock.advance(Duration::from_secs(advance));
+            client
+                .endpoint("/config")
+                .method(Method::GET)
+                .send()
+                .await
+                .unwrap();
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[rstest]
+    fn parses_cache_control() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, "public, Max-Age=300".parse().unwrap());
+        assert_eq!(
+            cache_control(&headers),
+            CacheControl {
+                max_age: Some(300),
+                ..Default::default()
+            }
+        );
+
+        headers.insert(CACHE_CONTROL, "no-store".parse().unwrap());
+        assert!(cache_control(&headers).no_store);
+    }
+}
@@ -0,0 +1,237 @@
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::CircuitOpenError;
+use crate::prelude::*;
+
+/// When a [`CircuitBreaker`] opens and for how long.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerPolicy {
+    failure_rate: f64,
+    window: usize,
+    min_requests: usize,
+    cool_down: Duration,
+}
+
+impl Default for CircuitBreakerPolicy {
+    fn default() -> Self {
+        CircuitBreakerPolicy {
+            failure_rate: 0.5,
+            window: 20,
+            min_requests: 10,
+            cool_down: Duration::from_secs(30),
+        }
+    }
+}
+
+impl CircuitBreakerPolicy {
+    pub fn new() -> Self {
+        CircuitBreakerPolicy::default()
+    }
+
+    /// Open once this fraction of the recent requests failed, between 0 and 1.
+    pub fn failure_rate(mut self, failure_rate: f64) -> Self {
+        self.failure_rate = failure_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// How many of the most recent outcomes the failure rate is computed over.
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// Never open before this many outcomes have been seen, so a single early failure doesn't trip it.
+    pub fn min_requests(mut self, min_requests: usize) -> Self {
+        self.min_requests = min_requests.max(1);
+        self
+    }
+
+    /// How long to fail fast before letting a probe request through.
+    pub fn cool_down(mut self, cool_down: Duration) -> Self {
+        self.cool_down = cool_down;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests flow normally.
+    Closed,
+    /// Requests fail immediately until the cool-down is over.
+    Open,
+    /// A single probe request is allowed through to decide whether to close again.
+    HalfOpen,
+}
+
+struct HostCircuit {
+    state: CircuitState,
+    outcomes: VecDeque<bool>,
+    opened_at: Instant,
+    probe_started: Option<Instant>,
+}
+
+/// Closed/open/half-open circuits keyed by host, shared by all endpoints of an [`super::ApiClient`].
+pub struct CircuitBreaker {
+    policy: CircuitBreakerPolicy,
+    hosts: Mutex<HashMap<String, HostCircuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(policy: CircuitBreakerPolicy) -> Self {
+        CircuitBreaker {
+            policy,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn state(&self, host: &str) -> CircuitState {
+        self.hosts
+            .lock()
+            .get(host)
+            .map(|circuit| circuit.state)
+            .unwrap_or(CircuitState::Closed)
+    }
+
+    /// Errors with a [`CircuitOpenError`] when requests to `host` should fail fast.
+    pub fn check(&self, host: &str) -> RResult<(), AnyErr2> {
+        let mut hosts = self.hosts.lock();
+        let Some(circuit) = hosts.get_mut(host) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+
+        if circuit.state == CircuitState::Open {
+            let elapsed = now.duration_since(circuit.opened_at);
+            if elapsed < self.policy.cool_down {
+                return Err(Report::new(CircuitOpenError {
+                    host: host.to_string(),
+                    retry_in: self.policy.cool_down - elapsed,
+                })
+                .change_context(err2!("Circuit breaker is open"))
+                .retryable());
+            }
+            circuit.state = CircuitState::HalfOpen;
+            circuit.probe_started = None;
+        }
+
+        if circuit.state == CircuitState::HalfOpen {
+            // A probe that never reported back (e.g. its future was dropped) doesn't block forever:
+            match circuit.probe_started {
+                Some(started) if now.duration_since(started) < self.policy.cool_down => {
+                    return Err(Report::new(CircuitOpenError {
+                        host: host.to_string(),
+                        retry_in: self.policy.cool_down - now.duration_since(started),
+                    })
+                    .change_context(err2!("Circuit breaker is half-open, waiting on probe"))
+                    .retryable());
+                }
+                _ => circuit.probe_started = Some(now),
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the outcome of a request to `host` that [`Self::check`] allowed.
+    pub fn record(&self, host: &str, success: bool) {
+        let mut hosts = self.hosts.lock();
+        let circuit = hosts
+            .entry(host.to_string())
+            .or_insert_with(|| HostCircuit {
+                state: CircuitState::Closed,
+                outcomes: VecDeque::new(),
+                opened_at: Instant::now(),
+                probe_started: None,
+            });
+
+        match circuit.state {
+            CircuitState::HalfOpen => {
+                circuit.probe_started = None;
+                circuit.outcomes.clear();
+                if success {
+                    info!("Circuit breaker for {} closed", host);
+                    circuit.state = CircuitState::Closed;
+                } else {
+                    warn!("Circuit breaker for {} probe failed, reopening", host);
+                    circuit.state = CircuitState::Open;
+                    circuit.opened_at = Instant::now();
+                }
+            }
+            CircuitState::Closed => {
+                circuit.outcomes.push_back(success);
+                while circuit.outcomes.len() > self.policy.window {
+                    circuit.outcomes.pop_front();
+                }
+                let total = circuit.outcomes.len();
+                let failures = circuit.outcomes.iter().filter(|ok| !**ok).count();
+                if total >= self.policy.min_requests
+                    && failures as f64 / total as f64 >= self.policy.failure_rate
+                {
+                    warn!(
+                        "Circuit breaker for {} opened after {}/{} failures",
+                        host, failures, total
+                    );
+                    circuit.state = CircuitState::Open;
+                    circuit.opened_at = Instant::now();
+                    circuit.outcomes.clear();
+                }
+            }
+            // Late outcomes of requests sent before opening don't change anything:
+            CircuitState::Open => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(
+            CircuitBreakerPolicy::new()
+                .failure_rate(0.5)
+                .window(4)
+                .min_requests(4)
+                .cool_down(Duration::from_millis(50)),
+        )
+    }
+
+    #[rstest]
+    fn opens_on_failure_rate() {
+        let breaker = breaker();
+        breaker.record("a", false);
+        breaker.record("a", true);
+        breaker.record("a", false);
+        assert_eq!(breaker.state("a"), CircuitState::Closed);
+        breaker.record("a", true);
+        assert_eq!(breaker.state("a"), CircuitState::Open);
+
+        let report = breaker.check("a").unwrap_err();
+        assert_eq!(report.downcast_ref::<CircuitOpenError>().unwrap().host, "a");
+        // Other hosts are unaffected:
+        assert!(breaker.check("b").is_ok());
+    }
+
+    #[rstest]
+    #[case(true, CircuitState::Closed)]
+    #[case(false, CircuitState::Open)]
+    #[tokio::test]
+    async fn half_open_probe(#[case] probe_succeeds: bool, #[case] expected: CircuitState) {
+        let breaker = breaker();
+        for _ in 0..4 {
+            breaker.record("a", false);
+        }
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        assert!(breaker.check("a").is_ok());
+        assert_eq!(breaker.state("a"), CircuitState::HalfOpen);
+        // Only one probe at a time:
+        assert!(breaker.check("a").is_err());
+
+        breaker.record("a", probe_succeeds);
+        assert_eq!(breaker.state("a"), expected);
+    }
+}
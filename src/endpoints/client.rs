@@ -0,0 +1,369 @@
+use reqwest::{Certificate, Client, Proxy};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{
+    Auth, CacheStore, Cassette, CircuitBreaker, CircuitBreakerPolicy, CookieJar, EndpointBuilder,
+    GraphQLEndpoint, Middleware, RateLimit, RateLimiter, RetryPolicy, Signer,
+};
+use crate::clock::Clock;
+use crate::prelude::*;
+
+#[derive(Default)]
+pub struct ApiClientBuilder {
+    base_url: Option<String>,
+    default_headers: HashMap<String, String>,
+    auth: Option<Auth>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    retry_policy: Option<RetryPolicy>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    rate_limit: Option<RateLimit>,
+    circuit_breaker: Option<CircuitBreakerPolicy>,
+    cache: Option<Arc<dyn CacheStore>>,
+    signer: Option<Arc<dyn Signer>>,
+    cassette: Option<Cassette>,
+    proxies: Vec<Proxy>,
+    no_proxy: bool,
+    root_certificates: Vec<Certificate>,
+    accept_invalid_certs: bool,
+    cookie_jar: Option<Arc<CookieJar>>,
+    clock: Option<Arc<dyn Clock>>,
+    errors: Vec<Report<AnyErr2>>,
+}
+
+impl ApiClientBuilder {
+    pub fn new() -> Self {
+        ApiClientBuilder::default()
+    }
+
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+
+    pub fn default_header(mut self, key: &str, value: &str) -> Self {
+        self.default_headers
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    pub fn bearer_token(self, token: &str) -> Self {
+        self.auth(Auth::Bearer(token.to_string()))
+    }
+
+    /// Total time allowed for each request, from connecting until the body has been read.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// The default retry policy for endpoints created from this client.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Registers a middleware that runs on every request sent through this client.
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Limits requests per host across every endpoint of the client.
+    ///
+    /// A 429 response with `Retry-After` holds back all requests to that host, not just the retry.
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Fails requests to a host immediately while it keeps failing, instead of waiting on timeouts and retries.
+    ///
+    /// Connection errors, timeouts and 5xx responses count as failures.
+    pub fn circuit_breaker(mut self, policy: CircuitBreakerPolicy) -> Self {
+        self.circuit_breaker = Some(policy);
+        self
+    }
+
+    /// Caches GET responses honoring `Cache-Control: max-age` and revalidating with `ETag`.
+    ///
+    /// Pass a [`super::MemoryCache`] for a single process, or a [`super::RedisCache`] to share between processes.
+    pub fn cache(mut self, cache: impl CacheStore + 'static) -> Self {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Signs every request, e.g. with an [`super::HmacSigner`] for internal services.
+    pub fn signer(mut self, signer: impl Signer + 'static) -> Self {
+        self.signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Records every interaction to, or replays them from, a JSON fixture, for deterministic tests.
+    pub fn cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// Sends all requests through `url`, e.g. `http://proxy:3128` or `socks5://127.0.0.1:1080`.
+    ///
+    /// Without an explicit proxy the `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY` env vars are used.
+    pub fn proxy(mut self, url: &str) -> Self {
+        match Proxy::all(url) {
+            Ok(proxy) => self.proxies.push(proxy),
+            Err(e) => self
+                .errors
+                .push(Report::new(e).change_context(err2!(format!("Invalid proxy url: {}", url)))),
+        }
+        self
+    }
+
+    /// Ignores proxies configured through env vars.
+    pub fn no_proxy(mut self) -> Self {
+        self.no_proxy = true;
+        self
+    }
+
+    /// Trusts an additional root CA certificate, PEM or DER encoded, e.g. for an internal CA.
+    pub fn root_certificate(mut self, cert: &[u8]) -> Self {
+        match Certificate::from_pem(cert).or_else(|_| Certificate::from_der(cert)) {
+            Ok(cert) => self.root_certificates.push(cert),
+            Err(e) => self
+                .errors
+                .push(Report::new(e).change_context(err2!("Invalid root certificate"))),
+        }
+        self
+    }
+
+    /// Like [`Self::root_certificate`] reading the certificate from a file.
+    pub fn root_certificate_file(mut self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match std::fs::read(path) {
+            Ok(cert) => self.root_certificate(&cert),
+            Err(e) => {
+                self.errors
+                    .push(Report::new(e).change_context(err2!(format!(
+                        "Failed to read root certificate {}",
+                        path.display()
+                    ))));
+                self
+            }
+        }
+    }
+
+    /// Accepts any TLS certificate, including self-signed and expired ones.
+    ///
+    /// Only for dev environments, this disables protection against man in the middle attacks.
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Keeps cookies set by responses and sends them with later requests.
+    pub fn cookies(self) -> Self {
+        self.cookie_jar(Arc::new(CookieJar::new()))
+    }
+
+    /// Like [`Self::cookies`] with a given jar, e.g. one loaded with [`CookieJar::load`].
+    pub fn cookie_jar(mut self, cookie_jar: Arc<CookieJar>) -> Self {
+        self.cookie_jar = Some(cookie_jar);
+        self
+    }
+
+    /// Where rate limits, cache freshness and retry delays get the time from.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn build(self) -> RResult<ApiClient, AnyErr2> {
+        if let Some(report) = self.errors.into_iter().reduce(|mut acc, report| {
+            acc.extend_one(report);
+            acc
+        }) {
+            return Err(report);
+        }
+        let base_url = self
+            .base_url
+            .ok_or_else(|| Report::new(err2!("Base URL is required")))?;
+
+        if self.accept_invalid_certs {
+            warn!("TLS certificate validation is disabled for {}", base_url);
+        }
+        let client_config = ClientConfig {
+            proxies: self.proxies,
+            no_proxy: self.no_proxy,
+            root_certificates: self.root_certificates,
+            cookie_jar: self.cookie_jar.clone(),
+            accept_invalid_certs: self.accept_invalid_certs,
+            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+        };
+        let client = client_config
+            .build()
+            .change_context(err2!("Failed to build HTTP client"))?;
+
+        Ok(ApiClient {
+            client,
+            client_config,
+            base_url,
+            default_headers: self.default_headers,
+            auth: self.auth,
+            retry_policy: self.retry_policy,
+            middlewares: self.middlewares,
+            rate_limiter: self.rate_limit.map(|limit| {
+                let limiter = RateLimiter::new(limit);
+                Arc::new(match &self.clock {
+                    Some(clock) => limiter.clock(clock.clone()),
+                    None => limiter,
+                })
+            }),
+            circuit_breaker: self
+                .circuit_breaker
+                .map(|policy| Arc::new(CircuitBreaker::new(policy))),
+            cache: self.cache,
+            signer: self.signer,
+            cassette: self.cassette.map(Arc::new),
+            cookie_jar: self.cookie_jar,
+            clock: self.clock,
+        })
+    }
+}
+
+/// What the reqwest client of an [`ApiClient`] is built from, so endpoints that need a client
+/// of their own, e.g. for another connect timeout, keep the proxies, certificates and cookies.
+#[derive(Clone, Default)]
+pub(crate) struct ClientConfig {
+    proxies: Vec<Proxy>,
+    no_proxy: bool,
+    root_certificates: Vec<Certificate>,
+    pub(crate) cookie_jar: Option<Arc<CookieJar>>,
+    accept_invalid_certs: bool,
+    timeout: Option<Duration>,
+    pub(crate) connect_timeout: Option<Duration>,
+}
+
+impl ClientConfig {
+    pub(crate) fn build(&self) -> reqwest::Result<Client> {
+        let mut builder = Client::builder();
+        if self.no_proxy {
+            builder = builder.no_proxy();
+        }
+        for proxy in &self.proxies {
+            builder = builder.proxy(proxy.clone());
+        }
+        for cert in &self.root_certificates {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        if let Some(cookie_jar) = &self.cookie_jar {
+            builder = builder.cookie_provider(cookie_jar.clone());
+        }
+        if self.accept_invalid_certs {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        builder.build()
+    }
+}
+
+/// Shares one connection pool and base configuration between many endpoints.
+///
+/// Cloning is cheap, clones share the same underlying connection pool.
+#[derive(Clone)]
+pub struct ApiClient {
+    client: Client,
+    client_config: ClientConfig,
+    base_url: String,
+    default_headers: HashMap<String, String>,
+    auth: Option<Auth>,
+    retry_policy: Option<RetryPolicy>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    cache: Option<Arc<dyn CacheStore>>,
+    signer: Option<Arc<dyn Signer>>,
+    cassette: Option<Arc<Cassette>>,
+    cookie_jar: Option<Arc<CookieJar>>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl ApiClient {
+    pub fn builder() -> ApiClientBuilder {
+        ApiClientBuilder::new()
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// The cookies of the session, to inspect or [`CookieJar::save`] them.
+    pub fn cookie_jar(&self) -> Option<&Arc<CookieJar>> {
+        self.cookie_jar.as_ref()
+    }
+
+    /// Starts an [`EndpointBuilder`] preconfigured with this client's settings.
+    ///
+    /// Anything set on the returned builder overrides the client defaults.
+    pub fn endpoint(&self, endpoint: &str) -> EndpointBuilder {
+        let mut builder = EndpointBuilder::new()
+            .client(self.client.clone())
+            .client_config(self.client_config.clone())
+            .base_url(&self.base_url)
+            .endpoint(endpoint);
+
+        if !self.default_headers.is_empty() {
+            builder = builder.headers(self.default_headers.clone());
+        }
+        if let Some(auth) = &self.auth {
+            builder = builder.auth(auth.clone());
+        }
+        if let Some(retry_policy) = &self.retry_policy {
+            builder = builder.retry_policy(retry_policy.clone());
+        }
+        for middleware in &self.middlewares {
+            builder = builder.middleware(middleware.clone());
+        }
+        if let Some(rate_limiter) = &self.rate_limiter {
+            builder = builder.rate_limiter(rate_limiter.clone());
+        }
+        if let Some(circuit_breaker) = &self.circuit_breaker {
+            builder = builder.circuit_breaker(circuit_breaker.clone());
+        }
+        if let Some(cache) = &self.cache {
+            builder = builder.cache(cache.clone());
+        }
+        if let Some(signer) = &self.signer {
+            builder = builder.signer(signer.clone());
+        }
+        if let Some(cassette) = &self.cassette {
+            builder = builder.cassette(cassette.clone());
+        }
+        if let Some(clock) = &self.clock {
+            builder = builder.clock(clock.clone());
+        }
+        builder
+    }
+
+    /// Starts a [`GraphQLEndpoint`] for the GraphQL api at `endpoint`.
+    pub fn graphql(&self, endpoint: &str) -> GraphQLEndpoint {
+        GraphQLEndpoint::new(self.endpoint(endpoint))
+    }
+}
@@ -0,0 +1,134 @@
+use parking_lot::RwLock;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use crate::prelude::*;
+
+/// A cookie store shared by all requests of an [`super::ApiClient`], e.g. for login-then-call flows.
+///
+/// Can be saved to and loaded from a JSON file to keep a session between runs.
+#[derive(Default)]
+pub struct CookieJar {
+    store: RwLock<cookie_store::CookieStore>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        CookieJar::default()
+    }
+
+    /// Loads cookies saved with [`Self::save`], skipping any that expired since.
+    pub fn load(path: impl AsRef<Path>) -> RResult<Self, AnyErr2> {
+        let path = path.as_ref();
+        let file = File::open(path).change_context(err2!(format!(
+            "Failed to open cookie file {}",
+            path.display()
+        )))?;
+        let store = cookie_store::serde::json::load(BufReader::new(file)).map_err(|e| {
+            Report::new(err2!(format!(
+                "Failed to parse cookie file {}: {}",
+                path.display(),
+                e
+            )))
+        })?;
+        Ok(CookieJar {
+            store: RwLock::new(store),
+        })
+    }
+
+    /// Saves all cookies, including session cookies without an expiry, so a login survives restarts.
+    pub fn save(&self, path: impl AsRef<Path>) -> RResult<(), AnyErr2> {
+        let path = path.as_ref();
+        let file = File::create(path).change_context(err2!(format!(
+            "Failed to create cookie file {}",
+            path.display()
+        )))?;
+        cookie_store::serde::json::save_incl_expired_and_nonpersistent(
+            &self.store.read(),
+            &mut BufWriter::new(file),
+        )
+        .map_err(|e| {
+            Report::new(err2!(format!(
+                "Failed to write cookie file {}: {}",
+                path.display(),
+                e
+            )))
+        })
+    }
+
+    /// Adds a cookie as if `url` had responded with `Set-Cookie: {cookie}`.
+    pub fn add(&self, cookie: &str, url: &Url) {
+        let _ = self.store.write().parse(cookie, url);
+    }
+
+    /// The value of the cookie `name` that would be sent to `url`.
+    pub fn get(&self, url: &Url, name: &str) -> Option<String> {
+        self.store
+            .read()
+            .get_request_values(url)
+            .find(|(cookie_name, _)| *cookie_name == name)
+            .map(|(_, value)| value.to_string())
+    }
+
+    pub fn clear(&self) {
+        self.store.write().clear();
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let mut store = self.store.write();
+        for header in cookie_headers {
+            if let Ok(cookie) = header.to_str() {
+                if let Err(e) = store.parse(cookie, url) {
+                    debug!("Ignoring cookie from {}: {:?}", url, e);
+                }
+            }
+        }
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let header = self
+            .store
+            .read()
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if header.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&header).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn save_and_load() {
+        let url = Url::parse("https://api.example.com/login").unwrap();
+        let jar = CookieJar::new();
+        jar.add("session=abc; Path=/; HttpOnly", &url);
+        jar.add("old=1; Path=/; Max-Age=0", &url);
+        assert_eq!(jar.get(&url, "session").as_deref(), Some("abc"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookies.json");
+        jar.save(&path).unwrap();
+
+        let loaded = CookieJar::load(&path).unwrap();
+        let other = Url::parse("https://api.example.com/items").unwrap();
+        assert_eq!(loaded.get(&other, "session").as_deref(), Some("abc"));
+        assert_eq!(loaded.get(&other, "old"), None);
+        assert_eq!(
+            reqwest::cookie::CookieStore::cookies(&loaded, &other).unwrap(),
+            "session=abc"
+        );
+    }
+}
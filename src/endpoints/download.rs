@@ -0,0 +1,224 @@
+use futures::StreamExt;
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{failed_response_report, Endpoint};
+use crate::prelude::*;
+
+type ProgressFn = Box<dyn Fn(u64, Option<u64>) + Send + Sync>;
+
+/// Options for [`Endpoint::download_to`].
+#[derive(Default)]
+pub struct DownloadOptions {
+    resume: bool,
+    sha256: Option<String>,
+    on_progress: Option<ProgressFn>,
+}
+
+impl DownloadOptions {
+    pub fn new() -> Self {
+        DownloadOptions::default()
+    }
+
+    /// Continue a partial download with a `Range` request if the file already exists.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Verify the complete file against this hex encoded sha256 digest.
+    pub fn sha256(mut self, sha256: &str) -> Self {
+        self.sha256 = Some(sha256.to_lowercase());
+        self
+    }
+
+    /// Called after each chunk with the bytes downloaded so far and the expected total if known.
+    pub fn on_progress(
+        mut self,
+        on_progress: impl Fn(u64, Option<u64>) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+}
+
+/// The total size from a `Content-Range: bytes 100-199/200` header.
+fn content_range_total(value: &str) -> Option<u64> {
+    value.rsplit('/').next()?.trim().parse().ok()
+}
+
+/// Where the body of a `Content-Range: bytes 100-199/200` response starts.
+fn content_range_start(value: &str) -> Option<u64> {
+    let range = value.trim().strip_prefix("bytes")?.trim_start();
+    range.split('-').next()?.trim().parse().ok()
+}
+
+/// Hashes what is already on disk so a resumed download can still be verified.
+async fn hash_existing(path: &Path, hasher: &mut Sha256) -> RResult<(), AnyErr2> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .change_context(err2!("Failed to open partial download"))?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .await
+            .change_context(err2!("Failed to read partial download"))?;
+        if read == 0 {
+            return Ok(());
+        }
+        hasher.update(&buf[..read]);
+    }
+}
+
+impl Endpoint {
+    /// Streams the response body into the file at `path`, returning the final size of the file.
+    ///
+    /// The size is checked against `Content-Length`, and against the checksum if one was given.
+    pub async fn download_to(
+        mut self,
+        path: impl AsRef<Path>,
+        options: DownloadOptions,
+    ) -> RResult<u64, AnyErr2> {
+        let path = path.as_ref();
+        let existing = if options.resume {
+            tokio::fs::metadata(path)
+                .await
+                .map(|m| m.len())
+                .unwrap_or(0)
+        } else {
+            0
+        };
+
+        // Without the range, for servers resuming from somewhere else:
+        let from_scratch = (existing > 0).then(|| self.clone());
+        if existing > 0 {
+            self.headers
+                .get_or_insert_with(HashMap::new)
+                .insert(RANGE.to_string(), format!("bytes={}-", existing));
+        }
+
+        let method = self.method.clone();
+        let response = self.execute().await?;
+        let status = response.status();
+
+        let mut hasher = Sha256::new();
+        let (mut file, mut written, expected) = if status == StatusCode::PARTIAL_CONTENT {
+            let content_range = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok());
+            let start = content_range.and_then(content_range_start);
+            if let (Some(from_scratch), false) = (from_scratch, start == Some(existing)) {
+                warn!(
+                    "Download of {} resumed from byte {:?} instead of {}, starting over",
+                    path.display(),
+                    start,
+                    existing
+                );
+                let mut options = options;
+                options.resume = false;
+                return Box::pin(from_scratch.download_to(path, options)).await;
+            }
+            let total = content_range
+                .and_then(content_range_total)
+                .or_else(|| response.content_length().map(|len| len + existing));
+            if options.sha256.is_some() {
+                hash_existing(path, &mut hasher).await?;
+            }
+            let file = tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(path)
+                .await
+                .change_context(err2!("Failed to open file for resuming"))?;
+            debug!(
+                "Resuming download of {} from byte {}",
+                path.display(),
+                existing
+            );
+            (file, existing, total)
+        } else if status == StatusCode::RANGE_NOT_SATISFIABLE && existing > 0 {
+            // Nothing left to fetch, the file is already complete:
+            if let Some(expected) = &options.sha256 {
+                hash_existing(path, &mut hasher).await?;
+                verify_sha256(hasher, expected)?;
+            }
+            return Ok(existing);
+        } else if status.is_success() {
+            let file = tokio::fs::File::create(path)
+                .await
+                .change_context(err2!(format!("Failed to create {}", path.display())))?;
+            (file, 0, response.content_length())
+        } else {
+            return Err(failed_response_report(response, method).await);
+        };
+
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.change_context(err2!("Failed to read download stream"))?;
+            file.write_all(&chunk)
+                .await
+                .change_context(err2!("Failed to write download to disk"))?;
+            if options.sha256.is_some() {
+                hasher.update(&chunk);
+            }
+            written += chunk.len() as u64;
+            if let Some(on_progress) = &options.on_progress {
+                on_progress(written, expected);
+            }
+        }
+        file.flush()
+            .await
+            .change_context(err2!("Failed to flush download to disk"))?;
+
+        if let Some(expected) = expected {
+            if written != expected {
+                return Err(
+                    Report::new(err2!("Download size mismatch")).attach_printable(format!(
+                        "Expected {} bytes, got {} bytes",
+                        expected, written
+                    )),
+                );
+            }
+        }
+        if let Some(expected) = &options.sha256 {
+            verify_sha256(hasher, expected)?;
+        }
+
+        Ok(written)
+    }
+}
+
+fn verify_sha256(hasher: Sha256, expected: &str) -> RResult<(), AnyErr2> {
+    let actual = hex::encode(hasher.finalize());
+    if actual != expected {
+        return Err(Report::new(err2!("Download checksum mismatch"))
+            .attach_printable(format!("Expected sha256 {}, got {}", expected, actual)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("bytes 100-199/200", Some(200))]
+    #[case("bytes 0-0/*", None)]
+    fn parses_content_range(#[case] value: &str, #[case] expected: Option<u64>) {
+        assert_eq!(content_range_total(value), expected);
+    }
+
+    #[rstest]
+    #[case("bytes 100-199/200", Some(100))]
+    #[case("bytes 0-0/*", Some(0))]
+    #[case("bytes */200", None)]
+    fn parses_content_range_start(#[case] value: &str, #[case] expected: Option<u64>) {
+        assert_eq!(content_range_start(value), expected);
+    }
+}
@@ -0,0 +1,146 @@
+use error_stack::{Context, Report};
+use reqwest::{header::HeaderMap, Method, StatusCode};
+use serde_json::Value;
+use std::time::Duration;
+
+/// Found in the report of a request that got a non-success response.
+///
+/// ```ignore
+/// match HttpError::from_report(&report) {
+///     Some(e) if e.status == StatusCode::NOT_FOUND => ...,
+///     _ => ...,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HttpError {
+    pub status: StatusCode,
+    /// The response body with any ANSI escape codes stripped.
+    pub body: String,
+    /// The body parsed as JSON, when it was valid JSON.
+    pub parsed_json: Option<Value>,
+    pub url: String,
+    pub method: Method,
+    /// The response headers, e.g. for `Retry-After` or `WWW-Authenticate`.
+    pub headers: HeaderMap,
+}
+
+impl HttpError {
+    /// The HTTP error in a report, if the report came from a failed response.
+    pub fn from_report<C>(report: &Report<C>) -> Option<&HttpError> {
+        report.downcast_ref::<HttpError>()
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} returned {}", self.method, self.url, self.status)
+    }
+}
+
+impl Context for HttpError {}
+
+/// Which limit was hit when a request timed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutKind {
+    /// Establishing the connection took too long.
+    Connect,
+    /// A single attempt took longer than the endpoint's total timeout.
+    Request,
+    /// All attempts including backoff delays took longer than the endpoint's deadline.
+    Deadline,
+}
+
+/// Found in the report of a request that timed out, check with `report.contains::<TimeoutError>()`.
+#[derive(Debug, Clone)]
+pub struct TimeoutError {
+    pub kind: TimeoutKind,
+    pub after: Option<Duration>,
+}
+
+impl std::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.after {
+            Some(after) => write!(f, "{:?} timeout after {:?}", self.kind, after),
+            None => write!(f, "{:?} timeout", self.kind),
+        }
+    }
+}
+
+impl Context for TimeoutError {}
+
+/// Found in the report of a request that was not sent because the host's circuit breaker is open.
+#[derive(Debug, Clone)]
+pub struct CircuitOpenError {
+    pub host: String,
+    /// When the next probe request will be allowed.
+    pub retry_in: Duration,
+}
+
+impl std::fmt::Display for CircuitOpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Circuit open for {}, retry in {:?}",
+            self.host, self.retry_in
+        )
+    }
+}
+
+impl Context for CircuitOpenError {}
+
+/// One entry of the `errors` array of a GraphQL response.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct GraphQLError {
+    pub message: String,
+    #[serde(default)]
+    pub path: Vec<Value>,
+    #[serde(default)]
+    pub locations: Vec<Value>,
+    #[serde(default)]
+    pub extensions: Option<Value>,
+}
+
+/// Found in the report of a GraphQL request whose response had errors.
+#[derive(Debug, Clone)]
+pub struct GraphQLErrors {
+    pub errors: Vec<GraphQLError>,
+    /// GraphQL allows partial results next to errors.
+    pub data: Option<Value>,
+}
+
+impl std::fmt::Display for GraphQLErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let messages: Vec<&str> = self.errors.iter().map(|e| e.message.as_str()).collect();
+        write!(f, "GraphQL errors: {}", messages.join("; "))
+    }
+}
+
+impl Context for GraphQLErrors {}
+
+/// Why [`super::WebhookVerifier`] rejected a payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebhookError {
+    MissingHeader(String),
+    InvalidTimestamp,
+    /// The timestamp is further from now than the tolerance, e.g. a replayed payload.
+    Expired {
+        age: Duration,
+    },
+    InvalidSignature,
+    /// The signature is valid but the body isn't the expected JSON.
+    InvalidPayload,
+}
+
+impl std::fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebhookError::MissingHeader(name) => write!(f, "Missing webhook header {}", name),
+            WebhookError::InvalidTimestamp => write!(f, "Invalid webhook timestamp"),
+            WebhookError::Expired { age } => write!(f, "Webhook timestamp is {:?} old", age),
+            WebhookError::InvalidSignature => write!(f, "Invalid webhook signature"),
+            WebhookError::InvalidPayload => write!(f, "Invalid webhook payload"),
+        }
+    }
+}
+
+impl Context for WebhookError {}
@@ -0,0 +1,132 @@
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use super::{EndpointBuilder, GraphQLError, GraphQLErrors, HttpError, Method};
+use crate::prelude::*;
+
+/// Sends a GraphQL query or mutation and returns its typed `data`.
+///
+/// A response with an `errors` array fails with a [`GraphQLErrors`] in the report, also when the
+/// server returns them with a non-success status.
+pub struct GraphQLEndpoint {
+    builder: EndpointBuilder,
+    query: String,
+    variables: Option<Value>,
+    operation_name: Option<String>,
+}
+
+impl GraphQLEndpoint {
+    /// `builder` points to the GraphQL url, the method and body are set by this endpoint.
+    pub fn new(builder: EndpointBuilder) -> Self {
+        GraphQLEndpoint {
+            builder,
+            query: String::new(),
+            variables: None,
+            operation_name: None,
+        }
+    }
+
+    pub fn query(mut self, query: &str) -> Self {
+        self.query = query.to_string();
+        self
+    }
+
+    pub fn mutation(self, mutation: &str) -> Self {
+        self.query(mutation)
+    }
+
+    pub fn variables(mut self, variables: Value) -> Self {
+        self.variables = Some(variables);
+        self
+    }
+
+    /// Selects the operation to run when the document contains several.
+    pub fn operation_name(mut self, operation_name: &str) -> Self {
+        self.operation_name = Some(operation_name.to_string());
+        self
+    }
+
+    /// Customize the underlying request, e.g. to add headers.
+    pub fn with_builder(mut self, f: impl FnOnce(EndpointBuilder) -> EndpointBuilder) -> Self {
+        self.builder = f(self.builder);
+        self
+    }
+
+    pub async fn send<T: DeserializeOwned>(self) -> RResult<T, AnyErr2> {
+        let mut body = json!({ "query": self.query });
+        if let Some(variables) = self.variables {
+            body["variables"] = variables;
+        }
+        if let Some(operation_name) = self.operation_name {
+            body["operationName"] = Value::String(operation_name);
+        }
+
+        let response = match self
+            .builder
+            .method(Method::POST)
+            .json_body(body)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(report) => {
+                let graphql_errors = HttpError::from_report(&report)
+                    .and_then(|http| http.parsed_json.as_ref())
+                    .and_then(graphql_errors);
+                return Err(match graphql_errors {
+                    Some(errors) => report.attach(errors),
+                    None => report,
+                });
+            }
+        };
+
+        if let Some(errors) = graphql_errors(&response) {
+            return Err(
+                Report::new(errors).change_context(err2!("GraphQL request returned errors"))
+            );
+        }
+
+        let data = response.get("data").cloned().unwrap_or(Value::Null);
+        serde_json::from_value::<T>(data.clone()).map_err(|e| {
+            Report::new(err2!(format!("Failed to parse GraphQL data: {:?}", e))).attach_printable(
+                format!(
+                    "Expected type: {}, data: {}",
+                    std::any::type_name::<T>(),
+                    data
+                ),
+            )
+        })
+    }
+}
+
+fn graphql_errors(response: &Value) -> Option<GraphQLErrors> {
+    let errors: Vec<GraphQLError> = serde_json::from_value(response.get("errors")?.clone()).ok()?;
+    if errors.is_empty() {
+        return None;
+    }
+    Some(GraphQLErrors {
+        errors,
+        data: response.get("data").filter(|data| !data.is_null()).cloned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn parses_errors() {
+        let response = json!({
+            "data": {"user": null},
+            "errors": [{"message": "Not found", "path": ["user"], "extensions": {"code": "NOT_FOUND"}}]
+        });
+        let errors = graphql_errors(&response).unwrap();
+        assert_eq!(errors.errors[0].message, "Not found");
+        assert_eq!(errors.errors[0].path, vec![json!("user")]);
+        assert_eq!(errors.data, Some(json!({"user": null})));
+
+        assert!(graphql_errors(&json!({"data": {}, "errors": []})).is_none());
+        assert!(graphql_errors(&json!({"data": {}})).is_none());
+    }
+}
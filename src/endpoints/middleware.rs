@@ -0,0 +1,18 @@
+use reqwest::{Request, Response};
+
+use crate::prelude::*;
+
+/// A hook into every request sent by an [`super::Endpoint`], e.g. for signing, request ids or metrics.
+///
+/// Called once per attempt, so retried requests pass through again. `on_request` receives the
+/// fully built request so it can see the final url, headers and body.
+pub trait Middleware: Send + Sync {
+    fn on_request(&self, _request: &mut Request) -> RResult<(), AnyErr2> {
+        Ok(())
+    }
+
+    fn on_response(&self, _response: &Response) {}
+
+    /// Called instead of `on_response` when the request fails before any response is received.
+    fn on_error(&self, _error: &reqwest::Error) {}
+}
@@ -0,0 +1,1229 @@
+mod auth;
+mod batch;
+mod body;
+mod cache;
+mod circuit_breaker;
+mod client;
+mod cookies;
+mod download;
+mod error;
+mod graphql;
+mod middleware;
+mod openapi;
+mod paginate;
+mod path;
+mod rate_limit;
+mod retry;
+mod sign;
+mod stream;
+mod trace;
+mod vcr;
+mod webhook;
+
+use crate::clock::{system_clock, Clock};
+use crate::prelude::*;
+use body::Body;
+use bytes::Bytes;
+use regex::Regex;
+use reqwest::{Client, Response, Url};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::Instrument;
+
+pub use auth::{ApiKeyLocation, Auth};
+pub use batch::{BatchResults, BatchStats};
+pub use body::MultipartPart;
+pub use cache::{CacheEntry, CacheStore, MemoryCache, RedisCache};
+pub use circuit_breaker::{CircuitBreaker, CircuitBreakerPolicy, CircuitState};
+use client::ClientConfig;
+pub use client::{ApiClient, ApiClientBuilder};
+pub use cookies::CookieJar;
+pub use download::DownloadOptions;
+pub use error::{
+    CircuitOpenError, GraphQLError, GraphQLErrors, HttpError, TimeoutError, TimeoutKind,
+    WebhookError,
+};
+pub use graphql::GraphQLEndpoint;
+pub use middleware::Middleware;
+pub use openapi::{generate_client, generate_client_file};
+pub use paginate::Pagination;
+pub use path::{join_url, render_path};
+pub use rate_limit::{RateLimit, RateLimiter};
+pub use reqwest::Method;
+pub use retry::RetryPolicy;
+pub use sign::{HmacSigner, Signer};
+pub use stream::SseEvent;
+pub use trace::{correlation_id, with_correlation_id, REQUEST_ID_HEADER, TRACEPARENT_HEADER};
+pub use vcr::{Cassette, Interaction, RecordedRequest, RecordedResponse, VcrMode};
+pub use webhook::WebhookVerifier;
+
+/// The http client items, also part of [`crate::prelude`].
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{
+        ApiClient, ApiClientBuilder, Auth, CacheStore, Endpoint, EndpointBuilder, HttpError,
+        MemoryCache, Method, RateLimit, RedisCache, RetryPolicy,
+    };
+}
+
+#[derive(Default)]
+pub struct EndpointBuilder {
+    base_url: Option<String>,
+    endpoint: Option<String>,
+    method: Option<Method>,
+    body: Option<Body>,
+    query_params: Option<HashMap<String, String>>,
+    path_params: Option<HashMap<String, String>>,
+    retry_policy: Option<RetryPolicy>,
+    headers: Option<HashMap<String, String>>,
+    auth: Option<Auth>,
+    client: Option<Client>,
+    client_config: Option<ClientConfig>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    deadline: Option<Duration>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    cache: Option<Arc<dyn CacheStore>>,
+    signer: Option<Arc<dyn Signer>>,
+    cassette: Option<Arc<Cassette>>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl EndpointBuilder {
+    pub fn new() -> Self {
+        EndpointBuilder::default()
+    }
+
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+
+    /// The path of the endpoint, may contain `{name}` placeholders filled from [`Self::path_params`].
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = Some(endpoint.to_string());
+        self
+    }
+
+    pub fn method(mut self, method: Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    pub fn json_body(mut self, json_body: Value) -> Self {
+        self.body = Some(Body::Json(json_body));
+        self
+    }
+
+    /// Sends the fields as an `application/x-www-form-urlencoded` body.
+    pub fn form_body(mut self, form: HashMap<String, String>) -> Self {
+        self.body = Some(Body::Form(form));
+        self
+    }
+
+    /// Sends the parts as a `multipart/form-data` body, see [`MultipartPart`].
+    pub fn multipart(mut self, parts: Vec<MultipartPart>) -> Self {
+        self.body = Some(Body::Multipart(parts));
+        self
+    }
+
+    pub fn query_params(mut self, query_params: HashMap<String, String>) -> Self {
+        self.query_params = Some(query_params);
+        self
+    }
+
+    /// Adds a single query param, keeping any already set.
+    pub fn query_param(mut self, key: &str, value: &str) -> Self {
+        self.query_params
+            .get_or_insert_with(HashMap::new)
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn path_params(mut self, path_params: HashMap<String, String>) -> Self {
+        self.path_params = Some(path_params);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers
+            .get_or_insert_with(HashMap::new)
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Adds to rather than replaces any headers already set, e.g. an [`ApiClient`]'s defaults.
+    pub fn headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.headers
+            .get_or_insert_with(HashMap::new)
+            .extend(headers);
+        self
+    }
+
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    pub fn bearer_token(self, token: &str) -> Self {
+        self.auth(Auth::Bearer(token.to_string()))
+    }
+
+    pub fn basic_auth(self, username: &str, password: Option<&str>) -> Self {
+        self.auth(Auth::Basic {
+            username: username.to_string(),
+            password: password.map(str::to_string),
+        })
+    }
+
+    pub fn api_key(self, name: &str, value: &str, location: ApiKeyLocation) -> Self {
+        self.auth(Auth::ApiKey {
+            name: name.to_string(),
+            value: value.to_string(),
+            location,
+        })
+    }
+
+    /// Use an existing client rather than creating a new one per request, keeping its connection pool.
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// How the [`ApiClient`]'s client was built, to build one like it for a connect timeout.
+    pub(crate) fn client_config(mut self, client_config: ClientConfig) -> Self {
+        self.client_config = Some(client_config);
+        self
+    }
+
+    /// Middlewares run in the order they were added.
+    pub fn middleware(mut self, middleware: Arc<dyn Middleware>) -> Self {
+        self.middlewares.push(middleware);
+        self
+    }
+
+    /// Maximum time for a single attempt, from connecting until the body has been read.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Connect timeouts are set on the client, so the endpoint gets its own client when set,
+    /// configured like the [`ApiClient`]'s but without sharing its connection pool. Can't be
+    /// combined with [`Self::client`], set it on that client instead.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// Maximum time across all attempts including retry backoff delays.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Share a limiter between endpoints, [`ApiClient`] does this for all of its endpoints.
+    pub fn rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    pub fn circuit_breaker(mut self, circuit_breaker: Arc<CircuitBreaker>) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    pub fn cache(mut self, cache: Arc<dyn CacheStore>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn signer(mut self, signer: Arc<dyn Signer>) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Record to or replay from a cassette instead of only talking to the network.
+    pub fn cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// Where cache freshness and the delays between retries come from.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn build(self) -> Result<Endpoint, Box<dyn std::error::Error>> {
+        let cookie_jar = self
+            .client_config
+            .as_ref()
+            .and_then(|config| config.cookie_jar.clone());
+        let client = match (self.connect_timeout, self.client_config, self.client) {
+            (Some(connect_timeout), Some(mut config), _) => {
+                config.connect_timeout = Some(connect_timeout);
+                config.build()?
+            }
+            (Some(_), None, Some(_)) => {
+                return Err("A connect timeout can't be added to a given client".into())
+            }
+            (Some(connect_timeout), None, None) => {
+                Client::builder().connect_timeout(connect_timeout).build()?
+            }
+            (None, _, client) => client.unwrap_or_default(),
+        };
+
+        Ok(Endpoint {
+            base_url: self.base_url.ok_or("Base URL is required")?,
+            endpoint: self.endpoint.ok_or("Endpoint is required")?,
+            method: self.method.ok_or("Method is required")?,
+            body: self.body,
+            query_params: self.query_params,
+            path_params: self.path_params,
+            retry_policy: self.retry_policy.unwrap_or_else(RetryPolicy::none),
+            headers: self.headers,
+            auth: self.auth,
+            client,
+            middlewares: self.middlewares,
+            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+            deadline: self.deadline,
+            rate_limiter: self.rate_limiter,
+            circuit_breaker: self.circuit_breaker,
+            cache: self.cache,
+            signer: self.signer,
+            cassette: self.cassette,
+            cookie_jar,
+            clock: self.clock.unwrap_or_else(system_clock),
+        })
+    }
+
+    fn try_build(self) -> RResult<Endpoint, AnyErr2> {
+        self.build()
+            .map_err(|e| Report::new(err2!(format!("Failed to build endpoint: {}", e))))
+    }
+
+    /// Builds and sends in one go, see [`Endpoint::send`].
+    pub async fn send(self) -> RResult<Value, AnyErr2> {
+        self.try_build()?.send().await
+    }
+
+    pub async fn send_typed<T: DeserializeOwned>(self) -> RResult<T, AnyErr2> {
+        self.try_build()?.send_typed().await
+    }
+
+    pub async fn send_text(self) -> RResult<String, AnyErr2> {
+        self.try_build()?.send_text().await
+    }
+
+    pub async fn send_bytes(self) -> RResult<Bytes, AnyErr2> {
+        self.try_build()?.send_bytes().await
+    }
+}
+
+#[derive(Clone)]
+pub struct Endpoint {
+    base_url: String,
+    endpoint: String,
+    method: Method,
+    body: Option<Body>,
+    query_params: Option<HashMap<String, String>>,
+    path_params: Option<HashMap<String, String>>,
+    retry_policy: RetryPolicy,
+    headers: Option<HashMap<String, String>>,
+    auth: Option<Auth>,
+    client: Client,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    deadline: Option<Duration>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    cache: Option<Arc<dyn CacheStore>>,
+    signer: Option<Arc<dyn Signer>>,
+    cassette: Option<Arc<Cassette>>,
+    /// The [`ApiClient`]'s cookies, part of the cache key.
+    cookie_jar: Option<Arc<CookieJar>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Endpoint {
+    pub fn builder() -> EndpointBuilder {
+        EndpointBuilder::new()
+    }
+
+    /// Sends the request, retrying according to the endpoint's [`RetryPolicy`].
+    ///
+    /// Returns the final response whatever its status, only transport failures are errors.
+    async fn execute(mut self) -> RResult<Response, AnyErr2> {
+        let span = self.trace_span();
+        self.inject_trace_headers(&span);
+
+        let (method, host) = (self.method.clone(), self.host());
+        let start = std::time::Instant::now();
+        let result = self
+            .execute_within_deadline()
+            .instrument(span.clone())
+            .await;
+        trace::record_outcome(&span, &result, start.elapsed());
+        trace::record_metrics(&method, &host, &result, start.elapsed());
+        result
+    }
+
+    async fn execute_within_deadline(self) -> RResult<Response, AnyErr2> {
+        match self.deadline {
+            Some(deadline) => tokio::time::timeout(deadline, self.execute_attempts())
+                .await
+                .map_err(|_| {
+                    Report::new(TimeoutError {
+                        kind: TimeoutKind::Deadline,
+                        after: Some(deadline),
+                    })
+                    .change_context(err2!("Request deadline exceeded"))
+                    .classify(ErrorClass::Timeout)
+                })?,
+            None => self.execute_attempts().await,
+        }
+    }
+
+    fn timeout_report(&self, e: &reqwest::Error) -> Report<AnyErr2> {
+        let timeout = if e.is_connect() {
+            TimeoutError {
+                kind: TimeoutKind::Connect,
+                after: self.connect_timeout,
+            }
+        } else {
+            TimeoutError {
+                kind: TimeoutKind::Request,
+                after: self.timeout,
+            }
+        };
+        Report::new(timeout)
+            .attach_printable(format!("{:?}", e))
+            .change_context(err2!("Request timed out"))
+            .classify(ErrorClass::Timeout)
+    }
+
+    /// The full url the request is sent to, including query params.
+    fn url(&self) -> RResult<Url, AnyErr2> {
+        let mut url = join_url(
+            &self.base_url,
+            &render_path(&self.endpoint, self.path_params.as_ref())?,
+            self.query_params.as_ref(),
+        )?;
+
+        if let Some(auth) = &self.auth {
+            auth.apply_to_url(&mut url);
+        }
+        Ok(url)
+    }
+
+    async fn execute_attempts(self) -> RResult<Response, AnyErr2> {
+        let url = self.url()?;
+        match self.cache.clone() {
+            Some(cache) if cache::is_cacheable_method(&self.method) => {
+                self.execute_cached(cache.as_ref(), url).await
+            }
+            _ => self.execute_url(url).await,
+        }
+    }
+
+    async fn execute_url(self, url: Url) -> RResult<Response, AnyErr2> {
+        let client = &self.client;
+
+        let max_attempts = if self.retry_policy.applies_to(&self.method) {
+            self.retry_policy.get_max_attempts()
+        } else {
+            1
+        };
+
+        let host = url.host_str().unwrap_or_default().to_string();
+        let query_key = self.auth.as_ref().and_then(Auth::query_key);
+        let logged_url = trace::redact_query(&url);
+
+        let mut attempt = 1;
+        loop {
+            let mut request = client.request(self.method.clone(), url.clone());
+
+            if let Some(headers) = &self.headers {
+                for (key, value) in headers {
+                    request = request.header(key.as_str(), value.as_str());
+                }
+            }
+
+            if let Some(auth) = &self.auth {
+                request = auth.apply_to_request(request);
+            }
+
+            if let Some(body) = &self.body {
+                request = body.apply(request).await?;
+            }
+
+            if let Some(timeout) = self.timeout {
+                request = request.timeout(timeout);
+            }
+
+            let mut request = request
+                .build()
+                .change_context(err2!("Failed to build request"))?;
+            for middleware in &self.middlewares {
+                middleware.on_request(&mut request)?;
+            }
+            if let Some(signer) = &self.signer {
+                signer.sign(&mut request)?;
+            }
+
+            // Replays never touch the network, so they skip limits and retries:
+            if let Some(cassette) = &self.cassette {
+                if cassette.mode() == VcrMode::Replay {
+                    return cassette.find(&request, query_key);
+                }
+            }
+
+            // An open circuit fails fast, including any remaining retries:
+            if let Some(circuit_breaker) = &self.circuit_breaker {
+                circuit_breaker.check(&host)?;
+            }
+
+            let can_retry = attempt < max_attempts;
+            let permit = match &self.rate_limiter {
+                Some(rate_limiter) => Some(rate_limiter.acquire(&host).await),
+                None => None,
+            };
+            let result = match &self.cassette {
+                Some(cassette) => {
+                    let recorded = RecordedRequest::from_request(&request, query_key);
+                    match client.execute(request).await {
+                        Ok(resp) => Ok(cassette.store(recorded, resp).await?),
+                        Err(e) => Err(e),
+                    }
+                }
+                None => client.execute(request).await,
+            };
+            drop(permit);
+
+            if let Some(circuit_breaker) = &self.circuit_breaker {
+                let success = match &result {
+                    Ok(resp) => !resp.status().is_server_error(),
+                    Err(_) => false,
+                };
+                circuit_breaker.record(&host, success);
+            }
+
+            if let (Some(rate_limiter), Ok(resp)) = (&self.rate_limiter, &result) {
+                if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                    if let Some(retry_after) = retry::retry_after(resp) {
+                        rate_limiter
+                            .pause(&host, self.retry_policy.delay(attempt, Some(retry_after)));
+                    }
+                }
+            }
+            for middleware in &self.middlewares {
+                match &result {
+                    Ok(resp) => middleware.on_response(resp),
+                    Err(e) => middleware.on_error(e),
+                }
+            }
+
+            // Reqwest errors print their url, which may hold the api key:
+            let delay = match result.map_err(reqwest::Error::without_url) {
+                Ok(resp) => {
+                    if !can_retry || !retry::is_retryable_status(resp.status()) {
+                        return Ok(resp);
+                    }
+                    let delay = self.retry_policy.delay(attempt, retry::retry_after(&resp));
+                    warn!(
+                        "Request to {} returned {}, retrying in {:?} (attempt {}/{})",
+                        logged_url,
+                        resp.status(),
+                        delay,
+                        attempt,
+                        max_attempts
+                    );
+                    delay
+                }
+                Err(e) => {
+                    if !can_retry || !retry::is_retryable_error(&e) {
+                        error!("Failed to send request: {:?}", e);
+                        if e.is_timeout() {
+                            return Err(self.timeout_report(&e));
+                        }
+                        let class = if retry::is_retryable_error(&e) {
+                            ErrorClass::Retryable
+                        } else {
+                            ErrorClass::Permanent
+                        };
+                        return Err(
+                            Report::new(err2!(format!("Failed to send request: {:?}", e)))
+                                .classify(class),
+                        );
+                    }
+                    let delay = self.retry_policy.backoff(attempt);
+                    warn!(
+                        "Failed to send request to {}: {}, retrying in {:?} (attempt {}/{})",
+                        logged_url, e, delay, attempt, max_attempts
+                    );
+                    delay
+                }
+            };
+
+            self.clock.sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Sends the request and returns the response if its status is a success.
+    async fn execute_ok(self) -> RResult<Response, AnyErr2> {
+        let method = self.method.clone();
+        let response = self.execute().await?;
+
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        Err(failed_response_report(response, method).await)
+    }
+
+    pub async fn send(self) -> RResult<Value, AnyErr2> {
+        let json = self.send_typed::<Value>().await?;
+        debug!("Request SUCCESS: {:#?}", json);
+        Ok(json)
+    }
+
+    /// Sends the request and deserializes the JSON response body into `T`.
+    pub async fn send_typed<T: DeserializeOwned>(self) -> RResult<T, AnyErr2> {
+        let response = self.execute_ok().await?;
+        let body = response
+            .bytes()
+            .await
+            .change_context(err2!("Failed to read response body"))?;
+
+        serde_json::from_slice::<T>(&body).map_err(|e| {
+            error!("Failed to parse JSON response: {:?}", e);
+            Report::new(err2!(format!("Failed to parse JSON response: {:?}", e))).attach_printable(
+                format!(
+                    "Expected type: {}, body: {}",
+                    std::any::type_name::<T>(),
+                    String::from_utf8_lossy(&body)
+                ),
+            )
+        })
+    }
+
+    /// Sends the request and returns the response body as text.
+    pub async fn send_text(self) -> RResult<String, AnyErr2> {
+        let response = self.execute_ok().await?;
+        response
+            .text()
+            .await
+            .change_context(err2!("Failed to read response text"))
+    }
+
+    /// Sends the request and returns the raw response body.
+    pub async fn send_bytes(self) -> RResult<Bytes, AnyErr2> {
+        let response = self.execute_ok().await?;
+        response
+            .bytes()
+            .await
+            .change_context(err2!("Failed to read response bytes"))
+    }
+}
+
+/// Logs a non-success response and turns it into a report carrying an [`HttpError`].
+async fn failed_response_report(response: Response, method: Method) -> Report<AnyErr2> {
+    let status = response.status();
+    let url = trace::redact_query(response.url());
+    let headers = response.headers().clone();
+    let error_text = match response.text().await {
+        Ok(error_text) => error_text,
+        Err(e) => return Report::new(e).change_context(err2!("Failed to get error text")),
+    };
+
+    let re = Regex::new(r"\x1B\[[0-9;]*[mK]").unwrap();
+    let cleaned_error_text = re.replace_all(&error_text, "").to_string();
+
+    let parsed_json = match serde_json::from_str::<Value>(&cleaned_error_text) {
+        Ok(mut json) => {
+            if let Some(details) = json.get_mut("details") {
+                if let Some(details_str) = details.as_str() {
+                    let cleaned_details = re.replace_all(details_str, "").to_string();
+                    *details = Value::String(cleaned_details);
+                }
+            }
+
+            error!(
+                "Request FAILED with status {:?} and error: {:#?}",
+                status, json
+            );
+            Some(json)
+        }
+        Err(_) => {
+            error!(
+                "Request FAILED with status {:?} and error: {}",
+                status, cleaned_error_text
+            );
+            None
+        }
+    };
+
+    let context = if parsed_json.is_some() {
+        err2!("Request failed with JSON error text")
+    } else {
+        err2!("Request failed with text error")
+    };
+    Report::new(HttpError {
+        status,
+        body: cleaned_error_text,
+        parsed_json,
+        url,
+        method,
+        headers,
+    })
+    .change_context(context)
+    .classify(ErrorClass::from_http_status(status.as_u16()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::prelude::*;
+    use tokio::net::TcpListener;
+
+    /// Accepts connections but never responds.
+    async fn silent_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut conns = vec![];
+            while let Ok((conn, _)) = listener.accept().await {
+                conns.push(conn);
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    /// Responds to every request with the given status and body.
+    async fn canned_server(status: u16, body: &'static str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut buf = [0; 4096];
+                let _ = conn.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 {} Canned\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = conn.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn http_error_is_structured() {
+        let report = Endpoint::builder()
+            .base_url(&canned_server(404, r#"{"error": "no such user"}"#).await)
+            .endpoint("/users/{id}")
+            .path_params(HashMap::from([("id".to_string(), "7".to_string())]))
+            .method(Method::GET)
+            .send()
+            .await
+            .unwrap_err();
+
+        let http = HttpError::from_report(&report).unwrap();
+        assert_eq!(http.status, reqwest::StatusCode::NOT_FOUND);
+        assert_eq!(http.method, Method::GET);
+        assert!(http.url.ends_with("/users/7"));
+        assert_eq!(http.parsed_json.as_ref().unwrap()["error"], "no such user");
+        assert_eq!(
+            crate::errors::error_class(&report),
+            Some(ErrorClass::NotFound)
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn typed_response() {
+        #[derive(serde::Deserialize)]
+        struct User {
+            name: String,
+        }
+
+        let user: User = Endpoint::builder()
+            .base_url(&canned_server(200, r#"{"name": "ada"}"#).await)
+            .endpoint("/me")
+            .method(Method::GET)
+            .send_typed()
+            .await
+            .unwrap();
+        assert_eq!(user.name, "ada");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn download_verifies_checksum() {
+        let base_url = canned_server(200, "hello world").await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hello.txt");
+        let endpoint = || {
+            Endpoint::builder()
+                .base_url(&base_url)
+                .endpoint("/hello.txt")
+                .method(Method::GET)
+                .build()
+                .unwrap()
+        };
+
+        let size = endpoint()
+            .download_to(
+                &path,
+                DownloadOptions::new()
+                    .sha256("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(size, 11);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+
+        assert!(endpoint()
+            .download_to(&path, DownloadOptions::new().sha256("00"))
+            .await
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::resumes(5)]
+    #[case::restarts_on_other_offsets(3)]
+    #[tokio::test]
+    async fn download_resumes_where_the_file_ends(#[case] served_from: usize) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Serves ranges from `served_from` whatever was asked for:
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let body = "hello world";
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut buf = [0; 4096];
+                let read = conn.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..read]).to_lowercase();
+                let response = if request.contains("range: bytes=") {
+                    format!(
+                        "HTTP/1.1 206 Partial Content\r\ncontent-range: bytes {}-{}/{}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        served_from,
+                        body.len() - 1,
+                        body.len(),
+                        body.len() - served_from,
+                        &body[served_from..]
+                    )
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                };
+                let _ = conn.write_all(response.as_bytes()).await;
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hello.txt");
+        std::fs::write(&path, "hello").unwrap();
+
+        let size = Endpoint::builder()
+            .base_url(&format!("http://{}", addr))
+            .endpoint("/hello.txt")
+            .method(Method::GET)
+            .build()
+            .unwrap()
+            .download_to(
+                &path,
+                DownloadOptions::new()
+                    .resume(true)
+                    .sha256("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(size, 11);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+    }
+
+    #[rstest]
+    #[case::request(None, TimeoutKind::Request)]
+    #[case::deadline(Some(Duration::from_millis(150)), TimeoutKind::Deadline)]
+    #[tokio::test]
+    async fn timeouts_are_distinguishable(
+        #[case] deadline: Option<Duration>,
+        #[case] expected: TimeoutKind,
+    ) {
+        let mut builder = Endpoint::builder()
+            .base_url(&silent_server().await)
+            .endpoint("/slow")
+            .method(Method::GET)
+            .timeout(Duration::from_millis(100))
+            .retry_policy(RetryPolicy::new().max_attempts(3).jitter(false));
+        if let Some(deadline) = deadline {
+            builder = builder.deadline(deadline);
+        }
+
+        let report = builder.send().await.unwrap_err();
+        let timeout = report.downcast_ref::<TimeoutError>().unwrap();
+        assert_eq!(timeout.kind, expected);
+        assert_eq!(
+            crate::errors::error_class(&report),
+            Some(ErrorClass::Timeout)
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn explicit_proxy() {
+        // A plain http proxy receives the request for the real host, so the canned server plays the proxy:
+        let proxy = canned_server(200, r#"{"via": "proxy"}"#).await;
+        let client = ApiClient::builder()
+            .base_url("http://upstream.invalid")
+            .proxy(&proxy)
+            .build()
+            .unwrap();
+        let response = client
+            .endpoint("/items")
+            .method(Method::GET)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response["via"], "proxy");
+
+        assert!(ApiClient::builder()
+            .base_url("http://upstream.invalid")
+            .proxy("not a url")
+            .build()
+            .is_err());
+        assert!(ApiClient::builder()
+            .base_url("http://upstream.invalid")
+            .root_certificate(b"not a certificate")
+            .build()
+            .is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn caps_retry_after(mock_clock: MockClock) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Asks to come back in an hour, then succeeds:
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let responses = [
+                "HTTP/1.1 429 Too Many Requests\r\nretry-after: 3600\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}",
+            ];
+            for response in responses {
+                let (mut conn, _) = listener.accept().await.unwrap();
+                let mut buf = [0; 4096];
+                let _ = conn.read(&mut buf).await;
+                let _ = conn.write_all(response.as_bytes()).await;
+            }
+        });
+        let client = ApiClient::builder()
+            .base_url(&format!("http://{}", addr))
+            .retry_policy(
+                RetryPolicy::new()
+                    .max_attempts(2)
+                    .max_backoff(Duration::from_secs(5)),
+            )
+            .clock(mock_clock.shared())
+            .build()
+            .unwrap();
+
+        client
+            .endpoint("/items")
+            .method(Method::GET)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(mock_clock.sleeps(), [Duration::from_secs(5)]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn query_api_key_is_redacted(
+        captured_logs: CapturedLogs,
+        memory_redis: MemoryRedis,
+        mock_clock: MockClock,
+        temp_dir: crate::files::TempWorkspace,
+    ) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Caches `/ok` and fails everything else:
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut buf = [0; 4096];
+                let read = conn.read(&mut buf).await.unwrap_or(0);
+                let response = match buf[..read].starts_with(b"GET /ok") {
+                    true => "HTTP/1.1 200 OK\r\ncache-control: max-age=60\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}",
+                    false => "HTTP/1.1 503 Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                };
+                let _ = conn.write_all(response.as_bytes()).await;
+            }
+        });
+        let cassette = temp_dir.path().join("cassette.json");
+        let client = ApiClient::builder()
+            .base_url(&format!("http://{}", addr))
+            .auth(Auth::ApiKey {
+                name: "key".to_string(),
+                value: "s3cret-key".to_string(),
+                location: ApiKeyLocation::Query,
+            })
+            .retry_policy(RetryPolicy::new().max_attempts(2))
+            .cache(RedisCache::new(memory_redis.clone()))
+            .cassette(Cassette::record(&cassette))
+            .clock(mock_clock.shared())
+            .build()
+            .unwrap();
+
+        client
+            .endpoint("/ok")
+            .method(Method::GET)
+            .query_param("page", "2")
+            .send()
+            .await
+            .unwrap();
+        let report = client
+            .endpoint("/fail")
+            .method(Method::GET)
+            .send()
+            .await
+            .unwrap_err();
+
+        let http_error = HttpError::from_report(&report).unwrap();
+        assert!(!http_error.url.contains("s3cret-key"));
+        assert!(!http_error.to_string().contains("s3cret-key"));
+        assert!(!format!("{:?}", report).contains("s3cret-key"));
+        captured_logs.assert_contains(tracing::Level::WARN, "retrying");
+        assert!(captured_logs
+            .events()
+            .iter()
+            .all(|event| !format!("{:?}", event).contains("s3cret-key")));
+        let recorded = std::fs::read_to_string(&cassette).unwrap();
+        assert!(recorded.contains("page=2") && !recorded.contains("s3cret-key"));
+        let keys = memory_redis.keys();
+        assert!(keys.iter().any(|key| key.contains("page=2")));
+        assert!(keys.iter().all(|key| !key.contains("s3cret-key")));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn sends_form_and_multipart_bodies(
+        mock_http: MockHttp,
+        temp_dir: crate::files::TempWorkspace,
+    ) {
+        mock_http.expect(Method::POST, "/login").respond(200, "{}");
+        mock_http.expect(Method::POST, "/upload").respond(200, "{}");
+        let endpoint = |path: &str| {
+            Endpoint::builder()
+                .base_url(&mock_http.url())
+                .endpoint(path)
+                .method(Method::POST)
+        };
+
+        endpoint("/login")
+            .form_body(HashMap::from([(
+                "user".to_string(),
+                "ada lovelace".to_string(),
+            )]))
+            .send()
+            .await
+            .unwrap();
+
+        // Larger than a read, so the file goes out in several chunks:
+        let csv = "id,name\n".repeat(10_000);
+        temp_dir.write("report.csv", &csv).unwrap();
+        endpoint("/upload")
+            .multipart(vec![
+                MultipartPart::text("title", "Q3"),
+                MultipartPart::bytes("meta", b"{}".to_vec()).mime("application/json"),
+                MultipartPart::file("report", temp_dir.path().join("report.csv")).mime("text/csv"),
+            ])
+            .send()
+            .await
+            .unwrap();
+        mock_http.verify();
+
+        let requests = mock_http.requests();
+        assert_eq!(
+            requests[0].headers["content-type"],
+            "application/x-www-form-urlencoded"
+        );
+        assert_eq!(requests[0].body, "user=ada+lovelace");
+
+        let upload = &requests[1];
+        let boundary = upload.headers["content-type"]
+            .strip_prefix("multipart/form-data; boundary=")
+            .unwrap();
+        // The file's length is known up front, so the body isn't chunked:
+        assert_eq!(
+            upload.headers["content-length"],
+            upload.body.len().to_string()
+        );
+        let parts = upload
+            .body
+            .split(&format!("--{}", boundary))
+            .filter_map(|part| part.strip_prefix("\r\n"))
+            .map(|part| {
+                let (headers, body) = part.split_once("\r\n\r\n").unwrap();
+                (headers.to_lowercase(), body.strip_suffix("\r\n").unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(parts.len(), 3);
+        assert!(parts[0].0.contains("name=\"title\""));
+        assert_eq!(parts[0].1, "Q3");
+        assert!(parts[1].0.contains("content-type: application/json"));
+        assert_eq!(parts[1].1, "{}");
+        assert!(parts[2]
+            .0
+            .contains("name=\"report\"; filename=\"report.csv\""));
+        assert!(parts[2].0.contains("content-type: text/csv"));
+        assert_eq!(parts[2].1, csv);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn connect_timeout_keeps_client_settings(mock_http: MockHttp) {
+        mock_http.expect(Method::GET, "/items").respond(200, "{}");
+        let client = ApiClient::builder()
+            .base_url("http://upstream.invalid")
+            .proxy(&mock_http.url())
+            .cookies()
+            .build()
+            .unwrap();
+        let url = "http://upstream.invalid/".parse().unwrap();
+        client.cookie_jar().unwrap().add("session=abc", &url);
+        client
+            .endpoint("/items")
+            .method(Method::GET)
+            .connect_timeout(Duration::from_secs(5))
+            .send()
+            .await
+            .unwrap();
+        let request = &mock_http.requests()[0];
+        assert_eq!(request.headers["host"], "upstream.invalid");
+        assert_eq!(request.headers["cookie"], "session=abc");
+
+        assert!(Endpoint::builder()
+            .base_url("http://upstream.invalid")
+            .endpoint("/items")
+            .method(Method::GET)
+            .client(Client::new())
+            .connect_timeout(Duration::from_secs(5))
+            .build()
+            .is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn circuit_breaker_fails_fast() {
+        let base_url = canned_server(503, r#"{"error": "down"}"#).await;
+        let client = ApiClient::builder()
+            .base_url(&base_url)
+            .circuit_breaker(CircuitBreakerPolicy::new().min_requests(2).window(2))
+            .build()
+            .unwrap();
+
+        for _ in 0..2 {
+            let report = client
+                .endpoint("/")
+                .method(Method::GET)
+                .send()
+                .await
+                .unwrap_err();
+            assert!(HttpError::from_report(&report).is_some());
+        }
+        let report = client
+            .endpoint("/")
+            .method(Method::GET)
+            .send()
+            .await
+            .unwrap_err();
+        assert!(report.contains::<CircuitOpenError>());
+    }
+
+    #[rstest]
+    #[case(r#"{"data": {"user": {"name": "ada"}}}"#, Some("ada"))]
+    #[case(r#"{"data": null, "errors": [{"message": "Unauthorized"}]}"#, None)]
+    #[tokio::test]
+    async fn graphql_data_or_errors(#[case] body: &'static str, #[case] expected: Option<&str>) {
+        #[derive(serde::Deserialize)]
+        struct User {
+            name: String,
+        }
+        #[derive(serde::Deserialize)]
+        struct Data {
+            user: User,
+        }
+
+        let base_url = canned_server(200, body).await;
+        let client = ApiClient::builder().base_url(&base_url).build().unwrap();
+        let result = client
+            .graphql("/graphql")
+            .query("query($id: ID!) { user(id: $id) { name } }")
+            .variables(serde_json::json!({"id": 1}))
+            .send::<Data>()
+            .await;
+
+        match expected {
+            Some(name) => assert_eq!(result.unwrap().user.name, name),
+            None => {
+                let report = result.err().unwrap();
+                let errors = report.downcast_ref::<GraphQLErrors>().unwrap();
+                assert_eq!(errors.errors[0].message, "Unauthorized");
+            }
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn send_all_keeps_order_and_isolates_errors() {
+        let ok = canned_server(200, r#"{"ok": true}"#).await;
+        let failing = canned_server(404, r#"{"error": "missing"}"#).await;
+        let client = ApiClient::builder().base_url(&ok).build().unwrap();
+
+        let endpoints = vec![
+            client.endpoint("/1").method(Method::GET),
+            client.endpoint("/2").method(Method::GET).base_url(&failing),
+            client.endpoint("/3").method(Method::GET),
+        ];
+        let batch = client.send_all(endpoints, 2).await;
+
+        assert_eq!(
+            (batch.stats.total, batch.stats.succeeded, batch.stats.failed),
+            (3, 2, 1)
+        );
+        assert!(batch.results[0].is_ok());
+        assert!(HttpError::from_report(batch.results[1].as_ref().unwrap_err()).is_some());
+        assert_eq!(batch.results[2].as_ref().unwrap()["ok"], true);
+
+        let report = batch.into_result().unwrap_err();
+        assert!(HttpError::from_report(&report).is_some());
+        assert!(format!("{:?}", report).contains("1/3 batch requests failed"));
+    }
+}
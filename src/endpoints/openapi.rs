@@ -0,0 +1,524 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::prelude::*;
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+/// Keywords that can't be raw identifiers, suffixed with `_` instead.
+const PATH_KEYWORDS: &[&str] = &["crate", "self", "super", "Self"];
+
+const METHODS: &[&str] = &[
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Generates Rust source from an OpenAPI 3 spec in JSON: a struct or enum per schema in
+/// `components/schemas` and a function per operation returning a ready [`super::EndpointBuilder`].
+///
+/// Each function takes the [`super::ApiClient`], the path params, the required query params and
+/// the JSON request body if any. The success response type is exported as `{Operation}Response`
+/// to use with `send_typed`. Convert YAML specs to JSON first.
+pub fn generate_client(spec: &str) -> RResult<String, AnyErr2> {
+    let spec: Value =
+        serde_json::from_str(spec).change_context(err2!("Failed to parse OpenAPI spec as JSON"))?;
+    Generator::new(&spec).generate()
+}
+
+/// Like [`generate_client`] reading and writing files, e.g. from a build script into `OUT_DIR`
+/// to be used with `include!(concat!(env!("OUT_DIR"), "/api.rs"))`.
+pub fn generate_client_file(
+    spec_path: impl AsRef<Path>,
+    out_path: impl AsRef<Path>,
+) -> RResult<(), AnyErr2> {
+    let spec_path = spec_path.as_ref();
+    let spec = std::fs::read_to_string(spec_path).change_context(err2!(format!(
+        "Failed to read OpenAPI spec {}",
+        spec_path.display()
+    )))?;
+    let code = generate_client(&spec)
+        .attach_printable_lazy(|| format!("Spec: {}", spec_path.display()))?;
+    std::fs::write(out_path.as_ref(), code).change_context(err2!(format!(
+        "Failed to write generated client {}",
+        out_path.as_ref().display()
+    )))
+}
+
+fn words(name: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut current = String::new();
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            prev_lower = false;
+            continue;
+        }
+        if c.is_uppercase() && prev_lower && !current.is_empty() {
+            words.push(std::mem::take(&mut current));
+        }
+        prev_lower = c.is_lowercase() || c.is_ascii_digit();
+        current.extend(c.to_lowercase());
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = words(name).join("_");
+    if snake.is_empty() || snake.starts_with(|c: char| c.is_ascii_digit()) {
+        snake.insert(0, '_');
+    }
+    if KEYWORDS.contains(&snake.as_str()) {
+        snake.insert_str(0, "r#");
+    } else if PATH_KEYWORDS.contains(&snake.as_str()) {
+        snake.push('_');
+    }
+    snake
+}
+
+fn pascal_case(name: &str) -> String {
+    let mut pascal: String = words(name)
+        .iter()
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    if pascal.is_empty() || pascal.starts_with(|c: char| c.is_ascii_digit()) {
+        pascal.insert(0, '_');
+    }
+    if PATH_KEYWORDS.contains(&pascal.as_str()) {
+        pascal.push('_');
+    }
+    pascal
+}
+
+fn doc_comment(out: &mut String, indent: &str, text: Option<&str>) {
+    if let Some(text) = text.filter(|text| !text.trim().is_empty()) {
+        for line in text.trim().lines() {
+            let _ = writeln!(out, "{}/// {}", indent, line.trim_end());
+        }
+    }
+}
+
+struct Generator<'a> {
+    spec: &'a Value,
+}
+
+impl<'a> Generator<'a> {
+    fn new(spec: &'a Value) -> Self {
+        Generator { spec }
+    }
+
+    /// The Rust type for a schema, `Value` for anything not expressible simply.
+    fn rust_type(&self, schema: &Value) -> String {
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            return match reference.strip_prefix("#/components/schemas/") {
+                Some(name) => pascal_case(name),
+                None => "serde_json::Value".to_string(),
+            };
+        }
+        let ty = match schema.get("type").and_then(Value::as_str) {
+            Some("string") => "String".to_string(),
+            Some("integer") => match schema.get("format").and_then(Value::as_str) {
+                Some("int32") => "i32".to_string(),
+                _ => "i64".to_string(),
+            },
+            Some("number") => match schema.get("format").and_then(Value::as_str) {
+                Some("float") => "f32".to_string(),
+                _ => "f64".to_string(),
+            },
+            Some("boolean") => "bool".to_string(),
+            Some("array") => format!(
+                "Vec<{}>",
+                self.rust_type(schema.get("items").unwrap_or(&Value::Null))
+            ),
+            Some("object") | None => match schema.get("additionalProperties") {
+                Some(inner @ Value::Object(_)) if schema.get("properties").is_none() => {
+                    format!(
+                        "std::collections::HashMap<String, {}>",
+                        self.rust_type(inner)
+                    )
+                }
+                _ => "serde_json::Value".to_string(),
+            },
+            Some(_) => "serde_json::Value".to_string(),
+        };
+        if schema.get("nullable").and_then(Value::as_bool) == Some(true) {
+            format!("Option<{}>", ty)
+        } else {
+            ty
+        }
+    }
+
+    fn schema_item(&self, out: &mut String, name: &str, schema: &Value) {
+        let type_name = pascal_case(name);
+        doc_comment(out, "", schema.get("description").and_then(Value::as_str));
+
+        if let Some(variants) = schema.get("enum").and_then(Value::as_array) {
+            let variants: Vec<&str> = variants.iter().filter_map(Value::as_str).collect();
+            if !variants.is_empty() {
+                let _ = writeln!(
+                    out,
+                    "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]"
+                );
+                let _ = writeln!(out, "pub enum {} {{", type_name);
+                for variant in variants {
+                    let _ = writeln!(out, "    #[serde(rename = {:?})]", variant);
+                    let _ = writeln!(out, "    {},", pascal_case(variant));
+                }
+                let _ = writeln!(out, "}}\n");
+                return;
+            }
+        }
+
+        let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+            let _ = writeln!(
+                out,
+                "pub type {} = {};\n",
+                type_name,
+                self.rust_type(schema)
+            );
+            return;
+        };
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|required| required.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        let _ = writeln!(
+            out,
+            "#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]"
+        );
+        let _ = writeln!(out, "pub struct {} {{", type_name);
+        for (property, property_schema) in properties {
+            doc_comment(
+                out,
+                "    ",
+                property_schema.get("description").and_then(Value::as_str),
+            );
+            let field = snake_case(property);
+            if field.trim_start_matches("r#") != property {
+                let _ = writeln!(out, "    #[serde(rename = {:?})]", property);
+            }
+            let ty = self.rust_type(property_schema);
+            if required.contains(&property.as_str()) {
+                let _ = writeln!(out, "    pub {}: {},", field, ty);
+            } else {
+                let _ = writeln!(
+                    out,
+                    "    #[serde(default, skip_serializing_if = \"Option::is_none\")]"
+                );
+                let ty = if ty.starts_with("Option<") {
+                    ty
+                } else {
+                    format!("Option<{}>", ty)
+                };
+                let _ = writeln!(out, "    pub {}: {},", field, ty);
+            }
+        }
+        let _ = writeln!(out, "}}\n");
+    }
+
+    /// Resolves `{"$ref": "#/components/parameters/..."}` parameters.
+    fn resolve<'b>(&'b self, value: &'b Value) -> &'b Value {
+        value
+            .get("$ref")
+            .and_then(Value::as_str)
+            .and_then(|reference| reference.strip_prefix('#'))
+            .and_then(|pointer| self.spec.pointer(pointer))
+            .unwrap_or(value)
+    }
+
+    fn json_schema<'b>(&self, content: Option<&'b Value>) -> Option<&'b Value> {
+        let content = content?.as_object()?;
+        content
+            .iter()
+            .find(|(media_type, _)| media_type.contains("json"))
+            .and_then(|(_, media)| media.get("schema"))
+    }
+
+    fn operation(
+        &self,
+        out: &mut String,
+        path: &str,
+        method: &str,
+        operation: &Value,
+        shared_parameters: &[Value],
+    ) -> RResult<(), AnyErr2> {
+        let name = match operation.get("operationId").and_then(Value::as_str) {
+            Some(operation_id) => snake_case(operation_id),
+            None => snake_case(&format!("{} {}", method, path.replace(['{', '}'], ""))),
+        };
+
+        let mut parameters: Vec<Value> = shared_parameters.to_vec();
+        if let Some(own) = operation.get("parameters").and_then(Value::as_array) {
+            parameters.extend(own.iter().map(|p| self.resolve(p).clone()));
+        }
+        let mut path_params = vec![];
+        let mut query_params = vec![];
+        for parameter in &parameters {
+            let Some(param_name) = parameter.get("name").and_then(Value::as_str) else {
+                return Err(Report::new(err2!("OpenAPI parameter without a name"))
+                    .attach_printable(format!("{} {}", method.to_uppercase(), path)));
+            };
+            let required = parameter.get("required").and_then(Value::as_bool) == Some(true);
+            match parameter.get("in").and_then(Value::as_str) {
+                Some("path") => path_params.push(param_name.to_string()),
+                Some("query") if required => query_params.push(param_name.to_string()),
+                _ => {}
+            }
+        }
+
+        let body_type = self
+            .json_schema(
+                self.resolve(operation.get("requestBody").unwrap_or(&Value::Null))
+                    .get("content"),
+            )
+            .map(|schema| self.rust_type(schema));
+        let response_type = operation
+            .get("responses")
+            .and_then(Value::as_object)
+            .and_then(|responses| {
+                responses
+                    .iter()
+                    .find(|(status, _)| status.starts_with('2'))
+                    .map(|(_, response)| response)
+            })
+            .and_then(|response| self.json_schema(self.resolve(response).get("content")))
+            .map(|schema| self.rust_type(schema));
+
+        let _ = writeln!(
+            out,
+            "pub type {}Response = {};\n",
+            pascal_case(&name),
+            response_type.as_deref().unwrap_or("serde_json::Value")
+        );
+        doc_comment(out, "", operation.get("summary").and_then(Value::as_str));
+        let _ = writeln!(out, "/// `{} {}`", method.to_uppercase(), path);
+
+        let mut args = vec!["client: &utils::endpoints::ApiClient".to_string()];
+        for param in path_params.iter().chain(query_params.iter()) {
+            args.push(format!("{}: impl std::fmt::Display", snake_case(param)));
+        }
+        if let Some(body_type) = &body_type {
+            args.push(format!("body: &{}", body_type));
+        }
+        let _ = writeln!(
+            out,
+            "pub fn {}({}) -> utils::endpoints::EndpointBuilder {{",
+            name,
+            args.join(", ")
+        );
+        let _ = writeln!(
+            out,
+            "    let builder = client\n        .endpoint({:?})\n        .method(utils::endpoints::Method::{});",
+            path,
+            method.to_uppercase()
+        );
+        if !path_params.is_empty() {
+            let _ = writeln!(
+                out,
+                "    let builder = builder.path_params(std::collections::HashMap::from(["
+            );
+            for param in &path_params {
+                let _ = writeln!(
+                    out,
+                    "        ({:?}.to_string(), {}.to_string()),",
+                    param,
+                    snake_case(param)
+                );
+            }
+            let _ = writeln!(out, "    ]));");
+        }
+        for param in &query_params {
+            let _ = writeln!(
+                out,
+                "    let builder = builder.query_param({:?}, &{}.to_string());",
+                param,
+                snake_case(param)
+            );
+        }
+        if body_type.is_some() {
+            let _ = writeln!(
+                out,
+                "    let builder = builder.json_body(serde_json::json!(body));"
+            );
+        }
+        let _ = writeln!(out, "    builder\n}}\n");
+        Ok(())
+    }
+
+    fn generate(&self) -> RResult<String, AnyErr2> {
+        let mut out = String::from("// Generated from an OpenAPI spec by utils::endpoints::generate_client, do not edit.\n\n");
+
+        let schemas = self
+            .spec
+            .pointer("/components/schemas")
+            .and_then(Value::as_object)
+            .cloned()
+            .unwrap_or_default();
+        for (name, schema) in &schemas {
+            self.schema_item(&mut out, name, schema);
+        }
+
+        let paths: BTreeMap<&String, &Value> = self
+            .spec
+            .get("paths")
+            .and_then(Value::as_object)
+            .ok_or_else(|| Report::new(err2!("OpenAPI spec has no paths")))?
+            .iter()
+            .collect();
+        for (path, item) in paths {
+            let empty = Map::new();
+            let item = item.as_object().unwrap_or(&empty);
+            let shared: Vec<Value> = item
+                .get("parameters")
+                .and_then(Value::as_array)
+                .map(|params| params.iter().map(|p| self.resolve(p).clone()).collect())
+                .unwrap_or_default();
+            for method in METHODS {
+                if let Some(operation) = item.get(*method) {
+                    self.operation(&mut out, path, method, operation, &shared)?;
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    const SPEC: &str = r##"{
+        "openapi": "3.0.0",
+        "paths": {
+            "/pets/{petId}": {
+                "parameters": [{"name": "petId", "in": "path", "required": true, "schema": {"type": "string"}}],
+                "get": {
+                    "operationId": "getPet",
+                    "summary": "Fetch a pet",
+                    "parameters": [{"name": "verbose", "in": "query", "required": true, "schema": {"type": "boolean"}}],
+                    "responses": {"200": {"content": {"application/json": {"schema": {"$ref": "#/components/schemas/Pet"}}}}}
+                }
+            },
+            "/pets": {
+                "post": {
+                    "requestBody": {"content": {"application/json": {"schema": {"$ref": "#/components/schemas/Pet"}}}},
+                    "responses": {"201": {"description": "created"}}
+                }
+            }
+        },
+        "components": {
+            "schemas": {
+                "Pet": {
+                    "required": ["id"],
+                    "properties": {
+                        "id": {"type": "integer", "format": "int32"},
+                        "type": {"$ref": "#/components/schemas/PetKind"},
+                        "tagNames": {"type": "array", "items": {"type": "string"}}
+                    }
+                },
+                "PetKind": {"type": "string", "enum": ["cat", "dog"]}
+            }
+        }
+    }"##;
+
+    #[rstest]
+    #[case("getPet", "get_pet", "GetPet")]
+    #[case("HTTPServer", "httpserver", "Httpserver")]
+    #[case("tag-names", "tag_names", "TagNames")]
+    #[case("type", "r#type", "Type")]
+    #[case("self", "self_", "Self_")]
+    #[case("crate", "crate_", "Crate")]
+    #[case("super", "super_", "Super")]
+    #[case("yield", "r#yield", "Yield")]
+    fn cases(#[case] name: &str, #[case] snake: &str, #[case] pascal: &str) {
+        assert_eq!(snake_case(name), snake);
+        assert_eq!(pascal_case(name), pascal);
+    }
+
+    #[rstest]
+    fn generates_types_and_endpoints() {
+        let code = generate_client(SPEC).unwrap();
+        for expected in [
+            "pub struct Pet {",
+            "    pub id: i32,",
+            "    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n    pub r#type: Option<PetKind>,",
+            "    #[serde(rename = \"tagNames\")]",
+            "    pub tag_names: Option<Vec<String>>,",
+            "pub enum PetKind {\n    #[serde(rename = \"cat\")]\n    Cat,",
+            "pub type GetPetResponse = Pet;",
+            "/// Fetch a pet\n/// `GET /pets/{petId}`\npub fn get_pet(client: &utils::endpoints::ApiClient, pet_id: impl std::fmt::Display, verbose: impl std::fmt::Display) -> utils::endpoints::EndpointBuilder {",
+            "(\"petId\".to_string(), pet_id.to_string()),",
+            "builder.query_param(\"verbose\", &verbose.to_string());",
+            "pub fn post_pets(client: &utils::endpoints::ApiClient, body: &Pet)",
+            "pub type PostPetsResponse = serde_json::Value;",
+            ".json_body(serde_json::json!(body))",
+        ] {
+            assert!(code.contains(expected), "missing {:?} in:\n{}", expected, code);
+        }
+    }
+
+    #[rstest]
+    #[case(SPEC)]
+    #[case(r##"{
+        "openapi": "3.0.0",
+        "paths": {
+            "/self/{super}": {
+                "get": {
+                    "operationId": "self",
+                    "parameters": [
+                        {"name": "super", "in": "path", "required": true, "schema": {"type": "string"}},
+                        {"name": "crate", "in": "query", "required": true, "schema": {"type": "string"}}
+                    ],
+                    "responses": {"200": {"content": {"application/json": {"schema": {"$ref": "#/components/schemas/self"}}}}}
+                }
+            }
+        },
+        "components": {
+            "schemas": {
+                "self": {
+                    "required": ["self", "crate"],
+                    "properties": {
+                        "self": {"type": "string"},
+                        "crate": {"$ref": "#/components/schemas/Super"},
+                        "type": {"type": "integer"},
+                        "yield": {"type": "boolean"}
+                    }
+                },
+                "Super": {"type": "string", "enum": ["self", "crate"]}
+            }
+        }
+    }"##)]
+    fn generates_valid_rust(#[case] spec: &str) {
+        let code = generate_client(spec).unwrap();
+        if let Err(e) = syn::parse_file(&code) {
+            panic!("{} in:\n{}", e, code);
+        }
+    }
+
+    #[rstest]
+    fn rejects_invalid_spec() {
+        assert!(generate_client("not json").is_err());
+        assert!(generate_client("{}").is_err());
+    }
+}
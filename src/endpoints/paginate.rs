@@ -0,0 +1,313 @@
+use futures::stream::{self, BoxStream};
+use futures::StreamExt;
+use reqwest::header::{HeaderMap, LINK};
+use reqwest::Url;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use super::{trace, Auth, Endpoint};
+use crate::prelude::*;
+
+/// How [`Endpoint::paginate`] finds the next page.
+#[derive(Debug, Clone)]
+pub enum Pagination {
+    /// Follow the `rel="next"` url of the `Link` header until there isn't one. Errors on links
+    /// to another origin, which would otherwise get the endpoint's auth.
+    LinkHeader,
+    /// Read the next cursor from the body at a JSON pointer (e.g. `/meta/next_cursor`)
+    /// and send it as the query parameter `param`, until the cursor is missing, null or empty.
+    Cursor {
+        cursor_pointer: String,
+        param: String,
+    },
+    /// Advance the query parameter `param` by the number of items at `items_pointer`,
+    /// until a page has no items.
+    Offset {
+        param: String,
+        items_pointer: String,
+    },
+}
+
+/// The url in `Link: <https://api/items?page=2>; rel="next", <...>; rel="last"`.
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|link| {
+            let mut parts = link.split(';');
+            let url = parts.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+            parts
+                .any(|param| {
+                    let param = param.trim().replace(' ', "");
+                    param == "rel=\"next\"" || param == "rel=next"
+                })
+                .then(|| url.to_string())
+        })
+}
+
+/// The items of a page, the whole body when `pointer` is empty.
+fn items_at(body: &Value, pointer: &str) -> Vec<Value> {
+    match body.pointer(pointer) {
+        Some(Value::Array(items)) => items.clone(),
+        _ => vec![],
+    }
+}
+
+impl Endpoint {
+    fn set_query_param(&mut self, key: &str, value: String) {
+        self.query_params
+            .get_or_insert_with(HashMap::new)
+            .insert(key.to_string(), value);
+    }
+
+    /// The endpoint for the page after the one just received, `None` once exhausted.
+    fn next_page(
+        &self,
+        pagination: &Pagination,
+        url: &Url,
+        headers: &HeaderMap,
+        body: &Value,
+    ) -> RResult<Option<Endpoint>, AnyErr2> {
+        let mut next = self.clone();
+        match pagination {
+            Pagination::LinkHeader => {
+                let Some(link) = next_link(headers) else {
+                    return Ok(None);
+                };
+                let link = url
+                    .join(&link)
+                    .change_context(err2!(format!("Invalid next link: {}", link)))?;
+
+                if link.origin() != url.origin() {
+                    return Err(
+                        Report::new(err2!("Next link leaves the origin of the endpoint"))
+                            .attach_printable(format!("Next link: {}", trace::redact_query(&link))),
+                    );
+                }
+
+                // The link is already complete, the original path and query are replaced. Only
+                // a query api key is left out, as the auth adds it again:
+                let query_key = self.auth.as_ref().and_then(Auth::query_key);
+                next.base_url = link.origin().ascii_serialization();
+                next.endpoint = link.path().to_string();
+                next.path_params = None;
+                next.query_params = Some(
+                    link.query_pairs()
+                        .into_owned()
+                        .filter(|(key, _)| Some(key.as_str()) != query_key)
+                        .collect(),
+                );
+            }
+            Pagination::Cursor {
+                cursor_pointer,
+                param,
+            } => {
+                let cursor = match body.pointer(cursor_pointer) {
+                    Some(Value::String(cursor)) if !cursor.is_empty() => cursor.clone(),
+                    Some(Value::Number(cursor)) => cursor.to_string(),
+                    _ => return Ok(None),
+                };
+                next.set_query_param(param, cursor);
+            }
+            Pagination::Offset {
+                param,
+                items_pointer,
+            } => {
+                let count = items_at(body, items_pointer).len() as u64;
+                if count == 0 {
+                    return Ok(None);
+                }
+                let offset = self
+                    .query_params
+                    .as_ref()
+                    .and_then(|params| params.get(param))
+                    .and_then(|offset| offset.parse::<u64>().ok())
+                    .unwrap_or(0);
+                next.set_query_param(param, (offset + count).to_string());
+            }
+        }
+        Ok(Some(next))
+    }
+
+    /// Fetches pages one after the other, yielding each page's JSON body until exhausted or an error.
+    pub fn paginate(self, pagination: Pagination) -> BoxStream<'static, RResult<Value, AnyErr2>> {
+        stream::unfold(Some(self), move |endpoint| {
+            let pagination = pagination.clone();
+            async move {
+                let endpoint = endpoint?;
+                let current = endpoint.clone();
+                let page = async {
+                    let response = endpoint.execute_ok().await?;
+                    let url = response.url().clone();
+                    let headers = response.headers().clone();
+                    let body = response
+                        .json::<Value>()
+                        .await
+                        .change_context(err2!("Failed to parse JSON page"))?;
+                    let next = current.next_page(&pagination, &url, &headers, &body)?;
+                    Ok::<_, Report<AnyErr2>>((body, next))
+                };
+                match page.await {
+                    Ok((body, next)) => Some((Ok(body), next)),
+                    Err(report) => Some((Err(report), None)),
+                }
+            }
+        })
+        .boxed()
+    }
+
+    /// Like [`Self::paginate`] but yields the individual items at `items_pointer` of every page.
+    ///
+    /// An empty pointer means each page's body is itself the array of items.
+    pub fn paginate_items(
+        self,
+        pagination: Pagination,
+        items_pointer: &str,
+    ) -> BoxStream<'static, RResult<Value, AnyErr2>> {
+        let items_pointer = items_pointer.to_string();
+        self.paginate(pagination)
+            .flat_map(move |page| {
+                let items: Vec<RResult<Value, AnyErr2>> = match page {
+                    Ok(body) => items_at(&body, &items_pointer)
+                        .into_iter()
+                        .map(Ok)
+                        .collect(),
+                    Err(report) => vec![Err(report)],
+                };
+                stream::iter(items)
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::{ApiKeyLocation, Method};
+    use rstest::*;
+    use serde_json::json;
+
+    fn endpoint() -> Endpoint {
+        Endpoint::builder()
+            .base_url("https://api.example.com")
+            .endpoint("/items")
+            .method(Method::GET)
+            .build()
+            .unwrap()
+    }
+
+    fn url() -> Url {
+        Url::parse("https://api.example.com/items").unwrap()
+    }
+
+    #[rstest]
+    fn parses_link_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            LINK,
+            r#"<https://api.example.com/items?page=1>; rel="prev", <https://api.example.com/items?page=3>; rel="next""#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            next_link(&headers).as_deref(),
+            Some("https://api.example.com/items?page=3")
+        );
+        assert_eq!(next_link(&HeaderMap::new()), None);
+    }
+
+    #[rstest]
+    fn follows_relative_link() {
+        let mut headers = HeaderMap::new();
+        headers.insert(LINK, r#"</items?page=2>; rel="next""#.parse().unwrap());
+        let next = endpoint()
+            .next_page(&Pagination::LinkHeader, &url(), &headers, &json!([]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(next.base_url, "https://api.example.com");
+        assert_eq!(next.endpoint, "/items");
+        assert_eq!(next.query_params.unwrap()["page"], "2");
+    }
+
+    #[rstest]
+    fn link_keeps_auth_on_origin() {
+        let mut endpoint = endpoint();
+        endpoint.auth = Some(Auth::ApiKey {
+            name: "api_key".to_string(),
+            value: "hunter2".to_string(),
+            location: ApiKeyLocation::Query,
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            LINK,
+            r#"</items?page=2&api_key=hunter2>; rel="next""#.parse().unwrap(),
+        );
+        let next = endpoint
+            .next_page(&Pagination::LinkHeader, &url(), &headers, &json!([]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            next.url().unwrap().as_str(),
+            "https://api.example.com/items?page=2&api_key=hunter2"
+        );
+
+        headers.insert(
+            LINK,
+            r#"<https://evil.example.com/items?page=2&api_key=hunter2>; rel="next""#
+                .parse()
+                .unwrap(),
+        );
+        let Err(report) = endpoint.next_page(&Pagination::LinkHeader, &url(), &headers, &json!([]))
+        else {
+            panic!("Followed a link to another origin");
+        };
+        let debug = format!("{:?}", report);
+        assert!(debug.contains("leaves the origin"), "{}", debug);
+        assert!(!debug.contains("hunter2"), "{}", debug);
+    }
+
+    #[rstest]
+    #[case(json!({"next": "abc"}), Some("abc"))]
+    #[case(json!({"next": 20}), Some("20"))]
+    #[case(json!({"next": ""}), None)]
+    #[case(json!({"next": null}), None)]
+    fn cursor(#[case] body: Value, #[case] expected: Option<&str>) {
+        let pagination = Pagination::Cursor {
+            cursor_pointer: "/next".to_string(),
+            param: "cursor".to_string(),
+        };
+        let next = endpoint()
+            .next_page(&pagination, &url(), &HeaderMap::new(), &body)
+            .unwrap();
+        assert_eq!(
+            next.map(|next| next.query_params.unwrap()["cursor"].clone())
+                .as_deref(),
+            expected
+        );
+    }
+
+    #[rstest]
+    fn offset_advances_by_item_count() {
+        let pagination = Pagination::Offset {
+            param: "offset".to_string(),
+            items_pointer: "/data".to_string(),
+        };
+        let body = json!({"data": [1, 2, 3]});
+        let second = endpoint()
+            .next_page(&pagination, &url(), &HeaderMap::new(), &body)
+            .unwrap()
+            .unwrap();
+        let third = second
+            .next_page(&pagination, &url(), &HeaderMap::new(), &body)
+            .unwrap()
+            .unwrap();
+        assert_eq!(third.query_params.as_ref().unwrap()["offset"], "6");
+
+        assert!(third
+            .next_page(&pagination, &url(), &HeaderMap::new(), &json!({"data": []}))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,184 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::Url;
+use std::collections::{HashMap, HashSet};
+
+use crate::prelude::*;
+
+/// Everything but the RFC 3986 unreserved characters, so a value can never add path segments.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Substitutes `{name}` placeholders in `template` with url-encoded values from `params`.
+///
+/// Errors if a placeholder has no value, a value has no placeholder, or a value is `.` or `..`,
+/// which urls treat as moving up the path even when percent-encoded.
+pub fn render_path(
+    template: &str,
+    params: Option<&HashMap<String, String>>,
+) -> RResult<String, AnyErr2> {
+    let empty = HashMap::new();
+    let params = params.unwrap_or(&empty);
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut used = HashSet::new();
+    let mut missing = vec![];
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let end = after.find('}').ok_or_else(|| {
+            Report::new(err2!("Unclosed '{' in endpoint path"))
+                .attach_printable(format!("Path: {}", template))
+        })?;
+
+        let name = &after[..end];
+        match params.get(name) {
+            Some(value) if value == "." || value == ".." => {
+                return Err(Report::new(err2!("Dot segment as path parameter"))
+                    .attach_printable(format!("Path: {}, {}: {:?}", template, name, value)));
+            }
+            Some(value) => {
+                rendered.extend(utf8_percent_encode(value, PATH_SEGMENT));
+                used.insert(name);
+            }
+            None => missing.push(name),
+        }
+        rest = &after[end + 1..];
+    }
+    rendered.push_str(rest);
+
+    if !missing.is_empty() {
+        return Err(Report::new(err2!("Missing path parameters"))
+            .attach_printable(format!("Path: {}, missing: {:?}", template, missing)));
+    }
+
+    let mut unused: Vec<&String> = params
+        .keys()
+        .filter(|key| !used.contains(key.as_str()))
+        .collect();
+    if !unused.is_empty() {
+        unused.sort();
+        return Err(Report::new(err2!("Unused path parameters"))
+            .attach_printable(format!("Path: {}, unused: {:?}", template, unused)));
+    }
+
+    Ok(rendered)
+}
+
+/// Appends `path` to the path of `base_url`, keeping any prefix like `/api/v1`.
+///
+/// Query params of the base url and of `path` are kept, `query_params` replace any with the same
+/// key and are added sorted by key so the same endpoint always gives the same url.
+pub fn join_url(
+    base_url: &str,
+    path: &str,
+    query_params: Option<&HashMap<String, String>>,
+) -> RResult<Url, AnyErr2> {
+    let mut url = Url::parse(base_url)
+        .change_context(err2!("Failed to parse URL"))
+        .attach_printable_lazy(|| format!("Base URL: {}", base_url))?;
+
+    let (path, path_query) = match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    };
+    if !path.is_empty() {
+        let joined = format!(
+            "{}/{}",
+            url.path().trim_end_matches('/'),
+            path.trim_start_matches('/')
+        );
+        url.set_path(&joined);
+    }
+
+    let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    if let Some(path_query) = path_query {
+        let mut parsed = url.clone();
+        parsed.set_query(Some(path_query));
+        pairs.extend(parsed.query_pairs().into_owned());
+    }
+    if let Some(params) = query_params {
+        pairs.retain(|(key, _)| !params.contains_key(key));
+        let mut params: Vec<_> = params.iter().collect();
+        params.sort();
+        pairs.extend(params.into_iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[rstest]
+    #[case("/users/{id}", &[("id", "42")], "/users/42")]
+    #[case("/users/{id}/posts/{post}", &[("id", "1"), ("post", "a b")], "/users/1/posts/a%20b")]
+    #[case("/files/{name}", &[("name", "../etc/passwd")], "/files/..%2Fetc%2Fpasswd")]
+    #[case("/static", &[], "/static")]
+    fn renders(#[case] template: &str, #[case] pairs: &[(&str, &str)], #[case] expected: &str) {
+        assert_eq!(
+            render_path(template, Some(&params(pairs))).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    fn no_params_without_placeholders() {
+        assert_eq!(render_path("/health", None).unwrap(), "/health");
+    }
+
+    #[rstest]
+    #[case("https://host", "/items", "https://host/items")]
+    #[case("https://host/", "/items", "https://host/items")]
+    #[case("https://host/api/v1", "/items", "https://host/api/v1/items")]
+    #[case("https://host/api/v1/", "/items", "https://host/api/v1/items")]
+    #[case("https://host/api/v1/", "items", "https://host/api/v1/items")]
+    #[case("https://host/api/v1", "items/", "https://host/api/v1/items/")]
+    #[case("https://host/api/v1", "", "https://host/api/v1")]
+    #[case("https://host/api", "/", "https://host/api/")]
+    fn joins_paths(#[case] base_url: &str, #[case] path: &str, #[case] expected: &str) {
+        assert_eq!(join_url(base_url, path, None).unwrap().as_str(), expected);
+    }
+
+    #[rstest]
+    fn merges_query_params() {
+        let url = join_url(
+            "https://host/api?version=2&tenant=a",
+            "/items?sort=name",
+            Some(&params(&[("tenant", "b"), ("page", "3"), ("limit", "10")])),
+        )
+        .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://host/api/items?version=2&sort=name&limit=10&page=3&tenant=b"
+        );
+    }
+
+    #[rstest]
+    #[case("/users/{id}", &[])]
+    #[case("/users", &[("id", "1")])]
+    #[case("/users/{id", &[("id", "1")])]
+    #[case("/files/{name}", &[("name", ".")])]
+    #[case("/files/{name}", &[("name", "..")])]
+    fn rejects(#[case] template: &str, #[case] pairs: &[(&str, &str)]) {
+        assert!(render_path(template, Some(&params(pairs))).is_err());
+    }
+}
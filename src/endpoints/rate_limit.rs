@@ -0,0 +1,221 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::clock::{system_clock, Clock};
+
+/// The slowest rate [`RateLimit::per_second`] allows, one request every 1000s.
+const MIN_RATE: f64 = 0.001;
+
+/// Client side limits applied separately to each host.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    requests_per_second: f64,
+    burst: u32,
+    max_in_flight: Option<usize>,
+}
+
+impl RateLimit {
+    /// Allow `requests_per_second` on average, with bursts of up to the same number of requests.
+    /// Rates below one request every 1000s, including zero, negative and NaN, are raised to it.
+    pub fn per_second(requests_per_second: f64) -> Self {
+        let requests_per_second = requests_per_second.max(MIN_RATE);
+        RateLimit {
+            requests_per_second,
+            burst: requests_per_second.ceil().max(1.0) as u32,
+            max_in_flight: None,
+        }
+    }
+
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// Maximum number of requests waiting for a response at the same time.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = Some(max_in_flight.max(1));
+        self
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    paused_until: Option<Instant>,
+}
+
+struct HostLimiter {
+    bucket: Mutex<Bucket>,
+    in_flight: Option<Arc<Semaphore>>,
+}
+
+/// Holds the in-flight slot of a request, released on drop.
+pub struct RatePermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+/// Token bucket and in-flight limiting keyed by host, shared by all endpoints of an [`super::ApiClient`].
+pub struct RateLimiter {
+    limit: RateLimit,
+    hosts: Mutex<HashMap<String, Arc<HostLimiter>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            hosts: Mutex::new(HashMap::new()),
+            clock: system_clock(),
+        }
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn host(&self, host: &str) -> Arc<HostLimiter> {
+        self.hosts
+            .lock()
+            .entry(host.to_string())
+            .or_insert_with(|| {
+                Arc::new(HostLimiter {
+                    bucket: Mutex::new(Bucket {
+                        tokens: self.limit.burst as f64,
+                        last_refill: self.clock.now(),
+                        paused_until: None,
+                    }),
+                    in_flight: self
+                        .limit
+                        .max_in_flight
+                        .map(|max| Arc::new(Semaphore::new(max))),
+                })
+            })
+            .clone()
+    }
+
+    /// Waits until a request to `host` is allowed.
+    pub async fn acquire(&self, host: &str) -> RatePermit {
+        let limiter = self.host(host);
+
+        let permit = match &limiter.in_flight {
+            Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+
+        loop {
+            let wait = {
+                let mut bucket = limiter.bucket.lock();
+                let now = self.clock.now();
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.limit.requests_per_second)
+                    .min(self.limit.burst as f64);
+                bucket.last_refill = now;
+
+                match bucket.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ if bucket.tokens >= 1.0 => {
+                        bucket.tokens -= 1.0;
+                        break;
+                    }
+                    _ => Duration::from_secs_f64(
+                        (1.0 - bucket.tokens) / self.limit.requests_per_second,
+                    ),
+                }
+            };
+            self.clock.sleep(wait).await;
+        }
+
+        RatePermit { _permit: permit }
+    }
+
+    /// Holds back all requests to `host`, e.g. after a 429 with a `Retry-After` header.
+    pub fn pause(&self, host: &str, duration: Duration) {
+        let limiter = self.host(host);
+        let mut bucket = limiter.bucket.lock();
+        let until = self.clock.now() + duration;
+        if bucket.paused_until.is_none_or(|current| current < until) {
+            bucket.paused_until = Some(until);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use rstest::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn limits_rate_per_host() {
+        let limiter = RateLimiter::new(RateLimit::per_second(20.0).burst(1));
+        let start = Instant::now();
+        for _ in 0..3 {
+            let _permit = limiter.acquire("a.example.com").await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(90));
+
+        // Other hosts have their own bucket:
+        let start = Instant::now();
+        let _permit = limiter.acquire("b.example.com").await;
+        assert!(start.elapsed() < Duration::from_millis(40));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn pause_blocks_host() {
+        let limiter = RateLimiter::new(RateLimit::per_second(1000.0));
+        limiter.pause("a.example.com", Duration::from_millis(100));
+        let start = Instant::now();
+        let _permit = limiter.acquire("a.example.com").await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn waits_on_clock() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::new(RateLimit::per_second(0.5).burst(1)).clock(clock.shared());
+        limiter.pause("a.example.com", Duration::from_secs(10));
+        for _ in 0..2 {
+            let _permit = limiter.acquire("a.example.com").await;
+        }
+        assert_eq!(
+            clock.sleeps(),
+            vec![Duration::from_secs(10), Duration::from_secs(2)]
+        );
+    }
+
+    #[rstest]
+    #[case(0.0)]
+    #[case(-5.0)]
+    #[case(f64::NAN)]
+    #[tokio::test]
+    async fn clamps_rate(#[case] rate: f64) {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::new(RateLimit::per_second(rate)).clock(clock.shared());
+        for _ in 0..2 {
+            let _permit = limiter.acquire("a.example.com").await;
+        }
+        assert_eq!(clock.sleeps(), vec![Duration::from_secs(1000)]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn limits_in_flight() {
+        let limiter = RateLimiter::new(RateLimit::per_second(1000.0).max_in_flight(1));
+        let first = limiter.acquire("a.example.com").await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), limiter.acquire("a.example.com"))
+                .await
+                .is_err()
+        );
+        drop(first);
+        let _second = limiter.acquire("a.example.com").await;
+    }
+}
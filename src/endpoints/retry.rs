@@ -0,0 +1,203 @@
+use chrono::{DateTime, Utc};
+use reqwest::{header::RETRY_AFTER, Method, Response, StatusCode};
+use std::time::Duration;
+
+use crate::errors::Backoff;
+
+/// Controls how failed requests are retried by [`super::Endpoint`].
+///
+/// By default only idempotent methods (GET, HEAD, PUT, DELETE, OPTIONS, TRACE) are retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    /// Only its delays are used, attempts are counted by the policy.
+    backoff: Backoff,
+    retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Backoff::new()
+                .initial_delay(Duration::from_millis(200))
+                .max_delay(Duration::from_secs(30)),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        RetryPolicy::default()
+    }
+
+    /// A policy that never retries, a single attempt is made.
+    pub fn none() -> Self {
+        RetryPolicy::default().max_attempts(1)
+    }
+
+    /// Total number of attempts including the first one.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.backoff = self.backoff.initial_delay(initial_backoff);
+        self
+    }
+
+    /// Also caps the server's `Retry-After`, so a server can't stall the caller for hours.
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.backoff = self.backoff.max_delay(max_backoff);
+        self
+    }
+
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.backoff = self.backoff.multiplier(multiplier);
+        self
+    }
+
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.backoff = self.backoff.jitter(jitter);
+        self
+    }
+
+    /// Also retry methods that are not idempotent, e.g. POST and PATCH.
+    pub fn retry_non_idempotent(mut self, retry_non_idempotent: bool) -> Self {
+        self.retry_non_idempotent = retry_non_idempotent;
+        self
+    }
+
+    pub fn get_max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Whether requests with this method may be retried under this policy.
+    pub fn applies_to(&self, method: &Method) -> bool {
+        self.retry_non_idempotent || is_idempotent(method)
+    }
+
+    /// The delay before the given retry, `attempt` starting at 1 for the first retry, see
+    /// [`Backoff::delay`].
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.backoff.delay(attempt)
+    }
+
+    /// The delay before the given retry, the server's `Retry-After` when it sent one. Either
+    /// way at most [`Self::max_backoff`].
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(retry_after) => retry_after.min(self.backoff.get_max_delay()),
+            None => self.backoff(attempt),
+        }
+    }
+}
+
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
+}
+
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+pub fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout()
+}
+
+/// Parses the `Retry-After` header, either as delay-seconds or as an HTTP date.
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, Utc::now())
+}
+
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn backoff_grows_and_caps() {
+        let policy = RetryPolicy::new()
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(350))
+            .jitter(false);
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+    }
+
+    #[rstest]
+    fn retry_after_is_capped() {
+        let policy = RetryPolicy::new()
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_secs(5))
+            .jitter(false);
+
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(3600))),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(2))),
+            Duration::from_secs(2)
+        );
+        assert_eq!(policy.delay(2, None), Duration::from_millis(200));
+    }
+
+    #[rstest]
+    fn jitter_stays_within_window() {
+        let policy = RetryPolicy::new().initial_backoff(Duration::from_millis(100));
+        for _ in 0..50 {
+            assert!(policy.backoff(1) <= Duration::from_millis(100));
+        }
+    }
+
+    #[rstest]
+    fn only_idempotent_by_default() {
+        let policy = RetryPolicy::new();
+        assert!(policy.applies_to(&Method::GET));
+        assert!(policy.applies_to(&Method::PUT));
+        assert!(!policy.applies_to(&Method::POST));
+        assert!(policy.retry_non_idempotent(true).applies_to(&Method::POST));
+    }
+
+    #[rstest]
+    fn retryable_statuses() {
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable_status(StatusCode::NOT_FOUND));
+    }
+
+    #[rstest]
+    #[case("120", Some(Duration::from_secs(120)))]
+    #[case("Wed, 21 Oct 2015 07:28:30 GMT", Some(Duration::from_secs(30)))]
+    #[case("Wed, 21 Oct 2015 07:27:00 GMT", Some(Duration::ZERO))]
+    #[case("soon", None)]
+    fn retry_after_parsing(#[case] value: &str, #[case] expected: Option<Duration>) {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(parse_retry_after(value, now), expected);
+    }
+}
@@ -0,0 +1,212 @@
+use bytes::Bytes;
+use futures::stream::{self, BoxStream};
+use futures::StreamExt;
+use reqwest::header::ACCEPT;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::Endpoint;
+use crate::prelude::*;
+
+/// One event from a `text/event-stream` response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The `event:` field, `None` means the default `message` type.
+    pub event: Option<String>,
+    /// All `data:` lines of the event joined with `\n`.
+    pub data: String,
+    pub id: Option<String>,
+    pub retry: Option<Duration>,
+}
+
+/// Accumulates lines until a blank line completes an event, following the WHATWG spec.
+#[derive(Default)]
+struct SseParser {
+    event: SseEvent,
+    has_data: bool,
+}
+
+impl SseParser {
+    fn feed_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            let has_data = std::mem::take(&mut self.has_data);
+            let event = std::mem::take(&mut self.event);
+            // Events without data are dropped, but the id persists:
+            return if has_data {
+                Some(event)
+            } else {
+                self.event.id = event.id;
+                None
+            };
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event.event = Some(value.to_string()),
+            "data" => {
+                if self.has_data {
+                    self.event.data.push('\n');
+                }
+                self.event.data.push_str(value);
+                self.has_data = true;
+            }
+            "id" => self.event.id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(millis) = value.parse() {
+                    self.event.retry = Some(Duration::from_millis(millis));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Splits a byte stream into lines, accepting `\n` and `\r\n` endings.
+fn into_lines(
+    bytes: BoxStream<'static, RResult<Bytes, AnyErr2>>,
+) -> BoxStream<'static, RResult<String, AnyErr2>> {
+    stream::unfold(
+        (bytes, Vec::new(), false),
+        |(mut bytes, mut buf, mut done)| async move {
+            loop {
+                if let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                    let mut line: Vec<u8> = buf.drain(..=pos).collect();
+                    line.pop();
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    let line = String::from_utf8_lossy(&line).into_owned();
+                    return Some((Ok(line), (bytes, buf, done)));
+                }
+                if done {
+                    if buf.is_empty() {
+                        return None;
+                    }
+                    let line = String::from_utf8_lossy(&buf).into_owned();
+                    return Some((Ok(line), (bytes, Vec::new(), done)));
+                }
+                match bytes.next().await {
+                    Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                    Some(Err(report)) => return Some((Err(report), (bytes, buf, true))),
+                    None => done = true,
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+impl Endpoint {
+    /// Sends the request and streams the response body as it arrives.
+    pub async fn send_stream(
+        self,
+    ) -> RResult<BoxStream<'static, RResult<Bytes, AnyErr2>>, AnyErr2> {
+        let response = self.execute_ok().await?;
+        Ok(response
+            .bytes_stream()
+            .map(|chunk| chunk.change_context(err2!("Failed to read response stream")))
+            .boxed())
+    }
+
+    /// Sends the request and streams the response body line by line, e.g. for JSON lines APIs.
+    pub async fn send_lines(
+        self,
+    ) -> RResult<BoxStream<'static, RResult<String, AnyErr2>>, AnyErr2> {
+        Ok(into_lines(self.send_stream().await?))
+    }
+
+    /// Sends the request as a Server-Sent-Events subscription, yielding each parsed event.
+    pub async fn send_sse(
+        mut self,
+    ) -> RResult<BoxStream<'static, RResult<SseEvent, AnyErr2>>, AnyErr2> {
+        self.headers
+            .get_or_insert_with(HashMap::new)
+            .entry(ACCEPT.to_string())
+            .or_insert_with(|| "text/event-stream".to_string());
+
+        let lines = self.send_lines().await?;
+        Ok(lines
+            .scan(SseParser::default(), |parser, line| {
+                let item = match line {
+                    Ok(line) => parser.feed_line(&line).map(Ok),
+                    Err(report) => Some(Err(report)),
+                };
+                futures::future::ready(Some(item))
+            })
+            .filter_map(futures::future::ready)
+            .boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn chunks(chunks: &[&'static str]) -> BoxStream<'static, RResult<Bytes, AnyErr2>> {
+        stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())))
+                .collect::<Vec<_>>(),
+        )
+        .boxed()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn lines_across_chunks() {
+        let lines: Vec<String> = into_lines(chunks(&["a\nb", "c\r\n", "\nd"]))
+            .map(|line| line.unwrap())
+            .collect()
+            .await;
+        assert_eq!(lines, vec!["a", "bc", "", "d"]);
+    }
+
+    #[rstest]
+    fn parses_sse() {
+        let mut parser = SseParser::default();
+        let input = [
+            ": keep-alive",
+            "event: delta",
+            "id: 1",
+            "data: {\"text\":",
+            "data:\"hi\"}",
+            "retry: 500",
+            "",
+            "id: 2",
+            "",
+            "data: done",
+            "",
+        ];
+        let events: Vec<SseEvent> = input
+            .iter()
+            .filter_map(|line| parser.feed_line(line))
+            .collect();
+
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: Some("delta".to_string()),
+                    data: "{\"text\":\n\"hi\"}".to_string(),
+                    id: Some("1".to_string()),
+                    retry: Some(Duration::from_millis(500)),
+                },
+                SseEvent {
+                    event: None,
+                    data: "done".to_string(),
+                    id: Some("2".to_string()),
+                    retry: None,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use opentelemetry::trace::TraceContextExt;
+use reqwest::{Response, Url};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+use tracing::field::Empty;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use super::{Auth, Endpoint};
+use crate::prelude::*;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+tokio::task_local! {
+    static CORRELATION_ID: String;
+}
+
+/// Runs `future` with `id` as the correlation id sent as `x-request-id` by every request made inside it.
+///
+/// Typically set from the incoming request's `x-request-id`, so client and server logs share the id.
+pub async fn with_correlation_id<F: Future>(id: impl Into<String>, future: F) -> F::Output {
+    CORRELATION_ID.scope(id.into(), future).await
+}
+
+/// The correlation id set by [`with_correlation_id`] for the current task.
+pub fn correlation_id() -> Option<String> {
+    CORRELATION_ID.try_with(|id| id.clone()).ok()
+}
+
+/// The url with the value of every query param replaced, since they often hold keys or tokens.
+pub(crate) fn redact_query(url: &Url) -> String {
+    redact_query_params(url, |_| true)
+}
+
+/// Like [`redact_query`] for the params `redact` picks only, so urls differing in the others
+/// stay apart, e.g. in cache keys and cassettes.
+pub(crate) fn redact_query_params(url: &Url, redact: impl Fn(&str) -> bool) -> String {
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, value)| match redact(&key) {
+            true => (key.into_owned(), "REDACTED".to_string()),
+            false => (key.into_owned(), value.into_owned()),
+        })
+        .collect();
+    if pairs.is_empty() {
+        return url.to_string();
+    }
+    let mut redacted = url.clone();
+    redacted.query_pairs_mut().clear().extend_pairs(pairs);
+    redacted.to_string()
+}
+
+/// A W3C `traceparent` for `span`, continuing its OpenTelemetry trace when there is one.
+fn traceparent(span: &Span) -> String {
+    let context = span.context();
+    let span_context = context.span().span_context().clone();
+    if span_context.is_valid() {
+        format!(
+            "00-{}-{}-{:02x}",
+            span_context.trace_id(),
+            span_context.span_id(),
+            span_context.trace_flags().to_u8()
+        )
+    } else {
+        format!(
+            "00-{:032x}-{:016x}-01",
+            rand::random::<u128>().max(1),
+            rand::random::<u64>().max(1)
+        )
+    }
+}
+
+impl Endpoint {
+    /// The span every send runs in, with the status and latency recorded once done.
+    pub(crate) fn trace_span(&self) -> Span {
+        let url = match self.url() {
+            Ok(url) => redact_query(&url),
+            Err(_) => format!("{}{}", self.base_url, self.endpoint),
+        };
+        tracing::info_span!(
+            "http.request",
+            http.method = %self.method,
+            http.url = %url,
+            http.status_code = Empty,
+            latency_ms = Empty,
+            request_id = Empty,
+        )
+    }
+
+    /// The url with the query api key redacted, other params kept.
+    pub(crate) fn redact_api_key(&self, url: &Url) -> String {
+        let key = self.auth.as_ref().and_then(Auth::query_key);
+        redact_query_params(url, |name| key == Some(name))
+    }
+
+    /// The host requests go to, for metrics.
+    pub(crate) fn host(&self) -> String {
+        self.url()
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+
+    /// Adds `x-request-id` and `traceparent` unless already set, the same for all attempts.
+    pub(crate) fn inject_trace_headers(&mut self, span: &Span) {
+        let headers = self.headers.get_or_insert_with(HashMap::new);
+        let has_header = |headers: &HashMap<String, String>, name: &str| {
+            headers.keys().any(|key| key.eq_ignore_ascii_case(name))
+        };
+
+        if !has_header(headers, REQUEST_ID_HEADER) {
+            let request_id =
+                correlation_id().unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+            headers.insert(REQUEST_ID_HEADER.to_string(), request_id);
+        }
+        if !has_header(headers, TRACEPARENT_HEADER) {
+            headers.insert(TRACEPARENT_HEADER.to_string(), traceparent(span));
+        }
+
+        if let Some((_, request_id)) = headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(REQUEST_ID_HEADER))
+        {
+            span.record("request_id", request_id.as_str());
+        }
+    }
+}
+
+pub(crate) fn record_outcome(span: &Span, result: &RResult<Response, AnyErr2>, latency: Duration) {
+    span.record("latency_ms", latency.as_millis() as u64);
+    let _entered = span.enter();
+    match result {
+        Ok(response) => {
+            span.record("http.status_code", response.status().as_u16());
+            debug!(
+                "Request finished with {} in {:?}",
+                response.status(),
+                latency
+            );
+        }
+        Err(_) => debug!("Request failed after {:?}", latency),
+    }
+}
+
+/// Counts and times the request in [`crate::metrics::global`], with the status or `error`.
+pub(crate) fn record_metrics(
+    method: &Method,
+    host: &str,
+    result: &RResult<Response, AnyErr2>,
+    latency: Duration,
+) {
+    let status = match result {
+        Ok(response) => response.status().as_u16().to_string(),
+        Err(_) => "error".to_string(),
+    };
+    crate::counter!(
+        "http_client_requests_total",
+        "method" => method,
+        "host" => host,
+        "status" => status,
+    )
+    .increment(1);
+    crate::histogram!(
+        "http_client_request_duration_seconds",
+        "method" => method,
+        "host" => host,
+    )
+    .record_duration(latency);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::Method;
+    use rstest::*;
+
+    #[rstest]
+    fn redacts_query_values() {
+        let url = Url::parse("https://api.example.com/items?api_key=secret&page=2").unwrap();
+        assert_eq!(
+            redact_query(&url),
+            "https://api.example.com/items?api_key=REDACTED&page=REDACTED"
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn injects_correlation_headers() {
+        let mut endpoint = Endpoint::builder()
+            .base_url("https://api.example.com")
+            .endpoint("/items")
+            .method(Method::GET)
+            .build()
+            .unwrap();
+        let span = endpoint.trace_span();
+        with_correlation_id("abc123", async { endpoint.inject_trace_headers(&span) }).await;
+
+        let headers = endpoint.headers.unwrap();
+        assert_eq!(headers[REQUEST_ID_HEADER], "abc123");
+        let parts: Vec<&str> = headers[TRACEPARENT_HEADER].split('-').collect();
+        assert_eq!(parts.len(), 4);
+        assert_eq!((parts[0], parts[1].len(), parts[2].len()), ("00", 32, 16));
+    }
+}
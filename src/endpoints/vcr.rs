@@ -0,0 +1,304 @@
+use parking_lot::Mutex;
+use reqwest::{Request, Response, ResponseBuilderExt, Url};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VcrMode {
+    /// Send real requests and append every interaction to the cassette file.
+    Record,
+    /// Answer requests from the cassette file, never touching the network.
+    Replay,
+}
+
+/// The parts of a request used to match it against a recording.
+///
+/// Request headers are deliberately not recorded so credentials don't end up in fixtures.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+impl RecordedRequest {
+    /// `query_key` is the param of a query api key, recorded redacted.
+    pub(crate) fn from_request(request: &Request, query_key: Option<&str>) -> Self {
+        let url = super::trace::redact_query_params(request.url(), |name| query_key == Some(name));
+        RecordedRequest {
+            method: request.method().to_string(),
+            url,
+            body: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(|bytes| String::from_utf8_lossy(bytes).into_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// The body when it is valid utf-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// The hex encoded body otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_hex: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+struct CassetteState {
+    interactions: Vec<Interaction>,
+    used: Vec<bool>,
+}
+
+/// A JSON fixture file of request/response pairs, see [`super::ApiClientBuilder::cassette`].
+pub struct Cassette {
+    path: PathBuf,
+    mode: VcrMode,
+    state: Mutex<CassetteState>,
+}
+
+impl Cassette {
+    /// Starts a new recording, overwriting `path` as interactions happen.
+    pub fn record(path: impl AsRef<Path>) -> Self {
+        Cassette {
+            path: path.as_ref().to_path_buf(),
+            mode: VcrMode::Record,
+            state: Mutex::new(CassetteState {
+                interactions: vec![],
+                used: vec![],
+            }),
+        }
+    }
+
+    /// Loads an existing recording to replay.
+    pub fn replay(path: impl AsRef<Path>) -> RResult<Self, AnyErr2> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .change_context(err2!(format!("Failed to read cassette {}", path.display())))?;
+        let interactions: Vec<Interaction> = serde_json::from_str(&contents).change_context(
+            err2!(format!("Failed to parse cassette {}", path.display())),
+        )?;
+        Ok(Cassette {
+            path: path.to_path_buf(),
+            mode: VcrMode::Replay,
+            state: Mutex::new(CassetteState {
+                used: vec![false; interactions.len()],
+                interactions,
+            }),
+        })
+    }
+
+    /// Replays if the cassette file exists, records it otherwise.
+    pub fn auto(path: impl AsRef<Path>) -> RResult<Self, AnyErr2> {
+        if path.as_ref().exists() {
+            Cassette::replay(path)
+        } else {
+            Ok(Cassette::record(path))
+        }
+    }
+
+    pub fn mode(&self) -> VcrMode {
+        self.mode
+    }
+
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.state.lock().interactions.clone()
+    }
+
+    /// The first not yet replayed recording matching the request, so repeated calls can get different responses.
+    pub(crate) fn find(
+        &self,
+        request: &Request,
+        query_key: Option<&str>,
+    ) -> RResult<Response, AnyErr2> {
+        let recorded = RecordedRequest::from_request(request, query_key);
+        let mut state = self.state.lock();
+        let state = &mut *state;
+
+        let index = state
+            .interactions
+            .iter()
+            .zip(state.used.iter())
+            .position(|(interaction, used)| !used && interaction.request == recorded)
+            .ok_or_else(|| {
+                Report::new(err2!("No recorded interaction for request")).attach_printable(format!(
+                    "{} {} in cassette {}",
+                    recorded.method,
+                    recorded.url,
+                    self.path.display()
+                ))
+            })?;
+        state.used[index] = true;
+        state.interactions[index]
+            .response
+            .to_response(request.url())
+    }
+
+    /// Reads the whole response to record it, returning an equivalent response to the caller.
+    pub(crate) async fn store(
+        &self,
+        request: RecordedRequest,
+        response: Response,
+    ) -> RResult<Response, AnyErr2> {
+        let url = response.url().clone();
+        let recorded = RecordedResponse::read(response).await?;
+        let response = recorded.to_response(&url)?;
+
+        let mut state = self.state.lock();
+        state.interactions.push(Interaction {
+            request,
+            response: recorded,
+        });
+        state.used.push(true);
+        let json = serde_json::to_string_pretty(&state.interactions)
+            .change_context(err2!("Failed to serialize cassette"))?;
+        std::fs::write(&self.path, json).change_context(err2!(format!(
+            "Failed to write cassette {}",
+            self.path.display()
+        )))?;
+
+        Ok(response)
+    }
+}
+
+impl RecordedResponse {
+    /// Reads the whole body of `response`.
+    pub(crate) async fn read(response: Response) -> RResult<Self, AnyErr2> {
+        let status = response.status().as_u16();
+        let headers: Vec<(String, String)> = response
+            .headers()
+            .iter()
+            .filter_map(|(key, value)| Some((key.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let bytes = response
+            .bytes()
+            .await
+            .change_context(err2!("Failed to read response for recording"))?;
+
+        let (body, body_hex) = match std::str::from_utf8(&bytes) {
+            Ok(text) => (Some(text.to_string()), None),
+            Err(_) => (None, Some(hex::encode(&bytes))),
+        };
+        Ok(RecordedResponse {
+            status,
+            headers,
+            body,
+            body_hex,
+        })
+    }
+
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Rebuilds an equivalent response, as if it had been received from `url`.
+    pub(crate) fn to_response(&self, url: &Url) -> RResult<Response, AnyErr2> {
+        let body = match (&self.body, &self.body_hex) {
+            (Some(body), _) => body.as_bytes().to_vec(),
+            (None, Some(body_hex)) => {
+                hex::decode(body_hex).change_context(err2!("Invalid hex body in recording"))?
+            }
+            (None, None) => vec![],
+        };
+
+        let mut builder = http::Response::builder()
+            .status(self.status)
+            .url(url.clone());
+        for (key, value) in &self.headers {
+            // The body is stored decoded, so these no longer apply:
+            if key.eq_ignore_ascii_case("content-encoding")
+                || key.eq_ignore_ascii_case("transfer-encoding")
+            {
+                continue;
+            }
+            builder = builder.header(key, value);
+        }
+        let response = builder
+            .body(body)
+            .change_context(err2!("Invalid recorded response"))?;
+        Ok(Response::from(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::{ApiClient, Method};
+    use rstest::*;
+    use serde_json::Value;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    async fn one_shot_server(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 4096];
+            let _ = conn.read(&mut buf).await;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = conn.write_all(response.as_bytes()).await;
+        });
+        format!("http://{}", addr)
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn records_then_replays() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.json");
+        let base_url = one_shot_server(r#"{"id": 1}"#).await;
+
+        let recording = ApiClient::builder()
+            .base_url(&base_url)
+            .cassette(Cassette::record(&path))
+            .build()
+            .unwrap();
+        let live = recording
+            .endpoint("/items/1")
+            .method(Method::GET)
+            .send()
+            .await
+            .unwrap();
+
+        // The server only answers once, so this can only succeed from the cassette:
+        let replaying = ApiClient::builder()
+            .base_url(&base_url)
+            .cassette(Cassette::auto(&path).unwrap())
+            .build()
+            .unwrap();
+        let replayed: Value = replaying
+            .endpoint("/items/1")
+            .method(Method::GET)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(live, replayed);
+
+        assert!(replaying
+            .endpoint("/items/2")
+            .method(Method::GET)
+            .send()
+            .await
+            .is_err());
+    }
+}
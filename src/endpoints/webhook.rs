@@ -0,0 +1,182 @@
+use hmac::{Hmac, Mac};
+use reqwest::header::HeaderMap;
+use serde::de::DeserializeOwned;
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::WebhookError;
+use crate::prelude::*;
+
+/// Verifies incoming webhooks signed with HMAC-SHA256 over `"{timestamp}.{body}"`.
+///
+/// Framework agnostic, pass the request headers and the raw body before any parsing.
+/// Failures carry a [`WebhookError`] to decide between e.g. a 400 and a 401.
+pub struct WebhookVerifier {
+    secret: Vec<u8>,
+    signature_header: String,
+    timestamp_header: String,
+    signature_prefix: String,
+    tolerance: Duration,
+}
+
+impl WebhookVerifier {
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        WebhookVerifier {
+            secret: secret.as_ref().to_vec(),
+            signature_header: "x-signature".to_string(),
+            timestamp_header: "x-timestamp".to_string(),
+            signature_prefix: String::new(),
+            tolerance: Duration::from_secs(5 * 60),
+        }
+    }
+
+    pub fn signature_header(mut self, name: &str) -> Self {
+        self.signature_header = name.to_string();
+        self
+    }
+
+    pub fn timestamp_header(mut self, name: &str) -> Self {
+        self.timestamp_header = name.to_string();
+        self
+    }
+
+    /// A prefix in front of the hex signature, e.g. `sha256=`.
+    pub fn signature_prefix(mut self, prefix: &str) -> Self {
+        self.signature_prefix = prefix.to_string();
+        self
+    }
+
+    /// How far the timestamp may be from now, limits replaying captured payloads.
+    pub fn tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    fn mac(&self, timestamp: u64, body: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        mac
+    }
+
+    /// The signature header value for `body` at `timestamp`, for sending webhooks or tests.
+    pub fn sign(&self, timestamp: u64, body: &[u8]) -> String {
+        format!(
+            "{}{}",
+            self.signature_prefix,
+            hex::encode(self.mac(timestamp, body).finalize().into_bytes())
+        )
+    }
+
+    fn header<'a>(&self, headers: &'a HeaderMap, name: &str) -> RResult<&'a str, AnyErr2> {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| {
+                Report::new(WebhookError::MissingHeader(name.to_string()))
+                    .change_context(err2!("Webhook verification failed"))
+            })
+    }
+
+    pub fn verify(&self, headers: &HeaderMap, body: &[u8]) -> RResult<(), AnyErr2> {
+        let fail =
+            |e: WebhookError| Report::new(e).change_context(err2!("Webhook verification failed"));
+
+        let timestamp: u64 = self
+            .header(headers, &self.timestamp_header)?
+            .trim()
+            .parse()
+            .map_err(|_| fail(WebhookError::InvalidTimestamp))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let age = Duration::from_secs(now.abs_diff(timestamp));
+        if age > self.tolerance {
+            return Err(fail(WebhookError::Expired { age }));
+        }
+
+        let signature = self.header(headers, &self.signature_header)?.trim();
+        let signature = signature
+            .strip_prefix(&self.signature_prefix)
+            .and_then(|signature| hex::decode(signature).ok())
+            .ok_or_else(|| fail(WebhookError::InvalidSignature))?;
+        // Constant time comparison:
+        self.mac(timestamp, body)
+            .verify_slice(&signature)
+            .map_err(|_| fail(WebhookError::InvalidSignature))
+    }
+
+    /// Verifies the payload then parses the JSON body into `T`.
+    pub fn parse<T: DeserializeOwned>(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> RResult<T, AnyErr2> {
+        self.verify(headers, body)?;
+        serde_json::from_slice(body).map_err(|e| {
+            Report::new(WebhookError::InvalidPayload)
+                .attach_printable(format!("{:?}", e))
+                .change_context(err2!(format!(
+                    "Failed to parse webhook payload as {}",
+                    std::any::type_name::<T>()
+                )))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Event {
+        id: u32,
+    }
+
+    fn headers(timestamp: u64, signature: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-timestamp", timestamp.to_string().parse().unwrap());
+        headers.insert("x-signature", signature.parse().unwrap());
+        headers
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[rstest]
+    fn accepts_valid_payload() {
+        let verifier = WebhookVerifier::new("secret").signature_prefix("sha256=");
+        let body = br#"{"id": 7}"#;
+        let signature = verifier.sign(now(), body);
+        assert!(signature.starts_with("sha256="));
+
+        let event: Event = verifier.parse(&headers(now(), &signature), body).unwrap();
+        assert_eq!(event.id, 7);
+    }
+
+    #[rstest]
+    #[case::tampered(0, br#"{"id": 8}"#, WebhookError::InvalidSignature)]
+    #[case::expired(3600, br#"{"id": 7}"#, WebhookError::Expired { age: Duration::from_secs(3600) })]
+    fn rejects(#[case] age: u64, #[case] body: &[u8], #[case] expected: WebhookError) {
+        let verifier = WebhookVerifier::new("secret");
+        let timestamp = now() - age;
+        let signature = verifier.sign(timestamp, br#"{"id": 7}"#);
+
+        let report = verifier
+            .verify(&headers(timestamp, &signature), body)
+            .unwrap_err();
+        let error = report.downcast_ref::<WebhookError>().unwrap();
+        assert_eq!(
+            std::mem::discriminant(error),
+            std::mem::discriminant(&expected)
+        );
+    }
+}
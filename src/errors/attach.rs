@@ -0,0 +1,165 @@
+use error_stack::{Context, Report};
+
+/// Where an error passed through, attached with [`AttachmentExt::located`].
+#[derive(Debug, Clone, Copy)]
+pub struct Location(pub &'static std::panic::Location<'static>);
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "at {}", self.0)
+    }
+}
+
+/// A message safe to show to end users, unlike the contexts which can leak internals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserFacingMessage(pub String);
+
+impl std::fmt::Display for UserFacingMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "User message: {}", self.0)
+    }
+}
+
+/// A hint on how to fix the error, e.g. `"run docker login first"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion(pub String);
+
+impl std::fmt::Display for Suggestion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Suggestion: {}", self.0)
+    }
+}
+
+/// The HTTP status a service should respond with for this error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpStatus(pub u16);
+
+impl std::fmt::Display for HttpStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP status: {}", self.0)
+    }
+}
+
+/// Attach the standard metadata to a report or to the error of a result.
+pub trait AttachmentExt: Sized {
+    fn user_msg(self, msg: impl Into<String>) -> Self;
+
+    fn suggest(self, suggestion: impl Into<String>) -> Self;
+
+    fn http_status(self, status: u16) -> Self;
+
+    /// Attaches the caller's source location, for errors passing through many `?`s.
+    #[track_caller]
+    fn located(self) -> Self;
+}
+
+impl<C> AttachmentExt for Report<C> {
+    fn user_msg(self, msg: impl Into<String>) -> Self {
+        self.attach_printable(UserFacingMessage(msg.into()))
+    }
+
+    fn suggest(self, suggestion: impl Into<String>) -> Self {
+        self.attach_printable(Suggestion(suggestion.into()))
+    }
+
+    fn http_status(self, status: u16) -> Self {
+        self.attach_printable(HttpStatus(status))
+    }
+
+    #[track_caller]
+    fn located(self) -> Self {
+        self.attach_printable(Location(std::panic::Location::caller()))
+    }
+}
+
+impl<T, C> AttachmentExt for Result<T, Report<C>> {
+    fn user_msg(self, msg: impl Into<String>) -> Self {
+        self.map_err(|report| report.user_msg(msg))
+    }
+
+    fn suggest(self, suggestion: impl Into<String>) -> Self {
+        self.map_err(|report| report.suggest(suggestion))
+    }
+
+    fn http_status(self, status: u16) -> Self {
+        self.map_err(|report| report.http_status(status))
+    }
+
+    #[track_caller]
+    fn located(self) -> Self {
+        let location = Location(std::panic::Location::caller());
+        self.map_err(|report| report.attach_printable(location))
+    }
+}
+
+/// The outermost [`UserFacingMessage`] attached to the report, if any.
+pub fn user_message<C>(report: &Report<C>) -> Option<&str> {
+    report
+        .frames()
+        .find_map(|frame| frame.downcast_ref::<UserFacingMessage>())
+        .map(|msg| msg.0.as_str())
+}
+
+/// All [`Suggestion`]s attached to the report, outermost first.
+pub fn suggestions<C>(report: &Report<C>) -> Vec<&str> {
+    report
+        .frames()
+        .filter_map(|frame| frame.downcast_ref::<Suggestion>())
+        .map(|suggestion| suggestion.0.as_str())
+        .collect()
+}
+
+/// The outermost [`HttpStatus`] attached to the report, if any.
+pub fn http_status<C>(report: &Report<C>) -> Option<u16> {
+    report
+        .frames()
+        .find_map(|frame| frame.downcast_ref::<HttpStatus>())
+        .map(|status| status.0)
+}
+
+/// Logs the report as an error event, with the user message and suggestions as fields so the
+/// redis `LogViewer` can show them.
+pub fn log_report<C: Context>(report: &Report<C>) {
+    let suggestions = suggestions(report).join("; ");
+    tracing::error!(
+        user_message = user_message(report).unwrap_or_default(),
+        suggestion = suggestions.as_str(),
+        "{:?}",
+        report
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{AnyErr2, RResult};
+    use error_stack::ResultExt;
+    use rstest::*;
+
+    fn login() -> RResult<(), AnyErr2> {
+        Err(Report::new(AnyErr2::new("registry returned 401")))
+            .suggest("run docker login first")
+            .http_status(401)
+    }
+
+    #[rstest]
+    fn attachments_are_found() {
+        let report = login()
+            .change_context(AnyErr2::new("failed to push image"))
+            .user_msg("Could not publish your image")
+            .suggest("check the registry url")
+            .located()
+            .unwrap_err();
+
+        assert_eq!(user_message(&report), Some("Could not publish your image"));
+        assert_eq!(
+            suggestions(&report),
+            vec!["check the registry url", "run docker login first"]
+        );
+        assert_eq!(http_status(&report), Some(401));
+
+        let debug = format!("{:?}", report);
+        assert!(debug.contains("Suggestion: run docker login first"));
+        assert!(debug.contains(&format!("at {}:", file!())));
+    }
+}
@@ -0,0 +1,143 @@
+use error_stack::Report;
+
+/// What kind of failure a report is, attached to it so retry loops can decide without knowing
+/// where the error came from.
+///
+/// The outermost attached class wins, so callers can reclassify an error from deeper down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// Transient, trying again later may succeed, e.g. a dropped connection or a 503.
+    Retryable,
+    /// Trying again will fail the same way.
+    Permanent,
+    Timeout,
+    /// Rejected for sending too much, retry after backing off.
+    RateLimited,
+    NotFound,
+    Unauthorized,
+    Forbidden,
+    InvalidInput,
+    Conflict,
+}
+
+impl ErrorClass {
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorClass::Retryable | ErrorClass::Timeout | ErrorClass::RateLimited
+        )
+    }
+
+    /// The class of a failed HTTP response status.
+    pub fn from_http_status(status: u16) -> Self {
+        match status {
+            400 | 422 => ErrorClass::InvalidInput,
+            401 => ErrorClass::Unauthorized,
+            403 => ErrorClass::Forbidden,
+            404 | 410 => ErrorClass::NotFound,
+            408 => ErrorClass::Timeout,
+            409 => ErrorClass::Conflict,
+            429 => ErrorClass::RateLimited,
+            501 | 505 => ErrorClass::Permanent,
+            500..=599 => ErrorClass::Retryable,
+            _ => ErrorClass::Permanent,
+        }
+    }
+
+    /// The status a service should respond with for an error of this class.
+    pub fn to_http_status(&self) -> u16 {
+        match self {
+            ErrorClass::Retryable => 503,
+            ErrorClass::Permanent => 500,
+            ErrorClass::Timeout => 504,
+            ErrorClass::RateLimited => 429,
+            ErrorClass::NotFound => 404,
+            ErrorClass::Unauthorized => 401,
+            ErrorClass::Forbidden => 403,
+            ErrorClass::InvalidInput => 400,
+            ErrorClass::Conflict => 409,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error class: {:?}", self)
+    }
+}
+
+/// The outermost [`ErrorClass`] attached to the report, if any.
+pub fn error_class<C>(report: &Report<C>) -> Option<ErrorClass> {
+    report
+        .frames()
+        .find_map(|frame| frame.downcast_ref::<ErrorClass>())
+        .copied()
+}
+
+/// Whether the report's [`ErrorClass`] says trying again may help, unclassified reports are not retryable.
+pub fn is_retryable<C>(report: &Report<C>) -> bool {
+    error_class(report).is_some_and(|class| class.is_retryable())
+}
+
+/// Attach an [`ErrorClass`] to a report or to the error of a result.
+pub trait ErrorClassExt: Sized {
+    fn classify(self, class: ErrorClass) -> Self;
+
+    fn retryable(self) -> Self {
+        self.classify(ErrorClass::Retryable)
+    }
+
+    fn permanent(self) -> Self {
+        self.classify(ErrorClass::Permanent)
+    }
+}
+
+impl<C> ErrorClassExt for Report<C> {
+    fn classify(self, class: ErrorClass) -> Self {
+        self.attach_printable(class)
+    }
+}
+
+impl<T, C> ErrorClassExt for Result<T, Report<C>> {
+    fn classify(self, class: ErrorClass) -> Self {
+        self.map_err(|report| report.classify(class))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{AnyErr2, RResult};
+    use error_stack::ResultExt;
+    use rstest::*;
+
+    fn failing() -> RResult<(), AnyErr2> {
+        Err(Report::new(AnyErr2::new("connection reset"))).retryable()
+    }
+
+    #[rstest]
+    fn outermost_class_wins() {
+        let report = failing().unwrap_err();
+        assert_eq!(error_class(&report), Some(ErrorClass::Retryable));
+        assert!(is_retryable(&report));
+
+        let report = failing()
+            .change_context(AnyErr2::new("giving up"))
+            .permanent()
+            .unwrap_err();
+        assert_eq!(error_class(&report), Some(ErrorClass::Permanent));
+        assert!(!is_retryable(&report));
+
+        assert!(!is_retryable(&Report::new(AnyErr2::new("unclassified"))));
+    }
+
+    #[rstest]
+    #[case(404, ErrorClass::NotFound)]
+    #[case(429, ErrorClass::RateLimited)]
+    #[case(503, ErrorClass::Retryable)]
+    #[case(501, ErrorClass::Permanent)]
+    #[case(418, ErrorClass::Permanent)]
+    fn http_status(#[case] status: u16, #[case] expected: ErrorClass) {
+        assert_eq!(ErrorClass::from_http_status(status), expected);
+    }
+}
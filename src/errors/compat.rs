@@ -0,0 +1,156 @@
+use error_stack::{IntoReportCompat, Report};
+use std::error::Error;
+
+use super::{AnyErr, RResult};
+
+type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
+/// Lets a report travel through `anyhow` and `Box<dyn Error>`, showing the full context chain
+/// and converting back to the original report.
+struct ReportError<C>(Report<C>);
+
+impl<C> std::fmt::Debug for ReportError<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl<C> std::fmt::Display for ReportError<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+impl<C> Error for ReportError<C> {}
+
+/// Converts an `anyhow::Error`, keeping its chain of causes as attachments.
+///
+/// An error that came from [`report_to_anyhow`] gives back the original report.
+pub fn anyhow_to_report(error: anyhow::Error) -> Report<AnyErr> {
+    match error.downcast::<ReportError<AnyErr>>() {
+        Ok(ReportError(report)) => report,
+        Err(error) => match Err::<(), _>(error).into_report() {
+            Err(report) => report.change_context(AnyErr),
+            Ok(()) => unreachable!(),
+        },
+    }
+}
+
+/// Converts a boxed std error, keeping its chain of sources as attachments.
+pub fn boxed_to_report(error: BoxError) -> Report<AnyErr> {
+    match error.downcast::<ReportError<AnyErr>>() {
+        Ok(report) => report.0,
+        Err(error) => {
+            let mut report = Report::new(AnyErr).attach_printable(error.to_string());
+            let mut source = error.source();
+            while let Some(cause) = source {
+                report = report.attach_printable(cause.to_string());
+                source = cause.source();
+            }
+            report
+        }
+    }
+}
+
+/// The error displays as the context chain, `{:#?}` shows the full report with attachments.
+pub fn report_to_anyhow<C>(report: Report<C>) -> anyhow::Error
+where
+    C: Send + Sync + 'static,
+{
+    anyhow::Error::new(ReportError(report))
+}
+
+pub fn report_to_boxed<C>(report: Report<C>) -> BoxError
+where
+    C: Send + Sync + 'static,
+{
+    Box::new(ReportError(report))
+}
+
+/// Turn the error of an `anyhow` or boxed std error result into a `Report<AnyErr>`.
+pub trait IntoAnyErr<T> {
+    fn into_any_err(self) -> RResult<T, AnyErr>;
+}
+
+impl<T> IntoAnyErr<T> for Result<T, anyhow::Error> {
+    fn into_any_err(self) -> RResult<T, AnyErr> {
+        self.map_err(anyhow_to_report)
+    }
+}
+
+impl<T> IntoAnyErr<T> for Result<T, BoxError> {
+    fn into_any_err(self) -> RResult<T, AnyErr> {
+        self.map_err(boxed_to_report)
+    }
+}
+
+/// Turn the report of a result into an `anyhow::Error` or a boxed std error, e.g. to return it
+/// from code built on `anyhow`.
+pub trait IntoStdError<T> {
+    fn into_anyhow(self) -> anyhow::Result<T>;
+
+    fn into_boxed(self) -> Result<T, BoxError>;
+}
+
+impl<T, C> IntoStdError<T> for RResult<T, C>
+where
+    C: Send + Sync + 'static,
+{
+    fn into_anyhow(self) -> anyhow::Result<T> {
+        self.map_err(report_to_anyhow)
+    }
+
+    fn into_boxed(self) -> Result<T, BoxError> {
+        self.map_err(report_to_boxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::AnyErr2;
+    use error_stack::ResultExt;
+    use rstest::*;
+
+    fn parse(value: &str) -> anyhow::Result<u32> {
+        use anyhow::Context;
+        value.parse::<u32>().context("Failed to parse port")
+    }
+
+    #[rstest]
+    fn anyhow_causes_are_kept() {
+        let report = parse("x").into_any_err().unwrap_err();
+        let debug = format!("{:?}", report);
+        assert!(debug.contains("Failed to parse port"));
+        assert!(debug.contains("invalid digit"));
+
+        let boxed: BoxError = "connection refused".into();
+        let report = Err::<(), _>(boxed).into_any_err().unwrap_err();
+        assert!(format!("{:?}", report).contains("connection refused"));
+    }
+
+    #[rstest]
+    fn reports_round_trip() {
+        let result: RResult<(), AnyErr2> = Err(Report::new(AnyErr2::new("inner")))
+            .change_context(AnyErr2::new("outer"))
+            .attach_printable("id: 42");
+
+        let anyhow = result.into_anyhow().unwrap_err();
+        assert_eq!(anyhow.to_string(), "outer: inner");
+        assert!(format!("{:#?}", anyhow).contains("id: 42"));
+
+        let original = Report::new(AnyErr).attach_printable("original");
+        let frames = original.frames().count();
+        let report = Err::<(), _>(original)
+            .into_anyhow()
+            .into_any_err()
+            .unwrap_err();
+        // Unwrapped rather than wrapped again:
+        assert_eq!(report.frames().count(), frames);
+        let report = Err::<(), _>(report)
+            .into_boxed()
+            .into_any_err()
+            .unwrap_err();
+        assert!(format!("{:?}", report).contains("original"));
+    }
+}
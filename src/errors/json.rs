@@ -0,0 +1,160 @@
+use error_stack::{AttachmentKind, Context, FrameKind, Report};
+use serde_json::{json, Map, Value};
+use std::backtrace::{Backtrace, BacktraceStatus};
+
+use super::attach::{
+    http_status, suggestions, user_message, HttpStatus, Suggestion, UserFacingMessage,
+};
+use super::class::{error_class, ErrorClass};
+
+/// A stable, machine-readable code for an error, e.g. `"user_not_found"`, for clients to match on.
+///
+/// Attach with `report.attach_printable(ErrorCode("user_not_found"))`, the outermost code wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ErrorCode(pub &'static str);
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error code: {}", self.0)
+    }
+}
+
+/// The outermost [`ErrorCode`] attached to the report, if any.
+pub fn error_code<C>(report: &Report<C>) -> Option<&'static str> {
+    report
+        .frames()
+        .find_map(|frame| frame.downcast_ref::<ErrorCode>())
+        .map(|code| code.0)
+}
+
+/// Serializes a report to `{code, message, contexts, backtrace?}` for error payloads and logs.
+///
+/// `message` is the outermost context, `contexts` lists every context outermost first with the
+/// printable attachments added on top of it. `backtrace` is only present when one was captured,
+/// likewise `user_message`, `suggestions`, `status` and the [`ErrorClass`] as `class`, e.g.
+/// `"NotFound"`, when those attachments are present.
+pub fn report_to_json<C: Context>(report: &Report<C>) -> Value {
+    let mut contexts = vec![];
+    let mut attachments = vec![];
+    let mut backtrace = None;
+
+    for frame in report.frames() {
+        if let Some(captured) = frame.downcast_ref::<Backtrace>() {
+            if backtrace.is_none() && captured.status() == BacktraceStatus::Captured {
+                backtrace = Some(captured.to_string());
+            }
+            continue;
+        }
+        if frame.is::<ErrorCode>()
+            || frame.is::<UserFacingMessage>()
+            || frame.is::<Suggestion>()
+            || frame.is::<HttpStatus>()
+            || frame.is::<ErrorClass>()
+        {
+            continue;
+        }
+        match frame.kind() {
+            FrameKind::Context(context) => {
+                // Frames go from the outermost in, so attachments seen so far belong to this context:
+                attachments.reverse();
+                contexts.push(json!({
+                    "message": context.to_string(),
+                    "attachments": std::mem::take(&mut attachments),
+                }));
+            }
+            FrameKind::Attachment(AttachmentKind::Printable(attachment)) => {
+                attachments.push(attachment.to_string());
+            }
+            FrameKind::Attachment(_) => {}
+        }
+    }
+
+    let mut value = Map::new();
+    value.insert("code".into(), error_code(report).into());
+    value.insert(
+        "message".into(),
+        report.current_context().to_string().into(),
+    );
+    value.insert("contexts".into(), contexts.into());
+    if let Some(user_message) = user_message(report) {
+        value.insert("user_message".into(), user_message.into());
+    }
+    let suggestions = suggestions(report);
+    if !suggestions.is_empty() {
+        value.insert("suggestions".into(), suggestions.into());
+    }
+    if let Some(status) = http_status(report) {
+        value.insert("status".into(), status.into());
+    }
+    if let Some(class) = error_class(report) {
+        value.insert("class".into(), format!("{:?}", class).into());
+    }
+    if let Some(backtrace) = backtrace {
+        value.insert("backtrace".into(), backtrace.into());
+    }
+    Value::Object(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{AnyErr2, AttachmentExt, ErrorClassExt};
+    use rstest::*;
+
+    #[rstest]
+    fn serializes_contexts_in_order() {
+        let report = Report::new(AnyErr2::new("connection refused"))
+            .attach_printable("host: db")
+            .change_context(AnyErr2::new("failed to load user"))
+            .attach_printable(ErrorCode("user_unavailable"))
+            .attach_printable("id: 42")
+            .attach_printable("tenant: a");
+
+        let value = report_to_json(&report);
+        assert_eq!(value["code"], "user_unavailable");
+        assert_eq!(value["message"], "failed to load user");
+        assert_eq!(
+            value["contexts"],
+            json!([
+                {"message": "failed to load user", "attachments": ["id: 42", "tenant: a"]},
+                {"message": "connection refused", "attachments": ["host: db"]},
+            ])
+        );
+    }
+
+    #[rstest]
+    fn surfaces_standard_attachments() {
+        let report = Report::new(AnyErr2::new("no such user"))
+            .http_status(404)
+            .user_msg("User not found")
+            .suggest("check the id");
+
+        let value = report_to_json(&report);
+        assert_eq!(value["status"], 404);
+        assert_eq!(value["user_message"], "User not found");
+        assert_eq!(value["suggestions"], json!(["check the id"]));
+        assert_eq!(value["contexts"][0]["attachments"], json!([]));
+        assert!(value.get("class").is_none());
+    }
+
+    #[rstest]
+    fn surfaces_the_outermost_class() {
+        let report = Report::new(AnyErr2::new("connection reset"))
+            .retryable()
+            .attach_printable("host: db")
+            .change_context(AnyErr2::new("no such user"))
+            .classify(ErrorClass::NotFound);
+
+        let value = report_to_json(&report);
+        assert_eq!(value["class"], "NotFound");
+        assert_eq!(value["contexts"][0]["attachments"], json!([]));
+        assert_eq!(value["contexts"][1]["attachments"], json!(["host: db"]));
+    }
+
+    #[rstest]
+    fn code_is_null_when_missing() {
+        let value = report_to_json(&Report::new(AnyErr2::new("oops")));
+        assert_eq!(value["code"], Value::Null);
+        assert_eq!(error_code(&Report::new(AnyErr2::new("oops"))), None);
+    }
+}
@@ -72,6 +72,53 @@ macro_rules! err2 {
     }};
 }
 
+/// Defines zero-sized error contexts named after what failed, instead of using `AnyErr` everywhere.
+///
+/// ```
+/// utils::define_error! {
+///     /// Loading or validating config failed.
+///     pub ConfigError,
+///     RedisError,
+/// }
+///
+/// let report = ConfigError::msg("missing key: port");
+/// assert_eq!(report.current_context().to_string(), "ConfigError");
+/// ```
+#[macro_export]
+macro_rules! define_error {
+    ($($(#[$meta:meta])* $vis:vis $name:ident),* $(,)?) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+            $vis struct $name;
+
+            impl std::fmt::Display for $name {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    write!(f, stringify!($name))
+                }
+            }
+
+            impl error_stack::Context for $name {}
+
+            #[allow(dead_code)]
+            impl $name {
+                /// A new report with this context.
+                pub fn report() -> error_stack::Report<Self> {
+                    error_stack::Report::new($name)
+                }
+
+                /// A new report with this context and a printable message attached.
+                pub fn msg<M>(msg: M) -> error_stack::Report<Self>
+                where
+                    M: std::fmt::Display + std::fmt::Debug + Send + Sync + 'static,
+                {
+                    error_stack::Report::new($name).attach_printable(msg)
+                }
+            }
+        )*
+    };
+}
+
 /// When working in a function that cannot return a result, use this to auto panic with the formatted error if something goes wrong.
 ///
 /// Allows use of e.g. `?` in the block.
@@ -103,6 +150,49 @@ macro_rules! panic_on_err_async {
     }};
 }
 
+/// Runs the block, wrapping any error escaping it in an `AnyErr2` context with the formatted message.
+///
+/// `with_context!("loading config from {}", path, { ... })` is equivalent to running the block in a
+/// closure and calling `.change_context_lazy(|| err2!(format!("loading config from {}", path)))`.
+/// The message is only formatted on error. Allows use of e.g. `?` in the block.
+#[macro_export]
+macro_rules! with_context {
+    ($fmt:literal, $($rest:tt)+) => {
+        $crate::with_context!(@args $fmt [] $($rest)+)
+    };
+
+    (@args $fmt:literal [$($args:expr),*] $content:block) => {{
+        #[allow(clippy::redundant_closure_call)]
+        let result: $crate::errors::RResult<_, _> = (|| $content)();
+        error_stack::ResultExt::change_context_lazy(result, || {
+            $crate::errors::AnyErr2::new(format!($fmt, $($args),*))
+        })
+    }};
+
+    (@args $fmt:literal [$($args:expr),*] $arg:expr, $($rest:tt)+) => {
+        $crate::with_context!(@args $fmt [$($args,)* $arg] $($rest)+)
+    };
+}
+
+/// Async version of [`with_context!`], the block can `.await`.
+#[macro_export]
+macro_rules! with_context_async {
+    ($fmt:literal, $($rest:tt)+) => {
+        $crate::with_context_async!(@args $fmt [] $($rest)+)
+    };
+
+    (@args $fmt:literal [$($args:expr),*] $content:block) => {{
+        let result: $crate::errors::RResult<_, _> = (async { $content }).await;
+        error_stack::ResultExt::change_context_lazy(result, || {
+            $crate::errors::AnyErr2::new(format!($fmt, $($args),*))
+        })
+    }};
+
+    (@args $fmt:literal [$($args:expr),*] $arg:expr, $($rest:tt)+) => {
+        $crate::with_context_async!(@args $fmt [$($args,)* $arg] $($rest)+)
+    };
+}
+
 #[cfg(test)]
 mod tests {
     use futures::FutureExt;
@@ -110,6 +200,71 @@ mod tests {
 
     use crate::prelude::*;
 
+    crate::define_error! {
+        ConfigError,
+        pub(crate) RedisError,
+    }
+
+    fn load_config() -> RResult<(), ConfigError> {
+        Err(RedisError::msg("connection refused")).change_context(ConfigError)
+    }
+
+    #[rstest]
+    fn define_error() {
+        let report = load_config().unwrap_err();
+        assert_eq!(report.current_context(), &ConfigError);
+        assert!(report.contains::<RedisError>());
+
+        let debug = format!("{:?}", report);
+        assert!(debug.contains("ConfigError"));
+        assert!(debug.contains("RedisError"));
+        assert!(debug.contains("connection refused"));
+        assert_eq!(
+            ConfigError::report().current_context().to_string(),
+            "ConfigError"
+        );
+    }
+
+    fn read_port(value: &str) -> RResult<u16, AnyErr2> {
+        value.parse::<u16>().change_context(err2!("Invalid port"))
+    }
+
+    #[rstest]
+    fn with_context() {
+        let path = "config.toml";
+        let result = with_context!("loading config from {}", path, {
+            let port = read_port("80")?;
+            Ok(port + 1)
+        });
+        assert_eq!(result.unwrap(), 81);
+
+        let report = with_context!("loading config from {} as {}", path, "toml", {
+            let port = read_port("eighty")?;
+            Ok(port)
+        })
+        .unwrap_err();
+        assert_eq!(
+            report.current_context().to_string(),
+            "loading config from config.toml as toml"
+        );
+        assert!(format!("{:?}", report).contains("Invalid port"));
+
+        let report = with_context!("no args", { read_port("") }).unwrap_err();
+        assert_eq!(report.current_context().to_string(), "no args");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn with_context_async() {
+        let report = with_context_async!("fetching {}", "/users", {
+            tokio::time::sleep(std::time::Duration::from_nanos(1)).await;
+            let port = read_port("-1")?;
+            Ok(port)
+        })
+        .unwrap_err();
+        assert_eq!(report.current_context().to_string(), "fetching /users");
+    }
+
     #[rstest]
     fn panic_on_err() {
         // Should work fine:
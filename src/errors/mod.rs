@@ -1,7 +1,28 @@
 mod any;
+mod attach;
+mod class;
+mod compat;
+mod json;
 mod macros;
+mod multi;
+mod panic;
+mod response;
+mod retry;
 
 pub use any::{AnyErr, AnyErr2};
+pub use attach::{
+    http_status, log_report, suggestions, user_message, AttachmentExt, HttpStatus, Location,
+    Suggestion, UserFacingMessage,
+};
+pub use class::{error_class, is_retryable, ErrorClass, ErrorClassExt};
+pub use compat::{
+    anyhow_to_report, boxed_to_report, report_to_anyhow, report_to_boxed, IntoAnyErr, IntoStdError,
+};
+pub use json::{error_code, report_to_json, ErrorCode};
+pub use multi::MultiReport;
+pub use panic::{catch_panic, catch_panic_async};
+pub use response::ApiError;
+pub use retry::{retry_async, retry_sync, Backoff};
 
 /// Shorthand for a [`Result`] with a [`error_stack::Report`] as the error variant
 pub type RResult<T, C> = Result<T, error_stack::Report<C>>;
@@ -12,12 +33,18 @@ pub mod prelude {
     pub use error_stack::{Report, ResultExt};
 
     #[allow(unused_imports)]
-    pub use super::{AnyErr, AnyErr2, RResult};
+    pub use super::{
+        AnyErr, AnyErr2, AttachmentExt, ErrorClass, ErrorClassExt, ErrorCode, IntoAnyErr,
+        IntoStdError, RResult,
+    };
 
     #[allow(unused_imports)]
     pub use crate::err2;
     #[allow(unused_imports)]
-    pub use crate::{anyerr, err, panic_on_err, panic_on_err_async};
+    pub use crate::{
+        anyerr, define_error, err, panic_on_err, panic_on_err_async, with_context,
+        with_context_async,
+    };
 }
 
 #[cfg(test)]
@@ -0,0 +1,144 @@
+use error_stack::{Context, Report};
+
+use super::RResult;
+
+/// Collects the errors of a batch of operations that shouldn't stop at the first failure.
+///
+/// Each child keeps its own context chain, [`Self::into_result`] joins them under one context.
+pub struct MultiReport<C> {
+    errors: Vec<Report<C>>,
+}
+
+impl<C> Default for MultiReport<C> {
+    fn default() -> Self {
+        MultiReport { errors: vec![] }
+    }
+}
+
+impl<C> MultiReport<C> {
+    pub fn new() -> Self {
+        MultiReport::default()
+    }
+
+    pub fn push(&mut self, report: Report<C>) {
+        self.errors.push(report);
+    }
+
+    /// Keeps the error of `result` if it failed, returning the value otherwise.
+    pub fn push_result<T>(&mut self, result: RResult<T, C>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(report) => {
+                self.push(report);
+                None
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn errors(&self) -> &[Report<C>] {
+        &self.errors
+    }
+
+    pub fn into_errors(self) -> Vec<Report<C>> {
+        self.errors
+    }
+
+    /// `Ok` if nothing was pushed, otherwise one report with every child as a source of `context`.
+    pub fn into_result<C2: Context>(self, context: C2) -> RResult<(), C2> {
+        let count = self.errors.len();
+        let mut errors = self.errors.into_iter();
+        let Some(mut report) = errors.next() else {
+            return Ok(());
+        };
+        for other in errors {
+            report.extend_one(other);
+        }
+        Err(report
+            .change_context(context)
+            .attach_printable(format!("{} errors", count)))
+    }
+}
+
+impl<C> FromIterator<Report<C>> for MultiReport<C> {
+    fn from_iter<I: IntoIterator<Item = Report<C>>>(iter: I) -> Self {
+        MultiReport {
+            errors: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<C> Extend<Report<C>> for MultiReport<C> {
+    fn extend<I: IntoIterator<Item = Report<C>>>(&mut self, iter: I) {
+        self.errors.extend(iter);
+    }
+}
+
+impl<C> std::fmt::Debug for MultiReport<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} errors", self.errors.len())?;
+        for (index, report) in self.errors.iter().enumerate() {
+            writeln!(
+                f,
+                "\n--- Error {}/{} ---\n{:?}",
+                index + 1,
+                self.errors.len(),
+                report
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::AnyErr2;
+    use error_stack::ResultExt;
+    use rstest::*;
+
+    fn parse(value: &str) -> RResult<u32, AnyErr2> {
+        value
+            .parse::<u32>()
+            .change_context(AnyErr2::new(format!("Invalid number: {}", value)))
+    }
+
+    #[rstest]
+    fn collects_all_failures() {
+        let mut errors = MultiReport::new();
+        let parsed: Vec<u32> = ["1", "x", "3", "y"]
+            .into_iter()
+            .filter_map(|value| errors.push_result(parse(value)))
+            .collect();
+        assert_eq!(parsed, vec![1, 3]);
+        assert_eq!(errors.len(), 2);
+
+        let grouped = format!("{:?}", errors);
+        assert!(grouped.contains("--- Error 2/2 ---"));
+
+        let report = errors
+            .into_result(AnyErr2::new("Failed to parse batch"))
+            .unwrap_err();
+        assert_eq!(
+            report.current_context().to_string(),
+            "Failed to parse batch"
+        );
+        let debug = format!("{:?}", report);
+        assert!(debug.contains("Invalid number: x"));
+        assert!(debug.contains("Invalid number: y"));
+        assert!(debug.contains("2 errors"));
+    }
+
+    #[rstest]
+    fn empty_is_ok() {
+        let errors: MultiReport<AnyErr2> = MultiReport::new();
+        assert!(errors.into_result(AnyErr2::new("unused")).is_ok());
+    }
+}
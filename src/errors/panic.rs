@@ -0,0 +1,102 @@
+use error_stack::Report;
+use futures::FutureExt;
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::RefCell;
+use std::future::Future;
+use std::panic::{AssertUnwindSafe, Location};
+use std::sync::Once;
+
+use super::{AnyErr, RResult};
+
+struct PanicDetails {
+    location: Option<String>,
+    backtrace: Backtrace,
+}
+
+thread_local! {
+    static LAST_PANIC: RefCell<Option<PanicDetails>> = const { RefCell::new(None) };
+}
+
+static HOOK: Once = Once::new();
+
+/// Wraps the current panic hook to remember where the last panic on each thread happened,
+/// the backtrace is gone by the time `catch_unwind` returns.
+fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let details = PanicDetails {
+                location: info.location().map(Location::to_string),
+                backtrace: Backtrace::capture(),
+            };
+            LAST_PANIC.with(|last| *last.borrow_mut() = Some(details));
+            previous(info);
+        }));
+    });
+}
+
+fn panic_report(payload: Box<dyn Any + Send>) -> Report<AnyErr> {
+    let message = if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    };
+
+    let mut report = Report::new(AnyErr).attach_printable(format!("Panicked: {}", message));
+    if let Some(details) = LAST_PANIC.with(|last| last.borrow_mut().take()) {
+        if let Some(location) = details.location {
+            report = report.attach_printable(format!("Panic location: {}", location));
+        }
+        if details.backtrace.status() == BacktraceStatus::Captured {
+            report = report.attach(details.backtrace);
+        }
+    }
+    report
+}
+
+/// Runs `f`, turning a panic into an error with the panic message, location and backtrace, e.g.
+/// to keep a long running worker alive when third-party code panics.
+pub fn catch_panic<T>(f: impl FnOnce() -> T) -> RResult<T, AnyErr> {
+    install_hook();
+    std::panic::catch_unwind(AssertUnwindSafe(f)).map_err(panic_report)
+}
+
+/// Async version of [`catch_panic`], catches panics while polling `fut`.
+pub async fn catch_panic_async<T>(fut: impl Future<Output = T>) -> RResult<T, AnyErr> {
+    install_hook();
+    AssertUnwindSafe(fut)
+        .catch_unwind()
+        .await
+        .map_err(panic_report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn catches_panics() {
+        assert_eq!(catch_panic(|| 1).unwrap(), 1);
+
+        let report = catch_panic(|| panic!("boom {}", 42)).unwrap_err();
+        let debug = format!("{:?}", report);
+        assert!(debug.contains("Panicked: boom 42"));
+        assert!(debug.contains(&format!("Panic location: {}", file!())));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn catches_async_panics() {
+        let report = catch_panic_async(async {
+            tokio::task::yield_now().await;
+            panic!("async boom");
+        })
+        .await
+        .unwrap_err();
+        assert!(format!("{:?}", report).contains("Panicked: async boom"));
+    }
+}
@@ -0,0 +1,124 @@
+use error_stack::{Context, Report};
+use serde_json::{json, Value};
+
+use super::attach::{http_status, log_report, suggestions, user_message};
+use super::{error_class, error_code};
+
+/// A report turned into an HTTP error response, return `Result<T, ApiError>` from handlers and
+/// `?` converts any report.
+///
+/// The status is the [`super::HttpStatus`] attachment, else derived from the [`super::ErrorClass`],
+/// else 500. The body is `{code, message, status, suggestions?}`, the message being the
+/// [`super::UserFacingMessage`] if any. Without one, 5xx errors don't expose their contexts and
+/// are logged instead.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    status: u16,
+    body: Value,
+}
+
+impl ApiError {
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn body(&self) -> &Value {
+        &self.body
+    }
+}
+
+impl<C: Context> From<Report<C>> for ApiError {
+    fn from(report: Report<C>) -> Self {
+        let status = http_status(&report)
+            .or_else(|| error_class(&report).map(|class| class.to_http_status()))
+            .unwrap_or(500);
+
+        let message = match user_message(&report) {
+            Some(message) => message.to_string(),
+            None if status < 500 => report.current_context().to_string(),
+            None => "Internal server error".to_string(),
+        };
+        if status >= 500 {
+            log_report(&report);
+        }
+
+        let mut body = json!({
+            "code": error_code(&report),
+            "message": message,
+            "status": status,
+        });
+        let suggestions = suggestions(&report);
+        if !suggestions.is_empty() {
+            body["suggestions"] = suggestions.into();
+        }
+        ApiError { status, body }
+    }
+}
+
+#[cfg(feature = "axum")]
+impl axum::response::IntoResponse for ApiError {
+    fn into_response(self) -> axum::response::Response {
+        use axum::http::{header, HeaderValue, StatusCode};
+
+        let mut response = self.body.to_string().into_response();
+        *response.status_mut() =
+            StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{AnyErr2, AttachmentExt, ErrorClass, ErrorClassExt, ErrorCode};
+    use rstest::*;
+
+    #[rstest]
+    fn client_errors_expose_the_message() {
+        let error = ApiError::from(
+            Report::new(AnyErr2::new("No user with id 42"))
+                .classify(ErrorClass::NotFound)
+                .attach_printable(ErrorCode("user_not_found")),
+        );
+        assert_eq!(error.status(), 404);
+        assert_eq!(
+            error.body(),
+            &json!({"code": "user_not_found", "message": "No user with id 42", "status": 404})
+        );
+    }
+
+    #[rstest]
+    fn server_errors_hide_internals() {
+        let error = ApiError::from(Report::new(AnyErr2::new("password=hunter2 rejected")));
+        assert_eq!(error.status(), 500);
+        assert_eq!(error.body()["message"], "Internal server error");
+
+        let error = ApiError::from(
+            Report::new(AnyErr2::new("upstream down"))
+                .http_status(502)
+                .user_msg("Payments are unavailable")
+                .suggest("try again in a minute"),
+        );
+        assert_eq!(error.status(), 502);
+        assert_eq!(error.body()["message"], "Payments are unavailable");
+        assert_eq!(
+            error.body()["suggestions"],
+            json!(["try again in a minute"])
+        );
+    }
+
+    #[cfg(feature = "axum")]
+    #[rstest]
+    fn into_response() {
+        use axum::response::IntoResponse;
+
+        let response =
+            ApiError::from(Report::new(AnyErr2::new("bad")).http_status(422)).into_response();
+        assert_eq!(response.status().as_u16(), 422);
+        assert_eq!(response.headers()["content-type"], "application/json");
+    }
+}
@@ -0,0 +1,299 @@
+use error_stack::Report;
+use rand::Rng;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+use super::{error_class, RResult};
+use crate::clock::{system_clock, Clock};
+
+/// How [`retry_async`] and [`retry_sync`] space out attempts and when they give up.
+///
+/// Only errors whose [`super::ErrorClass`] is retryable are retried, anything else is returned
+/// straight away.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    max_attempts: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    jitter: bool,
+    max_elapsed: Option<Duration>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: true,
+            max_elapsed: None,
+            clock: system_clock(),
+        }
+    }
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Backoff::default()
+    }
+
+    /// Total number of attempts including the first one.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn get_max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    pub fn initial_delay(mut self, initial_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn get_max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Don't start another attempt if its delay would end after this long since the first one.
+    pub fn max_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = Some(max_elapsed);
+        self
+    }
+
+    /// Where elapsed time and the sleeps between attempts come from, e.g. a
+    /// [`crate::clock::MockClock`] in tests.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The delay before the given retry, `attempt` starting at 1 for the first retry.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .multiplier
+            .powi(attempt.saturating_sub(1).min(i32::MAX as u32) as i32);
+        // In seconds, as a Duration panics on overflow before it could be capped:
+        let max_secs = self.max_delay.as_secs_f64();
+        let mut secs = self.initial_delay.as_secs_f64() * exp;
+        secs = if secs.is_nan() {
+            max_secs
+        } else {
+            secs.clamp(0.0, max_secs)
+        };
+
+        if self.jitter && secs > 0.0 {
+            // Full jitter, spreads retries from many clients across the whole window:
+            secs *= rand::thread_rng().gen_range(0.0..=1.0);
+        }
+        Duration::try_from_secs_f64(secs).unwrap_or(self.max_delay)
+    }
+
+    /// The delay before the next attempt, or why the error should be returned instead.
+    fn next_delay<C>(
+        &self,
+        report: &Report<C>,
+        attempt: u32,
+        started: Instant,
+    ) -> Result<Duration, String> {
+        match error_class(report) {
+            Some(class) if class.is_retryable() => {}
+            Some(class) => return Err(format!("Not retrying, {}", class)),
+            None => return Err("Not retrying, error is unclassified".to_string()),
+        }
+        if attempt >= self.max_attempts {
+            return Err(format!("Gave up after {} attempts", attempt));
+        }
+        let delay = self.delay(attempt);
+        if let Some(max_elapsed) = self.max_elapsed {
+            if self.clock.now().saturating_duration_since(started) + delay > max_elapsed {
+                return Err(format!(
+                    "Gave up after {} attempts, retrying would exceed {:?}",
+                    attempt, max_elapsed
+                ));
+            }
+        }
+        Ok(delay)
+    }
+}
+
+/// Runs `op` until it succeeds, retrying errors classified as retryable according to `backoff`.
+///
+/// Each attempt runs in its own `retry_attempt` span.
+pub async fn retry_async<T, C, F, Fut>(backoff: &Backoff, mut op: F) -> RResult<T, C>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = RResult<T, C>>,
+{
+    let started = backoff.clock.now();
+    let mut attempt = 1;
+    loop {
+        let span = tracing::info_span!("retry_attempt", attempt);
+        let report = match op().instrument(span).await {
+            Ok(value) => return Ok(value),
+            Err(report) => report,
+        };
+        match backoff.next_delay(&report, attempt, started) {
+            Ok(delay) => {
+                tracing::warn!("Attempt {} failed, retrying in {:?}", attempt, delay);
+                backoff.clock.sleep(delay).await;
+                attempt += 1;
+            }
+            Err(reason) => return Err(report.attach_printable(reason)),
+        }
+    }
+}
+
+/// Blocking version of [`retry_async`], sleeps the current thread between attempts.
+pub fn retry_sync<T, C, F>(backoff: &Backoff, mut op: F) -> RResult<T, C>
+where
+    F: FnMut() -> RResult<T, C>,
+{
+    let started = backoff.clock.now();
+    let mut attempt = 1;
+    loop {
+        let span = tracing::info_span!("retry_attempt", attempt);
+        let report = match span.in_scope(&mut op) {
+            Ok(value) => return Ok(value),
+            Err(report) => report,
+        };
+        match backoff.next_delay(&report, attempt, started) {
+            Ok(delay) => {
+                tracing::warn!("Attempt {} failed, retrying in {:?}", attempt, delay);
+                backoff.clock.sleep_blocking(delay);
+                attempt += 1;
+            }
+            Err(reason) => return Err(report.attach_printable(reason)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::errors::{AnyErr2, ErrorClassExt};
+    use rstest::*;
+
+    fn backoff() -> Backoff {
+        Backoff::new()
+            .max_attempts(4)
+            .initial_delay(Duration::from_millis(1))
+            .jitter(false)
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn retries_until_success() {
+        let mut calls = 0;
+        let result = retry_async(&backoff(), || {
+            calls += 1;
+            let calls = calls;
+            async move {
+                if calls < 3 {
+                    Err(Report::new(AnyErr2::new("flaky"))).retryable()
+                } else {
+                    Ok(calls)
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[rstest]
+    #[case(Some(crate::errors::ErrorClass::Retryable), 4)]
+    #[case(Some(crate::errors::ErrorClass::NotFound), 1)]
+    #[case(None, 1)]
+    fn retries_only_retryable(
+        #[case] class: Option<crate::errors::ErrorClass>,
+        #[case] expected_calls: u32,
+    ) {
+        let mut calls = 0;
+        let result: RResult<(), AnyErr2> = retry_sync(&backoff(), || {
+            calls += 1;
+            let report = Report::new(AnyErr2::new("failed"));
+            Err(match class {
+                Some(class) => report.classify(class),
+                None => report,
+            })
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, expected_calls);
+    }
+
+    #[rstest]
+    fn stops_at_max_elapsed() {
+        let backoff = backoff()
+            .max_attempts(100)
+            .initial_delay(Duration::from_millis(20))
+            .multiplier(1.0)
+            .max_elapsed(Duration::from_millis(50));
+        let mut calls = 0;
+        let result: RResult<(), AnyErr2> = retry_sync(&backoff, || {
+            calls += 1;
+            Err(Report::new(AnyErr2::new("down"))).retryable()
+        });
+        assert!(format!("{:?}", result.unwrap_err()).contains("would exceed"));
+        assert_eq!(calls, 3);
+    }
+
+    #[rstest]
+    #[case(2.0, 1_000)]
+    #[case(2.0, u32::MAX)]
+    #[case(f64::INFINITY, 2)]
+    #[case(f64::NAN, 2)]
+    fn caps_huge_delays(#[case] multiplier: f64, #[case] attempt: u32) {
+        let backoff = backoff()
+            .initial_delay(Duration::from_secs(1))
+            .max_delay(Duration::from_secs(60))
+            .multiplier(multiplier);
+        assert_eq!(backoff.delay(attempt), Duration::from_secs(60));
+        assert!(backoff.jitter(true).delay(attempt) <= Duration::from_secs(60));
+        let unbounded = Backoff::new()
+            .multiplier(multiplier)
+            .max_delay(Duration::MAX);
+        assert_eq!(unbounded.jitter(false).delay(attempt), Duration::MAX);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn sleeps_on_clock() {
+        let clock = MockClock::new();
+        let backoff = backoff()
+            .initial_delay(Duration::from_secs(30))
+            .max_delay(Duration::from_secs(600))
+            .max_elapsed(Duration::from_secs(100))
+            .clock(clock.shared());
+        let result: RResult<(), AnyErr2> = retry_async(&backoff, || async {
+            Err(Report::new(AnyErr2::new("down"))).retryable()
+        })
+        .await;
+        assert!(format!("{:?}", result.unwrap_err()).contains("would exceed"));
+        assert_eq!(
+            clock.sleeps(),
+            vec![Duration::from_secs(30), Duration::from_secs(60)]
+        );
+    }
+}
@@ -0,0 +1,505 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use super::dir::walk;
+use crate::prelude::*;
+
+/// The most [`extract_zip`] unpacks, a few kilobytes of zip can inflate to terabytes.
+pub const MAX_ZIP_EXTRACT_SIZE: u64 = 16 << 30;
+
+/// Packs everything below `src_dir` into a gzipped tarball at `dst`, paths relative to `src_dir`.
+///
+/// Permissions and modification times are kept, symlinks are stored as symlinks.
+pub fn create_tar_gz(src_dir: impl AsRef<Path>, dst: impl AsRef<Path>) -> RResult<(), AnyErr2> {
+    let (src_dir, dst) = (src_dir.as_ref(), dst.as_ref());
+    let file = create_file(dst)?;
+    let mut builder =
+        tar::Builder::new(GzEncoder::new(BufWriter::new(file), Compression::default()));
+    builder.follow_symlinks(false);
+
+    walk(src_dir, |path, _| {
+        builder
+            .append_path_with_name(src_dir.join(path), path)
+            .change_context(err2!("Failed to add to archive"))
+            .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+        Ok(true)
+    })?;
+
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .and_then(|mut writer| writer.flush())
+        .change_context(err2!("Failed to write archive"))
+        .attach_printable_lazy(|| format!("Path: {}", dst.display()))
+}
+
+/// Unpacks a gzipped tarball into `dst`, creating it if needed.
+///
+/// Fails before writing anything outside `dst`, i.e. for absolute paths, `..` or links pointing
+/// out of it, also through links extracted before. Entries before the offending one are already
+/// extracted.
+pub fn extract_tar_gz(archive: impl AsRef<Path>, dst: impl AsRef<Path>) -> RResult<(), AnyErr2> {
+    let (archive, dst) = (archive.as_ref(), dst.as_ref());
+    let file = open_file(archive)?;
+    create_dir(dst)?;
+    let root = canonicalize(dst)?;
+
+    let mut tarball = tar::Archive::new(GzDecoder::new(BufReader::new(file)));
+    let entries = tarball
+        .entries()
+        .change_context(err2!("Failed to read archive"))
+        .attach_printable_lazy(|| format!("Path: {}", archive.display()))?;
+    for entry in entries {
+        let mut entry = entry
+            .change_context(err2!("Failed to read archive entry"))
+            .attach_printable_lazy(|| format!("Path: {}", archive.display()))?;
+        let path = entry
+            .path()
+            .change_context(err2!("Invalid archive entry path"))?
+            .into_owned();
+        let relative = safe_path(&path)?;
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let target = entry
+                .link_name()
+                .change_context(err2!("Invalid archive link target"))?
+                .unwrap_or_default()
+                .into_owned();
+            check_link(&root, &relative, &target, entry_type.is_hard_link())?;
+        }
+
+        entry
+            .unpack_in(dst)
+            .change_context(err2!("Failed to extract archive entry"))
+            .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Packs everything below `src_dir` into a deflate compressed zip at `dst`, zip64 when needed.
+///
+/// Unix permissions and modification times are kept, symlinks are stored as symlinks.
+pub fn create_zip(src_dir: impl AsRef<Path>, dst: impl AsRef<Path>) -> RResult<(), AnyErr2> {
+    let (src_dir, dst) = (src_dir.as_ref(), dst.as_ref());
+    let mut zip = ZipWriter::new(BufWriter::new(create_file(dst)?));
+
+    walk(src_dir, |path, metadata| {
+        let full = src_dir.join(path);
+        let name = zip_name(path);
+        let options = zip_options(metadata);
+        if metadata.is_symlink() {
+            let target = fs::read_link(&full)
+                .change_context(err2!("Failed to read link"))
+                .attach_printable_lazy(|| format!("Path: {}", full.display()))?;
+            zip.add_symlink(name, target.to_string_lossy(), options)
+                .change_context(err2!("Failed to add to archive"))
+        } else if metadata.is_dir() {
+            zip.add_directory(name, options)
+                .change_context(err2!("Failed to add to archive"))
+        } else {
+            let mut file = open_file(&full)?;
+            zip.start_file(name, options)
+                .change_context(err2!("Failed to add to archive"))
+                .and_then(|_| {
+                    io::copy(&mut file, &mut zip).change_context(err2!("Failed to add to archive"))
+                })
+                .map(|_| ())
+        }
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+        Ok(true)
+    })?;
+
+    zip.finish()
+        .change_context(err2!("Failed to write archive"))
+        .and_then(|mut writer| {
+            writer
+                .flush()
+                .change_context(err2!("Failed to write archive"))
+        })
+        .attach_printable_lazy(|| format!("Path: {}", dst.display()))
+}
+
+/// Unpacks a zip into `dst`, creating it if needed.
+///
+/// Like [`extract_tar_gz`], fails before writing anything outside `dst`. Also fails once more
+/// than [`MAX_ZIP_EXTRACT_SIZE`] would be unpacked.
+pub fn extract_zip(archive: impl AsRef<Path>, dst: impl AsRef<Path>) -> RResult<(), AnyErr2> {
+    extract_zip_limited(archive.as_ref(), dst.as_ref(), MAX_ZIP_EXTRACT_SIZE)
+}
+
+fn extract_zip_limited(archive: &Path, dst: &Path, max_size: u64) -> RResult<(), AnyErr2> {
+    let file = open_file(archive)?;
+    create_dir(dst)?;
+    let root = canonicalize(dst)?;
+
+    let mut zip = ZipArchive::new(BufReader::new(file))
+        .change_context(err2!("Invalid zip archive"))
+        .attach_printable_lazy(|| format!("Path: {}", archive.display()))?;
+    let mut remaining = max_size;
+    for index in 0..zip.len() {
+        let mut entry = zip
+            .by_index(index)
+            .change_context(err2!("Failed to read archive entry"))
+            .attach_printable_lazy(|| format!("Path: {}", archive.display()))?;
+        let name = entry.name().to_string();
+        let relative = safe_path(Path::new(&name))?;
+        let target = dst.join(&relative);
+        if entry.is_dir() {
+            create_dir(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            create_dir(parent)?;
+        }
+
+        if entry.is_symlink() {
+            let mut link = String::new();
+            entry
+                .read_to_string(&mut link)
+                .change_context(err2!("Failed to read archive entry"))
+                .attach_printable_lazy(|| format!("Path: {}", name))?;
+            check_link(&root, &relative, Path::new(&link), false)?;
+            create_symlink(Path::new(&link), &target)?;
+            continue;
+        }
+
+        // The declared size can lie, so reading stops right after the limit either way:
+        let mut contents = (&mut entry).take(remaining.saturating_add(1));
+        let mut out = BufWriter::new(create_file(&target)?);
+        let written = io::copy(&mut contents, &mut out)
+            .and_then(|written| out.flush().map(|_| written))
+            .change_context(err2!("Failed to extract archive entry"))
+            .attach_printable_lazy(|| format!("Path: {}", name))?;
+        if written > remaining {
+            return Err(Report::new(err2!("Zip archive unpacks to too much"))
+                .attach_printable(format!("Limit: {} bytes", max_size))
+                .attach_printable(format!("Path: {}", archive.display())));
+        }
+        remaining -= written;
+
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&target, fs::Permissions::from_mode(mode & 0o777));
+        }
+    }
+    Ok(())
+}
+
+/// `path` without `.` components, if it's relative and has no `..`.
+fn safe_path(path: &Path) -> RResult<PathBuf, AnyErr2> {
+    let unsafe_component = path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if unsafe_component {
+        return Err(
+            Report::new(err2!("Archive entry points outside the destination"))
+                .attach_printable(format!("Path: {}", path.display())),
+        );
+    }
+    Ok(path.components().collect())
+}
+
+/// Fails unless the link at `relative` to `target` stays inside the canonical `root`. Hard links
+/// are relative to the archive root, symlinks to where they are.
+fn check_link(root: &Path, relative: &Path, target: &Path, hard: bool) -> RResult<(), AnyErr2> {
+    let from = match relative.parent() {
+        Some(parent) if !hard => parent.join(target),
+        _ => target.to_path_buf(),
+    };
+    let inside = !target.is_absolute()
+        && resolve(root, &from).is_some_and(|resolved| resolved.starts_with(root));
+    if !inside {
+        return Err(
+            Report::new(err2!("Archive link points outside the destination"))
+                .attach_printable(format!("{} -> {}", relative.display(), target.display())),
+        );
+    }
+    Ok(())
+}
+
+/// Where `path` below `root` ends up, following the links already on disk, e.g. with `d -> .`
+/// extracted `d/..` is above `root`. `None` for absolute paths and links that can't be followed.
+fn resolve(root: &Path, path: &Path) -> Option<PathBuf> {
+    let mut resolved = root.to_path_buf();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => {
+                resolved.push(part);
+                if resolved.symlink_metadata().is_ok() {
+                    resolved = fs::canonicalize(&resolved).ok()?;
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(resolved)
+}
+
+fn zip_name(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn zip_options(metadata: &fs::Metadata) -> SimpleFileOptions {
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(zip_time(metadata.modified().ok()))
+        .large_file(metadata.len() >= u32::MAX as u64);
+    #[cfg(unix)]
+    let options = {
+        use std::os::unix::fs::PermissionsExt;
+        options.unix_permissions(metadata.permissions().mode() & 0o777)
+    };
+    options
+}
+
+/// Zip's local date and time, 2 second precision and from 1980.
+fn zip_time(modified: Option<std::time::SystemTime>) -> zip::DateTime {
+    use chrono::{Datelike, Timelike};
+    let Some(modified) = modified else {
+        return zip::DateTime::default();
+    };
+    let local = chrono::DateTime::<chrono::Local>::from(modified);
+    zip::DateTime::from_date_and_time(
+        u16::try_from(local.year()).unwrap_or_default(),
+        local.month() as u8,
+        local.day() as u8,
+        local.hour() as u8,
+        local.minute() as u8,
+        local.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> RResult<(), AnyErr2> {
+    std::os::unix::fs::symlink(target, link)
+        .change_context(err2!("Failed to create link"))
+        .attach_printable_lazy(|| format!("{} -> {}", link.display(), target.display()))
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &Path, link: &Path) -> RResult<(), AnyErr2> {
+    Err(
+        Report::new(err2!("Symlinks in zip archives are only extracted on unix"))
+            .attach_printable(format!("Path: {}", link.display())),
+    )
+}
+
+fn open_file(path: &Path) -> RResult<File, AnyErr2> {
+    File::open(path)
+        .change_context(err2!("Failed to open file"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))
+}
+
+fn create_file(path: &Path) -> RResult<File, AnyErr2> {
+    File::create(path)
+        .change_context(err2!("Failed to create file"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))
+}
+
+fn create_dir(path: &Path) -> RResult<(), AnyErr2> {
+    fs::create_dir_all(path)
+        .change_context(err2!("Failed to create directory"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))
+}
+
+fn canonicalize(path: &Path) -> RResult<PathBuf, AnyErr2> {
+    fs::canonicalize(path)
+        .change_context(err2!("Failed to resolve directory"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("bin/empty")).unwrap();
+        fs::write(dir.path().join("README.md"), "hello").unwrap();
+        fs::write(
+            dir.path().join("bin/run.sh"),
+            "#!/bin/sh\necho hi\n".repeat(100),
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(
+                dir.path().join("bin/run.sh"),
+                fs::Permissions::from_mode(0o755),
+            )
+            .unwrap();
+        }
+        dir
+    }
+
+    fn assert_same_tree(src: &Path, dst: &Path) {
+        assert_eq!(
+            super::super::hash_dir(src).unwrap(),
+            super::super::hash_dir(dst).unwrap()
+        );
+        assert!(dst.join("bin/empty").is_dir());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dst.join("bin/run.sh"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+    }
+
+    #[rstest]
+    #[case::tar_gz("out.tar.gz")]
+    #[case::zip("out.zip")]
+    fn round_trips(#[case] name: &str) {
+        let src = tree();
+        let out = tempfile::tempdir().unwrap();
+        let archive = out.path().join(name);
+        let dst = out.path().join("extracted");
+        if name.ends_with(".zip") {
+            create_zip(src.path(), &archive).unwrap();
+            extract_zip(&archive, &dst).unwrap();
+        } else {
+            create_tar_gz(src.path(), &archive).unwrap();
+            extract_tar_gz(&archive, &dst).unwrap();
+        }
+        assert_same_tree(src.path(), &dst);
+    }
+
+    #[rstest]
+    fn rejects_tar_traversal() {
+        let out = tempfile::tempdir().unwrap();
+        let archive = out.path().join("evil.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(&archive).unwrap(),
+            Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        // set_path() refuses "..", so write the raw name:
+        header.as_old_mut().name[..11].copy_from_slice(b"../evil.txt");
+        header.set_size(4);
+        header.set_cksum();
+        builder.append(&header, &b"evil"[..]).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let dst = out.path().join("dst");
+        let report = extract_tar_gz(&archive, &dst).unwrap_err();
+        assert!(format!("{:?}", report).contains("outside the destination"));
+        assert!(!out.path().join("evil.txt").exists());
+
+        let archive = out.path().join("link.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(&archive).unwrap(),
+            Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "sub/escape", "../../etc")
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+        assert!(extract_tar_gz(&archive, &dst).is_err());
+
+        // Each link stays inside on its own, but through the first the second points out:
+        let archive = out.path().join("chain.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(&archive).unwrap(),
+            Compression::default(),
+        ));
+        for (path, target) in [("d", "."), ("d/e", "..")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(&mut header, path, target).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        let dst = out.path().join("chain");
+        let report = extract_tar_gz(&archive, &dst).unwrap_err();
+        assert!(format!("{:?}", report).contains("d/e -> .."));
+        assert!(dst.join("d").is_symlink());
+        assert!(!dst.join("e").exists());
+    }
+
+    #[rstest]
+    fn rejects_zip_traversal() {
+        let src = tree();
+        let out = tempfile::tempdir().unwrap();
+        let archive = out.path().join("evil.zip");
+        let mut zip = ZipWriter::new(File::create(&archive).unwrap());
+        zip.start_file("../evil.txt", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"evil").unwrap();
+        zip.finish().unwrap();
+
+        let report = extract_zip(&archive, out.path().join("dst")).unwrap_err();
+        assert!(format!("{:?}", report).contains("outside the destination"));
+        assert!(!out.path().join("evil.txt").exists());
+
+        #[cfg(unix)]
+        {
+            // Stored as a link rather than followed, then refused on the way out:
+            std::os::unix::fs::symlink(out.path(), src.path().join("escape")).unwrap();
+            create_zip(src.path(), &archive).unwrap();
+            let names = ZipArchive::new(File::open(&archive).unwrap())
+                .unwrap()
+                .file_names()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>();
+            assert!(names.contains(&"escape".to_string()));
+            assert!(!names.iter().any(|name| name.contains("evil.zip")));
+            let report = extract_zip(&archive, out.path().join("dst")).unwrap_err();
+            assert!(format!("{:?}", report).contains("link points outside"));
+        }
+    }
+
+    #[rstest]
+    fn caps_zip_size() {
+        let src = tempfile::tempdir().unwrap();
+        fs::write(src.path().join("zeros"), vec![0; 1 << 20]).unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let archive = out.path().join("bomb.zip");
+        create_zip(src.path(), &archive).unwrap();
+        assert!(fs::metadata(&archive).unwrap().len() < 10_000);
+
+        let report = extract_zip_limited(&archive, &out.path().join("dst"), 1000).unwrap_err();
+        assert!(format!("{:?}", report).contains("unpacks to too much"));
+        extract_zip_limited(&archive, &out.path().join("dst"), 1 << 20).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    fn zip_keeps_links() {
+        let src = tree();
+        std::os::unix::fs::symlink("run.sh", src.path().join("bin/latest")).unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let archive = out.path().join("out.zip");
+        create_zip(src.path(), &archive).unwrap();
+        let dst = out.path().join("extracted");
+        extract_zip(&archive, &dst).unwrap();
+        assert_eq!(
+            fs::read_link(dst.join("bin/latest")).unwrap(),
+            Path::new("run.sh")
+        );
+        assert_same_tree(src.path(), &dst);
+    }
+}
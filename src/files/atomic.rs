@@ -0,0 +1,83 @@
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+
+use crate::prelude::*;
+
+/// Replaces the contents of `path` so that it's either fully the old or fully the new contents,
+/// even on crash or power loss.
+///
+/// Writes to a temp file in the same directory, fsyncs it and renames it over `path`.
+pub fn write_atomic(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> RResult<(), AnyErr2> {
+    let path = path.as_ref();
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut temp = tempfile::Builder::new()
+        .prefix(".tmp-")
+        .tempfile_in(dir)
+        .change_context(err2!("Failed to create temp file"))
+        .attach_printable_lazy(|| format!("Directory: {}", dir.display()))?;
+    temp.write_all(contents.as_ref())
+        .and_then(|_| temp.as_file().sync_all())
+        .change_context(err2!("Failed to write temp file"))
+        .attach_printable_lazy(|| format!("Path: {}", temp.path().display()))?;
+
+    // Keep the permissions of the file being replaced, the temp file is created as 0600:
+    if let Ok(metadata) = std::fs::metadata(path) {
+        let _ = temp.as_file().set_permissions(metadata.permissions());
+    }
+
+    temp.persist(path)
+        .map_err(|e| e.error)
+        .change_context(err2!("Failed to replace file"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+
+    // Make the rename itself durable:
+    #[cfg(unix)]
+    if let Ok(dir) = std::fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Atomically replaces `path` with `value` as pretty printed json, see [`write_atomic`].
+pub fn write_json_atomic<T: Serialize + ?Sized>(
+    path: impl AsRef<Path>,
+    value: &T,
+) -> RResult<(), AnyErr2> {
+    let json =
+        serde_json::to_vec_pretty(value).change_context(err2!("Failed to serialize json"))?;
+    write_atomic(path, json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn replaces_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        write_atomic(&path, "old").unwrap();
+        write_json_atomic(&path, &serde_json::json!({"version": 2})).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&contents).unwrap()["version"],
+            2
+        );
+        // No temp files left behind:
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[rstest]
+    fn missing_dir_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(write_atomic(dir.path().join("missing/state.json"), "x").is_err());
+    }
+}
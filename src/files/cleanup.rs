@@ -0,0 +1,127 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::prelude::*;
+
+/// Limits for [`cleanup_dir`], a file is removed if it breaks any of them. `None` means no limit.
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Remove files last modified longer ago than this.
+    pub max_age: Option<Duration>,
+    /// Remove the oldest files until the rest fit in this many bytes.
+    pub max_total_size: Option<u64>,
+    /// Keep at most the newest n files.
+    pub keep_last_n: Option<usize>,
+}
+
+/// Prunes files directly in `dir` (not in subdirectories) by `policy`, oldest first, e.g. rotated
+/// logs or build artifacts.
+///
+/// Returns the removed files, see [`cleanup_dir_dry_run`] to check a policy first.
+pub fn cleanup_dir(
+    dir: impl AsRef<Path>,
+    policy: &RetentionPolicy,
+) -> RResult<Vec<PathBuf>, AnyErr2> {
+    let expired = cleanup_dir_dry_run(dir, policy)?;
+    for path in &expired {
+        std::fs::remove_file(path)
+            .change_context(err2!("Failed to remove file"))
+            .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+    }
+    if !expired.is_empty() {
+        debug!("Cleanup removed {} files", expired.len());
+    }
+    Ok(expired)
+}
+
+/// The files [`cleanup_dir`] would remove, without removing them.
+pub fn cleanup_dir_dry_run(
+    dir: impl AsRef<Path>,
+    policy: &RetentionPolicy,
+) -> RResult<Vec<PathBuf>, AnyErr2> {
+    let dir = dir.as_ref();
+    let mut files = vec![];
+    let entries = std::fs::read_dir(dir)
+        .change_context(err2!("Failed to read directory"))
+        .attach_printable_lazy(|| format!("Path: {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.change_context(err2!("Failed to read directory entry"))?;
+        let metadata = entry
+            .metadata()
+            .change_context(err2!("Failed to read metadata"))
+            .attach_printable_lazy(|| format!("Path: {}", entry.path().display()))?;
+        if metadata.is_file() {
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((entry.path(), modified, metadata.len()));
+        }
+    }
+    // Newest first, by name when tied so results are deterministic:
+    files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let now = SystemTime::now();
+    let mut total_size = 0;
+    let mut expired = vec![];
+    for (index, (path, modified, size)) in files.into_iter().enumerate() {
+        total_size += size;
+        let too_old = policy
+            .max_age
+            .is_some_and(|max_age| now.duration_since(modified).unwrap_or_default() > max_age);
+        let too_big = policy.max_total_size.is_some_and(|max| total_size > max);
+        let too_many = policy.keep_last_n.is_some_and(|n| index >= n);
+        if too_old || too_big || too_many {
+            expired.push(path);
+        }
+    }
+    // Oldest first:
+    expired.reverse();
+    Ok(expired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    /// Files aged 0, 1, 2, .. hours, 10 bytes each.
+    fn logs(count: usize) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("subdir")).unwrap();
+        for i in 0..count {
+            let path = dir.path().join(format!("app.{}.log", i));
+            std::fs::write(&path, "0123456789").unwrap();
+            let modified = SystemTime::now() - Duration::from_secs(i as u64 * 3600);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        dir
+    }
+
+    fn names(paths: &[PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[rstest]
+    #[case(RetentionPolicy::default(), &[])]
+    #[case(RetentionPolicy { keep_last_n: Some(3), ..Default::default() }, &["app.4.log", "app.3.log"])]
+    #[case(RetentionPolicy { max_total_size: Some(25), ..Default::default() }, &["app.4.log", "app.3.log", "app.2.log"])]
+    #[case(RetentionPolicy { max_age: Some(Duration::from_secs(90 * 60)), ..Default::default() }, &["app.4.log", "app.3.log", "app.2.log"])]
+    #[case(RetentionPolicy { max_age: Some(Duration::from_secs(90 * 60)), keep_last_n: Some(1), ..Default::default() }, &["app.4.log", "app.3.log", "app.2.log", "app.1.log"])]
+    fn applies_policy(#[case] policy: RetentionPolicy, #[case] expected: &[&str]) {
+        let dir = logs(5);
+        let planned = cleanup_dir_dry_run(dir.path(), &policy).unwrap();
+        assert_eq!(names(&planned), expected);
+        assert!(planned.iter().all(|p| p.exists()));
+
+        let removed = cleanup_dir(dir.path(), &policy).unwrap();
+        assert_eq!(removed, planned);
+        assert!(removed.iter().all(|p| !p.exists()));
+        assert!(dir.path().join("subdir").exists());
+    }
+}
@@ -0,0 +1,420 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+
+/// Loads a typed config from layers merged in order, later layers overriding earlier ones.
+///
+/// Usually defaults, then files (TOML, YAML or JSON by extension), then env vars. Objects are
+/// merged key by key, anything else is replaced. Errors name the offending key and which layer
+/// set it.
+#[derive(Debug, Default)]
+pub struct ConfigLoader {
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug)]
+enum Layer {
+    Value {
+        source: String,
+        value: Value,
+    },
+    File {
+        path: PathBuf,
+        required: bool,
+    },
+    Env {
+        prefix: String,
+    },
+    /// Defaults that failed to serialize, reported by [`ConfigLoader::load`].
+    Invalid {
+        source: String,
+        error: String,
+    },
+}
+
+impl ConfigLoader {
+    pub fn new() -> Self {
+        ConfigLoader::default()
+    }
+
+    /// The lowest layer, usually `T::default()`.
+    pub fn defaults(mut self, defaults: impl Serialize) -> Self {
+        match serde_json::to_value(defaults) {
+            Ok(value) => self.layer("defaults", value),
+            Err(e) => {
+                self.layers.push(Layer::Invalid {
+                    source: "defaults".to_string(),
+                    error: e.to_string(),
+                });
+                self
+            }
+        }
+    }
+
+    /// A layer from anywhere else, `source` is used in errors.
+    pub fn layer(mut self, source: impl Into<String>, value: Value) -> Self {
+        self.layers.push(Layer::Value {
+            source: source.into(),
+            value,
+        });
+        self
+    }
+
+    /// A file that must exist.
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.layers.push(Layer::File {
+            path: path.into(),
+            required: true,
+        });
+        self
+    }
+
+    /// A file that's skipped when missing, e.g. a local override.
+    pub fn optional_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.layers.push(Layer::File {
+            path: path.into(),
+            required: false,
+        });
+        self
+    }
+
+    /// Env vars starting with `{prefix}__`, nested keys separated by `__`.
+    ///
+    /// E.g. `APP__DATABASE__PORT=5432` sets `database.port`. Values are parsed as json when
+    /// possible, quote them to force a string: `APP__NAME='"0123"'`.
+    pub fn env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.layers.push(Layer::Env {
+            prefix: prefix.into(),
+        });
+        self
+    }
+
+    /// The merged layers before deserializing.
+    pub fn merged(&self) -> RResult<Value, AnyErr2> {
+        Ok(self.merge()?.0)
+    }
+
+    pub fn load<T: DeserializeOwned>(&self) -> RResult<T, AnyErr2> {
+        let (merged, sources) = self.merge()?;
+        deserialize(&merged, &sources)
+    }
+
+    /// The merged value and which layer set each key.
+    fn merge(&self) -> RResult<(Value, HashMap<String, String>), AnyErr2> {
+        let mut merged = Value::Object(Map::new());
+        let mut sources = HashMap::new();
+
+        for layer in &self.layers {
+            match layer {
+                Layer::Value { source, value } => {
+                    merge_into(&mut merged, value.clone(), "", source, &mut sources);
+                }
+                Layer::File { path, required } => {
+                    if !required && !path.exists() {
+                        debug!("Optional config file {} not found", path.display());
+                        continue;
+                    }
+                    let value = read_file(path)?;
+                    let source = path.display().to_string();
+                    merge_into(&mut merged, value, "", &source, &mut sources);
+                }
+                Layer::Env { prefix } => {
+                    let mut vars: Vec<(String, String)> = std::env::vars().collect();
+                    vars.sort();
+                    for (name, value) in vars {
+                        if let Some(value) = env_value(prefix, &name, &value) {
+                            let source = format!("env {}", name);
+                            merge_into(&mut merged, value, "", &source, &mut sources);
+                        }
+                    }
+                }
+                Layer::Invalid { source, error } => {
+                    return Err(Report::new(err2!(format!(
+                        "Failed to serialize config {}",
+                        source
+                    )))
+                    .attach_printable(error.clone()));
+                }
+            }
+        }
+        Ok((merged, sources))
+    }
+}
+
+/// Loads `T` from `paths` merged in order, see [`ConfigLoader`] for defaults and env overrides.
+pub fn load_config<T: DeserializeOwned>(
+    paths: impl IntoIterator<Item = impl Into<PathBuf>>,
+) -> RResult<T, AnyErr2> {
+    paths
+        .into_iter()
+        .fold(ConfigLoader::new(), |loader, path| loader.file(path))
+        .load()
+}
+
+/// Parses a config file by its extension.
+pub fn read_file(path: &Path) -> RResult<Value, AnyErr2> {
+    let contents = std::fs::read_to_string(path)
+        .change_context(err2!("Failed to read config file"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let value = match extension.as_str() {
+        "json" => serde_json::from_str(&contents).change_context(err2!("Invalid json")),
+        "yaml" | "yml" => serde_yaml::from_str(&contents).change_context(err2!("Invalid yaml")),
+        "toml" => contents
+            .parse::<toml_edit::Document>()
+            .change_context(err2!("Invalid toml"))
+            .map(|document| toml_table(document.as_table())),
+        _ => Err(Report::new(err2!(
+            "Unknown config file format, expected .toml, .yaml, .yml or .json"
+        ))),
+    };
+    value.attach_printable_lazy(|| format!("Path: {}", path.display()))
+}
+
+fn toml_table(table: &toml_edit::Table) -> Value {
+    Value::Object(
+        table
+            .iter()
+            .filter_map(|(key, item)| Some((key.to_string(), toml_item(item)?)))
+            .collect(),
+    )
+}
+
+fn toml_item(item: &toml_edit::Item) -> Option<Value> {
+    match item {
+        toml_edit::Item::None => None,
+        toml_edit::Item::Value(value) => Some(toml_value(value)),
+        toml_edit::Item::Table(table) => Some(toml_table(table)),
+        toml_edit::Item::ArrayOfTables(tables) => {
+            Some(Value::Array(tables.iter().map(toml_table).collect()))
+        }
+    }
+}
+
+fn toml_value(value: &toml_edit::Value) -> Value {
+    use toml_edit::Value as Toml;
+    match value {
+        Toml::String(s) => Value::String(s.value().clone()),
+        Toml::Integer(i) => Value::from(*i.value()),
+        Toml::Float(f) => Value::from(*f.value()),
+        Toml::Boolean(b) => Value::Bool(*b.value()),
+        Toml::Datetime(d) => Value::String(d.value().to_string()),
+        Toml::Array(array) => Value::Array(array.iter().map(toml_value).collect()),
+        Toml::InlineTable(table) => Value::Object(
+            table
+                .iter()
+                .map(|(key, value)| (key.to_string(), toml_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// The nested value an env var sets, if it has the prefix.
+fn env_value(prefix: &str, name: &str, value: &str) -> Option<Value> {
+    let rest = name.strip_prefix(prefix)?.strip_prefix("__")?;
+    let parsed = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+    let keys: Vec<&str> = rest.split("__").collect();
+    Some(keys.into_iter().rev().fold(parsed, |inner, key| {
+        Value::Object(Map::from_iter([(key.to_lowercase(), inner)]))
+    }))
+}
+
+fn merge_into(
+    target: &mut Value,
+    value: Value,
+    path: &str,
+    source: &str,
+    sources: &mut HashMap<String, String>,
+) {
+    let Value::Object(value) = value else {
+        // Keys under a replaced value came from an earlier layer:
+        let nested = format!("{}.", path);
+        sources.retain(|key, _| !key.starts_with(&nested));
+        sources.insert(path.to_string(), source.to_string());
+        *target = value;
+        return;
+    };
+
+    if !target.is_object() {
+        sources.remove(path);
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in value {
+            let path = if path.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", path, key)
+            };
+            let entry = target.entry(key).or_insert(Value::Null);
+            merge_into(entry, value, &path, source, sources);
+        }
+    }
+}
+
+/// Deserializes through pretty printed json so errors have a line to find the key from.
+fn deserialize<T: DeserializeOwned>(
+    merged: &Value,
+    sources: &HashMap<String, String>,
+) -> RResult<T, AnyErr2> {
+    let pretty = serde_json::to_string_pretty(merged).change_context(err2!("Invalid config"))?;
+    serde_json::from_str(&pretty).map_err(|e| {
+        let key = key_at_line(&pretty, e.line());
+        let mut report = Report::new(e).change_context(err2!("Invalid config"));
+        if !key.is_empty() {
+            report = report.attach_printable(format!("Key: {}", key));
+        }
+        let nested = format!("{}.", key);
+        if let Some(source) = sources.get(&key).or_else(|| {
+            sources
+                .iter()
+                .find(|(path, _)| path.starts_with(&nested))
+                .map(|(_, source)| source)
+        }) {
+            report = report.attach_printable(format!("Set by: {}", source));
+        }
+        report
+    })
+}
+
+/// The dotted key of the given 1-based line of pretty printed json.
+fn key_at_line(pretty: &str, line: usize) -> String {
+    let mut stack: Vec<String> = vec![];
+    let key_of = |line: &str| {
+        line.strip_prefix('"')
+            .and_then(|rest| rest.split_once("\": "))
+            .map(|(key, _)| key.to_string())
+    };
+
+    for (index, text) in pretty.lines().enumerate() {
+        let text = text.trim();
+        if index + 1 == line {
+            if let Some(key) = key_of(text) {
+                stack.push(key);
+            }
+            break;
+        }
+        if text.starts_with('}') || text.starts_with(']') {
+            stack.pop();
+        } else if text.ends_with('{') || text.ends_with('[') {
+            // The root and array elements have no key:
+            if index > 0 {
+                stack.push(key_of(text).unwrap_or_else(|| "[]".to_string()));
+            }
+        }
+    }
+    stack.join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    struct Database {
+        host: String,
+        port: u16,
+    }
+
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    struct Config {
+        name: String,
+        debug: bool,
+        database: Database,
+        tags: Vec<String>,
+    }
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[rstest]
+    fn merges_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml = write(
+            dir.path(),
+            "base.toml",
+            "name = \"api\"\ntags = [\"a\"]\n\n[database]\nhost = \"db\"\nport = 5432\n",
+        );
+        let yaml = write(dir.path(), "local.yaml", "database:\n  host: localhost\n");
+
+        std::env::set_var("RUTILS_TEST_MERGE__DEBUG", "true");
+        std::env::set_var("RUTILS_TEST_MERGE__DATABASE__PORT", "6543");
+        let config: Config = ConfigLoader::new()
+            .defaults(Config::default())
+            .file(&toml)
+            .file(&yaml)
+            .optional_file(dir.path().join("missing.json"))
+            .env_prefix("RUTILS_TEST_MERGE")
+            .load()
+            .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                name: "api".into(),
+                debug: true,
+                database: Database {
+                    host: "localhost".into(),
+                    port: 6543,
+                },
+                tags: vec!["a".into()],
+            }
+        );
+    }
+
+    #[rstest]
+    fn errors_point_at_the_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = write(
+            dir.path(),
+            "config.json",
+            r#"{"name": "api", "debug": false, "tags": [], "database": {"host": "db", "port": "high"}}"#,
+        );
+
+        let report = load_config::<Config>([&json]).unwrap_err();
+        let debug = format!("{:?}", report);
+        assert!(debug.contains("Key: database.port"), "{}", debug);
+        assert!(debug.contains(&format!("Set by: {}", json.display())));
+    }
+
+    #[rstest]
+    fn reports_invalid_defaults() {
+        // Maps need string keys to become json:
+        let defaults = std::collections::BTreeMap::from([(vec![1u8], 1)]);
+        let report = ConfigLoader::new()
+            .defaults(defaults)
+            .load::<Value>()
+            .unwrap_err();
+        let debug = format!("{:?}", report);
+        assert!(
+            debug.contains("Failed to serialize config defaults"),
+            "{}",
+            debug
+        );
+        assert!(debug.contains("key must be a string"), "{}", debug);
+    }
+
+    #[rstest]
+    #[case("/cfg.ini")]
+    #[case("/missing.toml")]
+    fn rejects_files(#[case] path: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("cfg.ini"), "a=1").unwrap();
+        let path = format!("{}{}", dir.path().display(), path);
+        assert!(load_config::<Value>([path]).is_err());
+    }
+}
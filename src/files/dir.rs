@@ -0,0 +1,267 @@
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+
+/// Counts for a [`copy_dir`] or [`sync_dir`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CopyStats {
+    pub files_copied: u64,
+    pub bytes_copied: u64,
+    /// Files [`sync_dir`] left alone because the destination was up to date.
+    pub files_skipped: u64,
+}
+
+/// Passed to progress callbacks after each file.
+#[derive(Debug)]
+pub struct CopyProgress<'a> {
+    /// Relative to the source directory.
+    pub path: &'a Path,
+    pub bytes: u64,
+    pub stats: &'a CopyStats,
+}
+
+/// Calls `visit` with the path relative to `root` and the metadata of every entry below it,
+/// parents before children. Symlinks aren't followed, `visit` returning false skips a directory.
+pub(crate) fn walk(
+    root: &Path,
+    mut visit: impl FnMut(&Path, &Metadata) -> RResult<bool, AnyErr2>,
+) -> RResult<(), AnyErr2> {
+    let mut pending = vec![PathBuf::new()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = fs::read_dir(root.join(&dir))
+            .change_context(err2!("Failed to read directory"))
+            .attach_printable_lazy(|| format!("Path: {}", root.join(&dir).display()))?
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(err2!("Failed to read directory entry"))?;
+        // Sorted so callbacks and results are deterministic:
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let mut subdirs = vec![];
+        for entry in entries {
+            let path = dir.join(entry.file_name());
+            let metadata = entry
+                .metadata()
+                .change_context(err2!("Failed to read metadata"))
+                .attach_printable_lazy(|| format!("Path: {}", root.join(&path).display()))?;
+            if visit(&path, &metadata)? && metadata.is_dir() {
+                subdirs.push(path);
+            }
+        }
+        pending.extend(subdirs.into_iter().rev());
+    }
+    Ok(())
+}
+
+/// Recursively copies `src` into `dst`, creating `dst` if needed and overwriting existing files.
+pub fn copy_dir(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> RResult<CopyStats, AnyErr2> {
+    copy_dir_with_progress(src, dst, |_| {})
+}
+
+/// Like [`copy_dir`], calling `on_progress` after each file.
+pub fn copy_dir_with_progress(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    on_progress: impl FnMut(&CopyProgress),
+) -> RResult<CopyStats, AnyErr2> {
+    copy_tree(src.as_ref(), dst.as_ref(), false, on_progress)
+}
+
+/// Makes `dst` a copy of `src` by only copying files that are missing or changed, i.e. newer or a
+/// different size. Files only in `dst` are kept.
+pub fn sync_dir(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> RResult<CopyStats, AnyErr2> {
+    sync_dir_with_progress(src, dst, |_| {})
+}
+
+/// Like [`sync_dir`], calling `on_progress` after each copied file.
+pub fn sync_dir_with_progress(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    on_progress: impl FnMut(&CopyProgress),
+) -> RResult<CopyStats, AnyErr2> {
+    copy_tree(src.as_ref(), dst.as_ref(), true, on_progress)
+}
+
+fn copy_tree(
+    src: &Path,
+    dst: &Path,
+    only_changed: bool,
+    mut on_progress: impl FnMut(&CopyProgress),
+) -> RResult<CopyStats, AnyErr2> {
+    if !src.is_dir() {
+        return Err(Report::new(err2!("Source is not a directory"))
+            .attach_printable(format!("Path: {}", src.display())));
+    }
+    create_dir(dst)?;
+
+    let mut stats = CopyStats::default();
+    walk(src, |path, metadata| {
+        let from = src.join(path);
+        let to = dst.join(path);
+
+        if metadata.is_dir() {
+            create_dir(&to)?;
+        } else if metadata.file_type().is_symlink() {
+            copy_symlink(&from, &to)?;
+        } else {
+            if only_changed && is_up_to_date(metadata, &to) {
+                stats.files_skipped += 1;
+                return Ok(true);
+            }
+            fs::copy(&from, &to)
+                .change_context(err2!("Failed to copy file"))
+                .attach_printable_lazy(|| format!("{} -> {}", from.display(), to.display()))?;
+            // Keep the modification time so a later sync sees the file as up to date:
+            if let Ok(modified) = metadata.modified() {
+                if let Ok(file) = fs::File::options().write(true).open(&to) {
+                    let _ = file.set_modified(modified);
+                }
+            }
+
+            stats.files_copied += 1;
+            stats.bytes_copied += metadata.len();
+            on_progress(&CopyProgress {
+                path,
+                bytes: metadata.len(),
+                stats: &stats,
+            });
+        }
+        Ok(true)
+    })?;
+    Ok(stats)
+}
+
+fn is_up_to_date(src: &Metadata, dst: &Path) -> bool {
+    let Ok(dst) = fs::metadata(dst) else {
+        return false;
+    };
+    match (src.modified(), dst.modified()) {
+        (Ok(src_modified), Ok(dst_modified)) => {
+            src.len() == dst.len() && src_modified <= dst_modified
+        }
+        _ => false,
+    }
+}
+
+fn create_dir(path: &Path) -> RResult<(), AnyErr2> {
+    fs::create_dir_all(path)
+        .change_context(err2!("Failed to create directory"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> RResult<(), AnyErr2> {
+    let target = fs::read_link(from)
+        .change_context(err2!("Failed to read symlink"))
+        .attach_printable_lazy(|| format!("Path: {}", from.display()))?;
+    if fs::symlink_metadata(to).is_ok() {
+        let _ = fs::remove_file(to);
+    }
+    std::os::unix::fs::symlink(&target, to)
+        .change_context(err2!("Failed to create symlink"))
+        .attach_printable_lazy(|| format!("{} -> {}", to.display(), target.display()))
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, to: &Path) -> RResult<(), AnyErr2> {
+    fs::copy(from, to)
+        .map(|_| ())
+        .change_context(err2!("Failed to copy file"))
+        .attach_printable_lazy(|| format!("{} -> {}", from.display(), to.display()))
+}
+
+/// Total size in bytes of all files below `dir`, symlinks aren't followed.
+pub fn dir_size(dir: impl AsRef<Path>) -> RResult<u64, AnyErr2> {
+    let mut size = 0;
+    walk(dir.as_ref(), |_, metadata| {
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+        Ok(true)
+    })?;
+    Ok(size)
+}
+
+/// Removes everything inside `dir`, keeping `dir` itself.
+pub fn remove_contents(dir: impl AsRef<Path>) -> RResult<(), AnyErr2> {
+    let dir = dir.as_ref();
+    let entries = fs::read_dir(dir)
+        .change_context(err2!("Failed to read directory"))
+        .attach_printable_lazy(|| format!("Path: {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.change_context(err2!("Failed to read directory entry"))?;
+        let path = entry.path();
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        let removed = if is_dir {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        removed
+            .change_context(err2!("Failed to remove"))
+            .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        fs::write(dir.path().join("top.txt"), "12345").unwrap();
+        fs::write(dir.path().join("a/one.txt"), "1").unwrap();
+        fs::write(dir.path().join("a/b/two.txt"), "22").unwrap();
+        dir
+    }
+
+    #[rstest]
+    fn copies_and_syncs() {
+        let src = tree();
+        let dst = tempfile::tempdir().unwrap();
+        let target = dst.path().join("copy");
+
+        let mut progress = vec![];
+        let stats =
+            copy_dir_with_progress(src.path(), &target, |p| progress.push(p.path.to_path_buf()))
+                .unwrap();
+        assert_eq!((stats.files_copied, stats.bytes_copied), (3, 8));
+        assert_eq!(
+            progress,
+            vec![
+                PathBuf::from("top.txt"),
+                PathBuf::from("a/one.txt"),
+                PathBuf::from("a/b/two.txt")
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(target.join("a/b/two.txt")).unwrap(),
+            "22"
+        );
+        assert_eq!(dir_size(&target).unwrap(), 8);
+
+        // Nothing changed:
+        let stats = sync_dir(src.path(), &target).unwrap();
+        assert_eq!((stats.files_copied, stats.files_skipped), (0, 3));
+
+        fs::write(src.path().join("a/one.txt"), "changed").unwrap();
+        fs::write(src.path().join("new.txt"), "n").unwrap();
+        let stats = sync_dir(src.path(), &target).unwrap();
+        assert_eq!((stats.files_copied, stats.files_skipped), (2, 2));
+        assert_eq!(
+            fs::read_to_string(target.join("a/one.txt")).unwrap(),
+            "changed"
+        );
+    }
+
+    #[rstest]
+    fn removes_contents() {
+        let dir = tree();
+        remove_contents(dir.path()).unwrap();
+        assert!(dir.path().exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
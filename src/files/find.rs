@@ -0,0 +1,215 @@
+use futures::Stream;
+use glob::{MatchOptions, Pattern};
+use std::path::{Path, PathBuf};
+
+use super::dir::walk;
+use crate::prelude::*;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// Gitignore-style patterns: without a `/` they match the name at any depth, otherwise the path
+/// relative to the root. `**` matches any number of directories, a trailing `/` only matches
+/// directories and a leading `!` re-includes what an earlier pattern excluded.
+pub(crate) struct PathMatcher {
+    rules: Vec<Rule>,
+}
+
+impl PathMatcher {
+    pub(crate) fn new<S: AsRef<str>>(
+        patterns: impl IntoIterator<Item = S>,
+    ) -> RResult<Self, AnyErr2> {
+        let mut rules = vec![];
+        for raw in patterns {
+            let raw = raw.as_ref().trim();
+            if raw.is_empty() || raw.starts_with('#') {
+                continue;
+            }
+            let (negated, rest) = match raw.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, raw),
+            };
+            let (dir_only, rest) = match rest.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, rest),
+            };
+            let glob = match rest.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if rest.contains('/') => rest.to_string(),
+                None => format!("**/{}", rest),
+            };
+            let pattern = Pattern::new(&glob)
+                .change_context(err2!("Invalid glob pattern"))
+                .attach_printable_lazy(|| format!("Pattern: {}", raw))?;
+            rules.push(Rule {
+                pattern,
+                negated,
+                dir_only,
+            });
+        }
+        Ok(PathMatcher { rules })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether the last pattern matching `path`, relative to the root, includes it.
+    pub(crate) fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                (!rule.dir_only || is_dir) && rule.pattern.matches_path_with(path, MATCH_OPTIONS)
+            })
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+/// All files below `root` matching any of `patterns` (all files if empty) and none of
+/// `ignore_patterns`, each directory's files before its subdirectories', sorted by name.
+///
+/// Both use gitignore-style patterns, e.g. `*.whl`, `dist/**/*.tar.gz` or `target/`, ignored
+/// directories aren't walked.
+pub fn find_files<S: AsRef<str>>(
+    root: impl AsRef<Path>,
+    patterns: &[S],
+    ignore_patterns: &[S],
+) -> RResult<Vec<PathBuf>, AnyErr2> {
+    let mut files = vec![];
+    visit_files(root.as_ref(), patterns, ignore_patterns, |path| {
+        files.push(path);
+        true
+    })?;
+    Ok(files)
+}
+
+/// Like [`find_files`] yielding paths as they're found, the walk runs on a blocking thread.
+pub fn find_files_stream(
+    root: impl Into<PathBuf>,
+    patterns: Vec<String>,
+    ignore_patterns: Vec<String>,
+) -> impl Stream<Item = RResult<PathBuf, AnyErr2>> {
+    let root = root.into();
+    let (tx, rx) = tokio::sync::mpsc::channel(64);
+    tokio::task::spawn_blocking(move || {
+        let result = visit_files(&root, &patterns, &ignore_patterns, |path| {
+            // Stops walking once the stream is dropped:
+            tx.blocking_send(Ok(path)).is_ok()
+        });
+        if let Err(report) = result {
+            let _ = tx.blocking_send(Err(report));
+        }
+    });
+    futures::stream::unfold(rx, |mut rx| async move {
+        let item = rx.recv().await?;
+        Some((item, rx))
+    })
+}
+
+fn visit_files<S: AsRef<str>>(
+    root: &Path,
+    patterns: &[S],
+    ignore_patterns: &[S],
+    mut on_file: impl FnMut(PathBuf) -> bool,
+) -> RResult<(), AnyErr2> {
+    let include = PathMatcher::new(patterns)?;
+    let ignore = PathMatcher::new(ignore_patterns)?;
+    let mut stopped = false;
+
+    walk(root, |path, metadata| {
+        if stopped {
+            return Ok(false);
+        }
+        let is_dir = metadata.is_dir();
+        if ignore.matches(path, is_dir) {
+            return Ok(false);
+        }
+        if !is_dir && (include.is_empty() || include.matches(path, false)) {
+            stopped = !on_file(root.join(path));
+        }
+        Ok(true)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use rstest::*;
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "dist/app-1.0.whl",
+            "dist/app-1.0.tar.gz",
+            "dist/old/app-0.9.whl",
+            "src/main.py",
+            "src/__pycache__/main.pyc",
+            "target/debug/app",
+            "README.md",
+        ] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "x").unwrap();
+        }
+        dir
+    }
+
+    fn relative(root: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .into_iter()
+            .map(|f| f.strip_prefix(root).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[rstest]
+    #[case(&["*.whl"], &[], &["dist/app-1.0.whl", "dist/old/app-0.9.whl"])]
+    #[case(&["*.whl"], &["old/"], &["dist/app-1.0.whl"])]
+    #[case(&["/dist/*"], &[], &["dist/app-1.0.tar.gz", "dist/app-1.0.whl"])]
+    #[case(&[], &["target/", "__pycache__/", "dist/", "!dist/"], &[
+        "README.md",
+        "dist/app-1.0.tar.gz",
+        "dist/app-1.0.whl",
+        "dist/old/app-0.9.whl",
+        "src/main.py",
+    ])]
+    #[case(&["src/**"], &["*.pyc"], &["src/main.py"])]
+    fn finds(#[case] patterns: &[&str], #[case] ignore: &[&str], #[case] expected: &[&str]) {
+        let dir = tree();
+        let files = find_files(dir.path(), patterns, ignore).unwrap();
+        assert_eq!(relative(dir.path(), files), expected);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn streams() {
+        let dir = tree();
+        let files: Vec<PathBuf> = find_files_stream(
+            dir.path(),
+            vec!["*.md".to_string(), "*.py".to_string()],
+            vec![],
+        )
+        .map(|file| file.unwrap())
+        .collect()
+        .await;
+        assert_eq!(
+            relative(dir.path(), files),
+            vec!["README.md", "src/main.py"]
+        );
+    }
+
+    #[rstest]
+    fn rejects_invalid_patterns() {
+        assert!(find_files(".", &["[a"], &[]).is_err());
+    }
+}
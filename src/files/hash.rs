@@ -0,0 +1,121 @@
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use super::dir::walk;
+use crate::prelude::*;
+
+/// Hex encoded sha256 of the file's contents, read in chunks so large files are fine.
+pub fn sha256_file(path: impl AsRef<Path>) -> RResult<String, AnyErr2> {
+    let mut hasher = Sha256::new();
+    read_chunks(path.as_ref(), |chunk| hasher.update(chunk))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Hex encoded md5 of the file's contents, e.g. to compare with an S3 ETag. Not for security.
+pub fn md5_file(path: impl AsRef<Path>) -> RResult<String, AnyErr2> {
+    let mut hasher = Md5::new();
+    read_chunks(path.as_ref(), |chunk| hasher.update(chunk))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// A stable hex encoded sha256 over the relative paths and contents of all files below `root`.
+///
+/// Doesn't depend on where `root` is, walk order or modification times, so it works as a cache
+/// key or to check an artifact directory didn't change. Symlinks aren't followed.
+pub fn hash_dir(root: impl AsRef<Path>) -> RResult<String, AnyErr2> {
+    let root = root.as_ref();
+    let mut files = vec![];
+    walk(root, |path, metadata| {
+        if metadata.is_file() {
+            // Forward slashes, so the digest is the same on windows:
+            let relative = path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push(relative);
+        }
+        Ok(true)
+    })?;
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for relative in files {
+        let digest = sha256_file(root.join(&relative))?;
+        hasher.update(relative.as_bytes());
+        hasher.update([0]);
+        hasher.update(digest.as_bytes());
+        hasher.update([b'\n']);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn read_chunks(path: &Path, mut on_chunk: impl FnMut(&[u8])) -> RResult<(), AnyErr2> {
+    let mut file = File::open(path)
+        .change_context(err2!("Failed to open file"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .change_context(err2!("Failed to read file"))
+            .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+        if read == 0 {
+            return Ok(());
+        }
+        on_chunk(&buf[..read]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("", "d41d8cd98f00b204e9800998ecf8427e")]
+    #[case("abc", "900150983cd24fb0d6963f7d28e17f72")]
+    #[case(
+        "12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+        "57edf4a22be3c955ac49da2e2107b67a"
+    )]
+    fn md5_vectors(#[case] input: &str, #[case] expected: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input");
+        std::fs::write(&path, input).unwrap();
+        assert_eq!(md5_file(&path).unwrap(), expected);
+
+        // Same digest when fed in uneven pieces:
+        let mut hasher = Md5::new();
+        for chunk in input.as_bytes().chunks(7) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hex::encode(hasher.finalize()), expected);
+    }
+
+    #[rstest]
+    fn sha256_and_dir_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "abc").unwrap();
+        assert_eq!(
+            sha256_file(dir.path().join("a.txt")).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), "b").unwrap();
+        let digest = hash_dir(dir.path()).unwrap();
+
+        // Same contents elsewhere hash the same:
+        let copy = tempfile::tempdir().unwrap();
+        super::super::copy_dir(dir.path(), copy.path()).unwrap();
+        assert_eq!(hash_dir(copy.path()).unwrap(), digest);
+
+        // Renames and edits change it:
+        std::fs::rename(copy.path().join("sub/b.txt"), copy.path().join("sub/c.txt")).unwrap();
+        assert_ne!(hash_dir(copy.path()).unwrap(), digest);
+    }
+}
@@ -0,0 +1,175 @@
+use futures::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter, Lines};
+
+use crate::prelude::*;
+
+/// Streams the lines of a file without the trailing newline, only buffering one line at a time.
+///
+/// The stream ends after the first error.
+pub async fn read_lines_stream(
+    path: impl AsRef<Path>,
+) -> RResult<impl Stream<Item = RResult<String, AnyErr2>>, AnyErr2> {
+    let path = path.as_ref().to_path_buf();
+    let file = File::open(&path)
+        .await
+        .change_context(err2!("Failed to open file"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+
+    let state: (Option<Lines<BufReader<File>>>, PathBuf, usize) =
+        (Some(BufReader::new(file).lines()), path, 0);
+    Ok(futures::stream::unfold(
+        state,
+        |(lines, path, line_number)| async move {
+            let mut lines = lines?;
+            match lines.next_line().await {
+                Ok(Some(line)) => Some((Ok(line), (Some(lines), path, line_number + 1))),
+                Ok(None) => None,
+                Err(e) => {
+                    let report = Report::new(e)
+                        .change_context(err2!("Failed to read line"))
+                        .attach_printable(format!("Path: {}", path.display()))
+                        .attach_printable(format!("Line: {}", line_number + 1));
+                    Some((Err(report), (None, path, line_number)))
+                }
+            }
+        },
+    ))
+}
+
+/// Appends each item as a line, creating the file if needed.
+pub async fn append_lines(
+    path: impl AsRef<Path>,
+    lines: impl IntoIterator<Item = impl AsRef<str>>,
+) -> RResult<(), AnyErr2> {
+    let path = path.as_ref();
+    let mut writer = open_append(path).await?;
+    for line in lines {
+        write_line(&mut writer, line.as_ref(), path).await?;
+    }
+    flush(&mut writer, path).await
+}
+
+/// Streams one deserialized item per non-empty line, errors name the line.
+pub async fn read_jsonl<T: DeserializeOwned>(
+    path: impl AsRef<Path>,
+) -> RResult<impl Stream<Item = RResult<T, AnyErr2>>, AnyErr2> {
+    let path = path.as_ref().to_path_buf();
+    let lines = read_lines_stream(path.clone()).await?.enumerate();
+    Ok(lines.filter_map(move |(index, line)| {
+        let item = line.and_then(|line| {
+            if line.trim().is_empty() {
+                return Ok(None);
+            }
+            serde_json::from_str(&line)
+                .map(Some)
+                .change_context(err2!("Invalid json line"))
+                .attach_printable_lazy(|| format!("Path: {}", path.display()))
+                .attach_printable_lazy(|| format!("Line: {}", index + 1))
+        });
+        futures::future::ready(item.transpose())
+    }))
+}
+
+/// Appends each item as a line of json, creating the file if needed.
+pub async fn append_jsonl<T: Serialize>(
+    path: impl AsRef<Path>,
+    items: impl IntoIterator<Item = T>,
+) -> RResult<(), AnyErr2> {
+    let path = path.as_ref();
+    let mut writer = open_append(path).await?;
+    for item in items {
+        let line =
+            serde_json::to_string(&item).change_context(err2!("Failed to serialize json line"))?;
+        write_line(&mut writer, &line, path).await?;
+    }
+    flush(&mut writer, path).await
+}
+
+async fn open_append(path: &Path) -> RResult<BufWriter<File>, AnyErr2> {
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .change_context(err2!("Failed to open file"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+    Ok(BufWriter::new(file))
+}
+
+async fn write_line(writer: &mut BufWriter<File>, line: &str, path: &Path) -> RResult<(), AnyErr2> {
+    let written = match writer.write_all(line.as_bytes()).await {
+        Ok(()) => writer.write_all(b"\n").await,
+        Err(e) => Err(e),
+    };
+    written
+        .change_context(err2!("Failed to write line"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))
+}
+
+async fn flush(writer: &mut BufWriter<File>, path: &Path) -> RResult<(), AnyErr2> {
+    writer
+        .flush()
+        .await
+        .change_context(err2!("Failed to write file"))
+        .attach_printable_lazy(|| format!("Path: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Event {
+        id: u32,
+        name: String,
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn appends_and_streams_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lines.txt");
+        append_lines(&path, ["a", "b"]).await.unwrap();
+        append_lines(&path, vec!["c".to_string()]).await.unwrap();
+
+        let lines: Vec<String> = read_lines_stream(&path)
+            .await
+            .unwrap()
+            .map(|line| line.unwrap())
+            .collect()
+            .await;
+        assert_eq!(lines, vec!["a", "b", "c"]);
+        assert!(read_lines_stream(dir.path().join("missing")).await.is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn round_trips_jsonl() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let events = (0..3).map(|id| Event {
+            id,
+            name: format!("event-{}", id),
+        });
+        append_jsonl(&path, events).await.unwrap();
+        append_lines(&path, ["", "not json"]).await.unwrap();
+
+        let items: Vec<RResult<Event, AnyErr2>> = read_jsonl(&path).await.unwrap().collect().await;
+        assert_eq!(items.len(), 4);
+        assert_eq!(
+            items[2].as_ref().unwrap(),
+            &Event {
+                id: 2,
+                name: "event-2".into()
+            }
+        );
+        let report = items[3].as_ref().unwrap_err();
+        assert!(format!("{:?}", report).contains("Line: 5"));
+    }
+}
@@ -0,0 +1,215 @@
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::errors::{ErrorClass, ErrorClassExt};
+use crate::prelude::*;
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An exclusive advisory lock on a file, released when dropped, e.g. so multiple instances of a
+/// CLI don't clobber the same state file.
+///
+/// Uses `flock` on unix and `LockFileEx` on windows, so it only coordinates processes that lock
+/// too. The lock file is created if missing and never removed, its contents are left alone.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl FileLock {
+    /// Blocks up to `timeout` until the lock is free, failing with [`ErrorClass::Timeout`].
+    pub fn acquire(path: impl AsRef<Path>, timeout: Duration) -> RResult<Self, AnyErr2> {
+        let path = path.as_ref();
+        let started = Instant::now();
+        loop {
+            if let Some(lock) = Self::try_acquire(path)? {
+                return Ok(lock);
+            }
+            if started.elapsed() >= timeout {
+                return Err(timed_out(path, timeout));
+            }
+            std::thread::sleep(RETRY_INTERVAL);
+        }
+    }
+
+    /// Like [`FileLock::acquire`] without blocking the runtime while waiting.
+    pub async fn acquire_async(
+        path: impl AsRef<Path>,
+        timeout: Duration,
+    ) -> RResult<Self, AnyErr2> {
+        let path = path.as_ref();
+        let started = Instant::now();
+        loop {
+            if let Some(lock) = Self::try_acquire(path)? {
+                return Ok(lock);
+            }
+            if started.elapsed() >= timeout {
+                return Err(timed_out(path, timeout));
+            }
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+    }
+
+    /// The lock if it's free right now.
+    pub fn try_acquire(path: impl AsRef<Path>) -> RResult<Option<Self>, AnyErr2> {
+        let path = path.as_ref();
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .change_context(err2!("Failed to open lock file"))
+            .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+        match sys::try_lock(&file) {
+            Ok(true) => Ok(Some(FileLock {
+                file,
+                path: path.to_path_buf(),
+            })),
+            Ok(false) => Ok(None),
+            Err(e) => Err(Report::new(e)
+                .change_context(err2!("Failed to lock file"))
+                .attach_printable(format!("Path: {}", path.display()))),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Closing the file releases it as well, this just doesn't depend on when that happens:
+        if let Err(e) = sys::unlock(&self.file) {
+            warn!("Failed to unlock {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn timed_out(path: &Path, timeout: Duration) -> Report<AnyErr2> {
+    Report::new(err2!("Timed out waiting for file lock"))
+        .attach_printable(format!("Path: {}", path.display()))
+        .attach_printable(format!("Timeout: {:?}", timeout))
+        .classify(ErrorClass::Timeout)
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+
+    /// False when another process holds it.
+    pub(super) fn try_lock(file: &File) -> io::Result<bool> {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok(true);
+        }
+        let e = io::Error::last_os_error();
+        match e.kind() {
+            io::ErrorKind::WouldBlock => Ok(false),
+            io::ErrorKind::Interrupted => try_lock(file),
+            _ => Err(e),
+        }
+    }
+
+    pub(super) fn unlock(file: &File) -> io::Result<()> {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::*;
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::{ERROR_IO_PENDING, ERROR_LOCK_VIOLATION, HANDLE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        LockFileEx, UnlockFile, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+    };
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    /// False when another process holds it.
+    pub(super) fn try_lock(file: &File) -> io::Result<bool> {
+        let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+        let locked = unsafe {
+            LockFileEx(
+                file.as_raw_handle() as HANDLE,
+                LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY,
+                0,
+                u32::MAX,
+                u32::MAX,
+                &mut overlapped,
+            )
+        };
+        if locked != 0 {
+            return Ok(true);
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(code)
+                if code == ERROR_LOCK_VIOLATION as i32 || code == ERROR_IO_PENDING as i32 =>
+            {
+                Ok(false)
+            }
+            _ => Err(e),
+        }
+    }
+
+    pub(super) fn unlock(file: &File) -> io::Result<()> {
+        let unlocked =
+            unsafe { UnlockFile(file.as_raw_handle() as HANDLE, 0, 0, u32::MAX, u32::MAX) };
+        if unlocked != 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn excludes_until_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.lock");
+
+        let lock = FileLock::acquire(&path, Duration::from_secs(1)).unwrap();
+        assert_eq!(lock.path(), path);
+        assert!(FileLock::try_acquire(&path).unwrap().is_none());
+
+        let report = FileLock::acquire(&path, Duration::from_millis(100)).unwrap_err();
+        assert_eq!(
+            crate::errors::error_class(&report),
+            Some(ErrorClass::Timeout)
+        );
+
+        drop(lock);
+        assert!(FileLock::try_acquire(&path).unwrap().is_some());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn waits_for_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.lock");
+        let lock = FileLock::acquire(&path, Duration::from_secs(1)).unwrap();
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            drop(lock);
+        });
+
+        FileLock::acquire_async(&path, Duration::from_secs(5))
+            .await
+            .unwrap();
+        release.await.unwrap();
+    }
+}
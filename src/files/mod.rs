@@ -0,0 +1,141 @@
+use crate::prelude::*;
+use std::fs;
+use std::path::Path;
+
+mod archive;
+mod atomic;
+mod cleanup;
+mod config;
+mod dir;
+mod find;
+mod hash;
+mod lines;
+mod lock;
+mod temp;
+mod watch;
+
+pub use archive::{create_tar_gz, create_zip, extract_tar_gz, extract_zip, MAX_ZIP_EXTRACT_SIZE};
+pub use atomic::{write_atomic, write_json_atomic};
+pub use cleanup::{cleanup_dir, cleanup_dir_dry_run, RetentionPolicy};
+pub use config::{load_config, read_file, ConfigLoader};
+pub use dir::{
+    copy_dir, copy_dir_with_progress, dir_size, remove_contents, sync_dir, sync_dir_with_progress,
+    CopyProgress, CopyStats,
+};
+pub use find::{find_files, find_files_stream};
+pub use hash::{hash_dir, md5_file, sha256_file};
+pub use lines::{append_jsonl, append_lines, read_jsonl, read_lines_stream};
+pub use lock::FileLock;
+pub use temp::TempWorkspace;
+pub use watch::{watch, FileEvent, FileEventKind, Watcher};
+
+/// Exits the process if any of `files` is missing, see [`check_files_exist`] to handle it instead.
+/// Directories count as files here and paths aren't globs.
+pub fn assert_files_exist(files: Vec<&str>) {
+    if let Err(report) = check_exist(&files, Kind::Any) {
+        error!("Error: Required files not found: {:?}", report);
+        std::process::exit(1);
+    }
+}
+
+/// Errors listing every missing file. Glob patterns like `dist/*.whl` need at least one match,
+/// paths that exist as written match themselves, e.g. `data[1].csv`.
+pub fn check_files_exist<S: AsRef<str>>(files: &[S]) -> RResult<(), AnyErr> {
+    check_exist(files, Kind::File)
+}
+
+/// Like [`check_files_exist`] for directories.
+pub fn check_dirs_exist<S: AsRef<str>>(dirs: &[S]) -> RResult<(), AnyErr> {
+    check_exist(dirs, Kind::Dir)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Exists at all, without globbing.
+    Any,
+    File,
+    Dir,
+}
+
+impl Kind {
+    fn matches(self, path: &Path) -> bool {
+        match self {
+            Kind::Any => fs::metadata(path).is_ok(),
+            Kind::File => path.is_file(),
+            Kind::Dir => path.is_dir(),
+        }
+    }
+}
+
+fn check_exist<S: AsRef<str>>(paths: &[S], kind: Kind) -> RResult<(), AnyErr> {
+    let mut missing = vec![];
+    for path in paths {
+        let path = path.as_ref();
+        let found = kind.matches(Path::new(path))
+            || (kind != Kind::Any
+                && path.contains(['*', '?', '['])
+                // Not being a valid pattern just means it's missing:
+                && glob::glob(path).is_ok_and(|mut matches| {
+                    matches.any(|matched| matched.is_ok_and(|matched| kind.matches(&matched)))
+                }));
+        if !found {
+            missing.push(path);
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let message = match kind {
+        Kind::Dir => "Required directories not found",
+        Kind::Any | Kind::File => "Required files not found",
+    };
+    let mut report = anyerr!(message);
+    for path in missing {
+        report = report.attach_printable(format!("Missing: {}", path));
+    }
+    Err(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn checks_files_and_dirs() {
+        let workspace = TempWorkspace::new("rutils-test-").unwrap();
+        workspace.write("dist/app-1.0.whl", "").unwrap();
+        let root = workspace.path().display().to_string();
+        let path = |rel: &str| format!("{}/{}", root, rel);
+
+        check_files_exist(&[path("dist/app-1.0.whl"), path("dist/*.whl")]).unwrap();
+        check_dirs_exist(&[path("dist"), path("d*")]).unwrap();
+
+        let report = check_files_exist(&[
+            path("dist"),
+            path("dist/*.tar.gz"),
+            path("dist/app-1.0.whl"),
+        ])
+        .unwrap_err();
+        let debug = format!("{:?}", report);
+        assert!(
+            debug.contains(&format!("Missing: {}", path("dist"))),
+            "{}",
+            debug
+        );
+        assert!(debug.contains(&format!("Missing: {}", path("dist/*.tar.gz"))));
+        assert!(!debug.contains(&format!("Missing: {}", path("dist/app-1.0.whl"))));
+        assert!(check_dirs_exist(&[path("dist/app-1.0.whl")]).is_err());
+
+        // Brackets are only a pattern when the path doesn't exist as written:
+        workspace.write("data[1].csv", "").unwrap();
+        check_files_exist(&[path("data[1].csv"), path("data[[]1].csv")]).unwrap();
+        let report = check_files_exist(&[path("data[2"), path("data[2].csv")]).unwrap_err();
+        assert!(format!("{:?}", report).contains(&format!("Missing: {}", path("data[2"))));
+
+        // Like before, anything existing passes, directories too and no globbing:
+        check_exist(&[path("dist"), path("data[1].csv")], Kind::Any).unwrap();
+        assert!(check_exist(&[path("dist/*.whl")], Kind::Any).is_err());
+    }
+}
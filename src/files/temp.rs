@@ -0,0 +1,80 @@
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+
+/// A temporary directory that's removed with everything in it when dropped, e.g. for tests or
+/// for staging a docker build context.
+#[derive(Debug)]
+pub struct TempWorkspace {
+    dir: tempfile::TempDir,
+}
+
+impl TempWorkspace {
+    /// Creates a new directory in the system temp dir, its name starting with `prefix`.
+    pub fn new(prefix: &str) -> RResult<Self, AnyErr2> {
+        let dir = tempfile::Builder::new()
+            .prefix(prefix)
+            .tempdir()
+            .change_context(err2!("Failed to create temp directory"))
+            .attach_printable_lazy(|| format!("Prefix: {}", prefix))?;
+        Ok(TempWorkspace { dir })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Writes `contents` to `relpath` inside the workspace, creating parent directories.
+    ///
+    /// Returns the full path.
+    pub fn write(
+        &self,
+        relpath: impl AsRef<Path>,
+        contents: impl AsRef<[u8]>,
+    ) -> RResult<PathBuf, AnyErr2> {
+        let path = self.path().join(relpath);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .change_context(err2!("Failed to create directory"))
+                .attach_printable_lazy(|| format!("Path: {}", parent.display()))?;
+        }
+        std::fs::write(&path, contents)
+            .change_context(err2!("Failed to write file"))
+            .attach_printable_lazy(|| format!("Path: {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Disables the cleanup and returns the path, e.g. to inspect a failed test's files.
+    pub fn keep(self) -> PathBuf {
+        self.dir.into_path()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn cleans_up_unless_kept() {
+        let workspace = TempWorkspace::new("rutils-test-").unwrap();
+        let file = workspace.write("nested/dir/file.txt", "hello").unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "hello");
+        assert!(workspace
+            .path()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("rutils-test-"));
+
+        let root = workspace.path().to_path_buf();
+        drop(workspace);
+        assert!(!root.exists());
+
+        let workspace = TempWorkspace::new("rutils-test-").unwrap();
+        workspace.write("file.txt", b"kept").unwrap();
+        let root = workspace.keep();
+        assert!(root.join("file.txt").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use futures::Stream;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+
+use super::dir::walk;
+use super::find::PathMatcher;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileEventKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEvent {
+    pub kind: FileEventKind,
+    pub path: PathBuf,
+}
+
+/// Watches files and directories for changes by polling, so it works the same everywhere,
+/// including network and docker mounted filesystems.
+///
+/// Changes are debounced: events are only emitted once nothing changed for the debounce period,
+/// so e.g. an editor's save or a `git checkout` comes through as one batch.
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    poll_interval: Duration,
+    debounce: Duration,
+    patterns: Vec<String>,
+    ignore_patterns: Vec<String>,
+}
+
+type Snapshot = HashMap<PathBuf, (Option<SystemTime>, u64)>;
+
+impl Watcher {
+    pub fn new(paths: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        Watcher {
+            paths: paths.into_iter().map(Into::into).collect(),
+            poll_interval: Duration::from_millis(500),
+            debounce: Duration::from_millis(200),
+            patterns: vec![],
+            ignore_patterns: vec![],
+        }
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Only report files matching any of these gitignore-style patterns, see [`super::find_files`].
+    pub fn patterns(mut self, patterns: &[&str]) -> Self {
+        self.patterns = patterns.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Never report files matching these gitignore-style patterns, e.g. `__pycache__/`.
+    pub fn ignore(mut self, ignore_patterns: &[&str]) -> Self {
+        self.ignore_patterns = ignore_patterns.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Starts watching, changes from before this call aren't reported.
+    ///
+    /// Polling stops when the stream is dropped.
+    pub fn stream(self) -> RResult<impl Stream<Item = FileEvent>, AnyErr2> {
+        let include = PathMatcher::new(&self.patterns)?;
+        let ignore = PathMatcher::new(&self.ignore_patterns)?;
+        let mut previous = snapshot(&self.paths, &include, &ignore);
+        let (poll_interval, debounce) = (self.poll_interval, self.debounce);
+        let scan = Arc::new((self.paths, include, ignore));
+
+        let (tx, rx) = tokio::sync::mpsc::channel(256);
+        tokio::spawn(async move {
+            let mut pending: BTreeMap<PathBuf, FileEventKind> = BTreeMap::new();
+            let mut last_change = Instant::now();
+            let mut interval = tokio::time::interval(poll_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if tx.is_closed() {
+                    return;
+                }
+
+                let scanning = scan.clone();
+                let Ok(current) = tokio::task::spawn_blocking(move || {
+                    let (paths, include, ignore) = &*scanning;
+                    snapshot(paths, include, ignore)
+                })
+                .await
+                else {
+                    return;
+                };
+
+                let changes = diff(&previous, &current);
+                previous = current;
+                if !changes.is_empty() {
+                    last_change = Instant::now();
+                    for (path, kind) in changes {
+                        coalesce(&mut pending, path, kind);
+                    }
+                }
+
+                if !pending.is_empty() && last_change.elapsed() >= debounce {
+                    for (path, kind) in std::mem::take(&mut pending) {
+                        if tx.send(FileEvent { kind, path }).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(futures::stream::unfold(rx, |mut rx| async move {
+            let event = rx.recv().await?;
+            Some((event, rx))
+        }))
+    }
+}
+
+/// Watches `paths` with the default poll interval and debounce, see [`Watcher`] to configure.
+pub fn watch(
+    paths: impl IntoIterator<Item = impl Into<PathBuf>>,
+) -> RResult<impl Stream<Item = FileEvent>, AnyErr2> {
+    Watcher::new(paths).stream()
+}
+
+fn snapshot(paths: &[PathBuf], include: &PathMatcher, ignore: &PathMatcher) -> Snapshot {
+    let mut files = Snapshot::new();
+    let mut record = |path: PathBuf, relative: &Path, metadata: &std::fs::Metadata| {
+        if include.is_empty() || include.matches(relative, false) {
+            files.insert(path, (metadata.modified().ok(), metadata.len()));
+        }
+    };
+
+    for root in paths {
+        let Ok(metadata) = std::fs::metadata(root) else {
+            // Missing for now, its files show up as created once it exists:
+            continue;
+        };
+        if metadata.is_file() {
+            let name = Path::new(root.file_name().unwrap_or_default());
+            if !ignore.matches(name, false) {
+                record(root.clone(), name, &metadata);
+            }
+            continue;
+        }
+
+        let result = walk(root, |relative, metadata| {
+            if ignore.matches(relative, metadata.is_dir()) {
+                return Ok(false);
+            }
+            if metadata.is_file() {
+                record(root.join(relative), relative, metadata);
+            }
+            Ok(true)
+        });
+        if let Err(report) = result {
+            warn!(
+                "Failed to scan {} for changes: {:?}",
+                root.display(),
+                report
+            );
+        }
+    }
+    files
+}
+
+fn diff(previous: &Snapshot, current: &Snapshot) -> Vec<(PathBuf, FileEventKind)> {
+    let mut changes = vec![];
+    for (path, state) in current {
+        match previous.get(path) {
+            None => changes.push((path.clone(), FileEventKind::Created)),
+            Some(old) if old != state => changes.push((path.clone(), FileEventKind::Modified)),
+            Some(_) => {}
+        }
+    }
+    for path in previous.keys() {
+        if !current.contains_key(path) {
+            changes.push((path.clone(), FileEventKind::Removed));
+        }
+    }
+    changes
+}
+
+/// Folds a new change into one not reported yet, e.g. created then removed is no change at all.
+fn coalesce(pending: &mut BTreeMap<PathBuf, FileEventKind>, path: PathBuf, kind: FileEventKind) {
+    use FileEventKind::*;
+    let merged = match (pending.get(&path), kind) {
+        (None, kind) => Some(kind),
+        (Some(Created), Modified) => Some(Created),
+        (Some(Created), Removed) => None,
+        (Some(Removed), Created) => Some(Modified),
+        (Some(_), kind) => Some(kind),
+    };
+    match merged {
+        Some(kind) => pending.insert(path, kind),
+        None => pending.remove(&path),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use rstest::*;
+
+    async fn next_batch(
+        events: &mut (impl Stream<Item = FileEvent> + Unpin),
+        count: usize,
+    ) -> Vec<FileEvent> {
+        let mut batch = vec![];
+        for _ in 0..count {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+                .await
+                .unwrap()
+                .unwrap();
+            batch.push(event);
+        }
+        batch
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reports_debounced_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("config.toml"), "a = 1").unwrap();
+        std::fs::write(dir.path().join("old.toml"), "").unwrap();
+
+        let events = Watcher::new([dir.path()])
+            .poll_interval(Duration::from_millis(10))
+            .debounce(Duration::from_millis(50))
+            .patterns(&["*.toml"])
+            .stream()
+            .unwrap();
+        let mut events = Box::pin(events);
+
+        std::fs::write(dir.path().join("config.toml"), "a = 22").unwrap();
+        std::fs::write(dir.path().join("new.toml"), "").unwrap();
+        std::fs::write(dir.path().join("ignored.txt"), "").unwrap();
+        std::fs::remove_file(dir.path().join("old.toml")).unwrap();
+
+        let batch = next_batch(&mut events, 3).await;
+        let kinds: Vec<(String, FileEventKind)> = batch
+            .into_iter()
+            .map(|e| {
+                (
+                    e.path.file_name().unwrap().to_string_lossy().to_string(),
+                    e.kind,
+                )
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("config.toml".to_string(), FileEventKind::Modified),
+                ("new.toml".to_string(), FileEventKind::Created),
+                ("old.toml".to_string(), FileEventKind::Removed),
+            ]
+        );
+    }
+
+    #[rstest]
+    fn coalesces() {
+        let mut pending = BTreeMap::new();
+        let path = PathBuf::from("a");
+        coalesce(&mut pending, path.clone(), FileEventKind::Created);
+        coalesce(&mut pending, path.clone(), FileEventKind::Modified);
+        assert_eq!(pending.get(&path), Some(&FileEventKind::Created));
+        coalesce(&mut pending, path.clone(), FileEventKind::Removed);
+        assert!(pending.is_empty());
+    }
+}
@@ -0,0 +1,414 @@
+use futures::StreamExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::clock::{system_clock, Clock};
+use crate::prelude::*;
+use crate::redis_manager::RedisLike;
+
+/// How a flag is rolled out, stored as json in redis.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlagDefinition {
+    /// Off means off for everyone, even the allowed ids.
+    pub enabled: bool,
+    /// The share of ids in `0-100` the flag is on for, all when `None`. Each id stays in or out
+    /// as the share grows, and contexts without an id are in only at 100.
+    pub percentage: Option<f64>,
+    /// Ids the flag is on for whatever the percentage, e.g. the team's accounts.
+    pub allow: Vec<String>,
+}
+
+impl FlagDefinition {
+    pub fn on() -> Self {
+        FlagDefinition {
+            enabled: true,
+            ..FlagDefinition::default()
+        }
+    }
+
+    pub fn off() -> Self {
+        FlagDefinition::default()
+    }
+
+    /// On for `percentage` of ids, see [`Self::percentage`].
+    pub fn rollout(percentage: f64) -> Self {
+        FlagDefinition {
+            enabled: true,
+            percentage: Some(percentage.clamp(0.0, 100.0)),
+            ..FlagDefinition::default()
+        }
+    }
+
+    pub fn allow(mut self, id: impl Into<String>) -> Self {
+        self.allow.push(id.into());
+        self
+    }
+
+    /// Whether the flag called `flag` is on for `context`.
+    pub fn evaluate(&self, flag: &str, context: &FlagContext) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let Some(id) = &context.id else {
+            return self.percentage.is_none_or(|percentage| percentage >= 100.0);
+        };
+        if self.allow.contains(id) {
+            return true;
+        }
+        match self.percentage {
+            Some(percentage) => (bucket(flag, id) as f64) < percentage * 100.0,
+            None => true,
+        }
+    }
+}
+
+/// Where `id` falls in `0..10000` for `flag`, the same in every process. Salting with the flag
+/// keeps the same ids from getting every new flag first.
+fn bucket(flag: &str, id: &str) -> u64 {
+    let digest = Sha256::digest(format!("{}:{}", flag, id));
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes) % 10_000
+}
+
+/// Who a flag is checked for, the id keeping percentage rollouts stable, e.g. a user id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlagContext {
+    id: Option<String>,
+}
+
+impl FlagContext {
+    pub fn new(id: impl Into<String>) -> Self {
+        FlagContext {
+            id: Some(id.into()),
+        }
+    }
+
+    /// A context without an id, only in percentage rollouts at 100.
+    pub fn anonymous() -> Self {
+        FlagContext::default()
+    }
+
+    pub fn get_id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+}
+
+/// Definitions with when they were read, `None` when the flag isn't defined. Invalidated ones
+/// have no read time, they're only used when redis fails.
+type Cache = HashMap<String, (Option<FlagDefinition>, Option<Instant>)>;
+
+/// Feature flags defined in redis as `{prefix}:{flag}` json [`FlagDefinition`]s, cached for
+/// [`Self::ttl`]. [`Self::define`] publishes changes on `{prefix}:changed`, which
+/// [`Self::listen`] picks up to invalidate cached definitions right away. When redis fails the
+/// last definition read is used, flags never read are off. Clones share the cache.
+#[derive(Clone)]
+pub struct FeatureFlags {
+    redis: Arc<dyn RedisLike>,
+    prefix: String,
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl std::fmt::Debug for FeatureFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FeatureFlags")
+            .field("prefix", &self.prefix)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+impl FeatureFlags {
+    pub fn new(redis: impl RedisLike + 'static) -> Self {
+        FeatureFlags {
+            redis: Arc::new(redis),
+            prefix: "flags".to_string(),
+            ttl: Duration::from_secs(30),
+            clock: system_clock(),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Prefix of the flag keys and change channel, `flags` by default.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// How long a definition is used before reading it again, 30s by default. Also bounds how
+    /// long a missed change notification goes unnoticed.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn key(&self, flag: &str) -> String {
+        format!("{}:{}", self.prefix, flag)
+    }
+
+    fn channel(&self) -> String {
+        format!("{}:changed", self.prefix)
+    }
+
+    /// Whether `flag` is on for `context`, off when it isn't defined or can't be read.
+    pub async fn is_enabled(&self, flag: &str, context: &FlagContext) -> bool {
+        match self.definition(flag).await {
+            Ok(definition) => {
+                definition.is_some_and(|definition| definition.evaluate(flag, context))
+            }
+            Err(e) => {
+                warn!("Failed to read flag {}, treating it as off: {:?}", flag, e);
+                false
+            }
+        }
+    }
+
+    /// The current definition of `flag`, `None` when it isn't defined. The last one read while
+    /// redis fails.
+    pub async fn definition(&self, flag: &str) -> RResult<Option<FlagDefinition>, AnyErr> {
+        let now = self.clock.now();
+        let cached = self.cache.lock().get(flag).cloned();
+        if let Some((definition, Some(read_at))) = &cached {
+            if now.duration_since(*read_at) < self.ttl {
+                return Ok(definition.clone());
+            }
+        }
+        let read = self
+            .redis
+            .get(&self.key(flag))
+            .await
+            .change_context(AnyErr)
+            .attach_printable_lazy(|| format!("Failed to read flag {}", flag));
+        let json = match (read, cached) {
+            (Ok(json), _) => json,
+            // Rather than flipping flags whenever redis blips:
+            (Err(report), Some((definition, _))) => {
+                warn!("Using the last definition read: {:?}", report);
+                return Ok(definition);
+            }
+            (Err(report), None) => return Err(report),
+        };
+        let definition = json
+            .map(|json| {
+                serde_json::from_str::<FlagDefinition>(&json)
+                    .change_context(AnyErr)
+                    .attach_printable_lazy(|| format!("Invalid definition of flag {}", flag))
+                    .classify(ErrorClass::InvalidInput)
+            })
+            .transpose()?;
+        self.cache
+            .lock()
+            .insert(flag.to_string(), (definition.clone(), Some(now)));
+        Ok(definition)
+    }
+
+    /// Stores the definition and tells listening processes it changed.
+    pub async fn define(&self, flag: &str, definition: &FlagDefinition) -> RResult<(), AnyErr> {
+        let json = serde_json::to_string(definition).change_context(AnyErr)?;
+        self.redis
+            .set(&self.key(flag), &json, None)
+            .await
+            .change_context(AnyErr)
+            .attach_printable_lazy(|| format!("Failed to define flag {}", flag))?;
+        self.invalidate(flag);
+        self.redis
+            .publish(&self.channel(), flag)
+            .await
+            .change_context(AnyErr)
+            .attach_printable("Failed to publish the flag change")?;
+        info!(flag, ?definition, "Defined flag");
+        Ok(())
+    }
+
+    /// Makes the next check read the current definition, the cached one is only used if that
+    /// fails.
+    pub fn invalidate(&self, flag: &str) {
+        if let Some((_, read_at)) = self.cache.lock().get_mut(flag) {
+            *read_at = None;
+        }
+    }
+
+    fn invalidate_all(&self) {
+        for (_, read_at) in self.cache.lock().values_mut() {
+            *read_at = None;
+        }
+    }
+
+    /// Invalidates cached definitions as changes are published, until the listener is dropped.
+    /// Stays subscribed, resubscribing when the connection is lost.
+    pub fn listen(&self) -> FlagListener {
+        let cancel = CancellationToken::new();
+        let (flags, cancelled) = (self.clone(), cancel.clone());
+        tokio::spawn(async move {
+            let channel = flags.channel();
+            loop {
+                let subscribed = tokio::select! {
+                    _ = cancelled.cancelled() => break,
+                    subscribed = flags.redis.subscribe(&channel) => subscribed,
+                };
+                match subscribed {
+                    Ok(mut changes) => {
+                        // Changes may have been missed while not subscribed:
+                        flags.invalidate_all();
+                        loop {
+                            let flag = tokio::select! {
+                                _ = cancelled.cancelled() => return,
+                                flag = changes.next() => flag,
+                            };
+                            let Some(flag) = flag else {
+                                break;
+                            };
+                            debug!("Flag {} changed", flag);
+                            flags.invalidate(&flag);
+                        }
+                        warn!("Lost the subscription to flag changes, resubscribing");
+                    }
+                    Err(e) => warn!("Failed to subscribe to flag changes: {:?}", e),
+                }
+                // Keeps a failing redis from being hammered:
+                tokio::select! {
+                    _ = cancelled.cancelled() => break,
+                    _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                }
+            }
+        });
+        FlagListener {
+            _guard: cancel.drop_guard(),
+        }
+    }
+}
+
+/// Keeps [`FeatureFlags::listen`] listening until dropped.
+pub struct FlagListener {
+    _guard: DropGuard,
+}
+
+/// The feature flag items, also part of [`crate::prelude`].
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{FeatureFlags, FlagContext, FlagDefinition};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::prelude::*;
+
+    #[rstest]
+    fn rolls_out_by_id() {
+        let ids = (0..2000).map(|i| FlagContext::new(format!("user-{}", i)));
+        let rollout = FlagDefinition::rollout(30.0).allow("user-vip");
+        let enabled = ids
+            .clone()
+            .filter(|id| rollout.evaluate("new-checkout", id))
+            .count();
+        assert!((500..700).contains(&enabled), "{} of 2000", enabled);
+
+        // Growing the rollout keeps everyone already in:
+        let wider = FlagDefinition::rollout(60.0);
+        assert!(ids
+            .filter(|id| rollout.evaluate("new-checkout", id))
+            .all(|id| wider.evaluate("new-checkout", &id)));
+
+        assert!(rollout.evaluate("new-checkout", &FlagContext::new("user-vip")));
+        assert!(!rollout.evaluate("new-checkout", &FlagContext::anonymous()));
+        assert!(FlagDefinition::on().evaluate("new-checkout", &FlagContext::anonymous()));
+        assert!(!FlagDefinition::off()
+            .allow("user-vip")
+            .evaluate("new-checkout", &FlagContext::new("user-vip")));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn caches_until_changed(memory_redis: MemoryRedis, mock_clock: MockClock) {
+        let user = FlagContext::new("user-1");
+        let flags = FeatureFlags::new(memory_redis.clone())
+            .ttl(Duration::from_secs(60))
+            .clock(mock_clock.shared());
+        assert!(!flags.is_enabled("dark-mode", &user).await);
+
+        // Another process turns it on, seen once the cached "undefined" expires:
+        let admin = FeatureFlags::new(memory_redis.clone());
+        admin
+            .define("dark-mode", &FlagDefinition::on())
+            .await
+            .unwrap();
+        assert!(!flags.is_enabled("dark-mode", &user).await);
+        mock_clock.advance(Duration::from_secs(60));
+        assert!(flags.is_enabled("dark-mode", &user).await);
+
+        // Or right away while listening:
+        let _listener = flags.listen();
+        let turned_off = async {
+            loop {
+                admin
+                    .define("dark-mode", &FlagDefinition::off())
+                    .await
+                    .unwrap();
+                if !flags.is_enabled("dark-mode", &user).await {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), turned_off)
+            .await
+            .unwrap();
+
+        memory_redis
+            .set("flags:broken", "{not json", None)
+            .await
+            .unwrap();
+        assert!(!flags.is_enabled("broken", &user).await);
+        let report = flags.definition("broken").await.unwrap_err();
+        assert_eq!(
+            crate::errors::error_class(&report),
+            Some(ErrorClass::InvalidInput)
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn keeps_last_definitions_while_redis_fails(
+        memory_redis: MemoryRedis,
+        mock_clock: MockClock,
+    ) {
+        let user = FlagContext::new("user-1");
+        let flags = FeatureFlags::new(memory_redis.clone())
+            .ttl(Duration::from_secs(60))
+            .clock(mock_clock.shared());
+        flags
+            .define("dark-mode", &FlagDefinition::on())
+            .await
+            .unwrap();
+        assert!(flags.is_enabled("dark-mode", &user).await);
+
+        memory_redis.set_unreachable(true);
+        mock_clock.advance(Duration::from_secs(60));
+        flags.invalidate("dark-mode");
+        assert!(flags.is_enabled("dark-mode", &user).await);
+        // Never read, so off:
+        assert!(flags.definition("new-checkout").await.is_err());
+        assert!(!flags.is_enabled("new-checkout", &user).await);
+
+        memory_redis.set_unreachable(false);
+        memory_redis
+            .set("flags:dark-mode", "{}", None)
+            .await
+            .unwrap();
+        assert!(!flags.is_enabled("dark-mode", &user).await);
+    }
+}
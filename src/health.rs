@@ -0,0 +1,446 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::clock::{system_clock, Clock};
+use crate::endpoints::ApiClient;
+use crate::metrics::Metrics;
+use crate::prelude::*;
+use crate::redis_manager::RedisLike;
+
+/// Liveness checks failing means the process should be restarted, readiness checks failing
+/// that it shouldn't get traffic for now, e.g. while a dependency is down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    Liveness,
+    Readiness,
+}
+
+/// The outcome of one check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub kind: CheckKind,
+    pub healthy: bool,
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+/// The outcome of the checks of [`HealthRegistry::check_all`] or [`HealthRegistry::check`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    /// Whether every check passed.
+    pub healthy: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+type CheckFn = Arc<dyn Fn() -> BoxFuture<'static, RResult<(), AnyErr>> + Send + Sync>;
+
+#[derive(Clone)]
+struct Check {
+    name: String,
+    kind: CheckKind,
+    check: CheckFn,
+}
+
+/// Where subsystems register their health checks, for [`Self::serve`] or reporting them some
+/// other way. Clones share the checks, so each subsystem can register its own:
+///
+/// ```ignore
+/// let health = HealthRegistry::new();
+/// health.redis("redis", redis.clone());
+/// health.upstream("billing", billing_client.clone(), "/status");
+/// let heartbeat = health.heartbeat("reports worker", Duration::from_secs(60));
+/// let _server = health.serve("0.0.0.0:8080").await?;
+/// ```
+#[derive(Clone)]
+pub struct HealthRegistry {
+    checks: Arc<Mutex<Vec<Check>>>,
+    timeout: Duration,
+    clock: Arc<dyn Clock>,
+    metrics: Option<Metrics>,
+}
+
+impl std::fmt::Debug for HealthRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = self
+            .checks
+            .lock()
+            .iter()
+            .map(|check| check.name.clone())
+            .collect::<Vec<_>>();
+        f.debug_struct("HealthRegistry")
+            .field("checks", &names)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Default for HealthRegistry {
+    fn default() -> Self {
+        HealthRegistry::new()
+    }
+}
+
+impl HealthRegistry {
+    pub fn new() -> Self {
+        HealthRegistry {
+            checks: Arc::new(Mutex::new(vec![])),
+            timeout: Duration::from_secs(5),
+            clock: system_clock(),
+            metrics: None,
+        }
+    }
+
+    /// How long a check may take before it counts as failed, 5s by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Where [`Self::heartbeat`] gets the time from.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Also serves `GET /metrics` from these metrics in the Prometheus format, usually
+    /// [`crate::metrics::global`].
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Adds a check, replacing one of the same name.
+    pub fn register<F, Fut>(&self, name: impl Into<String>, kind: CheckKind, check: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = RResult<(), AnyErr>> + Send + 'static,
+    {
+        let check = Check {
+            name: name.into(),
+            kind,
+            check: Arc::new(move || check().boxed()),
+        };
+        let mut checks = self.checks.lock();
+        checks.retain(|existing| existing.name != check.name);
+        checks.push(check);
+    }
+
+    /// A readiness check that redis answers.
+    pub fn redis(&self, name: impl Into<String>, redis: impl RedisLike + 'static) {
+        let redis = Arc::new(redis);
+        self.register(name, CheckKind::Readiness, move || {
+            let redis = redis.clone();
+            async move {
+                redis
+                    .get("health:ping")
+                    .await
+                    .change_context(AnyErr)
+                    .attach_printable("Redis didn't answer")?;
+                Ok(())
+            }
+        });
+    }
+
+    /// A readiness check that `GET {path}` of the client's upstream succeeds.
+    pub fn upstream(&self, name: impl Into<String>, client: ApiClient, path: &str) {
+        let path = path.to_string();
+        self.register(name, CheckKind::Readiness, move || {
+            let request = client.endpoint(&path).method(Method::GET);
+            async move {
+                request.send_bytes().await.change_context(AnyErr)?;
+                Ok(())
+            }
+        });
+    }
+
+    /// A liveness check for a background worker, failing once it didn't call
+    /// [`Heartbeat::beat`] for `max_age`, e.g. because it's stuck. Counts from now.
+    pub fn heartbeat(&self, name: impl Into<String>, max_age: Duration) -> Heartbeat {
+        let heartbeat = Heartbeat {
+            last: Arc::new(Mutex::new(self.clock.now())),
+            clock: self.clock.clone(),
+        };
+        let beats = heartbeat.clone();
+        self.register(name, CheckKind::Liveness, move || {
+            let age = beats.age();
+            async move {
+                if age > max_age {
+                    return Err(err!(AnyErr, "No heartbeat for {:?}", age)
+                        .attach_printable(format!("Max age: {:?}", max_age)));
+                }
+                Ok(())
+            }
+        });
+        heartbeat
+    }
+
+    /// Runs every check at the same time.
+    pub async fn check_all(&self) -> HealthReport {
+        self.run(|_| true).await
+    }
+
+    /// Runs the checks of `kind`, readiness includes liveness as a stuck process isn't ready
+    /// either.
+    pub async fn check(&self, kind: CheckKind) -> HealthReport {
+        self.run(|check| kind == CheckKind::Readiness || check.kind == kind)
+            .await
+    }
+
+    async fn run(&self, include: impl Fn(&Check) -> bool) -> HealthReport {
+        let checks = self
+            .checks
+            .lock()
+            .iter()
+            .filter(|check| include(check))
+            .cloned()
+            .collect::<Vec<_>>();
+        let checks = futures::future::join_all(checks.into_iter().map(|check| async move {
+            let started = Instant::now();
+            let error = match tokio::time::timeout(self.timeout, (check.check)()).await {
+                Ok(Ok(())) => None,
+                // Reports are served to anyone asking, only the logs get their attachments:
+                Ok(Err(e)) => {
+                    warn!("Health check {} failed: {:?}", check.name, e);
+                    Some(e.current_context().to_string())
+                }
+                Err(_) => {
+                    warn!(
+                        "Health check {} timed out after {:?}",
+                        check.name, self.timeout
+                    );
+                    Some(format!("Timed out after {:?}", self.timeout))
+                }
+            };
+            CheckResult {
+                name: check.name,
+                kind: check.kind,
+                healthy: error.is_none(),
+                error,
+                elapsed_ms: started.elapsed().as_millis() as u64,
+            }
+        }))
+        .await;
+        HealthReport {
+            healthy: checks.iter().all(|check| check.healthy),
+            checks,
+        }
+    }
+
+    /// Serves `GET /healthz` with the liveness and `GET /readyz` with the readiness checks as
+    /// json, with status 503 when one failed. Stops when the server is dropped.
+    pub async fn serve(&self, addr: &str) -> RResult<HealthServer, AnyErr> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .change_context(AnyErr)
+            .attach_printable_lazy(|| format!("Failed to listen on {}", addr))?;
+        let local_addr = listener.local_addr().change_context(AnyErr)?;
+        let cancel = CancellationToken::new();
+        let (registry, cancelled) = (self.clone(), cancel.clone());
+        tokio::spawn(async move {
+            loop {
+                let conn = tokio::select! {
+                    _ = cancelled.cancelled() => break,
+                    conn = listener.accept() => conn,
+                };
+                let Ok((conn, _)) = conn else {
+                    continue;
+                };
+                let registry = registry.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = respond(&registry, conn).await {
+                        debug!("Failed to answer a health request: {}", e);
+                    }
+                });
+            }
+        });
+        info!("Serving health checks on {}", local_addr);
+        Ok(HealthServer {
+            addr: local_addr,
+            _guard: cancel.drop_guard(),
+        })
+    }
+}
+
+/// How long a client gets to send its request, so idle connections don't pile up.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn respond(
+    registry: &HealthRegistry,
+    mut conn: tokio::net::TcpStream,
+) -> std::io::Result<()> {
+    // Only the request line matters:
+    let mut buf = vec![0; 4096];
+    let read = tokio::time::timeout(READ_TIMEOUT, conn.read(&mut buf))
+        .await
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+    let request = String::from_utf8_lossy(&buf[..read]);
+    let mut parts = request.split_whitespace();
+    let (method, path) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    let path = path.split('?').next().unwrap_or_default();
+    let kind = match path {
+        "/healthz" | "/livez" => Some(CheckKind::Liveness),
+        "/readyz" => Some(CheckKind::Readiness),
+        _ => None,
+    };
+    let json = "application/json";
+    let readable = method == "GET" || method == "HEAD";
+    let (status, content_type, body) = match (kind, &registry.metrics) {
+        (Some(kind), _) if readable => {
+            let report = registry.check(kind).await;
+            let status = match report.healthy {
+                true => "200 OK",
+                false => "503 Service Unavailable",
+            };
+            (
+                status,
+                json,
+                serde_json::to_string(&report).unwrap_or_default(),
+            )
+        }
+        (None, Some(metrics)) if readable && path == "/metrics" => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            metrics.render_prometheus(),
+        ),
+        (Some(_), _) => ("405 Method Not Allowed", json, String::new()),
+        _ => ("404 Not Found", json, String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        if method == "HEAD" { "" } else { body.as_str() }
+    );
+    conn.write_all(response.as_bytes()).await?;
+    conn.shutdown().await
+}
+
+/// Serves the health endpoints until dropped, see [`HealthRegistry::serve`].
+pub struct HealthServer {
+    addr: SocketAddr,
+    _guard: DropGuard,
+}
+
+impl HealthServer {
+    /// Where it listens, with the port picked when binding port 0.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+/// Tells a [`HealthRegistry::heartbeat`] check a worker is still making progress.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    last: Arc<Mutex<Instant>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Heartbeat {
+    pub fn beat(&self) {
+        *self.last.lock() = self.clock.now();
+    }
+
+    /// Since the last beat.
+    pub fn age(&self) -> Duration {
+        self.clock
+            .now()
+            .saturating_duration_since(*self.last.lock())
+    }
+}
+
+/// The health items, also part of [`crate::prelude`].
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{CheckKind, HealthRegistry, HealthReport, Heartbeat};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::prelude::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn reports_each_check(
+        mock_clock: MockClock,
+        memory_redis: MemoryRedis,
+        mock_http: MockHttp,
+    ) {
+        let health = HealthRegistry::new()
+            .timeout(Duration::from_millis(100))
+            .clock(mock_clock.shared());
+        health.redis("redis", memory_redis);
+        mock_http
+            .expect(reqwest::Method::GET, "/status")
+            .respond(503, "down for maintenance");
+        mock_http
+            .expect(reqwest::Method::GET, "/ping")
+            .respond(200, "pong");
+        let billing = ApiClient::builder()
+            .base_url(&mock_http.url())
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .unwrap();
+        health.upstream("billing", billing.clone(), "/status");
+        health.upstream("users", billing, "/ping");
+        health.register("slow", CheckKind::Readiness, || async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        });
+        let heartbeat = health.heartbeat("worker", Duration::from_secs(60));
+
+        let report = health.check_all().await;
+        let failed = report
+            .checks
+            .iter()
+            .filter(|check| !check.healthy)
+            .map(|check| check.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(failed, ["billing", "slow"]);
+        assert!(!report.healthy);
+
+        assert!(health.check(CheckKind::Liveness).await.healthy);
+        mock_clock.advance(Duration::from_secs(61));
+        assert!(!health.check(CheckKind::Liveness).await.healthy);
+        heartbeat.beat();
+        assert!(health.check(CheckKind::Liveness).await.healthy);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn serves_endpoints() {
+        let health = HealthRegistry::new();
+        health.register("always", CheckKind::Liveness, || async { Ok(()) });
+        health.register("database", CheckKind::Readiness, || async {
+            Err(err!(AnyErr, "Connection refused"))
+        });
+        let server = health.serve("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", server.addr());
+
+        let live = reqwest::get(format!("{}/healthz", url)).await.unwrap();
+        assert_eq!(live.status(), 200);
+        let ready = reqwest::get(format!("{}/readyz", url)).await.unwrap();
+        assert_eq!(ready.status(), 503);
+        let report: serde_json::Value = ready.json().await.unwrap();
+        assert_eq!(report["checks"].as_array().unwrap().len(), 2);
+        assert_eq!(report["checks"][1]["name"], "database");
+        assert_eq!(report["checks"][1]["error"], "AnyErr");
+        let missing = reqwest::get(format!("{}/metrics", url)).await.unwrap();
+        assert_eq!(missing.status(), 404);
+    }
+}
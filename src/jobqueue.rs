@@ -0,0 +1,1021 @@
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use parking_lot::Mutex;
+use redis::aio::MultiplexedConnection;
+use redis::streams::{
+    StreamClaimOptions, StreamId, StreamRangeReply, StreamReadOptions, StreamReadReply,
+};
+use redis::AsyncCommands;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, Level};
+
+use crate::errors::{catch_panic_async, error_class, Backoff};
+use crate::prelude::*;
+use crate::redis_manager::RedisManager;
+use crate::redis_tracing::store_log;
+
+/// A payload for a [`JobQueue`], each type has its own stream named after [`QueueJob::NAME`].
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct SendEmail { to: String }
+///
+/// impl QueueJob for SendEmail {
+///     const NAME: &'static str = "send_email";
+/// }
+/// ```
+pub trait QueueJob: Serialize + DeserializeOwned + Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+/// How jobs are stored in the streams and the delayed set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Envelope {
+    id: String,
+    /// Counting from 1.
+    attempt: u32,
+    payload: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Delayed, or waiting to be retried.
+    Scheduled,
+    Queued,
+    Running,
+    Succeeded,
+    /// Failed every attempt, or with an error not worth retrying, see [`JobQueue::dead_letters`].
+    Dead,
+}
+
+/// Where an enqueued job got to, see [`JobQueue::status`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub id: String,
+    pub state: JobState,
+    /// Attempts started so far.
+    pub attempts: u32,
+    /// Of the last failed attempt.
+    pub error: Option<String>,
+    /// Unix seconds.
+    pub updated: i64,
+}
+
+/// A job that ended in the dead letter stream.
+#[derive(Debug, Clone)]
+pub struct DeadLetter<J> {
+    pub id: String,
+    pub job: J,
+    pub attempts: u32,
+    pub error: String,
+}
+
+/// Background jobs on redis streams: [`Self::enqueue`] adds a [`QueueJob`], workers of
+/// [`Self::worker`] run them.
+///
+/// Keys are `{prefix}:{name}` for the stream, `{prefix}:{name}:delayed` for jobs to run later,
+/// `{prefix}:{name}:dead` for jobs that failed for good and `{prefix}:{name}:status:{id}`.
+#[derive(Clone)]
+pub struct JobQueue {
+    redis: RedisManager,
+    prefix: String,
+    status_ttl: Duration,
+    tracing_app: Option<String>,
+}
+
+impl std::fmt::Debug for JobQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JobQueue")
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl JobQueue {
+    pub fn new(redis: RedisManager) -> Self {
+        JobQueue {
+            redis,
+            prefix: "jobs".to_string(),
+            status_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            tracing_app: None,
+        }
+    }
+
+    /// `jobs` by default.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// How long statuses are kept after their last update, a week by default.
+    pub fn status_ttl(mut self, ttl: Duration) -> Self {
+        self.status_ttl = ttl;
+        self
+    }
+
+    /// Also stores when jobs start, retry and finish in the redis traces of `app`, with the job
+    /// id as trace and job id, see [`crate::redis_tracing::LogViewer::view_logs_by_job_id`].
+    pub fn tracing_app(mut self, app: impl Into<String>) -> Self {
+        self.tracing_app = Some(app.into());
+        self
+    }
+
+    /// The stream of `J`.
+    pub fn stream<J: QueueJob>(&self) -> String {
+        format!("{}:{}", self.prefix, J::NAME)
+    }
+
+    /// Queues `job` to run as soon as a worker is free, returning its id.
+    pub async fn enqueue<J: QueueJob>(&self, job: &J) -> RResult<String, AnyErr> {
+        let envelope = new_envelope(job)?;
+        // Before queueing, a worker could otherwise finish it before it's marked as queued:
+        self.set_status::<J>(&envelope, JobState::Queued, None)
+            .await?;
+        let mut con = self.connection().await?;
+        xadd(
+            &mut con,
+            &self.stream::<J>(),
+            &[("job", to_json(&envelope)?)],
+        )
+        .await?;
+        self.redis.return_async_connection(con).await;
+        Ok(envelope.id)
+    }
+
+    /// Queues `job` to run once `delay` passed.
+    pub async fn enqueue_in<J: QueueJob>(
+        &self,
+        job: &J,
+        delay: Duration,
+    ) -> RResult<String, AnyErr> {
+        let run_at = Utc::now() + chrono::Duration::from_std(delay).change_context(AnyErr)?;
+        self.enqueue_at(job, run_at).await
+    }
+
+    /// Queues `job` to run at `run_at`, within the block time of the workers.
+    pub async fn enqueue_at<J: QueueJob>(
+        &self,
+        job: &J,
+        run_at: DateTime<Utc>,
+    ) -> RResult<String, AnyErr> {
+        let envelope = new_envelope(job)?;
+        self.set_status::<J>(&envelope, JobState::Scheduled, None)
+            .await?;
+        self.schedule::<J>(&envelope, run_at).await?;
+        Ok(envelope.id)
+    }
+
+    pub async fn status<J: QueueJob>(&self, id: &str) -> RResult<Option<JobStatus>, AnyErr> {
+        let mut con = self.connection().await?;
+        let json: Option<String> = con
+            .get(self.status_key::<J>(id))
+            .await
+            .change_context(AnyErr)?;
+        self.redis.return_async_connection(con).await;
+        json.map(|json| serde_json::from_str(&json).change_context(AnyErr))
+            .transpose()
+    }
+
+    /// The oldest `count` jobs of `J` that failed for good.
+    pub async fn dead_letters<J: QueueJob>(
+        &self,
+        count: usize,
+    ) -> RResult<Vec<DeadLetter<J>>, AnyErr> {
+        let mut con = self.connection().await?;
+        let reply: StreamRangeReply = con
+            .xrange_count(self.dead_stream::<J>(), "-", "+", count)
+            .await
+            .change_context(AnyErr)?;
+        self.redis.return_async_connection(con).await;
+        reply
+            .ids
+            .iter()
+            .map(|entry| {
+                let envelope = parse_envelope(entry)?;
+                Ok(DeadLetter {
+                    job: serde_json::from_value(envelope.payload).change_context(AnyErr)?,
+                    id: envelope.id,
+                    attempts: envelope.attempt,
+                    error: entry.get("error").unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+
+    /// A worker running the jobs of `J` with `handler`, see [`Worker::start`].
+    pub fn worker<J, F, Fut>(&self, handler: F) -> Worker<J>
+    where
+        J: QueueJob,
+        F: Fn(J, JobContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = RResult<(), AnyErr>> + Send + 'static,
+    {
+        Worker {
+            queue: self.clone(),
+            handler: Arc::new(move |job, context| handler(job, context).boxed()),
+            concurrency: 1,
+            backoff: Backoff::new()
+                .initial_delay(Duration::from_secs(1))
+                .max_delay(Duration::from_secs(10 * 60)),
+            group: "workers".to_string(),
+            consumer: format!("worker-{}", std::process::id()),
+            block: Duration::from_secs(1),
+            visibility_timeout: Duration::from_secs(5 * 60),
+            running: Mutex::new(HashSet::new()),
+            reread: AtomicBool::new(false),
+            _job: PhantomData,
+        }
+    }
+
+    fn dead_stream<J: QueueJob>(&self) -> String {
+        format!("{}:dead", self.stream::<J>())
+    }
+
+    fn delayed_key<J: QueueJob>(&self) -> String {
+        format!("{}:delayed", self.stream::<J>())
+    }
+
+    fn status_key<J: QueueJob>(&self, id: &str) -> String {
+        format!("{}:status:{}", self.stream::<J>(), id)
+    }
+
+    async fn connection(&self) -> RResult<MultiplexedConnection, AnyErr> {
+        self.redis
+            .get_async_connection()
+            .await
+            .change_context(AnyErr)
+    }
+
+    async fn schedule<J: QueueJob>(
+        &self,
+        envelope: &Envelope,
+        run_at: DateTime<Utc>,
+    ) -> RResult<(), AnyErr> {
+        let mut con = self.connection().await?;
+        con.zadd::<_, _, _, ()>(
+            self.delayed_key::<J>(),
+            to_json(envelope)?,
+            run_at.timestamp_millis(),
+        )
+        .await
+        .change_context(AnyErr)?;
+        self.redis.return_async_connection(con).await;
+        Ok(())
+    }
+
+    /// Moves the delayed jobs that are due to the stream.
+    async fn promote_due<J: QueueJob>(&self) -> RResult<(), AnyErr> {
+        let mut con = self.connection().await?;
+        let due: Vec<String> = con
+            .zrangebyscore_limit(
+                self.delayed_key::<J>(),
+                "-inf",
+                Utc::now().timestamp_millis(),
+                0,
+                100,
+            )
+            .await
+            .change_context(AnyErr)?;
+        for json in due {
+            // Only the worker that removed it queues it:
+            let removed: usize = con
+                .zrem(self.delayed_key::<J>(), &json)
+                .await
+                .change_context(AnyErr)?;
+            if removed == 1 {
+                let envelope: Envelope = serde_json::from_str(&json).change_context(AnyErr)?;
+                self.set_status::<J>(&envelope, JobState::Queued, None)
+                    .await?;
+                xadd(&mut con, &self.stream::<J>(), &[("job", json)]).await?;
+            }
+        }
+        self.redis.return_async_connection(con).await;
+        Ok(())
+    }
+
+    async fn set_status<J: QueueJob>(
+        &self,
+        envelope: &Envelope,
+        state: JobState,
+        error: Option<String>,
+    ) -> RResult<(), AnyErr> {
+        let status = JobStatus {
+            id: envelope.id.clone(),
+            state,
+            attempts: match state {
+                JobState::Queued => envelope.attempt - 1,
+                // Retries are scheduled with their coming attempt:
+                JobState::Scheduled => envelope.attempt.saturating_sub(1),
+                _ => envelope.attempt,
+            },
+            error,
+            updated: Utc::now().timestamp(),
+        };
+        let mut con = self.connection().await?;
+        con.pset_ex::<_, _, ()>(
+            self.status_key::<J>(&envelope.id),
+            to_json(&status)?,
+            (self.status_ttl.as_millis() as u64).max(1),
+        )
+        .await
+        .change_context(AnyErr)?;
+        self.redis.return_async_connection(con).await;
+        Ok(())
+    }
+
+    async fn trace<J: QueueJob>(&self, level: Level, id: &str, message: String) {
+        let Some(app) = &self.tracing_app else {
+            return;
+        };
+        let log = LogData::new(level, message)
+            .span(id, "queue_job")
+            .trace_id(id)
+            .job_id(id)
+            .service_name(J::NAME);
+        if let Err(e) = store_log(&self.redis, app, &log).await {
+            warn!("Failed to store the trace of job {}: {:?}", id, e);
+        }
+    }
+}
+
+fn new_envelope<J: QueueJob>(job: &J) -> RResult<Envelope, AnyErr> {
+    Ok(Envelope {
+        id: format!("{:032x}", rand::random::<u128>()),
+        attempt: 1,
+        payload: serde_json::to_value(job).change_context(AnyErr)?,
+    })
+}
+
+fn to_json(value: &impl Serialize) -> RResult<String, AnyErr> {
+    serde_json::to_string(value).change_context(AnyErr)
+}
+
+fn parse_envelope(entry: &StreamId) -> RResult<Envelope, AnyErr> {
+    let json = entry
+        .get::<String>("job")
+        .ok_or_else(|| err!(AnyErr, "Stream entry {} has no job", entry.id))?;
+    serde_json::from_str(&json)
+        .change_context(AnyErr)
+        .attach_printable_lazy(|| format!("Invalid job in stream entry {}", entry.id))
+}
+
+async fn xadd(
+    con: &mut MultiplexedConnection,
+    stream: &str,
+    fields: &[(&str, String)],
+) -> RResult<String, AnyErr> {
+    con.xadd(stream, "*", fields).await.change_context(AnyErr)
+}
+
+/// Whether a failed attempt is tried again: unless it was the last one or its error is
+/// classified as not worth retrying, e.g. [`ErrorClass::InvalidInput`].
+fn should_retry<C>(report: &Report<C>, attempt: u32, max_attempts: u32) -> bool {
+    attempt < max_attempts && error_class(report).is_none_or(|class| class.is_retryable())
+}
+
+/// The job run a handler is called for.
+#[derive(Debug, Clone)]
+pub struct JobContext {
+    pub id: String,
+    /// Counting from 1.
+    pub attempt: u32,
+}
+
+type Handler<J> =
+    Arc<dyn Fn(J, JobContext) -> BoxFuture<'static, RResult<(), AnyErr>> + Send + Sync>;
+
+/// Runs the jobs of one [`QueueJob`] type, see [`JobQueue::worker`]. Workers of the same group
+/// share the jobs, each runs once unless it fails or its worker stops responding, see
+/// [`Self::visibility_timeout`]. Handler errors and panics are retried with the
+/// [`Self::backoff`] delays, then moved to the dead letter stream.
+pub struct Worker<J> {
+    queue: JobQueue,
+    handler: Handler<J>,
+    concurrency: usize,
+    backoff: Backoff,
+    group: String,
+    consumer: String,
+    block: Duration,
+    visibility_timeout: Duration,
+    /// Stream ids of the entries being handled, skipped when read again.
+    running: Mutex<HashSet<String>>,
+    /// Set when handling an entry failed, to read this consumer's pending entries again.
+    reread: AtomicBool,
+    _job: PhantomData<fn(J)>,
+}
+
+impl<J: QueueJob> Worker<J> {
+    /// How many jobs run at the same time, 1 by default.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// The delays between attempts and how many there are, 5 attempts starting 1s apart by
+    /// default.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// The consumer group, `workers` by default.
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.group = group.into();
+        self
+    }
+
+    /// This worker's name in the group, unique per process by default. Jobs a worker read but
+    /// didn't finish are run again when a worker of the same name starts, or by any worker of
+    /// the group after [`Self::visibility_timeout`].
+    pub fn consumer(mut self, consumer: impl Into<String>) -> Self {
+        self.consumer = consumer.into();
+        self
+    }
+
+    /// How long to wait for new jobs before checking for due delayed ones, 1s by default.
+    pub fn block(mut self, block: Duration) -> Self {
+        self.block = block;
+        self
+    }
+
+    /// How long a job can go without its worker checking in before another worker of the group
+    /// takes it over, e.g. after a crash mid-job, 5 minutes by default. Workers check in on
+    /// their running jobs every half of it, so it doesn't need to outlast the jobs.
+    pub fn visibility_timeout(mut self, timeout: Duration) -> Self {
+        self.visibility_timeout = timeout.max(Duration::from_millis(2));
+        self
+    }
+
+    /// Runs jobs on the current runtime until [`WorkerHandle::shutdown`] or dropping the handle.
+    pub fn start(self) -> WorkerHandle {
+        let cancel = CancellationToken::new();
+        let worker = Arc::new(self);
+        let task = tokio::spawn({
+            let cancel = cancel.clone();
+            async move { worker.run(cancel).await }
+        });
+        WorkerHandle {
+            cancel,
+            task: Some(task),
+        }
+    }
+
+    async fn run(self: Arc<Self>, cancel: CancellationToken) {
+        let stream = self.queue.stream::<J>();
+        info!(
+            "Running jobs of {} as {}/{}",
+            stream, self.group, self.consumer
+        );
+        let permits = Arc::new(Semaphore::new(self.concurrency));
+        // Jobs read before a restart first, after the last one of those handed out:
+        let mut id = "0".to_string();
+        let mut claim_at = tokio::time::Instant::now();
+        loop {
+            if self.reread.swap(false, Ordering::SeqCst) {
+                id = "0".to_string();
+            }
+            let first = tokio::select! {
+                _ = cancel.cancelled() => None,
+                permit = permits.clone().acquire_owned() => permit.ok(),
+            };
+            let Some(first) = first else {
+                break;
+            };
+            let mut acquired = vec![first];
+            while let Ok(permit) = permits.clone().try_acquire_owned() {
+                acquired.push(permit);
+            }
+            let claim = id == ">" && tokio::time::Instant::now() >= claim_at;
+            let entries = tokio::select! {
+                _ = cancel.cancelled() => break,
+                entries = self.read(&id, acquired.len(), claim) => entries,
+            };
+            let entries = match entries {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("Failed to read jobs of {}: {:?}", stream, e);
+                    tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        _ = tokio::time::sleep(self.block) => continue,
+                    }
+                }
+            };
+            // Until a claim comes back short there may be more idle jobs to take over:
+            if claim && entries.len() < acquired.len() {
+                claim_at = tokio::time::Instant::now() + self.visibility_timeout / 2;
+            }
+            if id != ">" {
+                id = match entries.last() {
+                    Some(entry) => entry.id.clone(),
+                    None => ">".to_string(),
+                };
+            }
+            for (entry, permit) in entries.into_iter().zip(acquired) {
+                if !self.running.lock().insert(entry.id.clone()) {
+                    continue;
+                }
+                let worker = self.clone();
+                tokio::spawn(async move {
+                    worker.handle(entry).await;
+                    drop(permit);
+                });
+            }
+        }
+        // Lets the running jobs finish:
+        let _ = permits.acquire_many(self.concurrency as u32).await;
+    }
+
+    /// Up to `count` entries, only new ones when `id` is `>`. With `claim`, the ones idle for
+    /// longer than the visibility timeout come first.
+    async fn read(&self, id: &str, count: usize, claim: bool) -> RResult<Vec<StreamId>, AnyErr> {
+        self.queue.promote_due::<J>().await?;
+        let stream = self.queue.stream::<J>();
+        let mut con = self.queue.connection().await?;
+        let created: Result<(), redis::RedisError> =
+            con.xgroup_create_mkstream(&stream, &self.group, "0").await;
+        if let Err(e) = created {
+            // Created already, by this or another worker:
+            if e.code() != Some("BUSYGROUP") {
+                return Err(e).change_context(AnyErr);
+            }
+        }
+        if claim {
+            let claimed = self.claim_idle(&mut con, &stream, count).await?;
+            if !claimed.is_empty() {
+                self.queue.redis.return_async_connection(con).await;
+                return Ok(claimed);
+            }
+        }
+        let mut options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(count);
+        if id == ">" {
+            options = options.block(self.block.as_millis() as usize);
+        }
+        let reply: Option<StreamReadReply> = con
+            .xread_options(&[&stream], &[id], &options)
+            .await
+            .change_context(AnyErr)?;
+        self.queue.redis.return_async_connection(con).await;
+        Ok(reply
+            .into_iter()
+            .flat_map(|reply| reply.keys)
+            .flat_map(|key| key.ids)
+            .collect())
+    }
+
+    /// Takes over up to `count` entries no worker checked in on within the visibility timeout.
+    async fn claim_idle(
+        &self,
+        con: &mut MultiplexedConnection,
+        stream: &str,
+        count: usize,
+    ) -> RResult<Vec<StreamId>, AnyErr> {
+        let reply: Vec<redis::Value> = redis::cmd("XAUTOCLAIM")
+            .arg(stream)
+            .arg(&self.group)
+            .arg(&self.consumer)
+            .arg(self.visibility_timeout.as_millis() as u64)
+            .arg("0")
+            .arg("COUNT")
+            .arg(count)
+            .query_async(con)
+            .await
+            .change_context(AnyErr)?;
+        let claimed = match reply.get(1) {
+            Some(entries) => {
+                redis::from_redis_value::<StreamRangeReply>(entries)
+                    .change_context(AnyErr)?
+                    .ids
+            }
+            None => vec![],
+        };
+        if !claimed.is_empty() {
+            info!(
+                "Took over {} idle jobs of {} as {}",
+                claimed.len(),
+                stream,
+                self.consumer
+            );
+        }
+        Ok(claimed)
+    }
+
+    /// Resets how long the entry has been idle, keeping other workers from taking it over.
+    async fn check_in(&self, entry_id: &str) -> RResult<(), AnyErr> {
+        let mut con = self.queue.connection().await?;
+        let _: Vec<String> = con
+            .xclaim_options(
+                self.queue.stream::<J>(),
+                &self.group,
+                &self.consumer,
+                0,
+                &[entry_id],
+                StreamClaimOptions::default().with_justid(),
+            )
+            .await
+            .change_context(AnyErr)?;
+        self.queue.redis.return_async_connection(con).await;
+        Ok(())
+    }
+
+    async fn handle(&self, entry: StreamId) {
+        let run = self.run_entry(&entry);
+        tokio::pin!(run);
+        let mut check_in = tokio::time::interval(self.visibility_timeout / 2);
+        check_in.tick().await;
+        let result = loop {
+            tokio::select! {
+                result = &mut run => break result,
+                _ = check_in.tick() => {
+                    if let Err(e) = self.check_in(&entry.id).await {
+                        warn!("Failed to check in on job entry {}: {:?}", entry.id, e);
+                    }
+                }
+            }
+        };
+        if let Err(e) = result {
+            // Left pending and read again, after a pause in case redis is failing:
+            warn!("Failed to handle job entry {}: {:?}", entry.id, e);
+            tokio::time::sleep(self.block).await;
+            self.running.lock().remove(&entry.id);
+            self.reread.store(true, Ordering::SeqCst);
+            return;
+        }
+        let stream = self.queue.stream::<J>();
+        let acked = async {
+            let mut con = self.queue.connection().await?;
+            let _: usize = con
+                .xack(&stream, &self.group, &[&entry.id])
+                .await
+                .change_context(AnyErr)?;
+            self.queue.redis.return_async_connection(con).await;
+            Ok::<_, Report<AnyErr>>(())
+        };
+        if let Err(e) = acked.await {
+            warn!("Failed to acknowledge job entry {}: {:?}", entry.id, e);
+        }
+        self.running.lock().remove(&entry.id);
+    }
+
+    async fn run_entry(&self, entry: &StreamId) -> RResult<(), AnyErr> {
+        let queue = &self.queue;
+        let envelope = match parse_envelope(entry) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                warn!("Dropping invalid job: {:?}", e);
+                return Ok(());
+            }
+        };
+        let span = tracing::info_span!(
+            "queue_job",
+            job = J::NAME,
+            job_id = %envelope.id,
+            attempt = envelope.attempt
+        );
+        let result = async {
+            queue
+                .set_status::<J>(&envelope, JobState::Running, None)
+                .await?;
+            queue
+                .trace::<J>(
+                    Level::INFO,
+                    &envelope.id,
+                    format!("Started attempt {}", envelope.attempt),
+                )
+                .await;
+            let context = JobContext {
+                id: envelope.id.clone(),
+                attempt: envelope.attempt,
+            };
+            let run = match serde_json::from_value::<J>(envelope.payload.clone()) {
+                Ok(job) => catch_panic_async((self.handler)(job, context))
+                    .await
+                    .and_then(|result| result),
+                Err(e) => Err(Report::new(e)
+                    .change_context(AnyErr)
+                    .attach_printable(format!("Invalid {} payload", J::NAME))
+                    .classify(ErrorClass::InvalidInput)),
+            };
+            self.finish(&envelope, run).await
+        }
+        .instrument(span)
+        .await;
+        result
+    }
+
+    /// Stores the outcome of the attempt, scheduling a retry or moving it to the dead letters.
+    async fn finish(&self, envelope: &Envelope, run: RResult<(), AnyErr>) -> RResult<(), AnyErr> {
+        let queue = &self.queue;
+        let report = match run {
+            Ok(()) => {
+                debug!("Finished job {}", envelope.id);
+                queue
+                    .set_status::<J>(envelope, JobState::Succeeded, None)
+                    .await?;
+                queue
+                    .trace::<J>(Level::INFO, &envelope.id, "Finished".to_string())
+                    .await;
+                return Ok(());
+            }
+            Err(report) => report,
+        };
+        let error = format!("{:?}", report);
+        if should_retry(&report, envelope.attempt, self.backoff.get_max_attempts()) {
+            let delay = self.backoff.delay(envelope.attempt);
+            warn!(
+                "Attempt {} of job {} failed, retrying in {:?}: {:?}",
+                envelope.attempt, envelope.id, delay, report
+            );
+            let retry = Envelope {
+                attempt: envelope.attempt + 1,
+                ..envelope.clone()
+            };
+            let run_at = Utc::now() + chrono::Duration::from_std(delay).change_context(AnyErr)?;
+            queue
+                .set_status::<J>(&retry, JobState::Scheduled, Some(error.clone()))
+                .await?;
+            queue.schedule::<J>(&retry, run_at).await?;
+            queue
+                .trace::<J>(
+                    Level::WARN,
+                    &envelope.id,
+                    format!("Attempt {} failed, retrying: {}", envelope.attempt, report),
+                )
+                .await;
+        } else {
+            error!(
+                "Job {} failed after {} attempts: {:?}",
+                envelope.id, envelope.attempt, report
+            );
+            let mut con = queue.connection().await?;
+            xadd(
+                &mut con,
+                &queue.dead_stream::<J>(),
+                &[("job", to_json(envelope)?), ("error", report.to_string())],
+            )
+            .await?;
+            queue.redis.return_async_connection(con).await;
+            queue
+                .set_status::<J>(envelope, JobState::Dead, Some(error))
+                .await?;
+            queue
+                .trace::<J>(
+                    Level::ERROR,
+                    &envelope.id,
+                    format!("Failed after {} attempts: {}", envelope.attempt, report),
+                )
+                .await;
+        }
+        Ok(())
+    }
+}
+
+/// Stops the worker when dropped, letting running jobs finish in the background.
+pub struct WorkerHandle {
+    cancel: CancellationToken,
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl WorkerHandle {
+    /// Stops reading jobs and waits for the running ones to finish.
+    pub async fn shutdown(mut self) {
+        self.cancel.cancel();
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for WorkerHandle {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// The job queue items, also part of [`crate::prelude`].
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{JobContext, JobQueue, QueueJob, Worker};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::prelude::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Resize {
+        image: String,
+    }
+
+    impl QueueJob for Resize {
+        const NAME: &'static str = "resize";
+    }
+
+    #[rstest]
+    fn retries_unless_permanent() {
+        let unclassified = err!(AnyErr, "Timed out");
+        assert!(should_retry(&unclassified, 1, 3));
+        assert!(!should_retry(&unclassified, 3, 3));
+        let invalid = err!(AnyErr, "No such image").classify(ErrorClass::InvalidInput);
+        assert!(!should_retry(&invalid, 1, 3));
+    }
+
+    async fn wait_for_state(queue: &JobQueue, id: &str, state: JobState) -> JobStatus {
+        let waited = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match queue.status::<Resize>(id).await.unwrap() {
+                    Some(status) if status.state == state => return status,
+                    _ => tokio::time::sleep(Duration::from_millis(20)).await,
+                }
+            }
+        });
+        waited.await.unwrap()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn runs_retries_and_dead_letters(redis_test: Option<RedisTest>) {
+        let Some(redis) = redis_test else { return };
+        let queue = JobQueue::new((*redis).clone()).tracing_app("resizer");
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counted = attempts.clone();
+        let worker = queue
+            .worker(move |job: Resize, context| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match job.image.as_str() {
+                        "broken.png" => Err(err!(AnyErr, "Corrupt image {}", context.id)),
+                        "flaky.png" if context.attempt < 2 => Err(err!(AnyErr, "Flaked")),
+                        _ => Ok(()),
+                    }
+                }
+            })
+            .concurrency(2)
+            .block(Duration::from_millis(50))
+            .backoff(
+                Backoff::new()
+                    .max_attempts(2)
+                    .initial_delay(Duration::from_millis(10))
+                    .jitter(false),
+            )
+            .start();
+
+        let ok = queue
+            .enqueue(&Resize {
+                image: "ok.png".to_string(),
+            })
+            .await
+            .unwrap();
+        let flaky = queue
+            .enqueue(&Resize {
+                image: "flaky.png".to_string(),
+            })
+            .await
+            .unwrap();
+        let broken = queue
+            .enqueue(&Resize {
+                image: "broken.png".to_string(),
+            })
+            .await
+            .unwrap();
+        let delayed = queue
+            .enqueue_in(
+                &Resize {
+                    image: "later.png".to_string(),
+                },
+                Duration::from_millis(300),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            queue
+                .status::<Resize>(&delayed)
+                .await
+                .unwrap()
+                .unwrap()
+                .state,
+            JobState::Scheduled
+        );
+
+        wait_for_state(&queue, &ok, JobState::Succeeded).await;
+        assert_eq!(
+            wait_for_state(&queue, &flaky, JobState::Succeeded)
+                .await
+                .attempts,
+            2
+        );
+        let dead = wait_for_state(&queue, &broken, JobState::Dead).await;
+        assert!(dead.error.unwrap().contains("Corrupt image"));
+        wait_for_state(&queue, &delayed, JobState::Succeeded).await;
+        worker.shutdown().await;
+        assert_eq!(attempts.load(Ordering::SeqCst), 6);
+
+        let letters = queue.dead_letters::<Resize>(10).await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(
+            (letters[0].id.as_str(), letters[0].attempts),
+            (broken.as_str(), 2)
+        );
+        let viewer = LogViewer::new(Arc::new((*redis).clone()));
+        let traced = viewer
+            .view_logs_by_job_id("resizer", &broken)
+            .await
+            .unwrap();
+        // Both attempts started and the retry, then giving up:
+        assert_eq!(traced.len(), 4);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn queues_due_jobs_with_short_ttls(redis_test: Option<RedisTest>) {
+        let Some(redis) = redis_test else { return };
+        let queue = JobQueue::new((*redis).clone()).status_ttl(Duration::from_millis(300));
+        let id = queue
+            .enqueue_at(
+                &Resize {
+                    image: "due.png".to_string(),
+                },
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+        queue.promote_due::<Resize>().await.unwrap();
+        let status = queue.status::<Resize>(&id).await.unwrap().unwrap();
+        assert_eq!((status.state, status.attempts), (JobState::Queued, 0));
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(queue.status::<Resize>(&id).await.unwrap().is_none());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn takes_over_jobs_of_dead_workers(redis_test: Option<RedisTest>) {
+        let Some(redis) = redis_test else { return };
+        let queue = JobQueue::new((*redis).clone());
+        let id = queue
+            .enqueue(&Resize {
+                image: "slow.png".to_string(),
+            })
+            .await
+            .unwrap();
+
+        // A worker on a runtime of its own dies with the job half done:
+        let url = format!("{}{}", LOCAL_REDIS, redis.db());
+        let (started, on_start) = std::sync::mpsc::channel();
+        let (kill, killed) = std::sync::mpsc::channel::<()>();
+        let dying = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime
+                .block_on(async move {
+                    let queue = JobQueue::new(RedisManager::new(&url).unwrap());
+                    let _worker = queue
+                        .worker(move |_: Resize, _| {
+                            started.send(()).unwrap();
+                            futures::future::pending()
+                        })
+                        .consumer("dying")
+                        .visibility_timeout(Duration::from_millis(200))
+                        .block(Duration::from_millis(50))
+                        .start();
+                    tokio::task::spawn_blocking(move || killed.recv())
+                        .await
+                        .unwrap()
+                })
+                .unwrap();
+            runtime.shutdown_background();
+        });
+        on_start.recv_timeout(Duration::from_secs(10)).unwrap();
+        kill.send(()).unwrap();
+        dying.join().unwrap();
+
+        let ran = Arc::new(AtomicUsize::new(0));
+        let counted = ran.clone();
+        let worker = queue
+            .worker(move |_: Resize, _| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                async { Ok(()) }
+            })
+            .consumer("alive")
+            .visibility_timeout(Duration::from_millis(200))
+            .block(Duration::from_millis(50))
+            .start();
+        let status = wait_for_state(&queue, &id, JobState::Succeeded).await;
+        worker.shutdown().await;
+        assert_eq!(status.attempts, 1);
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,319 @@
+use k8s_openapi::api::batch::v1::{Job, JobSpec};
+use k8s_openapi::api::core::v1::{
+    ConfigMapEnvSource, Container, EnvFromSource, EnvVar, EnvVarSource, LocalObjectReference,
+    PodSpec, PodTemplateSpec, ResourceRequirements, SecretEnvSource, SecretKeySelector, Toleration,
+};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use kube::api::ObjectMeta;
+use std::collections::BTreeMap;
+
+/// Builds the [`Job`] for [`super::KubeManager::run_job`], running one container that isn't
+/// restarted, kubernetes retries with new pods up to the backoff limit.
+///
+/// ```ignore
+/// let job = JobBuilder::new("train-42", "ghcr.io/acme/trainer:v3")
+///     .args(["--epochs", "10"])
+///     .env("MODE", "full")
+///     .env_from_secret("trainer-credentials")
+///     .requests("2", "8Gi")
+///     .limits("4", "16Gi")
+///     .node_selector("pool", "gpu")
+///     .backoff_limit(2)
+///     .ttl_seconds_after_finished(3600)
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobBuilder {
+    name: String,
+    image: String,
+    command: Vec<String>,
+    args: Vec<String>,
+    env: Vec<EnvVar>,
+    env_from: Vec<EnvFromSource>,
+    requests: BTreeMap<String, Quantity>,
+    limits: BTreeMap<String, Quantity>,
+    labels: BTreeMap<String, String>,
+    annotations: BTreeMap<String, String>,
+    node_selector: BTreeMap<String, String>,
+    tolerations: Vec<Toleration>,
+    image_pull_secrets: Vec<String>,
+    service_account: Option<String>,
+    backoff_limit: Option<i32>,
+    ttl_seconds_after_finished: Option<i32>,
+    active_deadline_seconds: Option<i64>,
+}
+
+impl JobBuilder {
+    pub fn new(name: impl Into<String>, image: impl Into<String>) -> Self {
+        JobBuilder {
+            name: name.into(),
+            image: image.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Replaces the image's entrypoint.
+    pub fn command<S: Into<String>>(mut self, command: impl IntoIterator<Item = S>) -> Self {
+        self.command = command.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn args<S: Into<String>>(mut self, args: impl IntoIterator<Item = S>) -> Self {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push(EnvVar {
+            name: key.into(),
+            value: Some(value.into()),
+            ..Default::default()
+        });
+        self
+    }
+
+    /// Sets `key` to the `secret_key` entry of the secret.
+    pub fn secret_env(
+        mut self,
+        key: impl Into<String>,
+        secret: impl Into<String>,
+        secret_key: impl Into<String>,
+    ) -> Self {
+        self.env.push(EnvVar {
+            name: key.into(),
+            value_from: Some(EnvVarSource {
+                secret_key_ref: Some(SecretKeySelector {
+                    name: Some(secret.into()),
+                    key: secret_key.into(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        self
+    }
+
+    /// Sets an env var per entry of the secret.
+    pub fn env_from_secret(mut self, secret: impl Into<String>) -> Self {
+        self.env_from.push(EnvFromSource {
+            secret_ref: Some(SecretEnvSource {
+                name: Some(secret.into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        self
+    }
+
+    /// Sets an env var per entry of the configmap.
+    pub fn env_from_configmap(mut self, configmap: impl Into<String>) -> Self {
+        self.env_from.push(EnvFromSource {
+            config_map_ref: Some(ConfigMapEnvSource {
+                name: Some(configmap.into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        self
+    }
+
+    /// What the pod is scheduled with, e.g. `("500m", "1Gi")`.
+    pub fn requests(self, cpu: &str, memory: &str) -> Self {
+        self.request("cpu", cpu).request("memory", memory)
+    }
+
+    /// What the pod may use at most, e.g. `("2", "4Gi")`.
+    pub fn limits(self, cpu: &str, memory: &str) -> Self {
+        self.limit("cpu", cpu).limit("memory", memory)
+    }
+
+    /// Requests any resource, e.g. `("ephemeral-storage", "10Gi")`.
+    pub fn request(mut self, resource: impl Into<String>, quantity: &str) -> Self {
+        self.requests
+            .insert(resource.into(), Quantity(quantity.to_string()));
+        self
+    }
+
+    /// Limits any resource, e.g. `("nvidia.com/gpu", "1")`.
+    pub fn limit(mut self, resource: impl Into<String>, quantity: &str) -> Self {
+        self.limits
+            .insert(resource.into(), Quantity(quantity.to_string()));
+        self
+    }
+
+    /// Labels the job and its pods.
+    pub fn label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.insert(key.into(), value.into());
+        self
+    }
+
+    /// Annotates the job, not its pods.
+    pub fn annotation(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.annotations.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn node_selector(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.node_selector.insert(key.into(), value.into());
+        self
+    }
+
+    /// Tolerates the `key=value:effect` taint, e.g. `("gpu", "true", "NoSchedule")`.
+    pub fn toleration(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+        effect: impl Into<String>,
+    ) -> Self {
+        self.tolerations.push(Toleration {
+            key: Some(key.into()),
+            operator: Some("Equal".to_string()),
+            value: Some(value.into()),
+            effect: Some(effect.into()),
+            ..Default::default()
+        });
+        self
+    }
+
+    /// Pulls the image with the registry credentials of this secret.
+    pub fn image_pull_secret(mut self, secret: impl Into<String>) -> Self {
+        self.image_pull_secrets.push(secret.into());
+        self
+    }
+
+    pub fn service_account(mut self, service_account: impl Into<String>) -> Self {
+        self.service_account = Some(service_account.into());
+        self
+    }
+
+    /// How often a failed pod is retried, kubernetes defaults to 6.
+    pub fn backoff_limit(mut self, retries: i32) -> Self {
+        self.backoff_limit = Some(retries);
+        self
+    }
+
+    /// Deletes the finished job and its pods after this long.
+    pub fn ttl_seconds_after_finished(mut self, seconds: i32) -> Self {
+        self.ttl_seconds_after_finished = Some(seconds);
+        self
+    }
+
+    /// Fails the job when it runs longer than this, including retries.
+    pub fn active_deadline_seconds(mut self, seconds: i64) -> Self {
+        self.active_deadline_seconds = Some(seconds);
+        self
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn build(self) -> Job {
+        let labels = (!self.labels.is_empty()).then_some(self.labels);
+        let resources =
+            (!self.requests.is_empty() || !self.limits.is_empty()).then(|| ResourceRequirements {
+                requests: (!self.requests.is_empty()).then_some(self.requests),
+                limits: (!self.limits.is_empty()).then_some(self.limits),
+                ..Default::default()
+            });
+        let container = Container {
+            name: self.name.clone(),
+            image: Some(self.image),
+            command: (!self.command.is_empty()).then_some(self.command),
+            args: (!self.args.is_empty()).then_some(self.args),
+            env: (!self.env.is_empty()).then_some(self.env),
+            env_from: (!self.env_from.is_empty()).then_some(self.env_from),
+            resources,
+            ..Default::default()
+        };
+        let image_pull_secrets = self
+            .image_pull_secrets
+            .into_iter()
+            .map(|name| LocalObjectReference { name: Some(name) })
+            .collect::<Vec<_>>();
+        Job {
+            metadata: ObjectMeta {
+                name: Some(self.name),
+                labels: labels.clone(),
+                annotations: (!self.annotations.is_empty()).then_some(self.annotations),
+                ..Default::default()
+            },
+            spec: Some(JobSpec {
+                backoff_limit: self.backoff_limit,
+                ttl_seconds_after_finished: self.ttl_seconds_after_finished,
+                active_deadline_seconds: self.active_deadline_seconds,
+                template: PodTemplateSpec {
+                    metadata: labels.map(|labels| ObjectMeta {
+                        labels: Some(labels),
+                        ..Default::default()
+                    }),
+                    spec: Some(PodSpec {
+                        containers: vec![container],
+                        restart_policy: Some("Never".to_string()),
+                        node_selector: (!self.node_selector.is_empty())
+                            .then_some(self.node_selector),
+                        tolerations: (!self.tolerations.is_empty()).then_some(self.tolerations),
+                        image_pull_secrets: (!image_pull_secrets.is_empty())
+                            .then_some(image_pull_secrets),
+                        service_account_name: self.service_account,
+                        ..Default::default()
+                    }),
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+
+    #[rstest]
+    fn builds_job_spec() {
+        let job = JobBuilder::new("train", "trainer:1")
+            .args(["--epochs", "10"])
+            .env("MODE", "full")
+            .secret_env("TOKEN", "hub", "token")
+            .env_from_configmap("trainer-config")
+            .requests("1", "2Gi")
+            .limit("nvidia.com/gpu", "1")
+            .node_selector("pool", "gpu")
+            .toleration("gpu", "true", "NoSchedule")
+            .image_pull_secret("ghcr")
+            .backoff_limit(2)
+            .ttl_seconds_after_finished(600)
+            .build();
+        assert_eq!(
+            serde_json::to_value(job.spec.unwrap()).unwrap(),
+            json!({
+                "backoffLimit": 2,
+                "ttlSecondsAfterFinished": 600,
+                "template": {"spec": {
+                    "containers": [{
+                        "name": "train",
+                        "image": "trainer:1",
+                        "args": ["--epochs", "10"],
+                        "env": [
+                            {"name": "MODE", "value": "full"},
+                            {"name": "TOKEN", "valueFrom": {"secretKeyRef": {"name": "hub", "key": "token"}}}
+                        ],
+                        "envFrom": [{"configMapRef": {"name": "trainer-config"}}],
+                        "resources": {
+                            "requests": {"cpu": "1", "memory": "2Gi"},
+                            "limits": {"nvidia.com/gpu": "1"}
+                        }
+                    }],
+                    "restartPolicy": "Never",
+                    "nodeSelector": {"pool": "gpu"},
+                    "tolerations": [{"key": "gpu", "operator": "Equal", "value": "true", "effect": "NoSchedule"}],
+                    "imagePullSecrets": [{"name": "ghcr"}]
+                }}
+            })
+        );
+    }
+}
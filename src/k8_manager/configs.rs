@@ -0,0 +1,147 @@
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use k8s_openapi::ByteString;
+use kube::api::{ObjectMeta, Patch, PatchParams};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::{kube_failed, KubeManager};
+use crate::prelude::*;
+
+/// The field manager of server-side applies, owning the fields it set.
+const FIELD_MANAGER: &str = "rutils";
+
+impl KubeManager {
+    /// Creates or replaces the configmap with an entry per file in `dir`, like
+    /// `kubectl create configmap --from-file=<dir>`. Non utf-8 files go into `binaryData`,
+    /// subdirectories are skipped.
+    pub async fn apply_configmap_from_dir(
+        &self,
+        name: &str,
+        dir: impl AsRef<Path>,
+    ) -> RResult<ConfigMap, AnyErr> {
+        let configmap = configmap_from_dir(name, dir.as_ref())?;
+        self.api::<ConfigMap>()
+            .patch(name, &apply_params(), &Patch::Apply(&configmap))
+            .await
+            .map_err(kube_failed(format!("apply configmap {}", name)))
+    }
+
+    /// Creates or replaces the secret with an entry per env var in `keys`, failing when one
+    /// isn't set.
+    pub async fn apply_secret_from_env(
+        &self,
+        name: &str,
+        keys: &[&str],
+    ) -> RResult<Secret, AnyErr> {
+        let secret = secret_from_env(name, keys)?;
+        self.api::<Secret>()
+            .patch(name, &apply_params(), &Patch::Apply(&secret))
+            .await
+            .map_err(kube_failed(format!("apply secret {}", name)))
+    }
+}
+
+/// Taking over fields other managers set, e.g. kubectl, as these own the whole object.
+pub(super) fn apply_params() -> PatchParams {
+    PatchParams::apply(FIELD_MANAGER).force()
+}
+
+fn configmap_from_dir(name: &str, dir: &Path) -> RResult<ConfigMap, AnyErr> {
+    let entries = std::fs::read_dir(dir)
+        .change_context(AnyErr)
+        .attach_printable_lazy(|| format!("Failed to read {}", dir.display()))?;
+    let mut data = BTreeMap::new();
+    let mut binary_data = BTreeMap::new();
+    for entry in entries {
+        let path = entry.change_context(AnyErr)?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let Some(key) = path.file_name().and_then(|key| key.to_str()) else {
+            continue;
+        };
+        let key = key.to_string();
+        let contents = std::fs::read(&path)
+            .change_context(AnyErr)
+            .attach_printable_lazy(|| format!("Failed to read {}", path.display()))?;
+        match String::from_utf8(contents) {
+            Ok(text) => {
+                data.insert(key, text);
+            }
+            Err(e) => {
+                binary_data.insert(key, ByteString(e.into_bytes()));
+            }
+        }
+    }
+    Ok(ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            ..Default::default()
+        },
+        data: Some(data),
+        binary_data: (!binary_data.is_empty()).then_some(binary_data),
+        ..Default::default()
+    })
+}
+
+fn secret_from_env(name: &str, keys: &[&str]) -> RResult<Secret, AnyErr> {
+    let mut data = BTreeMap::new();
+    for key in keys {
+        let value = std::env::var(key)
+            .map_err(|_| err!(AnyErr, "Env var {} for secret {} isn't set", key, name))?;
+        data.insert(key.to_string(), ByteString(value.into_bytes()));
+    }
+    Ok(Secret {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            ..Default::default()
+        },
+        data: Some(data),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fake_api;
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+
+    #[rstest]
+    #[tokio::test]
+    async fn applies_configmap_from_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.toml"), "port = 80\n").unwrap();
+        std::fs::write(dir.path().join("logo.bin"), [0xff, 0xfe]).unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+
+        let manager = fake_api(|method, path, body| {
+            assert_eq!(method, "PATCH");
+            assert!(path.starts_with("/api/v1/namespaces/test/configmaps/app?"));
+            assert!(path.contains("fieldManager=rutils") && path.contains("force=true"));
+            let body: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(body["kind"], "ConfigMap");
+            assert_eq!(body["data"], json!({"app.toml": "port = 80\n"}));
+            assert_eq!(body["binaryData"], json!({"logo.bin": "//4="}));
+            (200, body.to_string())
+        })
+        .await;
+        let configmap = manager
+            .apply_configmap_from_dir("app", dir.path())
+            .await
+            .unwrap();
+        assert_eq!(configmap.metadata.name.as_deref(), Some("app"));
+    }
+
+    #[rstest]
+    fn secret_needs_all_keys() {
+        std::env::set_var("RUTILS_TEST_SECRET_TOKEN", "s3cret");
+        let secret = secret_from_env("hub", &["RUTILS_TEST_SECRET_TOKEN"]).unwrap();
+        assert_eq!(
+            serde_json::to_value(secret.data.unwrap()).unwrap(),
+            json!({"RUTILS_TEST_SECRET_TOKEN": "czNjcmV0"})
+        );
+        assert!(secret_from_env("hub", &["RUTILS_TEST_SECRET_MISSING"]).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::batch::v1::{CronJob, CronJobSpec, Job, JobTemplateSpec};
+use kube::api::{ListParams, ObjectMeta, Patch, PatchParams, PostParams};
+use kube::Resource;
+use std::collections::BTreeMap;
+
+use super::jobs::finished;
+use super::{kube_failed, KubeManager};
+use crate::prelude::*;
+
+/// A job started by a cronjob, see [`KubeManager::list_recent_runs`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronJobRun {
+    pub job_name: String,
+    pub started: Option<DateTime<Utc>>,
+    pub finished: Option<DateTime<Utc>>,
+    /// None while running.
+    pub succeeded: Option<bool>,
+    /// Started by [`KubeManager::trigger_now`] rather than the schedule.
+    pub manual: bool,
+}
+
+/// The annotation kubectl sets on jobs created from a cronjob by hand.
+const INSTANTIATE_ANNOTATION: &str = "cronjob.kubernetes.io/instantiate";
+
+impl KubeManager {
+    /// Runs `job`, e.g. from a [`super::JobBuilder`], on the cron `schedule`, e.g. `0 3 * * *`.
+    pub async fn create_cronjob(
+        &self,
+        name: &str,
+        schedule: &str,
+        job: &Job,
+    ) -> RResult<CronJob, AnyErr> {
+        self.api::<CronJob>()
+            .create(
+                &PostParams::default(),
+                &cronjob_from_job(name, schedule, job),
+            )
+            .await
+            .map_err(kube_failed(format!("create cronjob {}", name)))
+    }
+
+    /// Stops scheduling new runs, running ones continue.
+    pub async fn suspend_cronjob(&self, name: &str) -> RResult<(), AnyErr> {
+        self.set_suspended(name, true).await
+    }
+
+    pub async fn resume_cronjob(&self, name: &str) -> RResult<(), AnyErr> {
+        self.set_suspended(name, false).await
+    }
+
+    async fn set_suspended(&self, name: &str, suspend: bool) -> RResult<(), AnyErr> {
+        let patch = serde_json::json!({"spec": {"suspend": suspend}});
+        self.api::<CronJob>()
+            .patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+            .map_err(kube_failed(format!(
+                "{} cronjob {}",
+                if suspend { "suspend" } else { "resume" },
+                name
+            )))?;
+        Ok(())
+    }
+
+    /// Starts a run now, like `kubectl create job --from=cronjob/<name>`.
+    pub async fn trigger_now(&self, name: &str) -> RResult<Job, AnyErr> {
+        let cronjob = self
+            .api::<CronJob>()
+            .get(name)
+            .await
+            .map_err(kube_failed(format!("get cronjob {}", name)))?;
+        let job = manual_job(&cronjob, Utc::now())?;
+        self.submit_job(&job).await
+    }
+
+    /// The cronjob's runs still around, newest first. How many are kept is set by the
+    /// cronjob's history limits.
+    pub async fn list_recent_runs(&self, name: &str) -> RResult<Vec<CronJobRun>, AnyErr> {
+        let jobs = self
+            .api::<Job>()
+            .list(&ListParams::default())
+            .await
+            .map_err(kube_failed(format!("list jobs of cronjob {}", name)))?;
+        let mut runs = jobs
+            .items
+            .iter()
+            .filter(|job| {
+                job.metadata
+                    .owner_references
+                    .iter()
+                    .flatten()
+                    .any(|owner| owner.kind == "CronJob" && owner.name == name)
+            })
+            .map(|job| {
+                let status = job.status.as_ref();
+                CronJobRun {
+                    job_name: job.metadata.name.clone().unwrap_or_default(),
+                    started: status.and_then(|s| s.start_time.as_ref()).map(|t| t.0),
+                    finished: status.and_then(|s| s.completion_time.as_ref()).map(|t| t.0),
+                    succeeded: finished(job),
+                    manual: job
+                        .metadata
+                        .annotations
+                        .as_ref()
+                        .is_some_and(|a| a.contains_key(INSTANTIATE_ANNOTATION)),
+                }
+            })
+            .collect::<Vec<_>>();
+        runs.sort_by_key(|run| std::cmp::Reverse(run.started));
+        Ok(runs)
+    }
+}
+
+fn cronjob_from_job(name: &str, schedule: &str, job: &Job) -> CronJob {
+    CronJob {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            labels: job.metadata.labels.clone(),
+            ..Default::default()
+        },
+        spec: Some(CronJobSpec {
+            schedule: schedule.to_string(),
+            // A run still going when the next is due is usually stuck or slow, not worth doubling:
+            concurrency_policy: Some("Forbid".to_string()),
+            job_template: JobTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    labels: job.metadata.labels.clone(),
+                    ..Default::default()
+                }),
+                spec: job.spec.clone(),
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// A job from the cronjob's template, owned by it so it shows up in its history.
+fn manual_job(cronjob: &CronJob, now: DateTime<Utc>) -> RResult<Job, AnyErr> {
+    let name = cronjob.metadata.name.clone().unwrap_or_default();
+    let template = cronjob
+        .spec
+        .as_ref()
+        .map(|spec| spec.job_template.clone())
+        .ok_or_else(|| err!(AnyErr, "Cronjob {} has no spec", name))?;
+    let owner = cronjob
+        .controller_owner_ref(&())
+        .ok_or_else(|| err!(AnyErr, "Cronjob {} has no uid", name))?;
+    let metadata = template.metadata.unwrap_or_default();
+    Ok(Job {
+        metadata: ObjectMeta {
+            name: Some(format!("{}-manual-{}", name, now.timestamp())),
+            labels: metadata.labels,
+            annotations: Some(BTreeMap::from([(
+                INSTANTIATE_ANNOTATION.to_string(),
+                "manual".to_string(),
+            )])),
+            owner_references: Some(vec![owner]),
+            ..Default::default()
+        },
+        spec: template.spec,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{fake_api, JobBuilder};
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+
+    #[rstest]
+    fn creates_runs_from_template() {
+        let job = JobBuilder::new("report", "reports:2")
+            .label("team", "data")
+            .build();
+        let mut cronjob = cronjob_from_job("nightly-report", "0 3 * * *", &job);
+        assert_eq!(cronjob.spec.as_ref().unwrap().schedule, "0 3 * * *");
+        cronjob.metadata.uid = Some("c0ffee".to_string());
+
+        let now = DateTime::from_timestamp(1_717_243_200, 0).unwrap();
+        let run = manual_job(&cronjob, now).unwrap();
+        assert_eq!(
+            run.metadata.name.as_deref(),
+            Some("nightly-report-manual-1717243200")
+        );
+        let owner = &run.metadata.owner_references.as_ref().unwrap()[0];
+        assert_eq!(
+            (owner.kind.as_str(), owner.name.as_str()),
+            ("CronJob", "nightly-report")
+        );
+        assert_eq!(run.spec, job.spec);
+        assert_eq!(run.metadata.labels, job.metadata.labels);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn lists_runs_newest_first() {
+        let manager = fake_api(|_, path, _| {
+            assert!(path.starts_with("/apis/batch/v1/namespaces/test/jobs"));
+            let owned = |name: &str, started: &str, conditions: serde_json::Value| {
+                json!({
+                    "metadata": {"name": name, "ownerReferences": [{
+                        "apiVersion": "batch/v1", "kind": "CronJob", "name": "nightly-report", "uid": "c0ffee"
+                    }]},
+                    "status": {"startTime": started, "conditions": conditions}
+                })
+            };
+            let body = json!({
+                "apiVersion": "batch/v1",
+                "kind": "JobList",
+                "metadata": {},
+                "items": [
+                    owned("nightly-report-1", "2024-06-01T03:00:00Z", json!([{"type": "Failed", "status": "True"}])),
+                    {"metadata": {"name": "unrelated"}},
+                    owned("nightly-report-2", "2024-06-02T03:00:00Z", json!([])),
+                ]
+            });
+            (200, body.to_string())
+        })
+        .await;
+        let runs = manager.list_recent_runs("nightly-report").await.unwrap();
+        assert_eq!(
+            runs.iter()
+                .map(|run| (run.job_name.as_str(), run.succeeded))
+                .collect::<Vec<_>>(),
+            [
+                ("nightly-report-2", None),
+                ("nightly-report-1", Some(false))
+            ]
+        );
+    }
+}
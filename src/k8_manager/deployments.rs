@@ -0,0 +1,303 @@
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{ListParams, Patch, PatchParams};
+use std::time::{Duration, Instant};
+
+use super::{kube_failed, KubeManager};
+use crate::prelude::*;
+
+/// How often [`KubeManager::wait_for_rollout`] checks the deployment.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A pod of a rollout that isn't becoming ready, e.g. with `ImagePullBackOff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PodIssue {
+    pub pod: String,
+    pub container: String,
+    pub reason: String,
+    pub message: String,
+    pub restarts: i32,
+}
+
+/// Where a rollout got to, see [`KubeManager::wait_for_rollout`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RolloutReport {
+    pub deployment: String,
+    /// The `deployment.kubernetes.io/revision` being rolled out.
+    pub revision: Option<String>,
+    pub desired: i32,
+    /// Pods running the new template.
+    pub updated: i32,
+    pub available: i32,
+    /// Pods of any template, old ones are left while the rollout isn't done.
+    pub total: i32,
+    pub complete: bool,
+    pub issues: Vec<PodIssue>,
+    pub duration: Duration,
+}
+
+impl std::fmt::Display for RolloutReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} revision {}: {}/{} updated, {} available, {} total",
+            self.deployment,
+            self.revision.as_deref().unwrap_or("?"),
+            self.updated,
+            self.desired,
+            self.available,
+            self.total
+        )?;
+        for issue in &self.issues {
+            write!(
+                f,
+                "\n  {}/{}: {} {} ({} restarts)",
+                issue.pod, issue.container, issue.reason, issue.message, issue.restarts
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl KubeManager {
+    /// Changes the image of one container of the deployment, starting a rollout.
+    pub async fn set_image(
+        &self,
+        deployment: &str,
+        container: &str,
+        image: &str,
+    ) -> RResult<(), AnyErr> {
+        // Containers are merged by name in strategic merge patches:
+        let patch = serde_json::json!({
+            "spec": {"template": {"spec": {"containers": [{"name": container, "image": image}]}}}
+        });
+        self.api::<Deployment>()
+            .patch(
+                deployment,
+                &PatchParams::default(),
+                &Patch::Strategic(&patch),
+            )
+            .await
+            .map_err(kube_failed(format!(
+                "set image of {}/{} to {}",
+                deployment, container, image
+            )))?;
+        info!("Set the image of {}/{} to {}", deployment, container, image);
+        Ok(())
+    }
+
+    /// Waits like `kubectl rollout status` for all replicas to run the latest template and
+    /// old ones to be gone. Fails when the deployment exceeds its progress deadline, and with
+    /// [`ErrorClass::Timeout`] after `timeout`, both with the [`RolloutReport`] attached.
+    pub async fn wait_for_rollout(
+        &self,
+        deployment: &str,
+        timeout: Duration,
+    ) -> RResult<RolloutReport, AnyErr> {
+        let started = Instant::now();
+        loop {
+            let current = self
+                .api::<Deployment>()
+                .get(deployment)
+                .await
+                .map_err(kube_failed(format!("get deployment {}", deployment)))?;
+            let mut report = rollout_report(&current, started.elapsed());
+            if report.complete {
+                info!("Rolled out {}", report);
+                return Ok(report);
+            }
+            report.issues = self.pod_issues(&current).await?;
+            if let Some(message) = deadline_exceeded(&current) {
+                return Err(err!(
+                    AnyErr,
+                    "Rollout of {} stalled: {}",
+                    deployment,
+                    message
+                ))
+                .attach_printable(report.to_string());
+            }
+            if started.elapsed() >= timeout {
+                return Err(err!(
+                    AnyErr,
+                    "Timed out waiting for the rollout of {}",
+                    deployment
+                ))
+                .attach_printable(report.to_string())
+                .classify(ErrorClass::Timeout);
+            }
+            debug!("Rolling out {}", report);
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Waiting containers of the deployment's pods.
+    async fn pod_issues(&self, deployment: &Deployment) -> RResult<Vec<PodIssue>, AnyErr> {
+        let Some(labels) = deployment
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.selector.match_labels.as_ref())
+        else {
+            return Ok(vec![]);
+        };
+        let selector = labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(",");
+        let pods = self
+            .api::<Pod>()
+            .list(&ListParams::default().labels(&selector))
+            .await
+            .map_err(kube_failed(format!("list pods of {}", selector)))?;
+        Ok(pods.items.iter().flat_map(waiting_containers).collect())
+    }
+}
+
+fn rollout_report(deployment: &Deployment, duration: Duration) -> RolloutReport {
+    let spec = deployment.spec.as_ref();
+    let status = deployment.status.clone().unwrap_or_default();
+    let desired = spec.and_then(|spec| spec.replicas).unwrap_or(1);
+    let updated = status.updated_replicas.unwrap_or_default();
+    let available = status.available_replicas.unwrap_or_default();
+    let total = status.replicas.unwrap_or_default();
+    // Until the controller saw the latest spec the counts are of the previous one:
+    let observed = status.observed_generation >= deployment.metadata.generation;
+    RolloutReport {
+        deployment: deployment.metadata.name.clone().unwrap_or_default(),
+        revision: deployment
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|a| a.get("deployment.kubernetes.io/revision").cloned()),
+        desired,
+        updated,
+        available,
+        total,
+        complete: observed && updated >= desired && total == updated && available >= updated,
+        issues: vec![],
+        duration,
+    }
+}
+
+fn deadline_exceeded(deployment: &Deployment) -> Option<String> {
+    deployment
+        .status
+        .as_ref()?
+        .conditions
+        .as_ref()?
+        .iter()
+        .find(|c| {
+            c.type_ == "Progressing" && c.reason.as_deref() == Some("ProgressDeadlineExceeded")
+        })
+        .map(|c| c.message.clone().unwrap_or_default())
+}
+
+fn waiting_containers(pod: &Pod) -> Vec<PodIssue> {
+    let pod_name = pod.metadata.name.clone().unwrap_or_default();
+    pod.status
+        .iter()
+        .flat_map(|status| status.container_statuses.iter().flatten())
+        .filter_map(|status| {
+            let waiting = status.state.as_ref()?.waiting.as_ref()?;
+            Some(PodIssue {
+                pod: pod_name.clone(),
+                container: status.name.clone(),
+                reason: waiting.reason.clone().unwrap_or_default(),
+                message: waiting.message.clone().unwrap_or_default(),
+                restarts: status.restart_count,
+            })
+        })
+        // Containers are briefly waiting while created:
+        .filter(|issue| issue.reason != "ContainerCreating")
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fake_api;
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn deployment(updated: i32, total: i32) -> String {
+        json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": {"name": "api", "generation": 2, "annotations": {"deployment.kubernetes.io/revision": "7"}},
+            "spec": {"replicas": 2, "selector": {"matchLabels": {"app": "api"}}, "template": {}},
+            "status": {"observedGeneration": 2, "replicas": total, "updatedReplicas": updated, "availableReplicas": updated}
+        })
+        .to_string()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn waits_for_rollout() {
+        let gets = Arc::new(AtomicUsize::new(0));
+        let counted = gets.clone();
+        let manager = fake_api(move |method, path, body| {
+            let path = path.split('?').next().unwrap_or_default();
+            match (method, path) {
+                ("PATCH", "/apis/apps/v1/namespaces/test/deployments/api") => {
+                    assert!(body.contains(r#""image":"api:v8""#));
+                    (200, deployment(0, 2))
+                }
+                ("GET", "/apis/apps/v1/namespaces/test/deployments/api") => {
+                    // An old pod is left on the first check:
+                    match counted.fetch_add(1, Ordering::SeqCst) {
+                        0 => (200, deployment(2, 3)),
+                        _ => (200, deployment(2, 2)),
+                    }
+                }
+                ("GET", "/api/v1/namespaces/test/pods") => (
+                    200,
+                    json!({
+                        "apiVersion": "v1", "kind": "PodList", "metadata": {},
+                        "items": [{"metadata": {"name": "api-7-x2"}, "status": {"containerStatuses": [{
+                            "name": "api", "image": "api:v8", "imageID": "", "ready": false, "restartCount": 3,
+                            "state": {"waiting": {"reason": "CrashLoopBackOff", "message": "back-off 40s"}}
+                        }]}}]
+                    })
+                    .to_string(),
+                ),
+                _ => panic!("Unexpected {} {}", method, path),
+            }
+        })
+        .await;
+
+        manager.set_image("api", "api", "api:v8").await.unwrap();
+        let report = manager
+            .wait_for_rollout("api", Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert!(report.complete);
+        assert_eq!(report.revision.as_deref(), Some("7"));
+        assert_eq!((report.updated, report.total), (2, 2));
+    }
+
+    #[rstest]
+    fn reports_waiting_pods() {
+        let pod: Pod = serde_json::from_value(json!({
+            "metadata": {"name": "api-7-x2"},
+            "status": {"containerStatuses": [
+                {"name": "api", "image": "", "imageID": "", "ready": false, "restartCount": 0,
+                 "state": {"waiting": {"reason": "ImagePullBackOff", "message": "not found"}}},
+                {"name": "sidecar", "image": "", "imageID": "", "ready": true, "restartCount": 0,
+                 "state": {"running": {}}}
+            ]}
+        }))
+        .unwrap();
+        assert_eq!(
+            waiting_containers(&pod),
+            [PodIssue {
+                pod: "api-7-x2".to_string(),
+                container: "api".to_string(),
+                reason: "ImagePullBackOff".to_string(),
+                message: "not found".to_string(),
+                restarts: 0,
+            }]
+        );
+    }
+}
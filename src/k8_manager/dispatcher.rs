@@ -0,0 +1,579 @@
+use chrono::Utc;
+use k8s_openapi::api::batch::v1::Job;
+use kube::api::ListParams;
+use redis::streams::{StreamId, StreamReadOptions, StreamReadReply};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::jobs::finished;
+use super::{kube_failed, JobBuilder, KubeManager};
+use crate::errors::error_class;
+use crate::prelude::*;
+use crate::redis_manager::RedisManager;
+
+/// Labels the jobs of a dispatcher with its stream, so it only tracks its own.
+const DISPATCHER_LABEL: &str = "rutils/dispatcher";
+/// Annotates jobs with their request, labels can't hold any id.
+const REQUEST_ANNOTATION: &str = "rutils/request-id";
+
+/// A job for a [`JobDispatcher`] to run, stored as json in the `job` field of stream entries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRequest {
+    /// Unique per request, the key of its [`JobStatus`].
+    pub id: String,
+    pub image: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Created, but no pod is running yet.
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// Where a requested job got to, stored as json at [`JobDispatcher::status_key`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub request_id: String,
+    /// Of the kubernetes job, None when it couldn't be created.
+    pub job_name: Option<String>,
+    pub state: JobState,
+    /// Why it failed before running, e.g. an invalid request.
+    pub error: Option<String>,
+    /// Unix seconds.
+    pub updated: i64,
+}
+
+type Customize = Arc<dyn Fn(JobBuilder, &JobRequest) -> JobBuilder + Send + Sync>;
+
+/// Runs the [`JobRequest`]s added to a redis stream as kubernetes jobs, keeping their
+/// [`JobStatus`] in redis and deleting finished jobs after a while.
+///
+/// ```ignore
+/// let dispatcher = JobDispatcher::new(kube, redis, "jobs:reports")
+///     .customize(|job, _| job.requests("1", "2Gi").backoff_limit(1));
+/// tokio::spawn(async move { dispatcher.run().await });
+/// ```
+///
+/// Dispatchers of the same stream and group share its entries, each is handled once.
+#[derive(Clone)]
+pub struct JobDispatcher {
+    kube: KubeManager,
+    redis: RedisManager,
+    stream: String,
+    group: String,
+    consumer: String,
+    customize: Option<Customize>,
+    batch_size: usize,
+    block: Duration,
+    retain_finished: Duration,
+    status_ttl: Duration,
+}
+
+impl std::fmt::Debug for JobDispatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JobDispatcher")
+            .field("stream", &self.stream)
+            .field("group", &self.group)
+            .field("consumer", &self.consumer)
+            .finish_non_exhaustive()
+    }
+}
+
+impl JobDispatcher {
+    pub fn new(kube: KubeManager, redis: RedisManager, stream: impl Into<String>) -> Self {
+        JobDispatcher {
+            kube,
+            redis,
+            stream: stream.into(),
+            group: "k8s-dispatcher".to_string(),
+            consumer: format!("k8s-dispatcher-{}", std::process::id()),
+            customize: None,
+            batch_size: 10,
+            block: Duration::from_secs(5),
+            retain_finished: Duration::from_secs(60 * 60),
+            status_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+
+    /// The consumer group reading the stream, `k8s-dispatcher` by default.
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.group = group.into();
+        self
+    }
+
+    /// This dispatcher's name in the group, unique per process by default.
+    pub fn consumer(mut self, consumer: impl Into<String>) -> Self {
+        self.consumer = consumer.into();
+        self
+    }
+
+    /// Adjusts the jobs built from requests, e.g. with resources or a service account.
+    pub fn customize(
+        mut self,
+        customize: impl Fn(JobBuilder, &JobRequest) -> JobBuilder + Send + Sync + 'static,
+    ) -> Self {
+        self.customize = Some(Arc::new(customize));
+        self
+    }
+
+    /// How many requests to read at once, 10 by default.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// How long to wait for new requests before checking the jobs again, 5s by default.
+    pub fn block(mut self, block: Duration) -> Self {
+        self.block = block;
+        self
+    }
+
+    /// How long finished jobs and their pods are kept for inspection, an hour by default.
+    pub fn retain_finished(mut self, retain: Duration) -> Self {
+        self.retain_finished = retain;
+        self
+    }
+
+    /// How long statuses are kept after their last update, a week by default.
+    pub fn status_ttl(mut self, ttl: Duration) -> Self {
+        self.status_ttl = ttl;
+        self
+    }
+
+    /// Where the [`JobStatus`] of the request is stored.
+    pub fn status_key(&self, request_id: &str) -> String {
+        format!("{}:status:{}", self.stream, request_id)
+    }
+
+    /// Adds the request to the stream, returning its entry id.
+    pub async fn submit(&self, request: &JobRequest) -> RResult<String, AnyErr> {
+        let json = serde_json::to_string(request).change_context(AnyErr)?;
+        let mut con = self
+            .redis
+            .get_async_connection()
+            .await
+            .change_context(AnyErr)?;
+        let entry: String = con
+            .xadd(&self.stream, "*", &[("job", json)])
+            .await
+            .change_context(AnyErr)?;
+        self.redis.return_async_connection(con).await;
+        Ok(entry)
+    }
+
+    pub async fn status(&self, request_id: &str) -> RResult<Option<JobStatus>, AnyErr> {
+        let mut con = self
+            .redis
+            .get_async_connection()
+            .await
+            .change_context(AnyErr)?;
+        let json: Option<String> = con
+            .get(self.status_key(request_id))
+            .await
+            .change_context(AnyErr)?;
+        self.redis.return_async_connection(con).await;
+        json.map(|json| serde_json::from_str(&json).change_context(AnyErr))
+            .transpose()
+    }
+
+    /// Dispatches requests and syncs statuses until the task is dropped, retrying errors.
+    pub async fn run(&self) {
+        info!(
+            "Dispatching jobs of {} as {}/{}",
+            self.stream, self.group, self.consumer
+        );
+        loop {
+            if let Err(e) = self.dispatch().await {
+                warn!("Failed to dispatch jobs of {}: {:?}", self.stream, e);
+                tokio::time::sleep(self.block).await;
+            }
+            if let Err(e) = self.sync().await {
+                warn!("Failed to sync jobs of {}: {:?}", self.stream, e);
+            }
+        }
+    }
+
+    /// Creates the jobs of new requests, waiting up to [`JobDispatcher::block`] for some.
+    /// Returns how many were read.
+    pub async fn dispatch(&self) -> RResult<usize, AnyErr> {
+        let mut con = self
+            .redis
+            .get_async_connection()
+            .await
+            .change_context(AnyErr)?;
+        let created: Result<(), redis::RedisError> = con
+            .xgroup_create_mkstream(&self.stream, &self.group, "0")
+            .await;
+        if let Err(e) = created {
+            // Created already, by this or another dispatcher:
+            if e.code() != Some("BUSYGROUP") {
+                return Err(e).change_context(AnyErr);
+            }
+        }
+        let options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(self.batch_size);
+        // Requests read before that failed to be created are retried first:
+        let mut entries = read_entries(&mut con, &self.stream, "0", &options).await?;
+        if entries.is_empty() {
+            let options = options.block(self.block.as_millis() as usize);
+            entries = read_entries(&mut con, &self.stream, ">", &options).await?;
+        }
+        self.redis.return_async_connection(con).await;
+
+        for entry in &entries {
+            let status = match entry
+                .get::<String>("job")
+                .map(|json| serde_json::from_str(&json))
+            {
+                Some(Ok(request)) => self.create(&request).await?,
+                _ => {
+                    warn!(
+                        "Skipping invalid job request {} of {}",
+                        entry.id, self.stream
+                    );
+                    None
+                }
+            };
+            if let Some(status) = status {
+                self.set_status(&status).await?;
+            }
+            let mut con = self
+                .redis
+                .get_async_connection()
+                .await
+                .change_context(AnyErr)?;
+            let _: usize = con
+                .xack(&self.stream, &self.group, &[&entry.id])
+                .await
+                .change_context(AnyErr)?;
+            self.redis.return_async_connection(con).await;
+        }
+        Ok(entries.len())
+    }
+
+    /// Creates the request's job, a failed status when kubernetes rejected it. Fails on errors
+    /// worth retrying, leaving the request unacknowledged.
+    async fn create(&self, request: &JobRequest) -> RResult<Option<JobStatus>, AnyErr> {
+        let job = self.job_for(request);
+        let job_name = job.metadata.name.clone();
+        let error = match self.kube.submit_job(&job).await {
+            Ok(_) => {
+                info!("Created job {:?} for request {}", job_name, request.id);
+                None
+            }
+            // Created before acknowledging the request failed, unless the name is another's:
+            Err(e) if error_class(&e) == Some(ErrorClass::Conflict) => {
+                let name = job_name.clone().unwrap_or_default();
+                let existing = self
+                    .kube
+                    .api::<Job>()
+                    .get(&name)
+                    .await
+                    .map_err(kube_failed(format!("get job {}", name)))?;
+                let owner = existing
+                    .metadata
+                    .annotations
+                    .as_ref()
+                    .and_then(|a| a.get(REQUEST_ANNOTATION));
+                match owner {
+                    Some(owner) if *owner == request.id => None,
+                    owner => {
+                        warn!(
+                            "Job {} of request {} already exists for request {:?}",
+                            name, request.id, owner
+                        );
+                        Some(format!("Job {} already exists for another request", name))
+                    }
+                }
+            }
+            Err(e) if error_class(&e) == Some(ErrorClass::Retryable) => return Err(e),
+            Err(e) => {
+                warn!(
+                    "Failed to create the job of request {}: {:?}",
+                    request.id, e
+                );
+                Some(e.to_string())
+            }
+        };
+        Ok(Some(JobStatus {
+            request_id: request.id.clone(),
+            job_name: error.is_none().then_some(job_name).flatten(),
+            state: match error {
+                None => JobState::Pending,
+                Some(_) => JobState::Failed,
+            },
+            error,
+            updated: Utc::now().timestamp(),
+        }))
+    }
+
+    fn job_for(&self, request: &JobRequest) -> Job {
+        let mut builder = JobBuilder::new(job_name(&self.stream, &request.id), &request.image)
+            .args(request.args.iter().cloned())
+            .label(DISPATCHER_LABEL, label_value(&self.stream))
+            .annotation(REQUEST_ANNOTATION, &request.id);
+        for (key, value) in &request.env {
+            builder = builder.env(key, value);
+        }
+        match &self.customize {
+            Some(customize) => customize(builder, request),
+            None => builder,
+        }
+        .build()
+    }
+
+    /// Stores the current state of this dispatcher's jobs and deletes the ones finished for
+    /// longer than [`JobDispatcher::retain_finished`].
+    pub async fn sync(&self) -> RResult<(), AnyErr> {
+        let selector = format!("{}={}", DISPATCHER_LABEL, label_value(&self.stream));
+        let jobs = self
+            .kube
+            .api::<Job>()
+            .list(&ListParams::default().labels(&selector))
+            .await
+            .map_err(kube_failed(format!("list jobs of {}", self.stream)))?;
+        let now = Utc::now();
+        for job in &jobs.items {
+            let Some(request_id) = job
+                .metadata
+                .annotations
+                .as_ref()
+                .and_then(|a| a.get(REQUEST_ANNOTATION))
+            else {
+                continue;
+            };
+            let job_name = job.metadata.name.clone().unwrap_or_default();
+            self.set_status(&JobStatus {
+                request_id: request_id.clone(),
+                job_name: Some(job_name.clone()),
+                state: job_state(job),
+                error: None,
+                updated: now.timestamp(),
+            })
+            .await?;
+            let completed = job
+                .status
+                .as_ref()
+                .and_then(|status| status.completion_time.as_ref())
+                .map(|time| time.0)
+                // Failed jobs have no completion time, the last transition is when they failed:
+                .or_else(|| {
+                    let conditions = job.status.as_ref()?.conditions.as_ref()?;
+                    conditions
+                        .iter()
+                        .filter_map(|c| c.last_transition_time.as_ref())
+                        .map(|time| time.0)
+                        .max()
+                });
+            let expired = completed.is_some_and(|completed| {
+                (now - completed).to_std().unwrap_or_default() >= self.retain_finished
+            });
+            if finished(job).is_some() && expired {
+                self.kube.delete_job(&job_name).await?;
+                debug!("Deleted finished job {}", job_name);
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_status(&self, status: &JobStatus) -> RResult<(), AnyErr> {
+        let json = serde_json::to_string(status).change_context(AnyErr)?;
+        let mut con = self
+            .redis
+            .get_async_connection()
+            .await
+            .change_context(AnyErr)?;
+        con.set_ex::<_, _, ()>(
+            self.status_key(&status.request_id),
+            json,
+            self.status_ttl.as_secs(),
+        )
+        .await
+        .change_context(AnyErr)?;
+        self.redis.return_async_connection(con).await;
+        Ok(())
+    }
+}
+
+async fn read_entries(
+    con: &mut redis::aio::MultiplexedConnection,
+    stream: &str,
+    id: &str,
+    options: &StreamReadOptions,
+) -> RResult<Vec<StreamId>, AnyErr> {
+    let reply: Option<StreamReadReply> = con
+        .xread_options(&[stream], &[id], options)
+        .await
+        .change_context(AnyErr)?;
+    Ok(reply
+        .into_iter()
+        .flat_map(|reply| reply.keys)
+        .flat_map(|key| key.ids)
+        .collect())
+}
+
+fn job_state(job: &Job) -> JobState {
+    match finished(job) {
+        Some(true) => JobState::Succeeded,
+        Some(false) => JobState::Failed,
+        None if job
+            .status
+            .as_ref()
+            .and_then(|s| s.active)
+            .unwrap_or_default()
+            > 0 =>
+        {
+            JobState::Running
+        }
+        None => JobState::Pending,
+    }
+}
+
+/// Lowercase alphanumerics and dashes, as job names have to be dns labels. Ends in a hash of
+/// the stream and request id, as ids differing only in case, punctuation or past the length
+/// limit would otherwise share a job.
+fn job_name(stream: &str, request_id: &str) -> String {
+    let name = format!("{}-{}", stream, request_id)
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect::<String>();
+    let hash = Sha256::digest(format!("{}\n{}", stream, request_id));
+    // Pods get a suffix of the job name, leaving room for it:
+    let name = &name[..name.len().min(47)];
+    format!("{}-{}", name.trim_matches('-'), hex::encode(&hash[..4]))
+}
+
+/// Label values are at most 63 alphanumerics, `-`, `_` or `.`.
+fn label_value(value: &str) -> String {
+    let value = value
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    let value = &value[..value.len().min(63)];
+    value
+        .trim_matches(|c: char| !c.is_ascii_alphanumeric())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{fake_api, fake_status};
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+
+    #[rstest]
+    #[case("jobs:reports", "Report 42", "jobs-reports-report-42-98437b16")]
+    #[case("jobs", "-x-", "jobs--x-db6d9f9f")]
+    fn names_jobs(#[case] stream: &str, #[case] id: &str, #[case] expected: &str) {
+        assert_eq!(job_name(stream, id), expected);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn keeps_colliding_ids_apart() {
+        let long = "a".repeat(60);
+        let ids = [
+            "Report 42".to_string(),
+            "report-42".to_string(),
+            "report.42".to_string(),
+            format!("{}1", long),
+            format!("{}2", long),
+        ];
+        let names = ids
+            .iter()
+            .map(|id| job_name("jobs:reports", id))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(names.len(), ids.len());
+        assert!(names.iter().all(|name| name.len() <= 56));
+
+        // A job already existing under the name only counts as created by the same request:
+        let kube = fake_api(|method, path, _| match method {
+            "POST" => fake_status(409, "AlreadyExists", "jobs.batch already exists"),
+            _ => {
+                assert!(
+                    path.starts_with("/apis/batch/v1/namespaces/test/jobs/"),
+                    "{}",
+                    path
+                );
+                let job = json!({"metadata": {"annotations": {REQUEST_ANNOTATION: "r-1"}}});
+                (200, job.to_string())
+            }
+        })
+        .await;
+        let redis = RedisManager::new("redis://127.0.0.1/").unwrap();
+        let dispatcher = JobDispatcher::new(kube, redis, "jobs:reports");
+        let request = |id: &str| JobRequest {
+            id: id.to_string(),
+            image: "reports:2".to_string(),
+            args: vec![],
+            env: BTreeMap::new(),
+        };
+        let same = dispatcher.create(&request("r-1")).await.unwrap().unwrap();
+        assert_eq!(same.state, JobState::Pending);
+        assert_eq!(same.error, None);
+        let other = dispatcher.create(&request("r-2")).await.unwrap().unwrap();
+        assert_eq!(other.state, JobState::Failed);
+        assert!(other.error.unwrap().contains("another request"));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn builds_jobs_from_requests() {
+        let kube = fake_api(|_, path, _| panic!("Unexpected {}", path)).await;
+        let redis = RedisManager::new("redis://127.0.0.1/").unwrap();
+        let dispatcher = JobDispatcher::new(kube, redis, "jobs:reports")
+            .customize(|job, request| job.label("team", "data").env("REQUEST", &request.id));
+        let request = JobRequest {
+            id: "r-1".to_string(),
+            image: "reports:2".to_string(),
+            args: vec!["--day".to_string(), "monday".to_string()],
+            env: BTreeMap::from([("MODE".to_string(), "full".to_string())]),
+        };
+        let job = dispatcher.job_for(&request);
+        assert_eq!(
+            job.metadata.name.as_deref(),
+            Some("jobs-reports-r-1-81895499")
+        );
+        assert_eq!(
+            serde_json::to_value(&job.metadata.labels).unwrap(),
+            json!({"rutils/dispatcher": "jobs_reports", "team": "data"})
+        );
+        assert_eq!(
+            job.metadata.annotations.as_ref().unwrap()[REQUEST_ANNOTATION],
+            "r-1"
+        );
+        let container = &job.spec.unwrap().template.spec.unwrap().containers[0];
+        assert_eq!(
+            serde_json::to_value(&container.env).unwrap(),
+            json!([{"name": "MODE", "value": "full"}, {"name": "REQUEST", "value": "r-1"}])
+        );
+        assert_eq!(dispatcher.status_key("r-1"), "jobs:reports:status:r-1");
+    }
+
+    #[rstest]
+    #[case(json!({"active": 1}), JobState::Running)]
+    #[case(json!({}), JobState::Pending)]
+    #[case(json!({"conditions": [{"type": "Failed", "status": "True"}]}), JobState::Failed)]
+    fn tracks_job_state(#[case] status: serde_json::Value, #[case] state: JobState) {
+        let job: Job = serde_json::from_value(json!({"metadata": {}, "status": status})).unwrap();
+        assert_eq!(job_state(&job), state);
+    }
+}
@@ -0,0 +1,225 @@
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Status;
+use kube::api::AttachParams;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::{kube_failed, KubeManager};
+use crate::prelude::*;
+
+/// What a command run by [`KubeManager::exec`] printed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// None when kubernetes didn't say which non zero code it was.
+    pub exit_code: Option<i32>,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+impl KubeManager {
+    /// Runs `command` in the container of the running pod, like `kubectl exec`, e.g. for a
+    /// migration. Commands exiting non zero are returned too, check
+    /// [`CommandOutput::success`]. Fails with [`ErrorClass::Timeout`] after `timeout`.
+    pub async fn exec<S: AsRef<str>>(
+        &self,
+        pod: &str,
+        container: &str,
+        command: &[S],
+        timeout: Duration,
+    ) -> RResult<CommandOutput, AnyErr> {
+        let command = command
+            .iter()
+            .map(|arg| arg.as_ref().to_string())
+            .collect::<Vec<_>>();
+        let timed_out = || {
+            err!(
+                AnyErr,
+                "Timed out running a command in {}/{}",
+                pod,
+                container
+            )
+            .attach_printable(format!("Timeout: {:?}", timeout))
+            .classify(ErrorClass::Timeout)
+        };
+        let deadline = tokio::time::Instant::now() + timeout;
+        let params = AttachParams::default().container(container);
+        let mut attached = tokio::time::timeout_at(
+            deadline,
+            self.api::<Pod>().exec(pod, command.clone(), &params),
+        )
+        .await
+        .map_err(|_| timed_out())?
+        .map_err(kube_failed(format!("exec in pod {}", pod)))?;
+
+        let (stdout, stderr, status) =
+            (attached.stdout(), attached.stderr(), attached.take_status());
+        let finished = async {
+            let status = async {
+                match status {
+                    Some(status) => status.await,
+                    None => None,
+                }
+            };
+            tokio::join!(read_all(stdout), read_all(stderr), status)
+        };
+        let (stdout, stderr, status) = match tokio::time::timeout_at(deadline, finished).await {
+            Ok(finished) => finished,
+            Err(_) => {
+                attached.abort();
+                return Err(timed_out());
+            }
+        };
+        let output = CommandOutput {
+            stdout: stdout.change_context(AnyErr)?,
+            stderr: stderr.change_context(AnyErr)?,
+            exit_code: exit_code(status)
+                .attach_printable_lazy(|| format!("Command: {:?}", command))?,
+        };
+        debug!(
+            "Ran {:?} in {}/{} with exit code {:?}",
+            command, pod, container, output.exit_code
+        );
+        Ok(output)
+    }
+}
+
+async fn read_all(reader: Option<impl AsyncRead + Unpin>) -> std::io::Result<String> {
+    let mut output = vec![];
+    if let Some(mut reader) = reader {
+        reader.read_to_end(&mut output).await?;
+    }
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// The exit code of the `Status` sent once the command exited, erroring when it couldn't run,
+/// e.g. as the executable doesn't exist.
+fn exit_code(status: Option<Status>) -> RResult<Option<i32>, AnyErr> {
+    let Some(status) = status else {
+        return Err(err!(AnyErr, "The exec ended without an exit status"));
+    };
+    match (status.status.as_deref(), status.reason.as_deref()) {
+        (Some("Success"), _) => Ok(Some(0)),
+        (_, Some("NonZeroExitCode")) => Ok(status
+            .details
+            .and_then(|details| details.causes)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|cause| cause.reason.as_deref() == Some("ExitCode"))
+            .and_then(|cause| cause.message?.parse().ok())),
+        _ => Err(err!(
+            AnyErr,
+            "Failed to run the command: {}",
+            status.message.unwrap_or_default()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fake_ws_api;
+    use super::*;
+    use crate::errors::error_class;
+    use futures::SinkExt;
+    use rstest::*;
+    use tokio_tungstenite::tungstenite::Message;
+
+    /// A message of a kubernetes exec channel.
+    fn channel(channel: u8, data: &str) -> Message {
+        Message::binary([&[channel], data.as_bytes()].concat())
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn captures_output() {
+        let manager = fake_ws_api(
+            |_, path, _| panic!("Unexpected {}", path),
+            |path, mut socket| async move {
+                assert_eq!(
+                    path,
+                    "/api/v1/namespaces/test/pods/api-0/exec?&stdout=true&stderr=true&container=api&command=.%2Fmigrate&command=--up"
+                );
+                let status = serde_json::json!({
+                    "status": "Failure",
+                    "reason": "NonZeroExitCode",
+                    "details": {"causes": [{"reason": "ExitCode", "message": "3"}]},
+                });
+                for message in [
+                    channel(1, "2 migrations\n"),
+                    channel(2, "migrated\n"),
+                    channel(3, &status.to_string()),
+                ] {
+                    socket.send(message).await.unwrap();
+                }
+                let _ = socket.close(None).await;
+            },
+        )
+        .await;
+
+        let output = manager
+            .exec(
+                "api-0",
+                "api",
+                &["./migrate", "--up"],
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            output,
+            CommandOutput {
+                stdout: "2 migrations\n".to_string(),
+                stderr: "migrated\n".to_string(),
+                exit_code: Some(3),
+            }
+        );
+        assert!(!output.success());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reports_commands_that_cant_run() {
+        let manager = fake_ws_api(
+            |_, path, _| panic!("Unexpected {}", path),
+            |_, mut socket| async move {
+                let status = serde_json::json!({
+                    "status": "Failure",
+                    "reason": "InternalError",
+                    "message": "executable file not found in $PATH",
+                });
+                socket.send(channel(3, &status.to_string())).await.unwrap();
+            },
+        )
+        .await;
+
+        let report = manager
+            .exec("api-0", "api", &["./nope"], Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", report).contains("executable file not found"));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn times_out() {
+        let manager = fake_ws_api(
+            |_, path, _| panic!("Unexpected {}", path),
+            |_, socket| async move {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                drop(socket);
+            },
+        )
+        .await;
+
+        let report = manager
+            .exec("api-0", "api", &["true"], Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert_eq!(error_class(&report), Some(ErrorClass::Timeout));
+    }
+}
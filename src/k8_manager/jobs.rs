@@ -0,0 +1,298 @@
+use futures::{AsyncBufReadExt, StreamExt};
+use k8s_openapi::api::batch::v1::Job;
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{DeleteParams, ListParams, LogParams, PostParams, PropagationPolicy};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+use super::{kube_failed, JobBuilder, KubeManager};
+use crate::prelude::*;
+
+/// How often [`KubeManager::run_job`] checks the job.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How a job run by [`KubeManager::run_job`] ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobResult {
+    pub name: String,
+    pub succeeded: bool,
+    /// Of the job's last pod, None when it never ran.
+    pub exit_code: Option<i32>,
+    /// The output of the job's last pod.
+    pub logs: String,
+    pub duration: Duration,
+}
+
+impl KubeManager {
+    /// Creates a job running `image_uri` once, see [`JobBuilder`] for more.
+    pub async fn create_job(&self, job_name: &str, image_uri: &str) -> RResult<Job, AnyErr> {
+        self.submit_job(&create_k8s_job(job_name, image_uri)).await
+    }
+
+    pub async fn submit_job(&self, job: &Job) -> RResult<Job, AnyErr> {
+        let name = job.metadata.name.clone().unwrap_or_default();
+        self.api::<Job>()
+            .create(&PostParams::default(), job)
+            .await
+            .map_err(kube_failed(format!("create job {}", name)))
+    }
+
+    /// Submits `job` and waits for it to succeed or fail, logging its pod's output while it
+    /// runs. After `timeout` the job is deleted and this fails with [`ErrorClass::Timeout`].
+    pub async fn run_job(&self, job: &Job, timeout: Duration) -> RResult<JobResult, AnyErr> {
+        let name = job
+            .metadata
+            .name
+            .clone()
+            .ok_or_else(|| err!(AnyErr, "Jobs to run need a name"))?;
+        let span = tracing::info_span!("k8s_job", job = %name);
+        async move {
+            let started = Instant::now();
+            self.submit_job(job).await?;
+            info!("Submitted job {}", name);
+
+            let mut followed: Option<(String, JoinHandle<String>)> = None;
+            let succeeded = loop {
+                if let Some(pod) = self.job_pod(&name).await? {
+                    let pod_name = pod.metadata.name.clone().unwrap_or_default();
+                    // A retried job gets a new pod, follow that one instead:
+                    let stale = followed.as_ref().is_some_and(|(name, _)| *name != pod_name);
+                    if (followed.is_none() || stale) && has_started(&pod) {
+                        let task = tokio::spawn(
+                            follow_logs(self.clone(), pod_name.clone())
+                                .instrument(tracing::Span::current()),
+                        );
+                        followed = Some((pod_name, task));
+                    }
+                }
+                let status = self
+                    .api::<Job>()
+                    .get_status(&name)
+                    .await
+                    .map_err(kube_failed(format!("get job {}", name)))?;
+                if let Some(succeeded) = finished(&status) {
+                    break succeeded;
+                }
+                if started.elapsed() >= timeout {
+                    self.delete_job(&name).await?;
+                    return Err(err!(AnyErr, "Timed out waiting for job {}", name))
+                        .attach_printable(format!("Timeout: {:?}", timeout))
+                        .classify(ErrorClass::Timeout);
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            };
+
+            let pod = self.job_pod(&name).await?;
+            let pod_name = pod.as_ref().and_then(|pod| pod.metadata.name.clone());
+            let logs = match (followed, &pod_name) {
+                (Some((followed, task)), Some(pod_name)) if followed == *pod_name => {
+                    task.await.unwrap_or_default()
+                }
+                // Finished before it was seen running:
+                (_, Some(pod_name)) => self
+                    .api::<Pod>()
+                    .logs(pod_name, &LogParams::default())
+                    .await
+                    .unwrap_or_default(),
+                (_, None) => String::new(),
+            };
+            let result = JobResult {
+                name: name.clone(),
+                succeeded,
+                exit_code: pod.as_ref().and_then(exit_code),
+                logs,
+                duration: started.elapsed(),
+            };
+            info!(
+                "Job {} {} with exit code {:?}",
+                name,
+                if succeeded { "succeeded" } else { "failed" },
+                result.exit_code
+            );
+            Ok(result)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Deletes the job and its pods.
+    pub async fn delete_job(&self, name: &str) -> RResult<(), AnyErr> {
+        let params = DeleteParams {
+            propagation_policy: Some(PropagationPolicy::Background),
+            ..Default::default()
+        };
+        self.api::<Job>()
+            .delete(name, &params)
+            .await
+            .map_err(kube_failed(format!("delete job {}", name)))?;
+        Ok(())
+    }
+
+    /// The newest pod of the job.
+    async fn job_pod(&self, job_name: &str) -> RResult<Option<Pod>, AnyErr> {
+        let pods = self
+            .api::<Pod>()
+            .list(&ListParams::default().labels(&format!("job-name={}", job_name)))
+            .await
+            .map_err(kube_failed(format!("list pods of job {}", job_name)))?;
+        Ok(pods
+            .items
+            .into_iter()
+            .max_by_key(|pod| pod.metadata.creation_timestamp.clone()))
+    }
+}
+
+/// Whether the job completed, succeeding or failing, from its conditions.
+pub(super) fn finished(job: &Job) -> Option<bool> {
+    let conditions = job.status.as_ref()?.conditions.as_ref()?;
+    conditions
+        .iter()
+        .filter(|condition| condition.status == "True")
+        .find_map(|condition| match condition.type_.as_str() {
+            "Complete" => Some(true),
+            "Failed" => Some(false),
+            _ => None,
+        })
+}
+
+/// Logs can only be followed once the containers were created.
+fn has_started(pod: &Pod) -> bool {
+    let phase = pod
+        .status
+        .as_ref()
+        .and_then(|status| status.phase.as_deref());
+    matches!(phase, Some("Running" | "Succeeded" | "Failed"))
+}
+
+fn exit_code(pod: &Pod) -> Option<i32> {
+    pod.status
+        .as_ref()?
+        .container_statuses
+        .as_ref()?
+        .iter()
+        .find_map(|status| Some(status.state.as_ref()?.terminated.as_ref()?.exit_code))
+}
+
+/// Logs the pod's output as it's written, returning all of it once the pod ends.
+async fn follow_logs(manager: KubeManager, pod: String) -> String {
+    let params = LogParams {
+        follow: true,
+        ..Default::default()
+    };
+    let stream = match manager.api::<Pod>().log_stream(&pod, &params).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!("Failed to follow the logs of pod {}: {}", pod, e);
+            return String::new();
+        }
+    };
+    let mut lines = stream.lines();
+    let mut logs = String::new();
+    while let Some(line) = lines.next().await {
+        let Ok(line) = line else {
+            break;
+        };
+        info!(pod = %pod, "{}", line);
+        logs.push_str(&line);
+        logs.push('\n');
+    }
+    logs
+}
+
+fn create_k8s_job(job_name: &str, image_uri: &str) -> Job {
+    JobBuilder::new(job_name, image_uri).build()
+}
+
+#[rstest::rstest]
+#[allow(unused_variables)]
+fn test_create_k8s_job() {
+    let job_name = "test-job";
+    let image_uri = "alelat/wondera:latest";
+
+    // let client = Client::try_default().unwrap();
+    // let job = create_k8s_job(client, job_name, image_uri).block().unwrap();
+
+    // assert_eq!(job.metadata.name.unwrap(), job_name);
+    // assert_eq!(
+    //     job.spec.unwrap().template.spec.unwrap().containers[0]
+    //         .image
+    //         .unwrap(),
+    //     image_uri
+    // );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fake_api;
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn job_json(conditions: serde_json::Value) -> String {
+        json!({
+            "apiVersion": "batch/v1",
+            "kind": "Job",
+            "metadata": {"name": "train", "namespace": "test"},
+            "status": {"conditions": conditions},
+        })
+        .to_string()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn runs_job_to_completion() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counted = polls.clone();
+        let manager = fake_api(move |method, path, _| {
+            let path = path.split('?').next().unwrap_or_default();
+            match (method, path) {
+                ("POST", "/apis/batch/v1/namespaces/test/jobs") => (201, job_json(json!([]))),
+                ("GET", "/apis/batch/v1/namespaces/test/jobs/train/status") => {
+                    // Running on the first check:
+                    let conditions = if counted.fetch_add(1, Ordering::SeqCst) == 0 {
+                        json!([])
+                    } else {
+                        json!([{"type": "Complete", "status": "True"}])
+                    };
+                    (200, job_json(conditions))
+                }
+                ("GET", "/api/v1/namespaces/test/pods") => (
+                    200,
+                    json!({
+                        "apiVersion": "v1",
+                        "kind": "PodList",
+                        "metadata": {},
+                        "items": [{
+                            "metadata": {"name": "train-x7k2p", "creationTimestamp": "2024-06-01T12:00:00Z"},
+                            "status": {"phase": "Succeeded", "containerStatuses": [{
+                                "name": "train", "image": "trainer:1", "imageID": "", "ready": false,
+                                "restartCount": 0, "state": {"terminated": {"exitCode": 0}}
+                            }]}
+                        }]
+                    })
+                    .to_string(),
+                ),
+                ("GET", "/api/v1/namespaces/test/pods/train-x7k2p/log") => {
+                    (200, "epoch 1\nepoch 2\n".to_string())
+                }
+                _ => panic!("Unexpected {} {}", method, path),
+            }
+        })
+        .await;
+
+        let result = manager
+            .run_job(
+                &create_k8s_job("train", "trainer:1"),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+        assert!(result.succeeded);
+        assert_eq!(result.exit_code, Some(0));
+        assert_eq!(result.logs, "epoch 1\nepoch 2\n");
+        assert_eq!(polls.load(Ordering::SeqCst), 2);
+    }
+}
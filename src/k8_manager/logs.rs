@@ -0,0 +1,226 @@
+use chrono::{DateTime, Utc};
+use futures::{AsyncBufReadExt, Stream, StreamExt};
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{ListParams, LogParams};
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tracing::Instrument;
+
+use super::KubeManager;
+use crate::prelude::*;
+
+/// How often [`KubeManager::stream_pod_logs`] looks for new pods.
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A line logged by a pod, see [`KubeManager::stream_pod_logs`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PodLogLine {
+    pub pod: String,
+    pub container: String,
+    pub line: String,
+}
+
+/// The logs of all pods matching a selector as they're written, ends when dropped.
+#[derive(Debug)]
+pub struct PodLogStream {
+    lines: mpsc::Receiver<PodLogLine>,
+    _cancel: DropGuard,
+}
+
+impl Stream for PodLogStream {
+    type Item = PodLogLine;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.lines.poll_recv(cx)
+    }
+}
+
+impl KubeManager {
+    /// Follows the containers of all pods matching the label `selector`, e.g. `app=api`,
+    /// including pods started later, from now on.
+    ///
+    /// With `tracing_app` the lines are also logged with the pod as their `service_name` in a
+    /// `pod_logs` span of the app, so with [`crate::redis_tracing`] set up they are stored with
+    /// the app's logs.
+    pub fn stream_pod_logs(&self, selector: &str, tracing_app: Option<&str>) -> PodLogStream {
+        let (sender, lines) = mpsc::channel(1024);
+        let cancel = CancellationToken::new();
+        let span = match tracing_app {
+            Some(app_name) => {
+                tracing::info_span!("pod_logs", selector = %selector, app_name = %app_name)
+            }
+            None => tracing::Span::none(),
+        };
+        tokio::spawn(
+            discover_pods(
+                self.clone(),
+                selector.to_string(),
+                tracing_app.is_some(),
+                sender,
+                cancel.clone(),
+            )
+            .instrument(span),
+        );
+        PodLogStream {
+            lines,
+            _cancel: cancel.drop_guard(),
+        }
+    }
+}
+
+/// Follows the containers of matching pods that aren't followed yet until cancelled.
+async fn discover_pods(
+    manager: KubeManager,
+    selector: String,
+    traced: bool,
+    sender: mpsc::Sender<PodLogLine>,
+    cancel: CancellationToken,
+) {
+    // When each container was last followed until, so restarts don't repeat lines:
+    let mut followed: BTreeMap<(String, String), Option<DateTime<Utc>>> = BTreeMap::new();
+    let (ended_sender, mut ended) = mpsc::unbounded_channel();
+    let started = Utc::now();
+    loop {
+        while let Ok(key) = ended.try_recv() {
+            followed.insert(key, Some(Utc::now()));
+        }
+        match manager
+            .api::<Pod>()
+            .list(&ListParams::default().labels(&selector))
+            .await
+        {
+            Ok(pods) => {
+                for (pod, container) in pods.items.iter().flat_map(running_containers) {
+                    let key = (pod.clone(), container.clone());
+                    let since = match followed.get(&key) {
+                        // Still being followed:
+                        Some(None) => continue,
+                        Some(Some(ended)) => *ended,
+                        None => started,
+                    };
+                    followed.insert(key.clone(), None);
+                    let (manager, sender, ended_sender, cancel) = (
+                        manager.clone(),
+                        sender.clone(),
+                        ended_sender.clone(),
+                        cancel.clone(),
+                    );
+                    let follow = async move {
+                        tokio::select! {
+                            _ = cancel.cancelled() => {}
+                            _ = follow_container(&manager, &pod, &container, since, traced, &sender) => {
+                                let _ = ended_sender.send(key);
+                            }
+                        }
+                    };
+                    tokio::spawn(follow.in_current_span());
+                }
+            }
+            Err(e) => warn!("Failed to list the pods of {}: {}", selector, e),
+        }
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = sender.closed() => return,
+            _ = tokio::time::sleep(DISCOVERY_INTERVAL) => {}
+        }
+    }
+}
+
+fn running_containers(pod: &Pod) -> Vec<(String, String)> {
+    let name = pod.metadata.name.clone().unwrap_or_default();
+    pod.status
+        .iter()
+        .flat_map(|status| status.container_statuses.iter().flatten())
+        .filter(|status| status.state.as_ref().is_some_and(|s| s.running.is_some()))
+        .map(|status| (name.clone(), status.name.clone()))
+        .collect()
+}
+
+async fn follow_container(
+    manager: &KubeManager,
+    pod: &str,
+    container: &str,
+    since: DateTime<Utc>,
+    traced: bool,
+    sender: &mpsc::Sender<PodLogLine>,
+) {
+    let params = LogParams {
+        container: Some(container.to_string()),
+        follow: true,
+        since_time: Some(since),
+        ..Default::default()
+    };
+    let stream = match manager.api::<Pod>().log_stream(pod, &params).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            debug!("Failed to follow the logs of {}/{}: {}", pod, container, e);
+            return;
+        }
+    };
+    let mut lines = stream.lines();
+    while let Some(Ok(line)) = lines.next().await {
+        if traced {
+            info!(service_name = %pod, container = %container, "{}", line);
+        }
+        let line = PodLogLine {
+            pod: pod.to_string(),
+            container: container.to_string(),
+            line,
+        };
+        if sender.send(line).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fake_api;
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+
+    #[rstest]
+    #[tokio::test]
+    async fn multiplexes_pods() {
+        let pod = |name: &str| {
+            json!({"metadata": {"name": name}, "status": {"phase": "Running", "containerStatuses": [{
+                "name": "api", "image": "", "imageID": "", "ready": true, "restartCount": 0,
+                "state": {"running": {}}
+            }]}})
+        };
+        let pods = json!({
+            "apiVersion": "v1", "kind": "PodList", "metadata": {},
+            "items": [pod("api-1"), pod("api-2")]
+        })
+        .to_string();
+        let manager = fake_api(move |_, path, _| {
+            let (path, query) = path.split_once('?').unwrap_or((path, ""));
+            match path {
+                "/api/v1/namespaces/test/pods" => {
+                    assert!(query.contains("labelSelector=app%3Dapi"));
+                    (200, pods.clone())
+                }
+                "/api/v1/namespaces/test/pods/api-1/log" => (200, "a\nb\n".to_string()),
+                "/api/v1/namespaces/test/pods/api-2/log" => (200, "c\n".to_string()),
+                _ => panic!("Unexpected {}", path),
+            }
+        })
+        .await;
+
+        let stream = manager.stream_pod_logs("app=api", None);
+        let mut lines =
+            tokio::time::timeout(Duration::from_secs(5), stream.take(3).collect::<Vec<_>>())
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|line| format!("{}/{}: {}", line.pod, line.container, line.line))
+                .collect::<Vec<_>>();
+        lines.sort();
+        assert_eq!(lines, ["api-1/api: a", "api-1/api: b", "api-2/api: c"]);
+    }
+}
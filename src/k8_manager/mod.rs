@@ -0,0 +1,352 @@
+use k8s_openapi::NamespaceResourceScope;
+use kube::config::KubeConfigOptions;
+use kube::{Api, Client, Config, Resource};
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+
+use crate::prelude::*;
+
+mod builder;
+mod configs;
+mod cronjobs;
+mod deployments;
+mod dispatcher;
+mod exec;
+mod jobs;
+mod logs;
+mod port_forward;
+mod rbac;
+mod watch;
+
+pub use builder::JobBuilder;
+pub use cronjobs::CronJobRun;
+pub use deployments::{PodIssue, RolloutReport};
+pub use dispatcher::{JobDispatcher, JobRequest, JobState, JobStatus};
+pub use exec::CommandOutput;
+pub use jobs::JobResult;
+pub use logs::{PodLogLine, PodLogStream};
+pub use port_forward::PortForward;
+pub use rbac::{job_runner_rules, policy_rule};
+pub use watch::WatchEvent;
+
+/// The kubernetes items, part of [`crate::prelude`] with the `k8s` feature.
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{JobBuilder, JobResult, KubeManager};
+}
+
+/// The context below the `AnyErr` of a failed kubernetes api call, get it with
+/// `report.frames().find_map(|f| f.downcast_ref::<KubeError>())`.
+#[derive(Debug, Clone)]
+pub struct KubeError {
+    /// What was attempted, e.g. `create job train-42`.
+    pub operation: String,
+    /// The http status the api answered with, None when it wasn't reached.
+    pub code: Option<u16>,
+    /// e.g. `AlreadyExists` or `NotFound`.
+    pub reason: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for KubeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to {}", self.operation)?;
+        if let Some(code) = self.code {
+            write!(f, ", {} {}", code, self.reason)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl error_stack::Context for KubeError {}
+
+/// Turns a kube error into a [`KubeError`] report classified by its status, for
+/// `.map_err(kube_failed("create job"))`.
+pub(crate) fn kube_failed(
+    operation: impl Into<String>,
+) -> impl FnOnce(kube::Error) -> Report<AnyErr> {
+    move |error| {
+        let (code, reason, message, class) = match &error {
+            kube::Error::Api(response) => (
+                Some(response.code),
+                response.reason.clone(),
+                response.message.clone(),
+                ErrorClass::from_http_status(response.code),
+            ),
+            kube::Error::HyperError(_) | kube::Error::Service(_) => (
+                None,
+                String::new(),
+                error.to_string(),
+                ErrorClass::Retryable,
+            ),
+            _ => (
+                None,
+                String::new(),
+                error.to_string(),
+                ErrorClass::Permanent,
+            ),
+        };
+        Report::new(KubeError {
+            operation: operation.into(),
+            code,
+            reason,
+            message,
+        })
+        .change_context(AnyErr)
+        .classify(class)
+    }
+}
+
+/// Where a [`KubeManager`] found its cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClusterSource {
+    /// `KUBECONFIG` or `~/.kube/config`.
+    Kubeconfig,
+    /// The service account of the pod this runs in.
+    InCluster,
+    /// Given to [`KubeManager::from_config`].
+    Config,
+}
+
+/// A kubernetes client working in one namespace.
+#[derive(Clone)]
+pub struct KubeManager {
+    client: Client,
+    namespace: String,
+    source: ClusterSource,
+}
+
+impl Debug for KubeManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KubeManager")
+            .field("namespace", &self.namespace)
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KubeManager {
+    /// Connects with the kubeconfig if there is one, otherwise with the service account of the
+    /// pod this runs in, using the namespace of the kubeconfig context or of the pod.
+    pub async fn new() -> RResult<Self, AnyErr> {
+        let (config, source) = match Config::from_kubeconfig(&KubeConfigOptions::default()).await {
+            Ok(config) => (config, ClusterSource::Kubeconfig),
+            Err(kubeconfig_err) => match Config::incluster() {
+                Ok(config) => (config, ClusterSource::InCluster),
+                Err(incluster_err) => {
+                    return Err(err!(AnyErr, "No kubernetes cluster configured"))
+                        .attach_printable(format!("Kubeconfig: {}", kubeconfig_err))
+                        .attach_printable(format!("In cluster: {}", incluster_err));
+                }
+            },
+        };
+        let mut manager = Self::from_config(config)?;
+        manager.source = source;
+        debug!(
+            "Using kubernetes from {:?} in namespace {}",
+            manager.source, manager.namespace
+        );
+        Ok(manager)
+    }
+
+    pub fn from_config(config: Config) -> RResult<Self, AnyErr> {
+        let namespace = config.default_namespace.clone();
+        let client = Client::try_from(config)
+            .change_context(AnyErr)
+            .attach_printable("Failed to create the kubernetes client")?;
+        Ok(KubeManager {
+            client,
+            namespace,
+            source: ClusterSource::Config,
+        })
+    }
+
+    /// Works in `namespace` instead of the configured one.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    pub fn get_namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn source(&self) -> ClusterSource {
+        self.source
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// An api for resources of the namespace.
+    pub fn api<K>(&self) -> Api<K>
+    where
+        K: Resource<Scope = NamespaceResourceScope> + Clone + DeserializeOwned + Debug,
+        K::DynamicType: Default,
+    {
+        Api::namespaced(self.client.clone(), &self.namespace)
+    }
+
+    /// An api for cluster scoped resources like namespaces, or resources of all namespaces.
+    pub fn cluster_api<K>(&self) -> Api<K>
+    where
+        K: Resource + Clone + DeserializeOwned + Debug,
+        K::DynamicType: Default,
+    {
+        Api::all(self.client.clone())
+    }
+}
+
+/// A manager for a fake api server answering requests with `respond(method, path, body)`.
+#[cfg(test)]
+pub(crate) async fn fake_api(
+    respond: impl Fn(&str, &str, &str) -> (u16, String) + Send + Sync + 'static,
+) -> KubeManager {
+    fake_ws_api(respond, |path, _| async move {
+        panic!("Unexpected upgrade of {}", path)
+    })
+    .await
+}
+
+/// The websocket of an upgraded request to the fake api server, e.g. of an exec.
+#[cfg(test)]
+pub(crate) type FakeSocket = tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>;
+
+/// Like [`fake_api`], handing websocket upgrades with their path to `connect`.
+#[cfg(test)]
+// The error response of the handshake callback is tungstenite's:
+#[allow(clippy::result_large_err)]
+pub(crate) async fn fake_ws_api<F>(
+    respond: impl Fn(&str, &str, &str) -> (u16, String) + Send + Sync + 'static,
+    connect: impl Fn(String, FakeSocket) -> F + Send + Sync + 'static,
+) -> KubeManager
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (respond, connect) = (Arc::new(respond), Arc::new(connect));
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = listener.accept().await {
+            let (respond, connect) = (respond.clone(), connect.clone());
+            tokio::spawn(async move {
+                // Upgrades are told apart by their headers, left unread for the handshake:
+                let mut head = vec![0; 8192];
+                let head = loop {
+                    let peeked = conn.peek(&mut head).await.unwrap_or_default();
+                    let text = String::from_utf8_lossy(&head[..peeked]).to_lowercase();
+                    if peeked == 0 || text.contains("\r\n\r\n") {
+                        break text;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                };
+                if head.contains("upgrade: websocket") {
+                    let mut path = String::new();
+                    let socket = tokio_tungstenite::accept_hdr_async(
+                        conn,
+                        |request: &Request, mut response: Response| {
+                            path = request.uri().to_string();
+                            response.headers_mut().insert(
+                                "sec-websocket-protocol",
+                                "v4.channel.k8s.io".parse().unwrap(),
+                            );
+                            Ok(response)
+                        },
+                    )
+                    .await
+                    .unwrap();
+                    connect(path, socket).await;
+                    return;
+                }
+                let mut conn = BufReader::new(conn);
+                let mut request_line = String::new();
+                conn.read_line(&mut request_line).await.unwrap_or_default();
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    if conn.read_line(&mut header).await.unwrap_or_default() == 0
+                        || header.trim().is_empty()
+                    {
+                        break;
+                    }
+                    let lower = header.to_lowercase();
+                    if let Some(length) = lower.strip_prefix("content-length:") {
+                        content_length = length.trim().parse().unwrap_or_default();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                conn.read_exact(&mut body).await.unwrap_or_default();
+                let mut parts = request_line.split_whitespace();
+                let (method, path) = (
+                    parts.next().unwrap_or_default(),
+                    parts.next().unwrap_or_default(),
+                );
+                let (status, body) = respond(method, path, &String::from_utf8_lossy(&body));
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = conn.get_mut().write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    let mut config = Config::new(format!("http://{}", addr).parse().unwrap());
+    config.default_namespace = "test".to_string();
+    KubeManager::from_config(config).unwrap()
+}
+
+/// A kubernetes `Status` body of a failed request.
+#[cfg(test)]
+pub(crate) fn fake_status(code: u16, reason: &str, message: &str) -> (u16, String) {
+    (
+        code,
+        serde_json::json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "status": "Failure",
+            "message": message,
+            "reason": reason,
+            "code": code,
+        })
+        .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn api_failures_are_typed() {
+        let manager = fake_api(|_, path, _| {
+            assert_eq!(path, "/apis/batch/v1/namespaces/jobs/jobs?");
+            fake_status(409, "AlreadyExists", "jobs.batch \"train\" already exists")
+        })
+        .await
+        .namespace("jobs");
+        assert_eq!(manager.get_namespace(), "jobs");
+        assert_eq!(manager.source(), ClusterSource::Config);
+
+        let report = manager.create_job("train", "trainer:1").await.unwrap_err();
+        let failed = report
+            .frames()
+            .find_map(|f| f.downcast_ref::<KubeError>())
+            .unwrap();
+        assert_eq!(failed.code, Some(409));
+        assert_eq!(failed.reason, "AlreadyExists");
+        assert_eq!(failed.operation, "create job train");
+        assert_eq!(
+            crate::errors::error_class(&report),
+            Some(ErrorClass::Conflict)
+        );
+    }
+}
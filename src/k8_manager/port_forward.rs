@@ -0,0 +1,299 @@
+use k8s_openapi::api::core::v1::{Pod, Service};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::api::ListParams;
+use kube::ResourceExt;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use super::{kube_failed, KubeManager};
+use crate::prelude::*;
+
+/// A port forwarded by [`KubeManager::port_forward`], stops forwarding when dropped.
+#[derive(Debug)]
+pub struct PortForward {
+    local_port: u16,
+    _cancel: DropGuard,
+}
+
+impl PortForward {
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    /// Where to connect to, e.g. `redis://{addr}`.
+    pub fn local_addr(&self) -> SocketAddr {
+        (Ipv4Addr::LOCALHOST, self.local_port).into()
+    }
+}
+
+impl KubeManager {
+    /// Forwards `local_port` on localhost to `remote_port` of `target`, e.g. `pod/redis-0` or
+    /// `svc/redis`, like `kubectl port-forward`. With a `local_port` of 0 a free one is picked.
+    ///
+    /// Each connection goes to a running pod of the target found as it connects, so replacing
+    /// the pod only drops the connections it had. Forwards until the [`PortForward`] is dropped.
+    pub async fn port_forward(
+        &self,
+        target: &str,
+        local_port: u16,
+        remote_port: u16,
+    ) -> RResult<PortForward, AnyErr> {
+        let failed = || format!("Failed to forward {} of {}", remote_port, target);
+        // Fails right away on targets that don't exist:
+        self.forward_target(target, remote_port)
+            .await
+            .attach_printable_lazy(failed)?;
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, local_port))
+            .await
+            .change_context(AnyErr)
+            .attach_printable_lazy(failed)?;
+        let local_port = listener.local_addr().change_context(AnyErr)?.port();
+        info!(
+            "Forwarding 127.0.0.1:{} to {} of {}",
+            local_port, remote_port, target
+        );
+
+        let cancel = CancellationToken::new();
+        let (manager, target, cancelled) = (self.clone(), target.to_string(), cancel.clone());
+        tokio::spawn(async move {
+            loop {
+                let conn = tokio::select! {
+                    _ = cancelled.cancelled() => break,
+                    conn = listener.accept() => conn,
+                };
+                let Ok((conn, _)) = conn else {
+                    continue;
+                };
+                let (manager, target, cancelled) =
+                    (manager.clone(), target.clone(), cancelled.clone());
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = cancelled.cancelled() => {}
+                        forwarded = manager.forward_connection(&target, remote_port, conn) => {
+                            if let Err(e) = forwarded {
+                                warn!("Failed to forward a connection to {}: {:?}", target, e);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        Ok(PortForward {
+            local_port,
+            _cancel: cancel.drop_guard(),
+        })
+    }
+
+    async fn forward_connection(
+        &self,
+        target: &str,
+        remote_port: u16,
+        mut conn: TcpStream,
+    ) -> RResult<(), AnyErr> {
+        let (pod, port) = self.forward_target(target, remote_port).await?;
+        let mut forwarder = self
+            .api::<Pod>()
+            .portforward(&pod, &[port])
+            .await
+            .map_err(kube_failed(format!("forward port {} of pod {}", port, pod)))?;
+        let mut upstream = forwarder
+            .take_stream(port)
+            .ok_or_else(|| err!(AnyErr, "No stream for port {} of pod {}", port, pod))?;
+        tokio::io::copy_bidirectional(&mut conn, &mut upstream)
+            .await
+            .change_context(AnyErr)
+            .attach_printable_lazy(|| format!("Forwarding port {} of pod {}", port, pod))?;
+        Ok(())
+    }
+
+    /// The pod and its port to forward to, for services a running pod they select and the
+    /// container port `remote_port` of the service targets.
+    async fn forward_target(
+        &self,
+        target: &str,
+        remote_port: u16,
+    ) -> RResult<(String, u16), AnyErr> {
+        let name = match target.split_once('/') {
+            Some(("svc" | "service" | "services", name)) => {
+                return self.service_target(name, remote_port).await
+            }
+            Some(("pod" | "pods" | "po", name)) => name,
+            None => target,
+            Some((kind, _)) => {
+                return Err(err!(
+                    AnyErr,
+                    "Can only forward to pods and services, not {}",
+                    kind
+                ))
+            }
+        };
+        self.api::<Pod>()
+            .get(name)
+            .await
+            .map_err(kube_failed(format!("get pod {}", name)))?;
+        Ok((name.to_string(), remote_port))
+    }
+
+    async fn service_target(&self, name: &str, remote_port: u16) -> RResult<(String, u16), AnyErr> {
+        let service = self
+            .api::<Service>()
+            .get(name)
+            .await
+            .map_err(kube_failed(format!("get service {}", name)))?;
+        let spec = service.spec.unwrap_or_default();
+        let selector = spec
+            .selector
+            .unwrap_or_default()
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(",");
+        if selector.is_empty() {
+            return Err(err!(AnyErr, "Service {} doesn't select pods", name));
+        }
+        let target_port = spec
+            .ports
+            .unwrap_or_default()
+            .into_iter()
+            .find(|port| port.port == remote_port as i32)
+            .and_then(|port| port.target_port);
+        let pod = self
+            .api::<Pod>()
+            .list(&ListParams::default().labels(&selector))
+            .await
+            .map_err(kube_failed(format!("list pods of service {}", name)))?
+            .items
+            .into_iter()
+            .find(|pod| {
+                pod.status
+                    .as_ref()
+                    .and_then(|status| status.phase.as_deref())
+                    == Some("Running")
+            })
+            .ok_or_else(|| err!(AnyErr, "No running pod of service {}", name))?;
+        let port = match target_port {
+            Some(IntOrString::Int(port)) => port as u16,
+            Some(IntOrString::String(port_name)) => pod
+                .spec
+                .iter()
+                .flat_map(|spec| &spec.containers)
+                .flat_map(|container| container.ports.iter().flatten())
+                .find(|port| port.name.as_deref() == Some(port_name.as_str()))
+                .map(|port| port.container_port as u16)
+                .ok_or_else(|| {
+                    err!(
+                        AnyErr,
+                        "Pod {} has no port {} of service {}",
+                        pod.name_any(),
+                        port_name,
+                        name
+                    )
+                })?,
+            None => remote_port,
+        };
+        Ok((pod.name_any(), port))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{fake_status, fake_ws_api};
+    use super::*;
+    use futures::{SinkExt, StreamExt};
+    use rstest::*;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    #[rstest]
+    #[tokio::test]
+    async fn forwards_connections_to_service_pods() {
+        let manager = fake_ws_api(
+            |_, path, _| match path {
+                "/api/v1/namespaces/test/services/redis" => {
+                    let service = json!({
+                        "metadata": {"name": "redis"},
+                        "spec": {
+                            "selector": {"app": "redis"},
+                            "ports": [{"port": 6379, "targetPort": "client"}],
+                        },
+                    });
+                    (200, service.to_string())
+                }
+                path if path.starts_with("/api/v1/namespaces/test/pods?") => {
+                    assert!(path.contains("labelSelector=app%3Dredis"), "{}", path);
+                    let pod = |name: &str, phase: &str| {
+                        json!({
+                            "metadata": {"name": name},
+                            "spec": {"containers": [{
+                                "name": "redis",
+                                "ports": [{"name": "client", "containerPort": 7000}],
+                            }]},
+                            "status": {"phase": phase},
+                        })
+                    };
+                    let pods = json!({
+                        "metadata": {},
+                        "items": [pod("redis-1", "Pending"), pod("redis-0", "Running")],
+                    });
+                    (200, pods.to_string())
+                }
+                path => panic!("Unexpected {}", path),
+            },
+            |path, mut socket| async move {
+                assert_eq!(
+                    path,
+                    "/api/v1/namespaces/test/pods/redis-0/portforward?&ports=7000"
+                );
+                // Each channel starts with its port, then the data channel echoes uppercased:
+                for channel in [0, 1] {
+                    let frame = [&[channel][..], &7000u16.to_le_bytes()].concat();
+                    socket.send(Message::binary(frame)).await.unwrap();
+                }
+                while let Some(Ok(Message::Binary(data))) = socket.next().await {
+                    let reply = data.to_ascii_uppercase();
+                    socket.send(Message::binary(reply)).await.unwrap();
+                }
+            },
+        )
+        .await;
+
+        let forward = manager.port_forward("svc/redis", 0, 6379).await.unwrap();
+        for _ in 0..2 {
+            let mut conn = TcpStream::connect(forward.local_addr()).await.unwrap();
+            conn.write_all(b"ping").await.unwrap();
+            let mut reply = [0; 4];
+            conn.read_exact(&mut reply).await.unwrap();
+            assert_eq!(&reply, b"PING");
+        }
+        let addr = forward.local_addr();
+        drop(forward);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reports_missing_targets() {
+        let manager = fake_ws_api(
+            |_, path, _| {
+                assert_eq!(path, "/api/v1/namespaces/test/services/redis");
+                fake_status(404, "NotFound", "services \"redis\" not found")
+            },
+            |path, _| async move { panic!("Unexpected upgrade of {}", path) },
+        )
+        .await;
+
+        let report = manager
+            .port_forward("svc/redis", 6390, 6379)
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", report).contains("services \"redis\" not found"));
+        let report = manager
+            .port_forward("deploy/redis", 6390, 6379)
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", report).contains("not deploy"));
+    }
+}
@@ -0,0 +1,154 @@
+use k8s_openapi::api::core::v1::{Namespace, ServiceAccount};
+use k8s_openapi::api::rbac::v1::{PolicyRule, Role, RoleBinding, RoleRef, Subject};
+use kube::api::{ObjectMeta, Patch};
+
+use super::configs::apply_params;
+use super::{kube_failed, KubeManager};
+use crate::prelude::*;
+
+/// A rule allowing `verbs` on `resources` of the api group, `""` being the core one.
+pub fn policy_rule(group: &str, resources: &[&str], verbs: &[&str]) -> PolicyRule {
+    PolicyRule {
+        api_groups: Some(vec![group.to_string()]),
+        resources: Some(resources.iter().map(|r| r.to_string()).collect()),
+        verbs: verbs.iter().map(|v| v.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+/// What [`KubeManager::run_job`] and the [`super::JobDispatcher`] need.
+pub fn job_runner_rules() -> Vec<PolicyRule> {
+    vec![
+        policy_rule(
+            "batch",
+            &["jobs", "cronjobs"],
+            &["create", "get", "list", "watch", "patch", "delete"],
+        ),
+        policy_rule("", &["pods", "pods/log"], &["get", "list", "watch"]),
+    ]
+}
+
+impl KubeManager {
+    /// Creates the namespace unless it exists. Use [`KubeManager::namespace`] to work in it.
+    pub async fn ensure_namespace(&self, name: &str) -> RResult<Namespace, AnyErr> {
+        let namespace = Namespace {
+            metadata: metadata(name),
+            ..Default::default()
+        };
+        self.cluster_api::<Namespace>()
+            .patch(name, &apply_params(), &Patch::Apply(&namespace))
+            .await
+            .map_err(kube_failed(format!("ensure namespace {}", name)))
+    }
+
+    pub async fn ensure_service_account(&self, name: &str) -> RResult<ServiceAccount, AnyErr> {
+        let account = ServiceAccount {
+            metadata: metadata(name),
+            ..Default::default()
+        };
+        self.api::<ServiceAccount>()
+            .patch(name, &apply_params(), &Patch::Apply(&account))
+            .await
+            .map_err(kube_failed(format!("ensure service account {}", name)))
+    }
+
+    /// Creates the role of the namespace, or replaces its rules.
+    pub async fn ensure_role(&self, name: &str, rules: Vec<PolicyRule>) -> RResult<Role, AnyErr> {
+        let role = Role {
+            metadata: metadata(name),
+            rules: Some(rules),
+        };
+        self.api::<Role>()
+            .patch(name, &apply_params(), &Patch::Apply(&role))
+            .await
+            .map_err(kube_failed(format!("ensure role {}", name)))
+    }
+
+    /// Grants the role to the service account of the namespace.
+    pub async fn ensure_role_binding(
+        &self,
+        name: &str,
+        role: &str,
+        service_account: &str,
+    ) -> RResult<RoleBinding, AnyErr> {
+        let binding = RoleBinding {
+            metadata: metadata(name),
+            role_ref: RoleRef {
+                api_group: "rbac.authorization.k8s.io".to_string(),
+                kind: "Role".to_string(),
+                name: role.to_string(),
+            },
+            subjects: Some(vec![Subject {
+                kind: "ServiceAccount".to_string(),
+                name: service_account.to_string(),
+                namespace: Some(self.get_namespace().to_string()),
+                ..Default::default()
+            }]),
+        };
+        self.api::<RoleBinding>()
+            .patch(name, &apply_params(), &Patch::Apply(&binding))
+            .await
+            .map_err(kube_failed(format!("ensure role binding {}", name)))
+    }
+
+    /// Sets up the namespace with a service account allowed to run jobs, named
+    /// `service_account` like its role and binding. Safe to run again, e.g. on every deploy.
+    pub async fn bootstrap_job_runner(&self, service_account: &str) -> RResult<(), AnyErr> {
+        self.ensure_namespace(self.get_namespace()).await?;
+        self.ensure_service_account(service_account).await?;
+        self.ensure_role(service_account, job_runner_rules())
+            .await?;
+        self.ensure_role_binding(service_account, service_account, service_account)
+            .await?;
+        info!(
+            "Bootstrapped job runner {} in {}",
+            service_account,
+            self.get_namespace()
+        );
+        Ok(())
+    }
+}
+
+fn metadata(name: &str) -> ObjectMeta {
+    ObjectMeta {
+        name: Some(name.to_string()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fake_api;
+    use rstest::*;
+    use std::sync::{Arc, Mutex};
+
+    #[rstest]
+    #[tokio::test]
+    async fn bootstraps_job_runner() {
+        let applied = Arc::new(Mutex::new(vec![]));
+        let recorded = applied.clone();
+        let manager = fake_api(move |method, path, body| {
+            assert_eq!(method, "PATCH");
+            assert!(path.contains("fieldManager=rutils"));
+            let body: serde_json::Value = serde_json::from_str(body).unwrap();
+            recorded.lock().unwrap().push(format!(
+                "{} {}",
+                body["kind"].as_str().unwrap(),
+                path.split('?').next().unwrap()
+            ));
+            (200, body.to_string())
+        })
+        .await;
+
+        manager.bootstrap_job_runner("runner").await.unwrap();
+        assert_eq!(
+            *applied.lock().unwrap(),
+            [
+                "Namespace /api/v1/namespaces/test",
+                "ServiceAccount /api/v1/namespaces/test/serviceaccounts/runner",
+                "Role /apis/rbac.authorization.k8s.io/v1/namespaces/test/roles/runner",
+                "RoleBinding /apis/rbac.authorization.k8s.io/v1/namespaces/test/rolebindings/runner",
+            ]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use k8s_openapi::NamespaceResourceScope;
+use kube::api::{ListParams, WatchParams};
+use kube::{Api, Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use std::time::Duration;
+
+use super::KubeManager;
+use crate::prelude::*;
+
+/// How long to wait before retrying a failed list or watch.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Watches are ended by the api server after at most this long, then resumed.
+const WATCH_TIMEOUT_SECS: u32 = 290;
+
+/// A change seen by [`KubeManager::watch`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchEvent<K> {
+    /// Added or modified.
+    Applied(K),
+    Deleted(K),
+    /// All current resources, first and whenever the watch fell too far behind to resume.
+    /// Ones missing from it were deleted meanwhile.
+    Restarted(Vec<K>),
+}
+
+enum WatchState<K> {
+    List,
+    Resume(String),
+    Watching(
+        String,
+        BoxStream<'static, kube::Result<kube::api::WatchEvent<K>>>,
+    ),
+}
+
+impl KubeManager {
+    /// Follows the resources of the namespace matching the label `selector`, `""` for all,
+    /// starting with a [`WatchEvent::Restarted`] of the current ones.
+    ///
+    /// Never ends: dropped connections are resumed where they left off, retrying errors, and
+    /// when the api server no longer has the changes since (410 Gone) everything is listed again.
+    pub fn watch<K>(&self, selector: &str) -> impl Stream<Item = WatchEvent<K>>
+    where
+        K: Resource<Scope = NamespaceResourceScope> + Clone + DeserializeOwned + Debug,
+        K: Send + 'static,
+        K::DynamicType: Default,
+    {
+        let api = self.api::<K>();
+        let (mut list_params, mut watch_params) = (
+            ListParams::default(),
+            WatchParams::default().timeout(WATCH_TIMEOUT_SECS),
+        );
+        if !selector.is_empty() {
+            list_params = list_params.labels(selector);
+            watch_params = watch_params.labels(selector);
+        }
+        futures::stream::unfold(WatchState::List, move |state| {
+            let (api, list_params, watch_params) =
+                (api.clone(), list_params.clone(), watch_params.clone());
+            async move { next_event(&api, &list_params, &watch_params, state).await }
+        })
+    }
+}
+
+async fn next_event<K>(
+    api: &Api<K>,
+    list_params: &ListParams,
+    watch_params: &WatchParams,
+    mut state: WatchState<K>,
+) -> Option<(WatchEvent<K>, WatchState<K>)>
+where
+    K: Resource + Clone + DeserializeOwned + Debug + Send + 'static,
+{
+    use kube::api::WatchEvent as Event;
+
+    loop {
+        state = match state {
+            WatchState::List => match api.list(list_params).await {
+                Ok(list) => {
+                    let version = list.metadata.resource_version.unwrap_or_default();
+                    return Some((
+                        WatchEvent::Restarted(list.items),
+                        WatchState::Resume(version),
+                    ));
+                }
+                Err(e) => {
+                    warn!("Failed to list {}: {}", api.resource_url(), e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    WatchState::List
+                }
+            },
+            WatchState::Resume(version) => match api.watch(watch_params, &version).await {
+                Ok(stream) => WatchState::Watching(version, stream.boxed()),
+                Err(e) if is_gone(&e) => WatchState::List,
+                Err(e) => {
+                    warn!("Failed to watch {}: {}", api.resource_url(), e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    WatchState::Resume(version)
+                }
+            },
+            WatchState::Watching(version, mut stream) => match stream.next().await {
+                Some(Ok(Event::Added(resource) | Event::Modified(resource))) => {
+                    let version = resource.resource_version().unwrap_or(version);
+                    return Some((
+                        WatchEvent::Applied(resource),
+                        WatchState::Watching(version, stream),
+                    ));
+                }
+                Some(Ok(Event::Deleted(resource))) => {
+                    let version = resource.resource_version().unwrap_or(version);
+                    return Some((
+                        WatchEvent::Deleted(resource),
+                        WatchState::Watching(version, stream),
+                    ));
+                }
+                Some(Ok(Event::Bookmark(bookmark))) => {
+                    WatchState::Watching(bookmark.metadata.resource_version, stream)
+                }
+                Some(Ok(Event::Error(e))) if e.code == 410 => WatchState::List,
+                Some(Ok(Event::Error(e))) => {
+                    warn!("Watch of {} failed: {}", api.resource_url(), e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    WatchState::Resume(version)
+                }
+                Some(Err(e)) if is_gone(&e) => WatchState::List,
+                Some(Err(e)) => {
+                    warn!("Watch of {} failed: {}", api.resource_url(), e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    WatchState::Resume(version)
+                }
+                // Ended by the api server's timeout:
+                None => WatchState::Resume(version),
+            },
+        }
+    }
+}
+
+fn is_gone(error: &kube::Error) -> bool {
+    matches!(error, kube::Error::Api(response) if response.code == 410)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fake_api;
+    use super::*;
+    use k8s_openapi::api::core::v1::Pod;
+    use rstest::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn pod(name: &str, version: &str) -> serde_json::Value {
+        json!({"metadata": {"name": name, "resourceVersion": version}})
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn relists_when_gone() {
+        let (lists, watches) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let manager = fake_api(move |_, path, _| {
+            assert!(path.contains("labelSelector=app%3Dapi"));
+            if !path.contains("watch=true") {
+                let (items, version) = match lists.fetch_add(1, Ordering::SeqCst) {
+                    0 => (json!([pod("a", "1")]), "1"),
+                    _ => (json!([pod("a", "1"), pod("b", "2")]), "5"),
+                };
+                let body = json!({
+                    "apiVersion": "v1", "kind": "PodList",
+                    "metadata": {"resourceVersion": version}, "items": items
+                });
+                return (200, body.to_string());
+            }
+            let events = match watches.fetch_add(1, Ordering::SeqCst) {
+                0 => {
+                    assert!(path.contains("resourceVersion=1"));
+                    vec![
+                        json!({"type": "ADDED", "object": pod("b", "2")}),
+                        json!({"type": "ERROR", "object": {
+                            "kind": "Status", "apiVersion": "v1", "metadata": {}, "status": "Failure",
+                            "message": "too old resource version", "reason": "Expired", "code": 410
+                        }}),
+                    ]
+                }
+                _ => {
+                    assert!(path.contains("resourceVersion=5"));
+                    vec![json!({"type": "DELETED", "object": pod("a", "6")})]
+                }
+            };
+            let lines = events.iter().map(|e| format!("{}\n", e)).collect();
+            (200, lines)
+        })
+        .await;
+
+        let names = |pods: &[Pod]| pods.iter().map(|p| p.name_any()).collect::<Vec<_>>();
+        let events = tokio::time::timeout(
+            Duration::from_secs(5),
+            manager.watch::<Pod>("app=api").take(4).collect::<Vec<_>>(),
+        )
+        .await
+        .unwrap();
+        let events = events
+            .iter()
+            .map(|event| match event {
+                WatchEvent::Applied(pod) => format!("applied {}", pod.name_any()),
+                WatchEvent::Deleted(pod) => format!("deleted {}", pod.name_any()),
+                WatchEvent::Restarted(pods) => format!("restarted {:?}", names(pods)),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                r#"restarted ["a"]"#,
+                "applied b",
+                r#"restarted ["a", "b"]"#,
+                "deleted a",
+            ]
+        );
+    }
+}
@@ -1,17 +1,28 @@
 #![allow(dead_code)]
 
+pub mod clock;
 pub mod cmd;
+pub mod config;
+#[cfg(feature = "docker")]
+pub mod docker;
 pub mod endpoints;
 pub mod errors;
 // pub mod logger;
 pub mod files;
+pub mod flags;
+pub mod health;
+pub mod jobqueue;
+#[cfg(feature = "k8s")]
+pub mod k8_manager;
+pub mod metrics;
 pub mod prelude;
 pub mod python;
 pub mod redis_manager;
 pub mod redis_tracing;
+pub mod scheduler;
+pub mod secrets;
 pub mod testing;
 
-// pub mod k8_manager;
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
@@ -0,0 +1,490 @@
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::health::{HealthRegistry, HealthServer};
+use crate::prelude::*;
+use crate::redis_manager::RedisLike;
+
+/// Histogram buckets in seconds, as most histograms time something.
+pub const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The counter called `name` in [`global`] metrics, with `"label" => value` pairs, e.g.
+/// `counter!("emails_sent_total", "template" => name).increment(1)`.
+#[macro_export]
+macro_rules! counter {
+    ($name:expr $(, $key:expr => $value:expr)* $(,)?) => {
+        $crate::metrics::global()
+            .counter($name, &[$(($key, &*::std::string::ToString::to_string(&$value))),*])
+    };
+}
+
+/// The histogram called `name` in [`global`] metrics, with `"label" => value` pairs, e.g.
+/// `histogram!("render_duration_seconds", "page" => page).record_duration(elapsed)`.
+#[macro_export]
+macro_rules! histogram {
+    ($name:expr $(, $key:expr => $value:expr)* $(,)?) => {
+        $crate::metrics::global()
+            .histogram($name, &[$(($key, &*::std::string::ToString::to_string(&$value))),*])
+    };
+}
+
+/// The name and sorted labels identifying a series.
+type Key = (String, Vec<(String, String)>);
+
+#[derive(Debug, Default)]
+struct Registry {
+    counters: BTreeMap<Key, Counter>,
+    histograms: BTreeMap<Key, Histogram>,
+}
+
+/// Counters and histograms, recorded in process and exported with
+/// [`Self::render_prometheus`], [`Self::serve`] or [`Self::push`] to redis. Usually the
+/// [`global`] ones through [`crate::counter`] and [`crate::histogram`], which the cmd, endpoints
+/// and redis_manager modules record into too. Clones share the series.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+    buckets: Arc<[f64]>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+/// The metrics of [`crate::counter`] and [`crate::histogram`].
+pub fn global() -> &'static Metrics {
+    static GLOBAL: OnceLock<Metrics> = OnceLock::new();
+    GLOBAL.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            registry: Arc::new(Mutex::new(Registry::default())),
+            buckets: DEFAULT_BUCKETS.into(),
+        }
+    }
+
+    /// The upper bounds of the histogram buckets, [`DEFAULT_BUCKETS`] by default.
+    pub fn buckets(mut self, mut buckets: Vec<f64>) -> Self {
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+        self.buckets = buckets.into();
+        self
+    }
+
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> Counter {
+        self.registry
+            .lock()
+            .counters
+            .entry(key(name, labels))
+            .or_default()
+            .clone()
+    }
+
+    pub fn histogram(&self, name: &str, labels: &[(&str, &str)]) -> Histogram {
+        self.registry
+            .lock()
+            .histograms
+            .entry(key(name, labels))
+            .or_insert_with(|| Histogram::new(&self.buckets))
+            .clone()
+    }
+
+    /// Every series in the Prometheus text format.
+    pub fn render_prometheus(&self) -> String {
+        let registry = self.registry.lock();
+        let mut text = String::new();
+        let mut last_name = None;
+        for ((name, labels), counter) in &registry.counters {
+            if last_name != Some(name) {
+                let _ = writeln!(text, "# TYPE {} counter", name);
+                last_name = Some(name);
+            }
+            let _ = writeln!(
+                text,
+                "{}{} {}",
+                name,
+                prometheus_labels(labels),
+                counter.get()
+            );
+        }
+        for ((name, labels), histogram) in &registry.histograms {
+            if last_name != Some(name) {
+                let _ = writeln!(text, "# TYPE {} histogram", name);
+                last_name = Some(name);
+            }
+            let snapshot = histogram.snapshot();
+            for (le, count) in &snapshot.buckets {
+                let mut labels = labels.clone();
+                labels.push(("le".to_string(), le.to_string()));
+                let _ = writeln!(
+                    text,
+                    "{}_bucket{} {}",
+                    name,
+                    prometheus_labels(&labels),
+                    count
+                );
+            }
+            let mut labels_inf = labels.clone();
+            labels_inf.push(("le".to_string(), "+Inf".to_string()));
+            let labels = prometheus_labels(labels);
+            let _ = writeln!(
+                text,
+                "{}_bucket{} {}",
+                name,
+                prometheus_labels(&labels_inf),
+                snapshot.count
+            );
+            let _ = writeln!(text, "{}_sum{} {}", name, labels, snapshot.sum);
+            let _ = writeln!(text, "{}_count{} {}", name, labels, snapshot.count);
+        }
+        text
+    }
+
+    /// Serves [`Self::render_prometheus`] as `GET /metrics`, next to empty health endpoints. Use
+    /// [`HealthRegistry::metrics`] to serve them with health checks.
+    pub async fn serve(&self, addr: &str) -> RResult<HealthServer, AnyErr> {
+        HealthRegistry::new()
+            .metrics(self.clone())
+            .serve(addr)
+            .await
+    }
+
+    /// Writes the current totals to a redis hash per metric, `metrics:{app}:{name}`, next to the
+    /// `traces:{app}` logs of [`crate::redis_tracing`]. Fields are the labels like
+    /// `method=GET,status=200`, histograms have `:count`, `:sum` and `:le=0.1` fields per
+    /// labels.
+    pub async fn push(&self, redis: &dyn RedisLike, app: &str) -> RResult<(), AnyErr> {
+        let mut hashes: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+        {
+            let registry = self.registry.lock();
+            for ((name, labels), counter) in &registry.counters {
+                hashes
+                    .entry(name.clone())
+                    .or_default()
+                    .push((redis_field(labels, ""), counter.get().to_string()));
+            }
+            for ((name, labels), histogram) in &registry.histograms {
+                let snapshot = histogram.snapshot();
+                let fields = hashes.entry(name.clone()).or_default();
+                fields.push((redis_field(labels, "count"), snapshot.count.to_string()));
+                fields.push((redis_field(labels, "sum"), snapshot.sum.to_string()));
+                for (le, count) in &snapshot.buckets {
+                    fields.push((
+                        redis_field(labels, &format!("le={}", le)),
+                        count.to_string(),
+                    ));
+                }
+            }
+        }
+        for (name, fields) in hashes {
+            redis
+                .hset(&format!("metrics:{}:{}", app, name), &fields)
+                .await
+                .change_context(AnyErr)
+                .attach_printable_lazy(|| format!("Failed to push metric {}", name))?;
+        }
+        Ok(())
+    }
+
+    /// [`Self::push`] every `interval` in the background, failures are logged.
+    pub fn push_every(
+        &self,
+        redis: impl RedisLike + 'static,
+        app: impl Into<String>,
+        interval: Duration,
+    ) -> MetricsPusher {
+        let (metrics, app, cancel) = (self.clone(), app.into(), CancellationToken::new());
+        let cancelled = cancel.clone();
+        let task = tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                let stopping = tokio::select! {
+                    _ = cancelled.cancelled() => true,
+                    _ = ticks.tick() => false,
+                };
+                if let Err(e) = metrics.push(&redis, &app).await {
+                    warn!("Failed to push metrics: {:?}", e);
+                }
+                if stopping {
+                    break;
+                }
+            }
+        });
+        MetricsPusher {
+            cancel,
+            task: Some(task),
+        }
+    }
+}
+
+fn key(name: &str, labels: &[(&str, &str)]) -> Key {
+    let mut labels = labels
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect::<Vec<_>>();
+    labels.sort();
+    (name.to_string(), labels)
+}
+
+fn prometheus_labels(labels: &[(String, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect::<Vec<_>>();
+    format!("{{{}}}", labels.join(","))
+}
+
+fn redis_field(labels: &[(String, String)], suffix: &str) -> String {
+    let labels = labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(",");
+    match (labels.is_empty(), suffix.is_empty()) {
+        (true, true) => "total".to_string(),
+        (_, true) => labels,
+        (true, _) => suffix.to_string(),
+        _ => format!("{}:{}", labels, suffix),
+    }
+}
+
+/// A count that only goes up, e.g. of requests.
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn increment(&self, by: u64) {
+        self.0.fetch_add(by, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct HistogramData {
+    bounds: Arc<[f64]>,
+    counts: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+/// The distribution of values like latencies, counted per bucket.
+#[derive(Debug, Clone)]
+pub struct Histogram(Arc<Mutex<HistogramData>>);
+
+/// A [`Histogram`]'s totals at one point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub sum: f64,
+    /// The upper bounds with how many values were at most that, leaving out `+Inf` whose
+    /// count is [`Self::count`].
+    pub buckets: Vec<(f64, u64)>,
+}
+
+impl Histogram {
+    fn new(bounds: &Arc<[f64]>) -> Self {
+        Histogram(Arc::new(Mutex::new(HistogramData {
+            bounds: bounds.clone(),
+            counts: vec![0; bounds.len()],
+            count: 0,
+            sum: 0.0,
+        })))
+    }
+
+    pub fn record(&self, value: f64) {
+        let mut data = self.0.lock();
+        if let Some(bucket) = data.bounds.iter().position(|bound| value <= *bound) {
+            data.counts[bucket] += 1;
+        }
+        data.count += 1;
+        data.sum += value;
+    }
+
+    /// Records the duration in seconds.
+    pub fn record_duration(&self, duration: Duration) {
+        self.record(duration.as_secs_f64());
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let data = self.0.lock();
+        let mut cumulative = 0;
+        let buckets = data
+            .bounds
+            .iter()
+            .zip(&data.counts)
+            .map(|(bound, count)| {
+                cumulative += count;
+                (*bound, cumulative)
+            })
+            .collect();
+        HistogramSnapshot {
+            count: data.count,
+            sum: data.sum,
+            buckets,
+        }
+    }
+}
+
+/// Pushes metrics in the background until shut down or dropped, see [`Metrics::push_every`].
+pub struct MetricsPusher {
+    cancel: CancellationToken,
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl MetricsPusher {
+    /// Stops after pushing the latest totals once more.
+    pub async fn shutdown(mut self) {
+        self.cancel.cancel();
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for MetricsPusher {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// The totals [`Metrics::push`] wrote for the metric called `name`, by field.
+pub async fn fetch_pushed(
+    redis: &dyn RedisLike,
+    app: &str,
+    name: &str,
+) -> RResult<HashMap<String, String>, AnyErr> {
+    redis
+        .hgetall(&format!("metrics:{}:{}", app, name))
+        .await
+        .change_context(AnyErr)
+        .attach_printable_lazy(|| format!("Failed to fetch metric {}", name))
+}
+
+/// The metrics items, also part of [`crate::prelude`].
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{Counter, Histogram, Metrics};
+    #[allow(unused_imports)]
+    pub use crate::{counter, histogram};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::run_command;
+    use crate::testing::prelude::*;
+
+    fn recorded() -> Metrics {
+        let metrics = Metrics::new().buckets(vec![0.5, 0.1]);
+        metrics
+            .counter("jobs_total", &[("queue", "emails"), ("outcome", "ok")])
+            .increment(2);
+        metrics
+            .counter("jobs_total", &[("outcome", "ok"), ("queue", "emails")])
+            .increment(1);
+        let latency = metrics.histogram("job_duration_seconds", &[]);
+        latency.record(0.0625);
+        latency.record(0.25);
+        latency.record_duration(Duration::from_secs(2));
+        metrics
+    }
+
+    #[rstest]
+    fn renders_prometheus() {
+        assert_eq!(
+            recorded().render_prometheus(),
+            "# TYPE jobs_total counter\n\
+             jobs_total{outcome=\"ok\",queue=\"emails\"} 3\n\
+             # TYPE job_duration_seconds histogram\n\
+             job_duration_seconds_bucket{le=\"0.1\"} 1\n\
+             job_duration_seconds_bucket{le=\"0.5\"} 2\n\
+             job_duration_seconds_bucket{le=\"+Inf\"} 3\n\
+             job_duration_seconds_sum 2.3125\n\
+             job_duration_seconds_count 3\n"
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn exports_to_redis_and_http(memory_redis: MemoryRedis) {
+        let metrics = recorded();
+        let pusher = metrics.push_every(memory_redis.clone(), "billing", Duration::from_secs(60));
+        metrics
+            .counter("jobs_total", &[("queue", "emails"), ("outcome", "ok")])
+            .increment(1);
+        pusher.shutdown().await;
+        let jobs = fetch_pushed(&memory_redis, "billing", "jobs_total")
+            .await
+            .unwrap();
+        assert_eq!(jobs["outcome=ok,queue=emails"], "4");
+        let latency = fetch_pushed(&memory_redis, "billing", "job_duration_seconds")
+            .await
+            .unwrap();
+        assert_eq!(latency["count"], "3");
+        assert_eq!(latency["le=0.5"], "2");
+
+        let server = metrics.serve("127.0.0.1:0").await.unwrap();
+        let response = reqwest::get(format!("http://{}/metrics", server.addr()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response
+            .text()
+            .await
+            .unwrap()
+            .contains("jobs_total{outcome=\"ok\",queue=\"emails\"} 4"));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn instruments_commands_and_requests(mock_http: MockHttp, _real_commands: RealCommands) {
+        let runs = || counter!("cmd_runs_total", "program" => "true", "outcome" => "ok").get();
+        let before = runs();
+        run_command("true", &[]).unwrap();
+        assert!(runs() > before);
+
+        mock_http.expect(Method::GET, "/status").respond(204, "");
+        let requests = || {
+            counter!(
+                "http_client_requests_total",
+                "method" => "GET",
+                "host" => "127.0.0.1",
+                "status" => 204,
+            )
+            .get()
+        };
+        let before = requests();
+        Endpoint::builder()
+            .base_url(&mock_http.url())
+            .endpoint("/status")
+            .method(Method::GET)
+            .send_bytes()
+            .await
+            .unwrap();
+        assert!(requests() > before);
+    }
+}
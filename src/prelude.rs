@@ -1,4 +1,33 @@
+//! Everything downstream crates usually need, in one `use utils::prelude::*;`. The items of
+//! each area are in the prelude of its module, e.g. [`crate::redis_manager::prelude`], for
+//! importing only those. Tests import [`crate::testing::prelude`] instead, which includes this.
 #[allow(unused_imports)]
 pub use crate::errors::prelude::*;
 #[allow(unused_imports)]
 pub use tracing::{debug, error, info, warn};
+
+#[allow(unused_imports)]
+pub use crate::config::prelude::*;
+#[allow(unused_imports)]
+pub use crate::endpoints::prelude::*;
+#[allow(unused_imports)]
+pub use crate::flags::prelude::*;
+#[allow(unused_imports)]
+pub use crate::health::prelude::*;
+#[allow(unused_imports)]
+pub use crate::jobqueue::prelude::*;
+#[allow(unused_imports)]
+pub use crate::metrics::prelude::*;
+#[allow(unused_imports)]
+pub use crate::redis_manager::prelude::*;
+#[allow(unused_imports)]
+pub use crate::scheduler::prelude::*;
+#[allow(unused_imports)]
+pub use crate::secrets::prelude::*;
+
+#[cfg(feature = "docker")]
+#[allow(unused_imports)]
+pub use crate::docker::prelude::*;
+#[cfg(feature = "k8s")]
+#[allow(unused_imports)]
+pub use crate::k8_manager::prelude::*;
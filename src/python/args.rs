@@ -0,0 +1,177 @@
+use std::{fmt, time::Duration};
+
+/// Arguments, env vars and a timeout for a python script.
+///
+/// Arguments are passed to the process as is, not through a shell, so nothing needs escaping.
+/// Displaying them quotes like a shell would, to copy paste from logs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PyArgs {
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    parse_log_levels: bool,
+    log_prefix: Option<String>,
+}
+
+impl PyArgs {
+    pub fn new() -> Self {
+        PyArgs::default()
+    }
+
+    /// A positional argument.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// `--key=value`, so values starting with `-` aren't mistaken for options by argparse.
+    pub fn kwarg(mut self, key: &str, value: impl ToString) -> Self {
+        self.args
+            .push(format!("{}={}", option(key), value.to_string()));
+        self
+    }
+
+    /// `--key` without a value.
+    pub fn flag(mut self, key: &str) -> Self {
+        self.args.push(option(key));
+        self
+    }
+
+    /// An env var for the script only.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Kills the script and everything it started once it runs longer than this, failing with
+    /// [`crate::errors::ErrorClass::Timeout`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Logs output lines in python's `logging` format at their own level, instead of stdout as
+    /// info and stderr as warn.
+    pub fn parse_log_levels(mut self) -> Self {
+        self.parse_log_levels = true;
+        self
+    }
+
+    /// Starts each logged output line with `prefix`, e.g. `[train.py]`.
+    pub fn log_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.log_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn as_args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn as_env(&self) -> &[(String, String)] {
+        &self.env
+    }
+
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn parses_log_levels(&self) -> bool {
+        self.parse_log_levels
+    }
+
+    pub fn get_log_prefix(&self) -> Option<&str> {
+        self.log_prefix.as_deref()
+    }
+
+    /// Adds the arguments and env vars to a command.
+    pub fn apply(&self, command: &mut std::process::Command) {
+        command.args(&self.args).envs(self.env.iter().cloned());
+    }
+
+    /// Like [`PyArgs::apply`] for a tokio command.
+    pub fn apply_tokio(&self, command: &mut tokio::process::Command) {
+        command.args(&self.args).envs(self.env.iter().cloned());
+    }
+}
+
+fn option(key: &str) -> String {
+    if key.starts_with('-') {
+        key.to_string()
+    } else {
+        format!("--{}", key)
+    }
+}
+
+impl From<&[&str]> for PyArgs {
+    fn from(args: &[&str]) -> Self {
+        PyArgs::new().args(args.iter().copied())
+    }
+}
+
+impl<const N: usize> From<&[&str; N]> for PyArgs {
+    fn from(args: &[&str; N]) -> Self {
+        PyArgs::new().args(args.iter().copied())
+    }
+}
+
+impl From<Vec<String>> for PyArgs {
+    fn from(args: Vec<String>) -> Self {
+        PyArgs::new().args(args)
+    }
+}
+
+impl From<&PyArgs> for PyArgs {
+    fn from(args: &PyArgs) -> Self {
+        args.clone()
+    }
+}
+
+impl fmt::Display for PyArgs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quoted = self
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, shell_quote(value)))
+            .chain(self.args.iter().map(|arg| shell_quote(arg)));
+        write!(f, "{}", quoted.collect::<Vec<_>>().join(" "))
+    }
+}
+
+fn shell_quote(value: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,@+%".contains(c);
+    if !value.is_empty() && value.chars().all(safe) {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn builds_and_displays() {
+        let args = PyArgs::new()
+            .arg("train.csv")
+            .kwarg("lr", -0.5)
+            .kwarg("--name", "it's a test")
+            .flag("verbose")
+            .env("CUDA_VISIBLE_DEVICES", "0");
+        assert_eq!(
+            args.as_args(),
+            ["train.csv", "--lr=-0.5", "--name=it's a test", "--verbose"]
+        );
+        assert_eq!(
+            args.to_string(),
+            r"CUDA_VISIBLE_DEVICES=0 train.csv --lr=-0.5 '--name=it'\''s a test' --verbose"
+        );
+        assert_eq!(PyArgs::from(&[] as &[&str]).as_args().len(), 0);
+    }
+}
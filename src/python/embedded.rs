@@ -0,0 +1,67 @@
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde_json::{Map, Value};
+
+use crate::prelude::*;
+
+/// Runs `code` in this process with `kwargs` as its globals and returns what it assigned to
+/// `result`, `null` if nothing. Much faster than spawning an interpreter for small glue code.
+///
+/// Values cross over as json. Holds the GIL while running, so call it from `spawn_blocking` in
+/// async code.
+pub fn run_python_code(code: &str, kwargs: &Map<String, Value>) -> RResult<Value, AnyErr> {
+    let kwargs = serde_json::to_string(kwargs)
+        .change_context(AnyErr)
+        .attach_printable("Failed to serialize python kwargs")?;
+
+    Python::with_gil(|py| {
+        let json = py.import_bound("json").map_err(|e| py_err(py, e))?;
+        let globals = json
+            .call_method1("loads", (kwargs,))
+            .and_then(|globals| globals.downcast_into::<PyDict>().map_err(PyErr::from))
+            .map_err(|e| py_err(py, e))?;
+        py.run_bound(code, Some(&globals), None)
+            .map_err(|e| py_err(py, e))?;
+
+        let Some(result) = globals.get_item("result").map_err(|e| py_err(py, e))? else {
+            return Ok(Value::Null);
+        };
+        let result: String = json
+            .call_method1("dumps", (result,))
+            .and_then(|dumped| dumped.extract())
+            .map_err(|e| py_err(py, e))?;
+        serde_json::from_str(&result)
+            .change_context(AnyErr)
+            .attach_printable("Invalid json from python result")
+    })
+}
+
+/// The exception and its traceback as a report.
+fn py_err(py: Python<'_>, e: PyErr) -> Report<AnyErr> {
+    let mut report = err!(AnyErr, "Python exception: {}", e);
+    if let Some(traceback) = e.traceback_bound(py).and_then(|tb| tb.format().ok()) {
+        report = report.attach_printable(traceback);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use serde_json::json;
+
+    #[rstest]
+    fn runs_in_process() {
+        let kwargs = json!({"values": [1, 2, 3]});
+        let result = run_python_code(
+            "result = {'total': sum(values)}",
+            kwargs.as_object().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(result, json!({"total": 6}));
+
+        let report = run_python_code("raise ValueError('bad input')", &Map::new()).unwrap_err();
+        assert!(format!("{:?}", report).contains("ValueError: bad input"));
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::PyArgs;
+use crate::cmd::which;
+use crate::prelude::*;
+
+/// What manages a [`PythonEnv`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PythonEnvKind {
+    /// A pdm project, i.e. a `pdm.lock`, or a `pyproject.toml` with `[tool.pdm]` or built by
+    /// `pdm-backend`.
+    Pdm,
+    /// A uv project with a `uv.lock`, using its `.venv`.
+    Uv,
+    /// A plain `.venv` or `venv` directory.
+    Venv,
+    /// Whatever `python3` is on the `PATH`.
+    System,
+}
+
+/// A python environment to install into and run scripts in, so nothing assumes `pdm` is on the
+/// `PATH`.
+#[derive(Debug, Clone)]
+pub struct PythonEnv {
+    kind: PythonEnvKind,
+    project_dir: PathBuf,
+    interpreter: PathBuf,
+}
+
+impl PythonEnv {
+    /// Finds the environment of the project in `project_dir`: pdm or uv when their lock file
+    /// exists and they're installed, then a `.venv` or `venv` directory, then the system python.
+    pub fn detect(project_dir: impl AsRef<Path>) -> RResult<Self, AnyErr> {
+        let project_dir = project_dir.as_ref();
+        let kind = if project_dir.join("uv.lock").exists() && which("uv").is_some() {
+            PythonEnvKind::Uv
+        } else if is_pdm_project(project_dir) && which("pdm").is_some() {
+            PythonEnvKind::Pdm
+        } else if venv_dir(project_dir).is_some() {
+            PythonEnvKind::Venv
+        } else {
+            PythonEnvKind::System
+        };
+        info!(
+            "Detected {:?} python env in {}",
+            kind,
+            project_dir.display()
+        );
+        Self::with_kind(project_dir, kind)
+    }
+
+    /// Creates the environment if needed: `pdm install`, `uv venv`, or `python3 -m venv .venv`.
+    pub fn create(project_dir: impl AsRef<Path>, kind: PythonEnvKind) -> RResult<Self, AnyErr> {
+        let project_dir = project_dir.as_ref();
+        match kind {
+            PythonEnvKind::Pdm => {
+                output(Command::new("pdm").arg("install").current_dir(project_dir))?;
+            }
+            PythonEnvKind::Uv => {
+                if venv_dir(project_dir).is_none() {
+                    output(Command::new("uv").arg("venv").current_dir(project_dir))?;
+                }
+            }
+            PythonEnvKind::Venv => {
+                if venv_dir(project_dir).is_none() {
+                    let python = system_python()?;
+                    output(
+                        Command::new(python)
+                            .args(["-m", "venv", ".venv"])
+                            .current_dir(project_dir),
+                    )?;
+                }
+            }
+            PythonEnvKind::System => {}
+        }
+        Self::with_kind(project_dir, kind)
+    }
+
+    fn with_kind(project_dir: &Path, kind: PythonEnvKind) -> RResult<Self, AnyErr> {
+        let interpreter = match kind {
+            PythonEnvKind::Pdm => {
+                let path = output(
+                    Command::new("pdm")
+                        .args(["info", "--python"])
+                        .current_dir(project_dir),
+                )?;
+                PathBuf::from(path.trim())
+            }
+            PythonEnvKind::Uv | PythonEnvKind::Venv => venv_dir(project_dir)
+                .map(|dir| venv_python(&dir))
+                .ok_or_else(|| {
+                    err!(
+                        AnyErr,
+                        "No virtualenv found, create it with PythonEnv::create()"
+                    )
+                    .attach_printable(format!("Project: {}", project_dir.display()))
+                })?,
+            PythonEnvKind::System => system_python()?,
+        };
+        debug!(
+            "Using {:?} python env with interpreter {}",
+            kind,
+            interpreter.display()
+        );
+        Ok(PythonEnv {
+            kind,
+            project_dir: project_dir.to_path_buf(),
+            interpreter,
+        })
+    }
+
+    pub fn kind(&self) -> PythonEnvKind {
+        self.kind
+    }
+
+    pub fn project_dir(&self) -> &Path {
+        &self.project_dir
+    }
+
+    /// The python executable of the environment.
+    pub fn interpreter(&self) -> &Path {
+        &self.interpreter
+    }
+
+    /// Installs a `requirements.txt` style file, with uv's pip for uv projects and `pip` otherwise.
+    pub fn install_requirements(&self, requirements: impl AsRef<Path>) -> RResult<(), AnyErr> {
+        let requirements = requirements.as_ref();
+        let mut command = if self.kind == PythonEnvKind::Uv {
+            let mut command = Command::new("uv");
+            command
+                .args(["pip", "install", "--python"])
+                .arg(&self.interpreter);
+            command
+        } else {
+            let mut command = Command::new(&self.interpreter);
+            command.args(["-m", "pip", "install"]);
+            command
+        };
+        command.arg("-r").arg(requirements);
+        output(command.current_dir(&self.project_dir))?;
+        Ok(())
+    }
+
+    /// Installs the project's locked dependencies, i.e. `pdm install` or `uv sync`.
+    pub fn sync(&self) -> RResult<(), AnyErr> {
+        let mut command = match self.kind {
+            PythonEnvKind::Pdm => Command::new("pdm"),
+            PythonEnvKind::Uv => Command::new("uv"),
+            kind => {
+                return Err(err!(
+                    AnyErr,
+                    "A {:?} env has no lock file to sync, use install_requirements()",
+                    kind
+                ))
+            }
+        };
+        let subcommand = if self.kind == PythonEnvKind::Pdm {
+            "install"
+        } else {
+            "sync"
+        };
+        output(command.arg(subcommand).current_dir(&self.project_dir))?;
+        Ok(())
+    }
+
+    /// Installed package names and versions, read from the interpreter so pip isn't needed.
+    pub fn installed_packages(&self) -> RResult<BTreeMap<String, String>, AnyErr> {
+        let script = "import json, importlib.metadata as m; \
+            print(json.dumps({d.metadata['Name']: d.version for d in m.distributions()}))";
+        let json = output(self.command().args(["-c", script]))?;
+        serde_json::from_str(&json)
+            .change_context(AnyErr)
+            .attach_printable("Failed to parse installed packages")
+    }
+
+    /// The PEP 508 values markers like `sys_platform == 'win32'` are evaluated with.
+    pub(crate) fn marker_environment(&self) -> RResult<BTreeMap<String, String>, AnyErr> {
+        let script = r#"
+import json, os, platform, sys
+impl = sys.implementation
+version = "{0.major}.{0.minor}.{0.micro}".format(impl.version)
+if impl.version.releaselevel != "final":
+    version += impl.version.releaselevel[0] + str(impl.version.serial)
+print(json.dumps({
+    "implementation_name": impl.name,
+    "implementation_version": version,
+    "os_name": os.name,
+    "platform_machine": platform.machine(),
+    "platform_python_implementation": platform.python_implementation(),
+    "platform_release": platform.release(),
+    "platform_system": platform.system(),
+    "platform_version": platform.version(),
+    "python_full_version": platform.python_version(),
+    "python_version": ".".join(platform.python_version_tuple()[:2]),
+    "sys_platform": sys.platform,
+}))
+"#;
+        let json = output(self.command().args(["-c", script]))?;
+        serde_json::from_str(&json)
+            .change_context(AnyErr)
+            .attach_printable("Failed to parse the marker environment")
+    }
+
+    /// A command running the interpreter in the project dir, add the script and its args to it.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.interpreter);
+        command.current_dir(&self.project_dir);
+        if self.kind != PythonEnvKind::System {
+            if let Some(env_dir) = self.interpreter.parent().and_then(|bin| bin.parent()) {
+                command.env("VIRTUAL_ENV", env_dir);
+            }
+        }
+        command
+    }
+
+    /// Runs `file` with `args` in the environment, logging its output.
+    ///
+    /// A non-zero exit is an error with a [`super::PythonScriptFailed`].
+    pub fn run_script(
+        &self,
+        file: impl AsRef<Path>,
+        args: impl Into<PyArgs>,
+    ) -> RResult<ExitStatus, AnyErr> {
+        let (file, args) = (file.as_ref(), args.into());
+        let mut command = self.command();
+        args.apply(command.arg(file));
+        let script = file.display().to_string();
+        let captured = super::run_captured(&mut command, &script, &args)?;
+        Ok(captured.check(&script)?.status)
+    }
+
+    /// Like [`PythonEnv::run_script`] without blocking the runtime.
+    pub async fn run_script_async(
+        &self,
+        file: impl AsRef<Path>,
+        args: impl Into<PyArgs>,
+    ) -> RResult<ExitStatus, AnyErr> {
+        self.run_script_cancellable(file.as_ref(), args.into(), &CancellationToken::new())
+            .await
+    }
+
+    /// Starts `file` in a new task, returning a handle to wait on or cancel it.
+    pub fn spawn_script(&self, file: impl AsRef<Path>, args: impl Into<PyArgs>) -> PythonRun {
+        let (env, file, args) = (self.clone(), file.as_ref().to_path_buf(), args.into());
+        let cancel = CancellationToken::new();
+        let token = cancel.clone();
+        let task =
+            tokio::spawn(async move { env.run_script_cancellable(&file, args, &token).await });
+        PythonRun { cancel, task }
+    }
+
+    async fn run_script_cancellable(
+        &self,
+        file: &Path,
+        args: PyArgs,
+        cancel: &CancellationToken,
+    ) -> RResult<ExitStatus, AnyErr> {
+        let mut command = self.tokio_command();
+        args.apply_tokio(command.arg(file));
+        let script = file.display().to_string();
+        let captured = super::run_captured_async(&mut command, &script, &args, cancel).await?;
+        Ok(captured.check(&script)?.status)
+    }
+
+    /// Like [`PythonEnv::command`] as a tokio command.
+    pub fn tokio_command(&self) -> tokio::process::Command {
+        self.command().into()
+    }
+}
+
+/// A script started by [`PythonEnv::spawn_script`].
+///
+/// Dropping it leaves the script running, use [`PythonRun::cancel`] to stop it.
+#[derive(Debug)]
+pub struct PythonRun {
+    cancel: CancellationToken,
+    task: JoinHandle<RResult<ExitStatus, AnyErr>>,
+}
+
+impl PythonRun {
+    /// Kills the script and everything it started, [`PythonRun::wait`] then returns an error.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Waits for the script like [`PythonEnv::run_script_async`].
+    pub async fn wait(self) -> RResult<ExitStatus, AnyErr> {
+        self.task
+            .await
+            .change_context(AnyErr)
+            .attach_printable("Python script task panicked")?
+    }
+}
+
+fn is_pdm_project(project_dir: &Path) -> bool {
+    project_dir.join("pdm.lock").exists()
+        || std::fs::read_to_string(project_dir.join("pyproject.toml")).is_ok_and(|pyproject| {
+            pyproject.contains("[tool.pdm")
+                || ["pdm-backend", "pdm.backend", "pdm-pep517", "pdm.pep517"]
+                    .iter()
+                    .any(|backend| pyproject.contains(backend))
+        })
+}
+
+fn venv_dir(project_dir: &Path) -> Option<PathBuf> {
+    [".venv", "venv"]
+        .into_iter()
+        .map(|name| project_dir.join(name))
+        .find(|dir| venv_python(dir).exists())
+}
+
+fn venv_python(venv_dir: &Path) -> PathBuf {
+    if cfg!(windows) {
+        venv_dir.join("Scripts").join("python.exe")
+    } else {
+        venv_dir.join("bin").join("python")
+    }
+}
+
+fn system_python() -> RResult<PathBuf, AnyErr> {
+    which("python3")
+        .or_else(|| which("python"))
+        .ok_or_else(|| err!(AnyErr, "No python3 or python found on the PATH"))
+}
+
+/// Runs to completion and returns stdout, errors include stderr.
+fn output(command: &mut Command) -> RResult<String, AnyErr> {
+    let program = format!("{:?}", command);
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .change_context(AnyErr)
+        .attach_printable_lazy(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        return Err(err!(
+            AnyErr,
+            "{} failed with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case(None, false)]
+    #[case(Some("[project]\nname = \"app\"\n"), false)]
+    #[case(Some("[tool.pdm.dev-dependencies]\ntest = []\n"), true)]
+    #[case(
+        Some("[build-system]\nrequires = [\"pdm-backend\"]\nbuild-backend = \"pdm.backend\"\n"),
+        true
+    )]
+    fn detects_pdm_projects(#[case] pyproject: Option<&str>, #[case] expected: bool) {
+        let dir = tempfile::tempdir().unwrap();
+        if let Some(pyproject) = pyproject {
+            std::fs::write(dir.path().join("pyproject.toml"), pyproject).unwrap();
+        }
+        assert_eq!(is_pdm_project(dir.path()), expected);
+        std::fs::write(dir.path().join("pdm.lock"), "").unwrap();
+        assert!(is_pdm_project(dir.path()));
+    }
+
+    #[rstest]
+    fn creates_and_uses_venv() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            PythonEnv::detect(dir.path()).unwrap().kind(),
+            PythonEnvKind::System
+        );
+
+        let env = PythonEnv::create(dir.path(), PythonEnvKind::Venv).unwrap();
+        assert!(env.interpreter().starts_with(dir.path().join(".venv")));
+        let detected = PythonEnv::detect(dir.path()).unwrap();
+        assert_eq!(detected.kind(), PythonEnvKind::Venv);
+        assert_eq!(detected.interpreter(), env.interpreter());
+
+        let packages = env.installed_packages().unwrap();
+        assert!(packages.contains_key("pip"), "{:?}", packages);
+
+        std::fs::write(
+            dir.path().join("script.py"),
+            "import sys; sys.exit(int(sys.argv[1]))",
+        )
+        .unwrap();
+        env.run_script("script.py", &["0"]).unwrap();
+        assert!(env.run_script("script.py", &["3"]).is_err());
+
+        let prefix = output(env.command().args(["-c", "import sys; print(sys.prefix)"])).unwrap();
+        assert!(Path::new(prefix.trim()).ends_with(".venv"));
+    }
+}
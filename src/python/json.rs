@@ -0,0 +1,103 @@
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+use super::{run_captured, PyArgs, PythonEnv};
+use crate::prelude::*;
+
+/// The env var holding the path a script run by [`run_python_json`] writes its json result to.
+pub const RESULT_PATH_ENV: &str = "RUTILS_RESULT_PATH";
+
+/// Runs `file` in the python environment of the current directory and parses its json result.
+///
+/// The script writes the result to the file named by [`RESULT_PATH_ENV`]:
+/// ```python
+/// import json, os
+/// with open(os.environ["RUTILS_RESULT_PATH"], "w") as f:
+///     json.dump({"accuracy": 0.93}, f)
+/// ```
+/// If it doesn't, the last line of stdout is parsed instead. A failing script is an error with a
+/// [`super::PythonScriptFailed`].
+pub fn run_python_json<T: DeserializeOwned>(
+    file: &str,
+    args: impl Into<PyArgs>,
+) -> RResult<T, AnyErr> {
+    PythonEnv::detect(".")?.run_json(file, args)
+}
+
+impl PythonEnv {
+    /// Like [`run_python_json`] in this environment.
+    pub fn run_json<T: DeserializeOwned>(
+        &self,
+        file: impl AsRef<Path>,
+        args: impl Into<PyArgs>,
+    ) -> RResult<T, AnyErr> {
+        let file = file.as_ref();
+        let result_file = tempfile::NamedTempFile::new()
+            .change_context(AnyErr)
+            .attach_printable("Failed to create result file")?;
+
+        let args = args.into();
+        let mut command = self.command();
+        args.apply(command.arg(file));
+        command.env(RESULT_PATH_ENV, result_file.path());
+        let script = file.display().to_string();
+        let output = run_captured(&mut command, &script, &args)?.check(&script)?;
+
+        let written = std::fs::read_to_string(result_file.path()).unwrap_or_default();
+        let (json, source) = if written.trim().is_empty() {
+            (output.last_stdout_line.as_str(), "last stdout line")
+        } else {
+            (written.as_str(), "result file")
+        };
+        serde_json::from_str(json)
+            .change_context(AnyErr)
+            .attach_printable_lazy(|| {
+                format!(
+                    "Invalid json result from python script {} in its {}",
+                    file.display(),
+                    source
+                )
+            })
+            .attach_printable_lazy(|| format!("Result: {}", json))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Metrics {
+        accuracy: f64,
+    }
+
+    #[rstest]
+    #[case::result_file(
+        "import json, os\nprint('training')\nwith open(os.environ['RUTILS_RESULT_PATH'], 'w') as f:\n    json.dump({'accuracy': 0.9}, f)\nprint('done')"
+    )]
+    #[case::last_stdout_line("print('training')\nprint('{\"accuracy\": 0.9}')")]
+    fn parses_result(#[case] script: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("train.py"), script).unwrap();
+        let env = PythonEnv::detect(dir.path()).unwrap();
+        let metrics: Metrics = env.run_json("train.py", &[]).unwrap();
+        assert_eq!(metrics, Metrics { accuracy: 0.9 });
+    }
+
+    #[rstest]
+    fn failures_include_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("fail.py"),
+            "import sys\nprint('about to fail', file=sys.stderr)\nraise ValueError('bad input')",
+        )
+        .unwrap();
+        let env = PythonEnv::detect(dir.path()).unwrap();
+        let report = env.run_json::<Metrics>("fail.py", &[]).unwrap_err();
+        let debug = format!("{:?}", report);
+        assert!(debug.contains("about to fail"), "{}", debug);
+        assert!(debug.contains("ValueError: bad input"), "{}", debug);
+    }
+}
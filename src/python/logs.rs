@@ -0,0 +1,101 @@
+use tracing::Level;
+
+use super::PyArgs;
+use crate::prelude::*;
+
+/// The span a script's output is logged in.
+pub(super) fn script_span(script: &str) -> tracing::Span {
+    tracing::info_span!("python_script", script = %script)
+}
+
+/// Logs a script's output lines, stdout as info and stderr as warn, tracebacks as error.
+#[derive(Debug)]
+pub(super) struct OutputLogger {
+    parse_levels: bool,
+    prefix: Option<String>,
+    in_traceback: bool,
+}
+
+impl OutputLogger {
+    /// Following the [`PyArgs::parse_log_levels`] and [`PyArgs::log_prefix`] of a run.
+    pub(super) fn new(args: &PyArgs) -> Self {
+        OutputLogger {
+            parse_levels: args.parses_log_levels(),
+            prefix: args.get_log_prefix().map(str::to_string),
+            in_traceback: false,
+        }
+    }
+
+    pub(super) fn stdout(&mut self, line: &str) {
+        let level = self.parsed_level(line).unwrap_or(Level::INFO);
+        self.log(level, "stdout", line);
+    }
+
+    pub(super) fn stderr(&mut self, line: &str) {
+        if line.starts_with("Traceback (most recent call last)") {
+            self.in_traceback = true;
+        }
+        let level = match self.parsed_level(line) {
+            Some(level) => {
+                // A new log line ends the traceback:
+                self.in_traceback = false;
+                level
+            }
+            None if self.in_traceback => Level::ERROR,
+            None => Level::WARN,
+        };
+        self.log(level, "stderr", line);
+    }
+
+    fn parsed_level(&self, line: &str) -> Option<Level> {
+        self.parse_levels.then(|| python_log_level(line)).flatten()
+    }
+
+    fn log(&self, level: Level, stream: &str, line: &str) {
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+        let sep = if prefix.is_empty() { "" } else { " " };
+        match level {
+            Level::ERROR => error!(stream, "{}{}{}", prefix, sep, line),
+            Level::WARN => warn!(stream, "{}{}{}", prefix, sep, line),
+            Level::INFO => info!(stream, "{}{}{}", prefix, sep, line),
+            _ => debug!(stream, "{}{}{}", prefix, sep, line),
+        }
+    }
+}
+
+/// The level of a line written by python's `logging`, e.g. `WARNING:root:msg` from
+/// `basicConfig()` or `2024-06-01 12:00:00,000 - app - ERROR - msg`.
+///
+/// Only looks at the first few words so messages mentioning a level aren't mistaken for one.
+fn python_log_level(line: &str) -> Option<Level> {
+    line.split(|c: char| !c.is_ascii_alphabetic())
+        .filter(|word| !word.is_empty())
+        .take(4)
+        .find_map(|word| match word {
+            "DEBUG" => Some(Level::DEBUG),
+            "INFO" => Some(Level::INFO),
+            "WARNING" | "WARN" => Some(Level::WARN),
+            "ERROR" | "CRITICAL" | "FATAL" => Some(Level::ERROR),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case::basic_config("WARNING:root:disk almost full", Some(Level::WARN))]
+    #[case::formatted(
+        "2024-06-01 12:00:00,123 - trainer - ERROR - nan loss",
+        Some(Level::ERROR)
+    )]
+    #[case::bracketed("[DEBUG] loading weights", Some(Level::DEBUG))]
+    #[case::critical("CRITICAL:app:out of memory", Some(Level::ERROR))]
+    #[case::plain("epoch 3 done", None)]
+    #[case::level_in_message("loaded the dataset, no ERROR found", None)]
+    fn parses_python_log_levels(#[case] line: &str, #[case] expected: Option<Level>) {
+        assert_eq!(python_log_level(line), expected);
+    }
+}
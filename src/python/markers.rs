@@ -0,0 +1,233 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// Whether the PEP 508 `marker`, e.g. `sys_platform == 'win32' and python_version < "3.11"`,
+/// holds for the `env` values of [`super::PythonEnv::marker_environment`]. `None` when it can't be
+/// parsed. Unknown variables are empty, so `extra == 'docs'` is false.
+pub(crate) fn evaluate(marker: &str, env: &BTreeMap<String, String>) -> Option<bool> {
+    let tokens = tokenize(marker)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        at: 0,
+        env,
+    };
+    let holds = parser.or()?;
+    (parser.at == tokens.len()).then_some(holds)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    Quoted(String),
+    Word(String),
+    Op(String),
+}
+
+fn tokenize(marker: &str) -> Option<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = marker.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            '\'' | '"' => {
+                chars.next();
+                let value = chars.by_ref().take_while(|&next| next != c).collect();
+                tokens.push(Token::Quoted(value));
+            }
+            '=' | '!' | '<' | '>' | '~' => {
+                let mut op = String::new();
+                while let Some(&next) = chars.peek().filter(|next| "=!<>~".contains(**next)) {
+                    op.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::Op(op));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' => {
+                let mut word = String::new();
+                while let Some(&next) = chars
+                    .peek()
+                    .filter(|next| next.is_alphanumeric() || **next == '_' || **next == '.')
+                {
+                    word.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+            _ => return None,
+        }
+    }
+    Some(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    at: usize,
+    env: &'a BTreeMap<String, String>,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.at)?;
+        self.at += 1;
+        Some(token)
+    }
+
+    fn next_is_word(&mut self, word: &str) -> bool {
+        let is_word = self.tokens.get(self.at) == Some(&Token::Word(word.to_string()));
+        self.at += is_word as usize;
+        is_word
+    }
+
+    fn or(&mut self) -> Option<bool> {
+        let mut holds = self.and()?;
+        while self.next_is_word("or") {
+            holds |= self.and()?;
+        }
+        Some(holds)
+    }
+
+    fn and(&mut self) -> Option<bool> {
+        let mut holds = self.atom()?;
+        while self.next_is_word("and") {
+            holds &= self.atom()?;
+        }
+        Some(holds)
+    }
+
+    fn atom(&mut self) -> Option<bool> {
+        if self.tokens.get(self.at) == Some(&Token::Open) {
+            self.at += 1;
+            let holds = self.or()?;
+            return (self.next()? == &Token::Close).then_some(holds);
+        }
+        let left = self.value()?;
+        let op = match self.next()?.clone() {
+            Token::Op(op) => op,
+            Token::Word(word) if word == "in" => word,
+            Token::Word(word) if word == "not" && self.next_is_word("in") => "not in".to_string(),
+            _ => return None,
+        };
+        let right = self.value()?;
+        compare(&left, &op, &right)
+    }
+
+    fn value(&mut self) -> Option<String> {
+        match self.next()?.clone() {
+            Token::Quoted(value) => Some(value),
+            Token::Word(name) => Some(self.env.get(&name).cloned().unwrap_or_default()),
+            _ => None,
+        }
+    }
+}
+
+fn compare(left: &str, op: &str, right: &str) -> Option<bool> {
+    match op {
+        "in" => return Some(right.contains(left)),
+        "not in" => return Some(!right.contains(left)),
+        "===" => return Some(left == right),
+        _ => {}
+    }
+    let (Some(left_version), Some(right_version)) =
+        (version(left), version(right.trim_end_matches(".*")))
+    else {
+        return match op {
+            "==" => Some(left == right),
+            "!=" => Some(left != right),
+            "<" | "<=" | ">" | ">=" | "~=" => Some(false),
+            _ => None,
+        };
+    };
+    let ordering = if right.ends_with(".*") {
+        // `== '3.*'` only compares the given parts:
+        let padded = left_version.iter().copied().chain(std::iter::repeat(0));
+        padded
+            .take(right_version.len())
+            .cmp(right_version.iter().copied())
+    } else {
+        compare_versions(&left_version, &right_version)
+    };
+    Some(match op {
+        "==" => ordering == Ordering::Equal,
+        "!=" => ordering != Ordering::Equal,
+        "<" => ordering == Ordering::Less,
+        "<=" => ordering != Ordering::Greater,
+        ">" => ordering == Ordering::Greater,
+        ">=" => ordering != Ordering::Less,
+        // `~= '3.11'` is `>= 3.11, == 3.*`:
+        "~=" if right_version.len() > 1 => {
+            let prefix = &right_version[..right_version.len() - 1];
+            ordering != Ordering::Less && left_version.starts_with(prefix)
+        }
+        _ => return None,
+    })
+}
+
+/// The release numbers of a version like `3.12.1rc1`, pre-releases counting as the release.
+fn version(value: &str) -> Option<Vec<u64>> {
+    value
+        .split('.')
+        .map(|part| {
+            let digits = part.len() - part.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            part[..digits].parse().ok()
+        })
+        .collect()
+}
+
+fn compare_versions(left: &[u64], right: &[u64]) -> Ordering {
+    let parts = left.len().max(right.len());
+    let padded = |version: &[u64]| {
+        let mut version = version.to_vec();
+        version.resize(parts, 0);
+        version
+    };
+    padded(left).cmp(&padded(right))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("sys_platform == 'linux'", Some(true))]
+    #[case("sys_platform == \"win32\"", Some(false))]
+    #[case("python_version < '3.11' or platform_machine == 'x86_64'", Some(true))]
+    #[case(
+        "python_full_version >= '3.12.1' and python_version < '3.13'",
+        Some(true)
+    )]
+    #[case("python_version == '3.*'", Some(true))]
+    #[case("python_version ~= '3.10'", Some(true))]
+    #[case("python_version ~= '3.13'", Some(false))]
+    #[case(
+        "(os_name == 'nt' or sys_platform == 'darwin') and python_version > '3'",
+        Some(false)
+    )]
+    #[case(
+        "'linux' in sys_platform and platform_machine not in 'arm64 aarch64'",
+        Some(true)
+    )]
+    #[case("extra == 'docs'", Some(false))]
+    #[case("sys_platform = 'linux'", None)]
+    #[case("(sys_platform == 'linux'", None)]
+    fn evaluates_markers(#[case] marker: &str, #[case] expected: Option<bool>) {
+        let env = BTreeMap::from(
+            [
+                ("os_name", "posix"),
+                ("platform_machine", "x86_64"),
+                ("python_full_version", "3.12.4"),
+                ("python_version", "3.12"),
+                ("sys_platform", "linux"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string())),
+        );
+        assert_eq!(evaluate(marker, &env), expected, "{}", marker);
+    }
+}
@@ -0,0 +1,408 @@
+use crate::prelude::*;
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader},
+    process::{Command, ExitStatus, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+use tokio::io::{AsyncBufReadExt, BufReader as AsyncBufReader};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+mod args;
+#[cfg(feature = "pyo3")]
+mod embedded;
+mod env;
+mod json;
+mod logs;
+mod markers;
+mod pool;
+mod verify;
+mod worker;
+
+pub use args::PyArgs;
+#[cfg(feature = "pyo3")]
+pub use embedded::run_python_code;
+pub use env::{PythonEnv, PythonEnvKind, PythonRun};
+pub use json::{run_python_json, RESULT_PATH_ENV};
+pub use pool::{PoolRun, PythonPool};
+pub use verify::{verify_python_env, PythonEnvDiff, VersionMismatch};
+pub use worker::{PythonWorker, PythonWorkerHandle, WorkerKeys, SHUTDOWN_COMMAND};
+
+/// How many lines of stderr errors include.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// The context below the `AnyErr` of a script exiting unsuccessfully, get it with
+/// `report.frames().find_map(|f| f.downcast_ref::<PythonScriptFailed>())`.
+#[derive(Debug, Clone)]
+pub struct PythonScriptFailed {
+    pub script: String,
+    /// None when killed by a signal.
+    pub code: Option<i32>,
+    /// Set when killed for running past its [`PyArgs::timeout`].
+    pub timed_out: Option<Duration>,
+    /// The last lines of stderr.
+    pub stderr_tail: String,
+}
+
+impl std::fmt::Display for PythonScriptFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.timed_out, self.code) {
+            (Some(timeout), _) => write!(
+                f,
+                "Python script {} timed out after {:?}",
+                self.script, timeout
+            ),
+            (None, Some(code)) => write!(
+                f,
+                "Python script {} failed with exit code {}",
+                self.script, code
+            ),
+            (None, None) => write!(f, "Python script {} was killed by a signal", self.script),
+        }?;
+        if !self.stderr_tail.is_empty() {
+            write!(f, ", stderr:\n{}", self.stderr_tail)?;
+        }
+        Ok(())
+    }
+}
+
+impl error_stack::Context for PythonScriptFailed {}
+
+/// Runs `file` in the python environment of the current directory, see [`PythonEnv::detect`].
+/// Use [`PythonEnv::run_script`] with [`PyArgs`] for kwargs and env vars.
+///
+/// Failures are errors with a [`PythonScriptFailed`], see
+/// [`run_python_script_with_args_lenient`] to only log them.
+pub fn run_python_script_with_args(
+    file: &str,
+    args: Option<&[&str]>,
+) -> RResult<ExitStatus, AnyErr> {
+    PythonEnv::detect(".")?.run_script(file, args.unwrap_or_default())
+}
+
+/// Like [`run_python_script_with_args`], logging failures instead of returning them.
+pub fn run_python_script_with_args_lenient(file: &str, args: Option<&[&str]>) {
+    if let Err(report) = run_python_script_with_args(file, args) {
+        info!("Python script failed: {:?}", report);
+    }
+}
+
+/// Why a script was killed.
+#[derive(Debug, Clone, Copy)]
+enum Stopped {
+    TimedOut(Duration),
+    Cancelled,
+}
+
+struct Captured {
+    status: ExitStatus,
+    stopped: Option<Stopped>,
+    last_stdout_line: String,
+    stderr_tail: VecDeque<String>,
+}
+
+impl Captured {
+    /// Errors with a [`PythonScriptFailed`] unless the script succeeded.
+    fn check(self, script: &str) -> RResult<Self, AnyErr> {
+        let timed_out = match self.stopped {
+            Some(Stopped::Cancelled) => {
+                return Err(err!(AnyErr, "Python script {} was cancelled", script))
+            }
+            Some(Stopped::TimedOut(timeout)) => Some(timeout),
+            None if self.status.success() => return Ok(self),
+            None => None,
+        };
+        let report = Report::new(PythonScriptFailed {
+            script: script.to_string(),
+            code: self.status.code(),
+            timed_out,
+            stderr_tail: Vec::from(self.stderr_tail).join("\n"),
+        })
+        .change_context(AnyErr);
+        Err(match timed_out {
+            Some(_) => report.classify(ErrorClass::Timeout),
+            None => report,
+        })
+    }
+}
+
+fn push_tail(tail: &mut VecDeque<String>, line: String) {
+    if tail.len() == STDERR_TAIL_LINES {
+        tail.pop_front();
+    }
+    tail.push_back(line);
+}
+
+/// Runs the command logging its output in a span for `script`, keeping the last stdout line and
+/// the end of stderr.
+///
+/// The script gets its own process group, which is killed after the [`PyArgs::timeout`].
+fn run_captured(command: &mut Command, script: &str, args: &PyArgs) -> RResult<Captured, AnyErr> {
+    let span = logs::script_span(script);
+    let _entered = span.enter();
+    let timeout = args.get_timeout();
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command, 0);
+    let mut cmd = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .change_context(AnyErr)
+        .attach_printable("Failed to start python script")?;
+
+    let stdout = BufReader::new(cmd.stdout.take().expect("Failed to capture stdout"));
+    let stderr = BufReader::new(cmd.stderr.take().expect("Failed to capture stderr"));
+
+    // Dropping done_tx once the script exits stops the watchdog:
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let pid = cmd.id();
+    let watchdog = timeout.map(|timeout| {
+        std::thread::spawn(move || match done_rx.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                kill_tree(pid);
+                Some(Stopped::TimedOut(timeout))
+            }
+            _ => None,
+        })
+    });
+
+    // Read both at once so a full stderr pipe can't block the script:
+    let mut stderr_logger = logs::OutputLogger::new(args);
+    let stderr_span = span.clone();
+    let stderr_handle = std::thread::spawn(move || {
+        let _entered = stderr_span.enter();
+        let mut tail = VecDeque::new();
+        for line in stderr.lines().map_while(Result::ok) {
+            stderr_logger.stderr(&line);
+            push_tail(&mut tail, line);
+        }
+        tail
+    });
+    let mut logger = logs::OutputLogger::new(args);
+    let mut last_stdout_line = String::new();
+    for line in stdout.lines().map_while(Result::ok) {
+        logger.stdout(&line);
+        if !line.trim().is_empty() {
+            last_stdout_line = line;
+        }
+    }
+    let stderr_tail = stderr_handle.join().unwrap_or_default();
+
+    let status = cmd
+        .wait()
+        .change_context(AnyErr)
+        .attach_printable("Failed to wait on child process")?;
+    drop(done_tx);
+    let stopped = watchdog.and_then(|watchdog| watchdog.join().ok().flatten());
+    Ok(Captured {
+        status,
+        stopped,
+        last_stdout_line,
+        stderr_tail,
+    })
+}
+
+/// Like [`run_captured`] without blocking the runtime, also killing the script on `cancel`.
+async fn run_captured_async(
+    command: &mut tokio::process::Command,
+    script: &str,
+    args: &PyArgs,
+    cancel: &CancellationToken,
+) -> RResult<Captured, AnyErr> {
+    let span = logs::script_span(script);
+    run_captured_in_span(command, args, cancel, span.clone())
+        .instrument(span)
+        .await
+}
+
+async fn run_captured_in_span(
+    command: &mut tokio::process::Command,
+    args: &PyArgs,
+    cancel: &CancellationToken,
+    span: tracing::Span,
+) -> RResult<Captured, AnyErr> {
+    let timeout = args.get_timeout();
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command.as_std_mut(), 0);
+    let mut cmd = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .change_context(AnyErr)
+        .attach_printable("Failed to start python script")?;
+
+    let stdout = AsyncBufReader::new(cmd.stdout.take().expect("Failed to capture stdout"));
+    let stderr = AsyncBufReader::new(cmd.stderr.take().expect("Failed to capture stderr"));
+
+    let (mut stdout_logger, mut stderr_logger) =
+        (logs::OutputLogger::new(args), logs::OutputLogger::new(args));
+    let stdout_task = tokio::spawn(
+        async move {
+            let mut lines = stdout.lines();
+            let mut last_line = String::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                stdout_logger.stdout(&line);
+                if !line.trim().is_empty() {
+                    last_line = line;
+                }
+            }
+            last_line
+        }
+        .instrument(span.clone()),
+    );
+    let stderr_task = tokio::spawn(
+        async move {
+            let mut lines = stderr.lines();
+            let mut tail = VecDeque::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                stderr_logger.stderr(&line);
+                push_tail(&mut tail, line);
+            }
+            tail
+        }
+        .instrument(span),
+    );
+
+    let deadline = async {
+        match timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    let stopped = tokio::select! {
+        _ = cmd.wait() => None,
+        _ = deadline => timeout.map(Stopped::TimedOut),
+        _ = cancel.cancelled() => Some(Stopped::Cancelled),
+    };
+    if stopped.is_some() {
+        if let Some(pid) = cmd.id() {
+            kill_tree(pid);
+        }
+    }
+    let status = cmd
+        .wait()
+        .await
+        .change_context(AnyErr)
+        .attach_printable("Failed to wait on child process")?;
+    let (last_stdout_line, stderr_tail) = tokio::join!(stdout_task, stderr_task);
+    Ok(Captured {
+        status,
+        stopped,
+        last_stdout_line: last_stdout_line.unwrap_or_default(),
+        stderr_tail: stderr_tail.unwrap_or_default(),
+    })
+}
+
+/// Kills the process and everything it started.
+#[cfg(unix)]
+fn kill_tree(pid: u32) {
+    // The script leads its own process group, see run_captured:
+    if unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) } != 0 {
+        warn!(
+            "Failed to kill python process group {}: {}",
+            pid,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Kills the process and everything it started.
+#[cfg(windows)]
+fn kill_tree(pid: u32) {
+    let killed = Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid.to_string()])
+        .output();
+    if !killed.is_ok_and(|output| output.status.success()) {
+        warn!("Failed to kill python process tree {}", pid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn failures_carry_exit_code_and_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("exit.py"),
+            "import sys\nprint('bad config', file=sys.stderr)\nsys.exit(int(sys.argv[1]))",
+        )
+        .unwrap();
+        let env = PythonEnv::detect(dir.path()).unwrap();
+
+        assert!(env.run_script("exit.py", &["0"]).unwrap().success());
+        let report = env.run_script("exit.py", &["3"]).unwrap_err();
+        let failed = report
+            .frames()
+            .find_map(|f| f.downcast_ref::<PythonScriptFailed>())
+            .unwrap();
+        assert_eq!(failed.code, Some(3));
+        assert_eq!(failed.stderr_tail, "bad config");
+
+        let report = env.run_script_async("exit.py", &["4"]).await.unwrap_err();
+        assert!(format!("{:?}", report).contains("failed with exit code 4"));
+    }
+
+    #[rstest]
+    fn passes_args_exactly() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("argv.py");
+        std::fs::write(
+            &script,
+            "import os, sys\nexpected = os.environ.get('EXPECTED', '')\nsys.exit(0 if '|'.join(sys.argv[1:]) == expected else 1)",
+        )
+        .unwrap();
+
+        // No args means none, not an empty one:
+        run_python_script_with_args(script.to_str().unwrap(), None).unwrap();
+        let env = PythonEnv::detect(dir.path()).unwrap();
+        let args = PyArgs::new()
+            .arg("a b")
+            .kwarg("lr", 0.1)
+            .env("EXPECTED", "a b|--lr=0.1");
+        env.run_script(&script, &args).unwrap();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn timeouts_and_cancels_kill_the_whole_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        // The grandchild holds on to stdout, so the run only ends when it's killed too:
+        std::fs::write(
+            dir.path().join("hang.py"),
+            "import subprocess, sys, time\nsubprocess.Popen([sys.executable, '-c', 'import time; time.sleep(30)'])\ntime.sleep(30)",
+        )
+        .unwrap();
+        let env = PythonEnv::detect(dir.path()).unwrap();
+        let started = std::time::Instant::now();
+
+        let args = PyArgs::new().timeout(Duration::from_millis(500));
+        let report = env.run_script("hang.py", &args).unwrap_err();
+        let failed = report
+            .frames()
+            .find_map(|f| f.downcast_ref::<PythonScriptFailed>())
+            .unwrap();
+        assert_eq!(failed.timed_out, Some(Duration::from_millis(500)));
+        assert_eq!(
+            crate::errors::error_class(&report),
+            Some(ErrorClass::Timeout)
+        );
+
+        let report = env.run_script_async("hang.py", &args).await.unwrap_err();
+        assert!(format!("{:?}", report).contains("timed out after 500ms"));
+
+        let run = env.spawn_script("hang.py", PyArgs::new());
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!run.is_finished());
+        run.cancel();
+        let report = run.wait().await.unwrap_err();
+        assert!(format!("{:?}", report).contains("was cancelled"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
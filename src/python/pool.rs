@@ -0,0 +1,106 @@
+use futures::{stream, StreamExt};
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+use super::{PyArgs, PythonEnv};
+use crate::prelude::*;
+
+/// The outcome of one script in [`PythonPool::run_all`].
+#[derive(Debug)]
+pub struct PoolRun {
+    pub script: PathBuf,
+    pub result: RResult<ExitStatus, AnyErr>,
+    pub duration: Duration,
+}
+
+/// Runs many scripts in one [`PythonEnv`] with a limit on how many run at once.
+#[derive(Debug, Clone)]
+pub struct PythonPool {
+    env: PythonEnv,
+}
+
+impl PythonPool {
+    pub fn new(env: PythonEnv) -> Self {
+        PythonPool { env }
+    }
+
+    /// Runs `scripts` at most `max_parallel` at a time, returning their runs in the same order.
+    ///
+    /// Output lines are prefixed with `[script]` unless their args set a
+    /// [`PyArgs::log_prefix`], a failing script doesn't stop the others.
+    pub async fn run_all<A: Into<PyArgs>>(
+        &self,
+        scripts: impl IntoIterator<Item = (impl Into<PathBuf>, A)>,
+        max_parallel: usize,
+    ) -> Vec<PoolRun> {
+        let start = Instant::now();
+        let runs: Vec<PoolRun> = stream::iter(scripts)
+            .map(|(script, args)| {
+                let (script, mut args) = (script.into(), args.into());
+                if args.get_log_prefix().is_none() {
+                    args = args.log_prefix(format!("[{}]", script.display()));
+                }
+                async move {
+                    let started = Instant::now();
+                    let result = self.env.run_script_async(&script, args).await;
+                    PoolRun {
+                        script,
+                        result,
+                        duration: started.elapsed(),
+                    }
+                }
+            })
+            .buffered(max_parallel.max(1))
+            .collect()
+            .await;
+
+        debug!(
+            "Ran {} python scripts, {} failed, in {:?}",
+            runs.len(),
+            runs.iter().filter(|run| run.result.is_err()).count(),
+            start.elapsed()
+        );
+        runs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use std::path::Path;
+
+    #[rstest]
+    #[tokio::test]
+    async fn runs_all_with_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        // Each script holds a slot for a while, so 4 scripts in 2 slots take at least 2 rounds:
+        std::fs::write(
+            dir.path().join("sleep.py"),
+            "import sys, time\ntime.sleep(0.3)\nprint('slept')\nsys.exit(int(sys.argv[1]))",
+        )
+        .unwrap();
+        let pool = PythonPool::new(PythonEnv::detect(dir.path()).unwrap());
+
+        let started = Instant::now();
+        let runs = pool
+            .run_all(
+                ["0", "1", "0", "0"].map(|code| ("sleep.py", PyArgs::new().arg(code))),
+                2,
+            )
+            .await;
+        assert!(started.elapsed() >= Duration::from_millis(600));
+
+        assert_eq!(
+            runs.iter()
+                .map(|run| run.result.is_ok())
+                .collect::<Vec<_>>(),
+            [true, false, true, true]
+        );
+        assert!(runs
+            .iter()
+            .all(|run| run.script == Path::new("sleep.py")
+                && run.duration >= Duration::from_millis(300)));
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use toml_edit::{InlineTable, Item, Table};
+
+use super::{markers, PythonEnv};
+use crate::prelude::*;
+
+/// A locked package installed at another version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionMismatch {
+    pub name: String,
+    pub locked: String,
+    pub installed: String,
+}
+
+/// How the installed packages differ from the lock file, packages missing from the lock file are
+/// ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PythonEnvDiff {
+    /// Locked but not installed.
+    pub missing: Vec<String>,
+    pub mismatched: Vec<VersionMismatch>,
+}
+
+impl PythonEnvDiff {
+    /// Whether the environment matches the lock file.
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+impl fmt::Display for PythonEnvDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "Installed packages match the lock file");
+        }
+        let mut lines =
+            self.missing
+                .iter()
+                .map(|name| format!("{}: not installed", name))
+                .chain(self.mismatched.iter().map(|m| {
+                    format!("{}: locked {}, installed {}", m.name, m.locked, m.installed)
+                }));
+        write!(f, "{}", lines.next().unwrap_or_default())?;
+        lines.try_for_each(|line| write!(f, "\n{}", line))
+    }
+}
+
+/// Compares the `uv.lock` or `pdm.lock` next to `pyproject_path` with what's installed in the
+/// project's [`PythonEnv`], to fail deploys before a script hits a bad import.
+///
+/// Only the packages the project needs by default on this platform count, not the ones for
+/// other platforms or python versions, optional extras or dev dependencies.
+pub fn verify_python_env(pyproject_path: impl AsRef<Path>) -> RResult<PythonEnvDiff, AnyErr> {
+    let pyproject_path = pyproject_path.as_ref();
+    let project_dir = match pyproject_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let lock_path = ["uv.lock", "pdm.lock"]
+        .into_iter()
+        .map(|name| project_dir.join(name))
+        .find(|path| path.exists())
+        .ok_or_else(|| {
+            err!(
+                AnyErr,
+                "No uv.lock or pdm.lock next to {}",
+                pyproject_path.display()
+            )
+        })?;
+    let lock = std::fs::read_to_string(&lock_path)
+        .change_context(AnyErr)
+        .attach_printable_lazy(|| format!("Failed to read {}", lock_path.display()))?;
+
+    let env = PythonEnv::detect(project_dir)?;
+    let locked = parse_lock(&lock, &env.marker_environment()?)
+        .attach_printable_lazy(|| format!("Lock: {}", lock_path.display()))?;
+    let installed = env.installed_packages()?;
+    Ok(diff(&locked, &installed))
+}
+
+/// Versions of the packages a uv or pdm lock installs by default with the `markers` of
+/// [`PythonEnv::marker_environment`], both list them as `[[package]]` tables.
+fn parse_lock(
+    lock: &str,
+    markers: &BTreeMap<String, String>,
+) -> RResult<BTreeMap<String, String>, AnyErr> {
+    let doc = lock
+        .parse::<toml_edit::Document>()
+        .change_context(AnyErr)
+        .attach_printable("Invalid lock file")?;
+    let Some(packages) = doc.get("package").and_then(|p| p.as_array_of_tables()) else {
+        return Ok(BTreeMap::new());
+    };
+    let packages = packages.iter().collect::<Vec<_>>();
+    let applies = |marker: Option<&str>| {
+        let Some(marker) = marker else {
+            return true;
+        };
+        markers::evaluate(marker, markers).unwrap_or_else(|| {
+            warn!("Can't evaluate marker {:?}, expecting the package", marker);
+            true
+        })
+    };
+    let mut locked = BTreeMap::new();
+    let mut lock_package = |package: &Table| {
+        let name = package.get("name").and_then(|name| name.as_str());
+        let version = package.get("version").and_then(|version| version.as_str());
+        if let (Some(name), Some(version)) = (name, version) {
+            locked.insert(normalize_name(name), version.to_string());
+        }
+    };
+
+    // pdm lists the groups needing each package and where it's needed:
+    if packages
+        .iter()
+        .any(|package| package.contains_key("groups"))
+    {
+        for package in packages {
+            let default = package
+                .get("groups")
+                .and_then(|groups| groups.as_array())
+                .is_some_and(|groups| groups.iter().any(|group| group.as_str() == Some("default")));
+            if default && applies(package.get("marker").and_then(|m| m.as_str())) {
+                lock_package(package);
+            }
+        }
+        return Ok(locked);
+    }
+
+    // uv locks the project itself too, what it needs is found through its dependencies:
+    let (projects, dependencies): (Vec<&Table>, Vec<&Table>) = packages
+        .iter()
+        .copied()
+        .partition(|package| is_project(package));
+    if projects.is_empty() {
+        dependencies.into_iter().for_each(lock_package);
+        return Ok(locked);
+    }
+    let mut pending = projects
+        .iter()
+        .flat_map(|project| dependency_entries(project.get("dependencies")))
+        .collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+    while let Some(dependency) = pending.pop() {
+        let Some(name) = dependency.get("name").and_then(|name| name.as_str()) else {
+            continue;
+        };
+        if !applies(dependency.get("marker").and_then(|m| m.as_str())) {
+            continue;
+        }
+        let version = dependency.get("version").and_then(|v| v.as_str());
+        let extras = dependency
+            .get("extra")
+            .and_then(|extras| extras.as_array())
+            .map(|extras| extras.iter().filter_map(|e| e.as_str()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if !seen.insert((name, version, extras.clone())) {
+            continue;
+        }
+        let Some(package) = find_package(&packages, name, version, applies) else {
+            continue;
+        };
+        if !is_project(package) {
+            lock_package(package);
+        }
+        pending.extend(dependency_entries(package.get("dependencies")));
+        let optional = package
+            .get("optional-dependencies")
+            .and_then(|optional| optional.as_table_like());
+        for extra in extras {
+            pending.extend(dependency_entries(
+                optional.and_then(|optional| optional.get(extra)),
+            ));
+        }
+    }
+    Ok(locked)
+}
+
+/// Whether the uv lock entry is the project or one of its workspace members.
+fn is_project(package: &Table) -> bool {
+    package
+        .get("source")
+        .and_then(|source| source.as_inline_table())
+        .is_some_and(|source| source.contains_key("editable") || source.contains_key("virtual"))
+}
+
+/// The `{ name = .., marker = .. }` tables of a uv lock dependency array.
+fn dependency_entries(dependencies: Option<&Item>) -> impl Iterator<Item = &InlineTable> {
+    dependencies
+        .and_then(|dependencies| dependencies.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|dependency| dependency.as_inline_table())
+}
+
+/// The locked `name`, which uv locks once per python version or platform it resolves
+/// differently for, then dependencies name the version.
+fn find_package<'a>(
+    packages: &[&'a Table],
+    name: &str,
+    version: Option<&str>,
+    applies: impl Fn(Option<&str>) -> bool,
+) -> Option<&'a Table> {
+    let candidates = packages
+        .iter()
+        .copied()
+        .filter(|package| {
+            package
+                .get("name")
+                .and_then(|n| n.as_str())
+                .is_some_and(|n| normalize_name(n) == normalize_name(name))
+                && version.is_none_or(|version| {
+                    package.get("version").and_then(|v| v.as_str()) == Some(version)
+                })
+        })
+        .collect::<Vec<_>>();
+    candidates
+        .iter()
+        .copied()
+        .find(|package| {
+            package
+                .get("resolution-markers")
+                .and_then(|markers| markers.as_array())
+                .is_none_or(|markers| markers.iter().any(|marker| applies(marker.as_str())))
+        })
+        .or(candidates.first().copied())
+}
+
+fn diff(locked: &BTreeMap<String, String>, installed: &BTreeMap<String, String>) -> PythonEnvDiff {
+    let installed: BTreeMap<_, _> = installed
+        .iter()
+        .map(|(name, version)| (normalize_name(name), version))
+        .collect();
+    let mut diff = PythonEnvDiff::default();
+    for (name, locked) in locked {
+        match installed.get(name) {
+            None => diff.missing.push(name.clone()),
+            Some(&installed) if installed != locked => diff.mismatched.push(VersionMismatch {
+                name: name.clone(),
+                locked: locked.clone(),
+                installed: installed.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    diff
+}
+
+/// PEP 503 names, so `Typing_Extensions` and `typing-extensions` match.
+fn normalize_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '-' | '_' | '.') {
+            if !normalized.ends_with('-') {
+                normalized.push('-');
+            }
+        } else {
+            normalized.push(c.to_ascii_lowercase());
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    const UV_LOCK: &str = r#"
+version = 1
+requires-python = ">=3.11"
+
+[[package]]
+name = "colorama"
+version = "0.4.6"
+source = { registry = "https://pypi.org/simple" }
+
+[[package]]
+name = "demo"
+version = "0.1.0"
+source = { editable = "." }
+dependencies = [
+    { name = "colorama", marker = "sys_platform == 'win32'" },
+    { name = "numpy", version = "2.0.1", source = { registry = "https://pypi.org/simple" }, marker = "python_full_version >= '3.10'" },
+    { name = "numpy", version = "1.24.4", source = { registry = "https://pypi.org/simple" }, marker = "python_full_version < '3.10'" },
+    { name = "ruamel-yaml" },
+]
+
+[package.optional-dependencies]
+docs = [
+    { name = "sphinx" },
+]
+
+[package.dev-dependencies]
+dev = [
+    { name = "pytest" },
+]
+
+[[package]]
+name = "numpy"
+version = "1.24.4"
+source = { registry = "https://pypi.org/simple" }
+resolution-markers = ["python_full_version < '3.10'"]
+
+[[package]]
+name = "numpy"
+version = "2.0.1"
+source = { registry = "https://pypi.org/simple" }
+resolution-markers = ["python_full_version >= '3.10'"]
+
+[[package]]
+name = "pytest"
+version = "8.3.2"
+source = { registry = "https://pypi.org/simple" }
+
+[[package]]
+name = "ruamel.yaml"
+version = "0.18.6"
+source = { registry = "https://pypi.org/simple" }
+dependencies = [
+    { name = "ruamel-yaml-clib", marker = "python_full_version < '3.13' and platform_python_implementation == 'CPython'" },
+    { name = "typing-extensions", extra = ["compat"] },
+]
+
+[[package]]
+name = "ruamel-yaml-clib"
+version = "0.2.8"
+source = { registry = "https://pypi.org/simple" }
+
+[[package]]
+name = "sphinx"
+version = "8.0.2"
+source = { registry = "https://pypi.org/simple" }
+
+[[package]]
+name = "typing-extensions"
+version = "4.12.2"
+source = { registry = "https://pypi.org/simple" }
+
+[package.optional-dependencies]
+compat = [
+    { name = "colorama", marker = "python_version < '3.0'" },
+]
+"#;
+
+    const PDM_LOCK: &str = r#"
+[[package]]
+name = "colorama"
+version = "0.4.6"
+groups = ["default"]
+marker = "sys_platform == \"win32\""
+
+[[package]]
+name = "numpy"
+version = "2.0.1"
+groups = ["default"]
+
+[[package]]
+name = "pytest"
+version = "8.3.2"
+groups = ["dev"]
+"#;
+
+    fn linux_cpython_312() -> BTreeMap<String, String> {
+        [
+            ("platform_python_implementation", "CPython"),
+            ("python_full_version", "3.12.4"),
+            ("python_version", "3.12"),
+            ("sys_platform", "linux"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+    }
+
+    #[rstest]
+    fn locks_what_this_platform_needs() {
+        let locked = parse_lock(PDM_LOCK, &linux_cpython_312()).unwrap();
+        assert_eq!(locked.keys().collect::<Vec<_>>(), ["numpy"]);
+
+        let mut windows = linux_cpython_312();
+        windows.insert("sys_platform".to_string(), "win32".to_string());
+        windows.insert("python_full_version".to_string(), "3.9.13".to_string());
+        windows.insert(
+            "platform_python_implementation".to_string(),
+            "PyPy".to_string(),
+        );
+        let locked = parse_lock(UV_LOCK, &windows).unwrap();
+        assert_eq!(
+            locked,
+            BTreeMap::from(
+                [
+                    ("colorama", "0.4.6"),
+                    ("numpy", "1.24.4"),
+                    ("ruamel-yaml", "0.18.6"),
+                    ("typing-extensions", "4.12.2"),
+                ]
+                .map(|(name, version)| (name.to_string(), version.to_string()))
+            )
+        );
+    }
+
+    #[rstest]
+    fn diffs_lock_with_installed() {
+        let locked = parse_lock(UV_LOCK, &linux_cpython_312()).unwrap();
+        assert_eq!(
+            locked.keys().collect::<Vec<_>>(),
+            [
+                "numpy",
+                "ruamel-yaml",
+                "ruamel-yaml-clib",
+                "typing-extensions"
+            ]
+        );
+
+        let installed = BTreeMap::from([
+            ("Typing_Extensions".to_string(), "4.12.2".to_string()),
+            ("numpy".to_string(), "1.26.4".to_string()),
+            ("pip".to_string(), "24.0".to_string()),
+        ]);
+        let diff = diff(&locked, &installed);
+        assert_eq!(diff.missing, ["ruamel-yaml", "ruamel-yaml-clib"]);
+        assert_eq!(
+            diff.mismatched,
+            [VersionMismatch {
+                name: "numpy".to_string(),
+                locked: "2.0.1".to_string(),
+                installed: "1.26.4".to_string(),
+            }]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "ruamel-yaml: not installed\nruamel-yaml-clib: not installed\nnumpy: locked 2.0.1, installed 1.26.4"
+        );
+    }
+
+    #[rstest]
+    fn verifies_pdm_project() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("pyproject.toml"),
+            "[project]\nname = \"demo\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("pdm.lock"),
+            "[[package]]\nname = \"surely-not-installed-rutils-pkg\"\nversion = \"1.0\"\n",
+        )
+        .unwrap();
+        let diff = verify_python_env(dir.path().join("pyproject.toml")).unwrap();
+        assert_eq!(diff.missing, ["surely-not-installed-rutils-pkg"]);
+
+        let report = verify_python_env(dir.path().join("missing/pyproject.toml")).unwrap_err();
+        assert!(format!("{:?}", report).contains("No uv.lock or pdm.lock"));
+    }
+}
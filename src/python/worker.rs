@@ -0,0 +1,449 @@
+use redis::AsyncCommands;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+use super::logs::OutputLogger;
+use super::{kill_tree, PyArgs, PythonEnv};
+use crate::prelude::*;
+use crate::redis_manager::RedisManager;
+
+/// Sent on the control list to ask the worker to finish its current task and exit.
+pub const SHUTDOWN_COMMAND: &str = "shutdown";
+
+/// The redis keys of a [`PythonWorker`], also passed to the script as env vars.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerKeys {
+    /// A list of json tasks, `RUTILS_TASKS_KEY`.
+    pub tasks: String,
+    /// A list of commands like [`SHUTDOWN_COMMAND`], `RUTILS_CONTROL_KEY`.
+    pub control: String,
+    /// Set by the script with a ttl while it's alive, `RUTILS_HEARTBEAT_KEY`.
+    pub heartbeat: String,
+}
+
+impl WorkerKeys {
+    pub fn new(name: &str) -> Self {
+        WorkerKeys {
+            tasks: format!("rutils:worker:{}:tasks", name),
+            control: format!("rutils:worker:{}:control", name),
+            heartbeat: format!("rutils:worker:{}:heartbeat", name),
+        }
+    }
+}
+
+/// A long-running python process consuming tasks from a redis list, restarted when it crashes or
+/// stops sending heartbeats.
+///
+/// The script gets the keys and `RUTILS_REDIS_URL` as env vars and is expected to loop like:
+/// ```python
+/// import json, os, redis
+/// r = redis.Redis.from_url(os.environ["RUTILS_REDIS_URL"])
+/// ttl = int(os.environ["RUTILS_HEARTBEAT_TTL_SECS"])
+/// while True:
+///     r.set(os.environ["RUTILS_HEARTBEAT_KEY"], "alive", ex=ttl)
+///     item = r.blpop([os.environ["RUTILS_CONTROL_KEY"], os.environ["RUTILS_TASKS_KEY"]], timeout=1)
+///     if item is None:
+///         continue
+///     key, value = item
+///     if key.decode() == os.environ["RUTILS_CONTROL_KEY"] and value == b"shutdown":
+///         break
+///     handle(json.loads(value))
+/// ```
+/// Listing the control key first means shutdown is handled before queued tasks.
+pub struct PythonWorker {
+    redis: RedisManager,
+    name: String,
+    script: PathBuf,
+    args: PyArgs,
+    env: Option<PythonEnv>,
+    heartbeat_interval: Duration,
+    restart_delay: Duration,
+    max_restarts: Option<u32>,
+    shutdown_timeout: Duration,
+}
+
+impl PythonWorker {
+    pub fn new(redis: RedisManager, name: impl Into<String>, script: impl Into<PathBuf>) -> Self {
+        PythonWorker {
+            redis,
+            name: name.into(),
+            script: script.into(),
+            args: PyArgs::new(),
+            env: None,
+            heartbeat_interval: Duration::from_secs(5),
+            restart_delay: Duration::from_secs(1),
+            max_restarts: None,
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Defaults to the environment of the current directory, see [`PythonEnv::detect`].
+    pub fn env(mut self, env: PythonEnv) -> Self {
+        self.env = Some(env);
+        self
+    }
+
+    pub fn args(mut self, args: impl Into<PyArgs>) -> Self {
+        self.args = args.into();
+        self
+    }
+
+    /// How often the script should refresh its heartbeat, it's restarted after missing ~3.
+    pub fn heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    pub fn restart_delay(mut self, restart_delay: Duration) -> Self {
+        self.restart_delay = restart_delay;
+        self
+    }
+
+    /// Give up after restarting this many times, unlimited by default.
+    pub fn max_restarts(mut self, max_restarts: u32) -> Self {
+        self.max_restarts = Some(max_restarts);
+        self
+    }
+
+    /// How long [`PythonWorkerHandle::shutdown`] waits before killing the process.
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Starts the process and its supervisor.
+    pub fn start(self) -> RResult<PythonWorkerHandle, AnyErr> {
+        let env = match self.env.clone() {
+            Some(env) => env,
+            None => PythonEnv::detect(".")?,
+        };
+        let keys = WorkerKeys::new(&self.name);
+        let redis = self.redis.clone();
+        let restarts = Arc::new(AtomicU32::new(0));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        let supervisor = Supervisor {
+            worker: self,
+            env,
+            keys: keys.clone(),
+            restarts: restarts.clone(),
+        };
+        let task = tokio::spawn(supervisor.run(shutdown_rx));
+        Ok(PythonWorkerHandle {
+            redis,
+            keys,
+            restarts,
+            shutdown_tx,
+            task,
+        })
+    }
+}
+
+/// Controls a started [`PythonWorker`], dropping it shuts the worker down without waiting.
+pub struct PythonWorkerHandle {
+    redis: RedisManager,
+    keys: WorkerKeys,
+    restarts: Arc<AtomicU32>,
+    shutdown_tx: watch::Sender<bool>,
+    task: JoinHandle<RResult<(), AnyErr>>,
+}
+
+impl PythonWorkerHandle {
+    pub fn keys(&self) -> &WorkerKeys {
+        &self.keys
+    }
+
+    /// Queues a task for the worker as json.
+    pub async fn submit(&self, task: &impl Serialize) -> RResult<(), AnyErr> {
+        let json = serde_json::to_string(task)
+            .change_context(AnyErr)
+            .attach_printable("Failed to serialize worker task")?;
+        let mut conn = self.redis.get_async_conn().await.change_context(AnyErr)?;
+        conn.rpush::<_, _, ()>(&self.keys.tasks, json)
+            .await
+            .change_context(AnyErr)
+            .attach_printable_lazy(|| format!("Failed to queue task on {}", self.keys.tasks))
+    }
+
+    /// Tasks queued and not picked up yet.
+    pub async fn pending(&self) -> RResult<usize, AnyErr> {
+        let mut conn = self.redis.get_async_conn().await.change_context(AnyErr)?;
+        conn.llen(&self.keys.tasks).await.change_context(AnyErr)
+    }
+
+    /// Whether the script's heartbeat is current.
+    pub async fn is_alive(&self) -> RResult<bool, AnyErr> {
+        let mut conn = self.redis.get_async_conn().await.change_context(AnyErr)?;
+        conn.exists(&self.keys.heartbeat)
+            .await
+            .change_context(AnyErr)
+    }
+
+    /// How often the process was restarted so far.
+    pub fn restarts(&self) -> u32 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    /// Sends [`SHUTDOWN_COMMAND`] and waits for the process to exit, killing it after the
+    /// shutdown timeout.
+    pub async fn shutdown(self) -> RResult<(), AnyErr> {
+        let _ = self.shutdown_tx.send(true);
+        self.wait().await
+    }
+
+    /// Waits for the worker to exit by itself, errors if it gave up restarting.
+    pub async fn wait(self) -> RResult<(), AnyErr> {
+        // Keep the sender alive, dropping it means shutting down:
+        let _shutdown_tx = self.shutdown_tx;
+        self.task
+            .await
+            .change_context(AnyErr)
+            .attach_printable("Python worker supervisor panicked")?
+    }
+}
+
+struct Supervisor {
+    worker: PythonWorker,
+    env: PythonEnv,
+    keys: WorkerKeys,
+    restarts: Arc<AtomicU32>,
+}
+
+enum Exit {
+    Exited(std::io::Result<ExitStatus>),
+    MissedHeartbeats,
+    Shutdown,
+}
+
+impl Supervisor {
+    fn heartbeat_ttl(&self) -> Duration {
+        self.worker.heartbeat_interval * 3
+    }
+
+    async fn run(self, mut shutdown: watch::Receiver<bool>) -> RResult<(), AnyErr> {
+        let name = self.worker.name.clone();
+        loop {
+            self.clear_control().await;
+            let mut child = self.spawn()?;
+            let started = Instant::now();
+            let mut heartbeat_check = tokio::time::interval(self.worker.heartbeat_interval);
+
+            let exit = loop {
+                tokio::select! {
+                    status = child.wait() => break Exit::Exited(status),
+                    _ = shutdown.changed() => break Exit::Shutdown,
+                    _ = heartbeat_check.tick() => {
+                        if started.elapsed() >= self.heartbeat_ttl() && !self.heartbeat_alive().await {
+                            break Exit::MissedHeartbeats;
+                        }
+                    }
+                }
+            };
+
+            match exit {
+                Exit::Shutdown => {
+                    self.request_shutdown().await;
+                    if tokio::time::timeout(self.worker.shutdown_timeout, child.wait())
+                        .await
+                        .is_err()
+                    {
+                        warn!(
+                            "Python worker {} didn't shut down in time, killing it",
+                            name
+                        );
+                        kill(&mut child).await;
+                    }
+                    info!("Python worker {} shut down", name);
+                    return Ok(());
+                }
+                Exit::Exited(Ok(status)) if status.success() => {
+                    info!("Python worker {} exited", name);
+                    return Ok(());
+                }
+                Exit::Exited(status) => {
+                    error!("Python worker {} crashed: {:?}", name, status);
+                }
+                Exit::MissedHeartbeats => {
+                    warn!("Python worker {} missed its heartbeats, restarting", name);
+                    kill(&mut child).await;
+                }
+            }
+
+            let restarts = self.restarts.fetch_add(1, Ordering::Relaxed) + 1;
+            if self.worker.max_restarts.is_some_and(|max| restarts > max) {
+                return Err(err!(
+                    AnyErr,
+                    "Python worker {} failed {} times, giving up",
+                    name,
+                    restarts
+                ));
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(self.worker.restart_delay) => {}
+                _ = shutdown.changed() => return Ok(()),
+            }
+        }
+    }
+
+    fn spawn(&self) -> RResult<tokio::process::Child, AnyErr> {
+        let mut command = self.env.tokio_command();
+        // Leads its own process group, so whatever it started is killed along with it:
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(command.as_std_mut(), 0);
+        self.worker
+            .args
+            .apply_tokio(command.arg(&self.worker.script));
+        command
+            .env("RUTILS_REDIS_URL", self.worker.redis.url())
+            .env("RUTILS_WORKER_NAME", &self.worker.name)
+            .env("RUTILS_TASKS_KEY", &self.keys.tasks)
+            .env("RUTILS_CONTROL_KEY", &self.keys.control)
+            .env("RUTILS_HEARTBEAT_KEY", &self.keys.heartbeat)
+            .env(
+                "RUTILS_HEARTBEAT_INTERVAL_SECS",
+                self.worker.heartbeat_interval.as_secs().max(1).to_string(),
+            )
+            .env(
+                "RUTILS_HEARTBEAT_TTL_SECS",
+                self.heartbeat_ttl().as_secs().max(1).to_string(),
+            )
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let mut child = command
+            .spawn()
+            .change_context(AnyErr)
+            .attach_printable_lazy(|| {
+                format!("Failed to start python worker {}", self.worker.name)
+            })?;
+        let span = tracing::info_span!("python_worker", worker = %self.worker.name);
+        if let Some(stdout) = child.stdout.take() {
+            let logger = OutputLogger::new(&self.worker.args);
+            tokio::spawn(log_lines(stdout, false, logger).instrument(span.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let logger = OutputLogger::new(&self.worker.args);
+            tokio::spawn(log_lines(stderr, true, logger).instrument(span));
+        }
+        Ok(child)
+    }
+
+    /// Redis being unreachable doesn't count as missing heartbeats.
+    async fn heartbeat_alive(&self) -> bool {
+        let result: RResult<bool, AnyErr> = async {
+            let mut conn = self
+                .worker
+                .redis
+                .get_async_conn()
+                .await
+                .change_context(AnyErr)?;
+            conn.exists(&self.keys.heartbeat)
+                .await
+                .change_context(AnyErr)
+        }
+        .await;
+        result.unwrap_or_else(|report| {
+            warn!("Failed to check python worker heartbeat: {:?}", report);
+            true
+        })
+    }
+
+    /// Drops commands left for an earlier process, e.g. a shutdown it was killed before reading.
+    async fn clear_control(&self) {
+        let result: RResult<(), AnyErr> = async {
+            let mut conn = self
+                .worker
+                .redis
+                .get_async_conn()
+                .await
+                .change_context(AnyErr)?;
+            conn.del(&self.keys.control).await.change_context(AnyErr)
+        }
+        .await;
+        if let Err(report) = result {
+            warn!("Failed to clear python worker commands: {:?}", report);
+        }
+    }
+
+    async fn request_shutdown(&self) {
+        let result: RResult<(), AnyErr> = async {
+            let mut conn = self
+                .worker
+                .redis
+                .get_async_conn()
+                .await
+                .change_context(AnyErr)?;
+            conn.lpush(&self.keys.control, SHUTDOWN_COMMAND)
+                .await
+                .change_context(AnyErr)
+        }
+        .await;
+        if let Err(report) = result {
+            warn!("Failed to send python worker shutdown: {:?}", report);
+        }
+    }
+}
+
+/// Kills the script and everything it started, then reaps it.
+async fn kill(child: &mut tokio::process::Child) {
+    if let Some(pid) = child.id() {
+        kill_tree(pid);
+    }
+    let _ = child.wait().await;
+}
+
+async fn log_lines(output: impl AsyncRead + Unpin, is_stderr: bool, mut logger: OutputLogger) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if is_stderr {
+            logger.stderr(&line);
+        } else {
+            logger.stdout(&line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn names_keys() {
+        let keys = WorkerKeys::new("ocr");
+        assert_eq!(keys.tasks, "rutils:worker:ocr:tasks");
+        assert_eq!(keys.control, "rutils:worker:ocr:control");
+        assert_eq!(keys.heartbeat, "rutils:worker:ocr:heartbeat");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn restarts_crashes_until_giving_up() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("crash.py"), "raise SystemExit(1)").unwrap();
+        // Nothing listens there, supervising a crashing script doesn't need redis:
+        let redis = RedisManager::new("redis://127.0.0.1:1").unwrap();
+
+        let handle = PythonWorker::new(redis, "crashing", "crash.py")
+            .env(PythonEnv::detect(dir.path()).unwrap())
+            .restart_delay(Duration::from_millis(10))
+            .max_restarts(2)
+            .start()
+            .unwrap();
+        let report = tokio::time::timeout(Duration::from_secs(30), handle.wait())
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(format!("{:?}", report).contains("failed 3 times"));
+    }
+}
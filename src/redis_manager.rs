@@ -1,9 +1,11 @@
 use anyhow::Result;
-use futures_util::stream::StreamExt;
+use async_trait::async_trait;
+use futures_util::stream::{BoxStream, StreamExt};
 use redis::{
     aio::MultiplexedConnection, aio::PubSub, AsyncCommands, Client, Connection, RedisError,
 };
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
 use std::sync::{Arc, Mutex};
 use tokio::time::{timeout, Duration};
 
@@ -12,6 +14,7 @@ static MAX_POOL_SIZE: usize = 100;
 #[derive(Clone)]
 pub struct RedisManager {
     client: Arc<Client>,
+    url: Arc<str>,
     sync_connection_pool: Arc<Mutex<VecDeque<redis::Connection>>>,
     async_connection_pool: Arc<Mutex<VecDeque<MultiplexedConnection>>>,
     pubsub_connection_pool: Arc<Mutex<VecDeque<PubSub>>>,
@@ -26,12 +29,18 @@ impl RedisManager {
 
         Ok(Self {
             client,
+            url: redis_url.into(),
             sync_connection_pool,
             async_connection_pool,
             pubsub_connection_pool,
         })
     }
 
+    /// The url this was created with, e.g. to hand to a subprocess.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
     pub fn get_sync_conn(&self) -> Result<SyncConnectionGuard, RedisError> {
         let mut pool = self.sync_connection_pool.lock().unwrap();
         if let Some(conn) = pool.pop_front() {
@@ -49,19 +58,19 @@ impl RedisManager {
     }
 
     pub async fn get_async_conn(&self) -> Result<AsyncConnectionGuard, RedisError> {
-        let mut pool = self.async_connection_pool.lock().unwrap();
-        if let Some(conn) = pool.pop_front() {
-            Ok(AsyncConnectionGuard {
-                manager: self.clone(),
-                connection: Some(conn),
-            })
-        } else {
-            let conn = self.client.get_multiplexed_async_connection().await?;
-            Ok(AsyncConnectionGuard {
-                manager: self.clone(),
-                connection: Some(conn),
-            })
-        }
+        let conn = {
+            let mut pool = self.async_connection_pool.lock().unwrap();
+            pool.pop_front()
+        };
+
+        let conn = match conn {
+            Some(conn) => conn,
+            None => self.client.get_multiplexed_async_connection().await?,
+        };
+        Ok(AsyncConnectionGuard {
+            manager: self.clone(),
+            connection: Some(conn),
+        })
     }
 
     // TODO drop these connections
@@ -128,7 +137,7 @@ impl RedisManager {
 
     pub async fn publish(&self, channel: &str, message: &str) -> Result<(), RedisError> {
         let mut conn = self.get_async_connection().await?;
-        conn.publish(channel, message).await?;
+        conn.publish::<_, _, ()>(channel, message).await?;
         self.return_async_connection(conn).await;
         Ok(())
     }
@@ -167,13 +176,178 @@ impl RedisManager {
     pub async fn flushdb(&self) -> Result<(), RedisError> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
 
-        redis::cmd("FLUSHDB").query_async(&mut conn).await?;
+        redis::cmd("FLUSHDB")
+            .query_async::<_, ()>(&mut conn)
+            .await?;
 
         drop(conn);
         Ok(())
     }
 }
 
+/// The commands the crate's redis backed utilities need, so their tests can use
+/// [`crate::testing::memory_redis::MemoryRedis`] instead of a server.
+#[async_trait]
+pub trait RedisLike: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, RedisError>;
+
+    /// Expires the key after `ttl` when given.
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), RedisError>;
+
+    /// Sets the key only when it isn't set yet, expiring after `ttl`, returning whether it was
+    /// set. The building block of locks.
+    async fn set_nx(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, RedisError>;
+
+    async fn zadd(&self, key: &str, member: &str, score: f64) -> Result<(), RedisError>;
+
+    /// The members scored between `min` and `max` inclusive, lowest first. Pass infinities for
+    /// no bound.
+    async fn zrangebyscore(&self, key: &str, min: f64, max: f64)
+        -> Result<Vec<String>, RedisError>;
+
+    /// Sets the fields of the hash, keeping its other fields.
+    async fn hset(&self, key: &str, fields: &[(String, String)]) -> Result<(), RedisError>;
+
+    async fn hgetall(&self, key: &str) -> Result<HashMap<String, String>, RedisError>;
+
+    async fn publish(&self, channel: &str, message: &str) -> Result<(), RedisError>;
+
+    /// The first message published to `channel` after subscribing.
+    async fn subscribe_and_wait_for_response(
+        &self,
+        channel: &str,
+        timeout: Duration,
+    ) -> Result<String, RedisError>;
+
+    /// The messages published to `channel` from now on, subscribed until the stream is dropped.
+    /// Ends when the connection is lost.
+    async fn subscribe(&self, channel: &str) -> Result<BoxStream<'static, String>, RedisError>;
+}
+
+#[async_trait]
+impl RedisLike for RedisManager {
+    async fn get(&self, key: &str) -> Result<Option<String>, RedisError> {
+        instrumented("GET", async {
+            let mut conn = self.get_async_conn().await?;
+            conn.get(key).await
+        })
+        .await
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), RedisError> {
+        instrumented("SET", async {
+            let mut conn = self.get_async_conn().await?;
+            match ttl {
+                Some(ttl) => conn.set_ex(key, value, ttl.as_secs().max(1)).await,
+                None => conn.set(key, value).await,
+            }
+        })
+        .await
+    }
+
+    async fn set_nx(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, RedisError> {
+        instrumented("SET", async {
+            let mut conn = self.get_async_conn().await?;
+            let set: Option<String> = redis::cmd("SET")
+                .arg(key)
+                .arg(value)
+                .arg("NX")
+                .arg("PX")
+                .arg(ttl.as_millis().max(1) as u64)
+                .query_async(&mut *conn)
+                .await?;
+            Ok(set.is_some())
+        })
+        .await
+    }
+
+    async fn zadd(&self, key: &str, member: &str, score: f64) -> Result<(), RedisError> {
+        instrumented("ZADD", async {
+            let mut conn = self.get_async_conn().await?;
+            conn.zadd(key, member, score).await
+        })
+        .await
+    }
+
+    async fn zrangebyscore(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<String>, RedisError> {
+        instrumented("ZRANGEBYSCORE", async {
+            let mut conn = self.get_async_conn().await?;
+            conn.zrangebyscore(key, score_bound(min), score_bound(max))
+                .await
+        })
+        .await
+    }
+
+    async fn hset(&self, key: &str, fields: &[(String, String)]) -> Result<(), RedisError> {
+        if fields.is_empty() {
+            return Ok(());
+        }
+        instrumented("HSET", async {
+            let mut conn = self.get_async_conn().await?;
+            conn.hset_multiple(key, fields).await
+        })
+        .await
+    }
+
+    async fn hgetall(&self, key: &str) -> Result<HashMap<String, String>, RedisError> {
+        instrumented("HGETALL", async {
+            let mut conn = self.get_async_conn().await?;
+            conn.hgetall(key).await
+        })
+        .await
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> Result<(), RedisError> {
+        instrumented("PUBLISH", RedisManager::publish(self, channel, message)).await
+    }
+
+    async fn subscribe_and_wait_for_response(
+        &self,
+        channel: &str,
+        timeout: Duration,
+    ) -> Result<String, RedisError> {
+        RedisManager::subscribe_and_wait_for_response(self, channel, timeout).await
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<BoxStream<'static, String>, RedisError> {
+        // Not from the pool, it stays subscribed:
+        let mut conn = self.client.get_async_pubsub().await?;
+        conn.subscribe(channel).await?;
+        Ok(conn
+            .into_on_message()
+            .filter_map(|msg| async move { msg.get_payload::<String>().ok() })
+            .boxed())
+    }
+}
+
+/// Counts and times the command in [`crate::metrics::global`].
+async fn instrumented<T>(
+    command: &str,
+    run: impl Future<Output = Result<T, RedisError>>,
+) -> Result<T, RedisError> {
+    let started = std::time::Instant::now();
+    let result = run.await;
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    crate::counter!("redis_commands_total", "command" => command, "outcome" => outcome)
+        .increment(1);
+    crate::histogram!("redis_command_duration_seconds", "command" => command)
+        .record_duration(started.elapsed());
+    result
+}
+
+fn score_bound(score: f64) -> String {
+    match score {
+        f64::INFINITY => "+inf".to_string(),
+        f64::NEG_INFINITY => "-inf".to_string(),
+        score => score.to_string(),
+    }
+}
+
 pub struct SyncConnectionGuard {
     manager: RedisManager,
     connection: Option<Connection>,
@@ -231,3 +405,12 @@ impl Drop for AsyncConnectionGuard {
         }
     }
 }
+
+/// The redis items, also part of [`crate::prelude`].
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{RedisLike, RedisManager};
+
+    #[allow(unused_imports)]
+    pub use crate::redis_tracing::{LogData, LogViewer};
+}
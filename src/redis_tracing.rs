@@ -1,12 +1,10 @@
 use chrono::{DateTime, Utc};
-use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
-use std::fmt::Write;
 use std::sync::Arc;
 use tokio::sync::Notify;
 use tracing::field::Visit;
-use tracing::{span, Level, Span};
+use tracing::Level;
 use tracing_core::Field;
 use tracing_subscriber::layer::{Context, Layer};
 use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
@@ -16,7 +14,7 @@ use tracing_subscriber::{filter::EnvFilter, fmt};
 
 use crate::errors::RResult;
 use crate::prelude::*;
-use crate::redis_manager::RedisManager;
+use crate::redis_manager::RedisLike;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LogData {
@@ -28,16 +26,119 @@ pub struct LogData {
     span_name: Option<String>,
     job_id: Option<String>,
     service_name: Option<String>,
+    #[serde(default)]
+    user_message: Option<String>,
+    #[serde(default)]
+    suggestion: Option<String>,
+}
+
+impl LogData {
+    /// Logged now, outside of any span, e.g. for seeding with [`crate::testing::traces::seed_traces`].
+    pub fn new(level: Level, message: impl Into<String>) -> Self {
+        LogData {
+            timestamp: Utc::now().to_rfc3339(),
+            level: level.to_string(),
+            message: message.into(),
+            span_id: None,
+            trace_id: "default_trace_id".to_string(),
+            span_name: None,
+            job_id: None,
+            service_name: None,
+            user_message: None,
+            suggestion: None,
+        }
+    }
+
+    pub fn timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = timestamp.to_rfc3339();
+        self
+    }
+
+    pub fn span(mut self, span_id: impl Into<String>, span_name: impl Into<String>) -> Self {
+        self.span_id = Some(span_id.into());
+        self.span_name = Some(span_name.into());
+        self
+    }
+
+    pub fn trace_id(mut self, trace_id: impl Into<String>) -> Self {
+        self.trace_id = trace_id.into();
+        self
+    }
+
+    pub fn job_id(mut self, job_id: impl Into<String>) -> Self {
+        self.job_id = Some(job_id.into());
+        self
+    }
+
+    pub fn service_name(mut self, service_name: impl Into<String>) -> Self {
+        self.service_name = Some(service_name.into());
+        self
+    }
+
+    pub fn user_message(mut self, user_message: impl Into<String>) -> Self {
+        self.user_message = Some(user_message.into());
+        self
+    }
+
+    pub fn suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    pub fn get_timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    pub fn get_level(&self) -> &str {
+        &self.level
+    }
+
+    pub fn get_message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn get_span_name(&self) -> Option<&str> {
+        self.span_name.as_deref()
+    }
+
+    pub fn get_trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    pub fn get_job_id(&self) -> Option<&str> {
+        self.job_id.as_deref()
+    }
+
+    pub fn get_service_name(&self) -> Option<&str> {
+        self.service_name.as_deref()
+    }
+}
+
+/// Adds `log_data` to the logs of `app_name`, scored by its timestamp.
+pub(crate) async fn store_log(
+    manager: &dyn RedisLike,
+    app_name: &str,
+    log_data: &LogData,
+) -> RResult<(), AnyErr> {
+    let timestamp = DateTime::parse_from_rfc3339(&log_data.timestamp)
+        .change_context(AnyErr)
+        .attach_printable_lazy(|| format!("Invalid log timestamp: {}", log_data.timestamp))?
+        .timestamp_millis();
+    let value = serde_json::to_string(log_data).change_context(AnyErr)?;
+    manager
+        .zadd(&format!("traces:{}", app_name), &value, timestamp as f64)
+        .await
+        .change_context(AnyErr)
 }
 
 struct RedisLogger {
     app_name: String,
-    manager: Arc<RedisManager>,
+    manager: Arc<dyn RedisLike>,
     notify: Arc<Notify>,
 }
 
 impl RedisLogger {
-    fn new(app_name: String, manager: Arc<RedisManager>) -> Self {
+    fn new(app_name: String, manager: Arc<dyn RedisLike>) -> Self {
         RedisLogger {
             app_name,
             manager,
@@ -50,22 +151,9 @@ impl RedisLogger {
         let app_name = self.app_name.clone();
         let notify = self.notify.clone();
         tokio::spawn(async move {
-            let mut con = manager
-                .get_async_connection()
+            store_log(manager.as_ref(), &app_name, &log_data)
                 .await
-                .expect("Failed to get Redis connection");
-
-            let key = format!("traces:{}", app_name);
-            let timestamp = DateTime::parse_from_rfc3339(&log_data.timestamp)
-                .unwrap()
-                .timestamp_millis();
-
-            let _: () = con
-                .zadd(key, serde_json::to_string(&log_data).unwrap(), timestamp)
-                .await
-                .unwrap();
-
-            manager.return_async_connection(con).await;
+                .expect("Failed to write log to Redis");
             notify.notify_one();
         });
     }
@@ -96,6 +184,8 @@ where
             span_name: None,
             job_id: field_visitor.job_id.clone(),
             service_name: field_visitor.service_name.clone(),
+            user_message: field_visitor.user_message.clone(),
+            suggestion: field_visitor.suggestion.clone(),
         };
 
         if let Some(scope) = ctx.event_scope(event) {
@@ -151,6 +241,8 @@ struct FieldVisitor {
     job_id: Option<String>,
     service_name: Option<String>,
     redis_logging: Option<bool>,
+    user_message: Option<String>,
+    suggestion: Option<String>,
 }
 
 impl FieldVisitor {
@@ -160,6 +252,8 @@ impl FieldVisitor {
             job_id: None,
             service_name: None,
             redis_logging: None,
+            user_message: None,
+            suggestion: None,
         }
     }
 }
@@ -201,6 +295,9 @@ impl Visit for FieldVisitor {
             "redis_logging" => {
                 self.redis_logging = value.parse::<bool>().ok();
             }
+            // Set by errors::log_report, empty when the report has none:
+            "user_message" if !value.is_empty() => self.user_message = Some(value.to_string()),
+            "suggestion" if !value.is_empty() => self.suggestion = Some(value.to_string()),
             _ => {}
         }
     }
@@ -214,7 +311,7 @@ impl Visit for FieldVisitor {
 
 fn prepare_global_logging(
     app_name: String,
-    manager: Arc<RedisManager>,
+    manager: Arc<dyn RedisLike>,
 ) -> RResult<Arc<RedisLogger>, AnyErr> {
     let logger = Arc::new(RedisLogger::new(app_name.clone(), manager.clone()));
     let subscriber = Registry::default()
@@ -238,25 +335,21 @@ struct JobId(String);
 struct ServiceName(String);
 
 pub struct LogViewer {
-    manager: Arc<RedisManager>,
+    manager: Arc<dyn RedisLike>,
 }
 
 impl LogViewer {
-    pub fn new(manager: Arc<RedisManager>) -> Self {
+    /// Reads logs from a [`crate::redis_manager::RedisManager`], or any other [`RedisLike`] store.
+    pub fn new(manager: Arc<dyn RedisLike>) -> Self {
         LogViewer { manager }
     }
 
     async fn fetch_logs(&self, app_name: &str) -> RResult<Vec<LogData>, AnyErr> {
-        let mut con = self
-            .manager
-            .get_async_connection()
-            .await
-            .change_context(AnyErr)?;
-
         let key = format!("traces:{}", app_name);
 
-        let logs: Vec<String> = con
-            .zrangebyscore(key, "-inf", "+inf")
+        let logs: Vec<String> = self
+            .manager
+            .zrangebyscore(&key, f64::NEG_INFINITY, f64::INFINITY)
             .await
             .change_context(AnyErr)?;
 
@@ -269,22 +362,33 @@ impl LogViewer {
             })
             .collect();
 
-        self.manager.return_async_connection(con).await;
-
         Ok(log_data_list)
     }
 
-    fn print_logs(log_data_list: &[LogData]) {
+    /// How the `view_logs_...` methods print logs.
+    pub fn format_logs(log_data_list: &[LogData]) -> String {
+        let mut formatted = String::new();
         for log_data in log_data_list {
-            println!(
-                "{} - [{}] - {} - {}: {}",
+            formatted.push_str(&format!(
+                "{} - [{}] - {} - {}: {}\n",
                 log_data.timestamp,
                 log_data.level,
                 log_data.trace_id,
                 log_data.span_name.clone().unwrap_or_else(|| "".to_string()),
                 log_data.message
-            );
+            ));
+            if let Some(user_message) = &log_data.user_message {
+                formatted.push_str(&format!("    user message: {}\n", user_message));
+            }
+            if let Some(suggestion) = &log_data.suggestion {
+                formatted.push_str(&format!("    suggestion: {}\n", suggestion));
+            }
         }
+        formatted
+    }
+
+    fn print_logs(log_data_list: &[LogData]) {
+        print!("{}", Self::format_logs(log_data_list));
     }
 
     pub async fn view_logs_by_app_name(&self, app_name: &str) -> RResult<Vec<LogData>, AnyErr> {
@@ -350,13 +454,14 @@ impl LogViewer {
 #[tokio::test]
 async fn test_tracing_and_logging() -> RResult<(), AnyErr> {
     // use tracing::instrument;
+    use crate::redis_manager::RedisManager;
     use tracing::{info, span, Level};
 
     let manager = Arc::new(RedisManager::new("redis://127.0.0.1/").change_context(AnyErr)?);
 
     manager.flushdb().await.change_context(AnyErr)?;
 
-    let logger = prepare_global_logging("test".to_string(), manager.clone())?;
+    let _logger = prepare_global_logging("test".to_string(), manager.clone())?;
 
     // #[instrument]
     async fn handle_request(job_id: &str, service_name: &str) {
@@ -415,7 +520,7 @@ async fn test_tracing_and_logging() -> RResult<(), AnyErr> {
 
     println!("---");
 
-    let service_logs = viewer.view_logs_by_service_name("test", "serviceA").await?;
+    let _service_logs = viewer.view_logs_by_service_name("test", "serviceA").await?;
     // assert_eq!(
     //     service_logs.len(),
     //     3,
@@ -431,3 +536,61 @@ async fn test_tracing_and_logging() -> RResult<(), AnyErr> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::prelude::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn views_logged_data(memory_redis: MemoryRedis) {
+        let logger = RedisLogger::new("test".to_string(), Arc::new(memory_redis.clone()));
+        let start = Utc::now();
+        for log in [
+            LogData::new(Level::INFO, "Processing job")
+                .timestamp(start + chrono::Duration::seconds(1))
+                .span("2", "apalis_job"),
+            LogData::new(Level::INFO, "Starting test").timestamp(start),
+        ] {
+            logger.log(log);
+            logger.flush().await;
+        }
+
+        let viewer = LogViewer::new(Arc::new(memory_redis));
+        let logs = viewer.view_logs_by_app_name("test").await.unwrap();
+        assert_eq!(
+            logs.iter()
+                .map(|log| log.message.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Starting test", "Processing job"]
+        );
+        let span_logs = viewer
+            .view_logs_by_span_name("test", "apalis_job")
+            .await
+            .unwrap();
+        assert_eq!(span_logs.len(), 1);
+    }
+
+    #[rstest]
+    fn formats_logs() {
+        let start = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z")
+            .unwrap()
+            .to_utc();
+        let logs = [
+            LogData::new(Level::INFO, "Processing job")
+                .timestamp(start)
+                .trace_id("7")
+                .span("8", "apalis_job"),
+            LogData::new(Level::ERROR, "Card declined")
+                .timestamp(start + chrono::Duration::seconds(1))
+                .trace_id("7")
+                .user_message("Your card was declined")
+                .suggestion("Try another card"),
+        ];
+        assert_matches_golden(
+            "src/snapshots/log_viewer.txt",
+            &LogViewer::format_logs(&logs),
+        );
+    }
+}
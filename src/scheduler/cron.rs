@@ -0,0 +1,222 @@
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Timelike, Utc};
+
+use crate::prelude::*;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A 5 field cron expression, `minute hour day-of-month month day-of-week`, evaluated in UTC.
+///
+/// Fields take `*`, numbers, ranges `1-5`, steps `*/15` or `0-30/10` and lists of them
+/// `1,15`. Months and weekdays also take names like `jan` and `mon`, sunday is 0 or 7.
+/// `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are shorthands. Like cron, when
+/// both days are restricted either matching is enough.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    expr: String,
+    /// Bit sets of the matching values.
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> RResult<Self, AnyErr> {
+        let expanded = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expanded => expanded,
+        };
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(err!(
+                AnyErr,
+                "Cron expression '{}' needs 5 fields, got {}",
+                expr,
+                fields.len()
+            ))
+            .classify(ErrorClass::InvalidInput);
+        };
+        let parsed = (|| -> RResult<Self, AnyErr> {
+            let mut weekday_bits = parse_field(weekdays, 0, 7, &WEEKDAYS)?;
+            // 7 is sunday too:
+            if weekday_bits & (1 << 7) != 0 {
+                weekday_bits = (weekday_bits & !(1 << 7)) | 1;
+            }
+            Ok(Cron {
+                expr: expr.trim().to_string(),
+                minutes: parse_field(minutes, 0, 59, &[])?,
+                hours: parse_field(hours, 0, 23, &[])?,
+                days: parse_field(days, 1, 31, &[])?,
+                months: parse_field(months, 1, 12, &MONTHS)?,
+                weekdays: weekday_bits,
+                any_day: days.starts_with('*'),
+                any_weekday: weekdays.starts_with('*'),
+            })
+        })();
+        parsed
+            .attach_printable_lazy(|| format!("Cron expression: {}", expr))
+            .classify(ErrorClass::InvalidInput)
+    }
+
+    pub fn expr(&self) -> &str {
+        &self.expr
+    }
+
+    /// The first matching minute strictly after `after`, `None` when it never matches, e.g.
+    /// `0 0 30 2 *`.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+        // Leap days come around at least every 8 years:
+        let last_year = after.year() + 8;
+        while time.year() <= last_year {
+            if !has(self.months, time.month()) {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                time = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+            } else if !self.day_matches(time.date_naive()) {
+                time = time
+                    .date_naive()
+                    .succ_opt()?
+                    .and_hms_opt(0, 0, 0)?
+                    .and_utc();
+            } else if !has(self.hours, time.hour()) {
+                time = time.with_minute(0)? + chrono::Duration::hours(1);
+            } else if !has(self.minutes, time.minute()) {
+                time += chrono::Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (false, true) => day,
+            (true, false) => weekday,
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+impl std::fmt::Display for Cron {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> RResult<u64, AnyErr> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, parse_value(step, 1, max, &[])?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (
+                parse_value(start, min, max, names)?,
+                parse_value(end, min, max, names)?,
+            ),
+            // `5/15` is every 15 from 5:
+            None if part.contains('/') => (parse_value(range, min, max, names)?, max),
+            None => {
+                let value = parse_value(range, min, max, names)?;
+                (value, value)
+            }
+        };
+        if start > end {
+            return Err(err!(AnyErr, "Range {} goes backwards", range));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn parse_value(value: &str, min: u32, max: u32, names: &[&str]) -> RResult<u32, AnyErr> {
+    let lower = value.to_lowercase();
+    let parsed = match names.iter().position(|name| *name == lower) {
+        // Months count from 1, weekdays from 0:
+        Some(index) => index as u32 + min,
+        None => value
+            .parse::<u32>()
+            .map_err(|_| err!(AnyErr, "Invalid cron value '{}'", value))?,
+    };
+    if parsed < min || parsed > max {
+        return Err(err!(
+            AnyErr,
+            "Cron value {} is outside {}-{}",
+            parsed,
+            min,
+            max
+        ));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().to_utc()
+    }
+
+    #[rstest]
+    #[case::every_minute("* * * * *", "2024-03-10T12:00:30Z", "2024-03-10T12:01:00Z")]
+    #[case::quarter_hours("*/15 * * * *", "2024-03-10T12:15:00Z", "2024-03-10T12:30:00Z")]
+    #[case::nightly("0 3 * * *", "2024-03-10T12:00:00Z", "2024-03-11T03:00:00Z")]
+    #[case::weekdays("30 9 * * mon-fri", "2024-03-08T10:00:00Z", "2024-03-11T09:30:00Z")]
+    #[case::sunday_as_7("0 0 * * 7", "2024-03-10T12:00:00Z", "2024-03-17T00:00:00Z")]
+    #[case::new_year("@yearly", "2024-03-10T12:00:00Z", "2025-01-01T00:00:00Z")]
+    #[case::leap_day("0 0 29 feb *", "2024-03-01T00:00:00Z", "2028-02-29T00:00:00Z")]
+    #[case::either_day("0 0 1 * fri", "2024-03-02T00:00:00Z", "2024-03-08T00:00:00Z")]
+    #[case::offset_steps("5/20 8-9 * * *", "2024-03-10T08:45:00Z", "2024-03-10T09:05:00Z")]
+    fn finds_next_run(#[case] expr: &str, #[case] after: &str, #[case] next: &str) {
+        assert_eq!(
+            Cron::parse(expr).unwrap().next_after(at(after)),
+            Some(at(next))
+        );
+    }
+
+    #[rstest]
+    #[case::fields("* * * *")]
+    #[case::range("60 * * * *")]
+    #[case::name("0 0 * * funday")]
+    #[case::backwards("0 5-1 * * *")]
+    fn rejects_invalid(#[case] expr: &str) {
+        let report = Cron::parse(expr).unwrap_err();
+        assert_eq!(
+            crate::errors::error_class(&report),
+            Some(ErrorClass::InvalidInput)
+        );
+    }
+
+    #[rstest]
+    fn never_matching_ends() {
+        let cron = Cron::parse("0 0 30 2 *").unwrap();
+        assert_eq!(cron.next_after(at("2024-01-01T00:00:00Z")), None);
+    }
+}
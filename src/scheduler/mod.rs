@@ -0,0 +1,541 @@
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use rand::Rng;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::clock::{system_clock, Clock};
+use crate::prelude::*;
+use crate::redis_manager::RedisLike;
+
+mod cron;
+
+pub use cron::Cron;
+
+/// When a [`ScheduledJob`] runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Cron(Cron),
+    /// Every interval since the unix epoch, so instances sharing a lock agree on the times,
+    /// e.g. every 5 minutes runs at :00, :05 and so on.
+    Every(Duration),
+}
+
+impl Schedule {
+    /// See [`Cron`] for the syntax.
+    pub fn cron(expr: &str) -> RResult<Self, AnyErr> {
+        Ok(Schedule::Cron(Cron::parse(expr)?))
+    }
+
+    pub fn every(interval: Duration) -> Self {
+        Schedule::Every(interval)
+    }
+
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Cron(cron) => cron.next_after(after),
+            Schedule::Every(interval) => {
+                let interval = (interval.as_millis() as i64).max(1);
+                let next = (after.timestamp_millis().div_euclid(interval) + 1) * interval;
+                DateTime::from_timestamp_millis(next)
+            }
+        }
+    }
+}
+
+/// What to do when a job is due while its previous run is still going.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overlap {
+    /// Drop the new run.
+    #[default]
+    Skip,
+    /// Start the new run once the previous one finished.
+    Queue,
+    /// Abort the previous run and start the new one.
+    Cancel,
+}
+
+/// The run a job's task is called for.
+#[derive(Debug, Clone)]
+pub struct ScheduledRun {
+    pub job: String,
+    /// When the run was due, before jitter.
+    pub scheduled_at: DateTime<Utc>,
+    /// Counting from 1, per scheduler instance.
+    pub run: u64,
+}
+
+type Task = Arc<dyn Fn(ScheduledRun) -> BoxFuture<'static, RResult<(), AnyErr>> + Send + Sync>;
+
+/// A named async task with its schedule, see [`Scheduler`].
+#[derive(Clone)]
+pub struct ScheduledJob {
+    name: String,
+    schedule: Schedule,
+    task: Task,
+    jitter: Duration,
+    overlap: Overlap,
+}
+
+impl std::fmt::Debug for ScheduledJob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScheduledJob")
+            .field("name", &self.name)
+            .field("schedule", &self.schedule)
+            .field("jitter", &self.jitter)
+            .field("overlap", &self.overlap)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ScheduledJob {
+    pub fn new<F, Fut>(name: impl Into<String>, schedule: Schedule, task: F) -> Self
+    where
+        F: Fn(ScheduledRun) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = RResult<(), AnyErr>> + Send + 'static,
+    {
+        ScheduledJob {
+            name: name.into(),
+            schedule,
+            task: Arc::new(move |run| task(run).boxed()),
+            jitter: Duration::ZERO,
+            overlap: Overlap::default(),
+        }
+    }
+
+    /// Delays each run by a random duration up to `jitter`, so many instances or jobs due at
+    /// the same time don't all hit a service at once.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn overlap(mut self, overlap: Overlap) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    fn jitter_delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        self.jitter.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+/// Runs [`ScheduledJob`]s in the background, each run in its own `scheduled_run` span. Failed
+/// runs are logged, the job keeps its schedule.
+///
+/// ```ignore
+/// let scheduler = Scheduler::new()
+///     .job(ScheduledJob::new("cleanup", Schedule::cron("0 3 * * *")?, |_| cleanup()))
+///     .job(
+///         ScheduledJob::new("sync", Schedule::every(Duration::from_secs(60)), |_| sync())
+///             .jitter(Duration::from_secs(5))
+///             .overlap(Overlap::Queue),
+///     )
+///     .distributed_lock(redis)
+///     .start();
+/// ```
+///
+/// With a [`Self::distributed_lock`], only the first instance to claim a run in redis runs it,
+/// so every replica can run the same scheduler.
+pub struct Scheduler {
+    jobs: Vec<ScheduledJob>,
+    clock: Arc<dyn Clock>,
+    lock: Option<Arc<dyn RedisLike>>,
+    lock_prefix: String,
+    lock_ttl: Duration,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler {
+            jobs: vec![],
+            clock: system_clock(),
+            lock: None,
+            lock_prefix: "scheduler".to_string(),
+            lock_ttl: Duration::from_secs(3600),
+        }
+    }
+
+    pub fn job(mut self, job: ScheduledJob) -> Self {
+        self.jobs.push(job);
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Claims each run under `{prefix}:{job}:{due unix millis}` first, skipping runs another
+    /// instance claimed.
+    pub fn distributed_lock(mut self, redis: impl RedisLike + 'static) -> Self {
+        self.lock = Some(Arc::new(redis));
+        self
+    }
+
+    /// `scheduler` by default.
+    pub fn lock_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.lock_prefix = prefix.into();
+        self
+    }
+
+    /// How long claims are kept, longer than the clocks of instances can differ by. An hour by
+    /// default.
+    pub fn lock_ttl(mut self, ttl: Duration) -> Self {
+        self.lock_ttl = ttl;
+        self
+    }
+
+    /// Schedules the jobs on the current runtime until [`SchedulerHandle::shutdown`] or dropping
+    /// the handle.
+    pub fn start(self) -> SchedulerHandle {
+        let cancel = CancellationToken::new();
+        let shared = Arc::new(Shared {
+            clock: self.clock,
+            lock: self.lock,
+            lock_prefix: self.lock_prefix,
+            lock_ttl: self.lock_ttl,
+        });
+        let loops = self
+            .jobs
+            .into_iter()
+            .map(|job| tokio::spawn(run_job(job, shared.clone(), cancel.clone())))
+            .collect();
+        SchedulerHandle { cancel, loops }
+    }
+}
+
+struct Shared {
+    clock: Arc<dyn Clock>,
+    lock: Option<Arc<dyn RedisLike>>,
+    lock_prefix: String,
+    lock_ttl: Duration,
+}
+
+impl Shared {
+    /// Whether this instance got to run `job` due at `scheduled_at`.
+    async fn claim(&self, job: &str, scheduled_at: DateTime<Utc>) -> bool {
+        let Some(redis) = &self.lock else {
+            return true;
+        };
+        let key = format!(
+            "{}:{}:{}",
+            self.lock_prefix,
+            job,
+            scheduled_at.timestamp_millis()
+        );
+        let owner = hostname::get()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        match redis.set_nx(&key, &owner, self.lock_ttl).await {
+            Ok(claimed) => {
+                if !claimed {
+                    debug!(
+                        "Run of {} at {} claimed by another instance",
+                        job, scheduled_at
+                    );
+                }
+                claimed
+            }
+            Err(e) => {
+                warn!("Failed to claim run of {} at {}: {}", job, scheduled_at, e);
+                false
+            }
+        }
+    }
+}
+
+/// Stops the scheduler when dropped, letting runs in progress finish in the background.
+pub struct SchedulerHandle {
+    cancel: CancellationToken,
+    loops: Vec<JoinHandle<()>>,
+}
+
+impl SchedulerHandle {
+    /// Stops scheduling runs and waits for the ones in progress to finish.
+    pub async fn shutdown(mut self) {
+        self.cancel.cancel();
+        for job_loop in std::mem::take(&mut self.loops) {
+            let _ = job_loop.await;
+        }
+    }
+}
+
+impl Drop for SchedulerHandle {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+async fn run_job(job: ScheduledJob, shared: Arc<Shared>, cancel: CancellationToken) {
+    let mut running: Option<JoinHandle<()>> = None;
+    let mut last: Option<DateTime<Utc>> = None;
+    let mut runs = 0;
+    loop {
+        let now = DateTime::<Utc>::from(shared.clock.system_now());
+        // Never the same run twice, were the clock to wake up early:
+        let after = last.map_or(now, |last| last.max(now));
+        let Some(scheduled_at) = job.schedule.next_after(after) else {
+            warn!("{} has no more runs scheduled", job.name);
+            break;
+        };
+        last = Some(scheduled_at);
+        let delay = (scheduled_at - now).to_std().unwrap_or_default() + job.jitter_delay();
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = shared.clock.sleep(delay) => {}
+        }
+
+        let previous = running.take().filter(|handle| !handle.is_finished());
+        if previous.is_some() && job.overlap == Overlap::Skip {
+            warn!(
+                "Skipping run of {} at {}, the previous one is still running",
+                job.name, scheduled_at
+            );
+            running = previous;
+            continue;
+        }
+        if !shared.claim(&job.name, scheduled_at).await {
+            running = previous;
+            continue;
+        }
+        if let Some(mut previous) = previous {
+            if job.overlap == Overlap::Cancel {
+                warn!(
+                    "Cancelling the previous run of {} at {}",
+                    job.name, scheduled_at
+                );
+                previous.abort();
+            } else {
+                tokio::select! {
+                    _ = cancel.cancelled() => {
+                        running = Some(previous);
+                        break;
+                    }
+                    _ = &mut previous => {}
+                }
+            }
+        }
+
+        runs += 1;
+        let run = ScheduledRun {
+            job: job.name.clone(),
+            scheduled_at,
+            run: runs,
+        };
+        let span = tracing::info_span!(
+            "scheduled_run",
+            job = %job.name,
+            run = runs,
+            scheduled_at = %scheduled_at
+        );
+        let (name, task) = (job.name.clone(), (job.task)(run));
+        running = Some(tokio::spawn(
+            async move {
+                match task.await {
+                    Ok(()) => debug!("Run of {} finished", name),
+                    Err(e) => error!("Run of {} failed: {:?}", name, e),
+                }
+            }
+            .instrument(span),
+        ));
+    }
+    if let Some(running) = running {
+        let _ = running.await;
+    }
+}
+
+/// The scheduler items, also part of [`crate::prelude`].
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{Overlap, Schedule, ScheduledJob, ScheduledRun, Scheduler};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::prelude::*;
+    use parking_lot::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[rstest]
+    fn intervals_align_to_the_epoch() {
+        let schedule = Schedule::every(Duration::from_secs(300));
+        let after = DateTime::parse_from_rfc3339("2024-03-10T12:07:30Z")
+            .unwrap()
+            .to_utc();
+        assert_eq!(
+            schedule.next_after(after).unwrap().to_rfc3339(),
+            "2024-03-10T12:10:00+00:00"
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn runs_on_schedule(mock_clock: MockClock) {
+        let runs = Arc::new(Mutex::new(vec![]));
+        let recorded = runs.clone();
+        let handle = Scheduler::new()
+            .clock(mock_clock.shared())
+            .job(ScheduledJob::new(
+                "sync",
+                Schedule::every(Duration::from_secs(60)),
+                move |run| {
+                    recorded.lock().push(run);
+                    async { Ok(()) }
+                },
+            ))
+            .start();
+        while runs.lock().len() < 3 {
+            tokio::task::yield_now().await;
+        }
+        handle.shutdown().await;
+
+        let runs = runs.lock();
+        assert_eq!(runs[0].scheduled_at.timestamp() % 60, 0);
+        assert_eq!(
+            runs[2].scheduled_at - runs[0].scheduled_at,
+            chrono::Duration::minutes(2)
+        );
+        assert_eq!(
+            runs.iter().map(|run| run.run).collect::<Vec<_>>()[..3],
+            [1, 2, 3]
+        );
+    }
+
+    /// Runs a job every 20ms taking 70ms for a bit, returning how many runs started and finished.
+    async fn overlapping_runs(overlap: Overlap) -> (usize, usize, usize) {
+        let (started, finished, concurrent) = (
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicUsize::new(0)),
+        );
+        let max_concurrent = Arc::new(AtomicUsize::new(0));
+        let counters = (
+            started.clone(),
+            finished.clone(),
+            concurrent.clone(),
+            max_concurrent.clone(),
+        );
+        let handle = Scheduler::new()
+            .job(
+                ScheduledJob::new(
+                    "slow",
+                    Schedule::every(Duration::from_millis(20)),
+                    move |_| {
+                        let (started, finished, concurrent, max_concurrent) = counters.clone();
+                        async move {
+                            started.fetch_add(1, Ordering::SeqCst);
+                            let now = concurrent.fetch_add(1, Ordering::SeqCst) + 1;
+                            max_concurrent.fetch_max(now, Ordering::SeqCst);
+                            // Also counted when aborted:
+                            struct Leave(Arc<AtomicUsize>);
+                            impl Drop for Leave {
+                                fn drop(&mut self) {
+                                    self.0.fetch_sub(1, Ordering::SeqCst);
+                                }
+                            }
+                            let _leave = Leave(concurrent);
+                            tokio::time::sleep(Duration::from_millis(70)).await;
+                            finished.fetch_add(1, Ordering::SeqCst);
+                            Ok(())
+                        }
+                    },
+                )
+                .overlap(overlap),
+            )
+            .start();
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        drop(handle);
+        (
+            started.load(Ordering::SeqCst),
+            finished.load(Ordering::SeqCst),
+            max_concurrent.load(Ordering::SeqCst),
+        )
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn prevents_overlap() {
+        let (started, _, concurrent) = overlapping_runs(Overlap::Skip).await;
+        assert!(started >= 2, "{}", started);
+        assert_eq!(concurrent, 1);
+
+        let (started, finished, concurrent) = overlapping_runs(Overlap::Cancel).await;
+        assert!(started > 5, "{}", started);
+        assert_eq!((finished, concurrent), (0, 1));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn one_instance_runs_each_claimed_run(memory_redis: MemoryRedis) {
+        let runs = Arc::new(Mutex::new(vec![]));
+        let instance = |redis: MemoryRedis| {
+            let recorded = runs.clone();
+            Scheduler::new()
+                .distributed_lock(redis)
+                .job(ScheduledJob::new(
+                    "report",
+                    Schedule::every(Duration::from_millis(20)),
+                    move |run| {
+                        recorded.lock().push(run.scheduled_at);
+                        async { Ok(()) }
+                    },
+                ))
+                .start()
+        };
+        let (first, second) = (
+            instance(memory_redis.clone()),
+            instance(memory_redis.clone()),
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        first.shutdown().await;
+        second.shutdown().await;
+
+        let mut runs = runs.lock().clone();
+        let total = runs.len();
+        runs.dedup();
+        assert!(total >= 5, "{}", total);
+        assert_eq!(runs.len(), total);
+        assert!(memory_redis
+            .keys()
+            .iter()
+            .all(|key| key.starts_with("scheduler:report:")));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn failed_runs_keep_the_schedule(mock_clock: MockClock) {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counted = attempts.clone();
+        let handle = Scheduler::new()
+            .clock(mock_clock.shared())
+            .job(ScheduledJob::new(
+                "flaky",
+                Schedule::cron("*/5 * * * *").unwrap(),
+                move |_| {
+                    counted.fetch_add(1, Ordering::SeqCst);
+                    async { Err(err!(AnyErr, "Upstream is down")) }
+                },
+            ))
+            .start();
+        while attempts.load(Ordering::SeqCst) < 2 {
+            tokio::task::yield_now().await;
+        }
+        handle.shutdown().await;
+    }
+}
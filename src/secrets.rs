@@ -0,0 +1,354 @@
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{system_clock, Clock};
+use crate::config::Secret;
+use crate::prelude::*;
+
+/// Somewhere secrets like api keys and registry tokens are read from by name.
+#[async_trait]
+pub trait SecretStore: std::fmt::Debug + Send + Sync {
+    /// Fails with [`ErrorClass::NotFound`] when there's no secret called `name`.
+    async fn get(&self, name: &str) -> RResult<Secret, AnyErr>;
+}
+
+fn not_found(name: &str, store: &dyn SecretStore) -> Report<AnyErr> {
+    err!(AnyErr, "Secret {} not found", name)
+        .attach_printable(format!("Store: {:?}", store))
+        .classify(ErrorClass::NotFound)
+}
+
+/// Secrets in env vars, `name` upper cased after the prefix, e.g. `github_token` is
+/// `APP_GITHUB_TOKEN` with the prefix `APP_`.
+#[derive(Debug, Clone, Default)]
+pub struct EnvSecrets {
+    prefix: String,
+}
+
+impl EnvSecrets {
+    pub fn new() -> Self {
+        EnvSecrets::default()
+    }
+
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn var(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name.to_uppercase().replace('-', "_"))
+    }
+}
+
+#[async_trait]
+impl SecretStore for EnvSecrets {
+    async fn get(&self, name: &str) -> RResult<Secret, AnyErr> {
+        std::env::var(self.var(name))
+            .map(Secret::new)
+            .map_err(|_| not_found(name, self))
+    }
+}
+
+/// Secrets as files in a directory, like docker and kubernetes mount them, e.g.
+/// `/run/secrets/github_token`. Trailing newlines are dropped.
+#[derive(Debug, Clone)]
+pub struct FileSecrets {
+    dir: PathBuf,
+}
+
+impl FileSecrets {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileSecrets { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl SecretStore for FileSecrets {
+    async fn get(&self, name: &str) -> RResult<Secret, AnyErr> {
+        // Names are file names, not paths into other directories:
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(err!(AnyErr, "Invalid secret name: {}", name))
+                .classify(ErrorClass::InvalidInput);
+        }
+        let path = self.dir.join(name);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => Ok(Secret::new(
+                contents.trim_end_matches(['\n', '\r']).to_string(),
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(not_found(name, self)),
+            Err(e) => Err(Report::new(e)
+                .change_context(AnyErr)
+                .attach_printable(format!("Failed to read {}", path.display()))),
+        }
+    }
+}
+
+/// The entries of a kubernetes secret in the manager's namespace, `name` being the key.
+#[cfg(feature = "k8s")]
+#[derive(Clone)]
+pub struct KubeSecrets {
+    kube: crate::k8_manager::KubeManager,
+    secret: String,
+}
+
+#[cfg(feature = "k8s")]
+impl KubeSecrets {
+    pub fn new(kube: crate::k8_manager::KubeManager, secret: impl Into<String>) -> Self {
+        KubeSecrets {
+            kube,
+            secret: secret.into(),
+        }
+    }
+}
+
+#[cfg(feature = "k8s")]
+impl std::fmt::Debug for KubeSecrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "KubeSecrets({}/{})",
+            self.kube.get_namespace(),
+            self.secret
+        )
+    }
+}
+
+#[cfg(feature = "k8s")]
+#[async_trait]
+impl SecretStore for KubeSecrets {
+    async fn get(&self, name: &str) -> RResult<Secret, AnyErr> {
+        let secret = self
+            .kube
+            .api::<k8s_openapi::api::core::v1::Secret>()
+            .get(&self.secret)
+            .await
+            .map_err(crate::k8_manager::kube_failed(format!(
+                "get secret {}",
+                self.secret
+            )))?;
+        let value = secret
+            .data
+            .and_then(|mut data| data.remove(name))
+            .ok_or_else(|| not_found(name, self))?;
+        String::from_utf8(value.0)
+            .map(Secret::new)
+            .map_err(|_| err!(AnyErr, "Secret {} isn't utf-8", name))
+    }
+}
+
+type RotateHook = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Values with when they were read, `None` once invalidated.
+type Cache = HashMap<String, (Secret, Option<Instant>)>;
+
+/// The one place secrets are read through: wraps a [`SecretStore`], caching values for
+/// [`Self::ttl`] and logging which secret was read from where, never the value. Stores are
+/// tried in order, so e.g. env vars can override mounted files locally.
+///
+/// When a secret is read again after expiring and has changed, the [`Self::on_rotate`] hooks
+/// run with its name, e.g. to rebuild clients holding the old key.
+#[derive(Clone)]
+pub struct Secrets {
+    stores: Vec<Arc<dyn SecretStore>>,
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    cache: Arc<Mutex<Cache>>,
+    on_rotate: Vec<RotateHook>,
+}
+
+impl std::fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secrets")
+            .field("stores", &self.stores)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+impl Secrets {
+    pub fn new(store: impl SecretStore + 'static) -> Self {
+        Secrets {
+            stores: vec![Arc::new(store)],
+            ttl: Duration::from_secs(300),
+            clock: system_clock(),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            on_rotate: vec![],
+        }
+    }
+
+    /// A store to fall back on for secrets the earlier ones don't have.
+    pub fn fallback(mut self, store: impl SecretStore + 'static) -> Self {
+        self.stores.push(Arc::new(store));
+        self
+    }
+
+    /// How long a value is used before reading it again, 5 minutes by default.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn on_rotate(mut self, hook: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.on_rotate.push(Arc::new(hook));
+        self
+    }
+
+    /// Forgets the cached value, so the next read gets the current one, e.g. after a request
+    /// failed as unauthorized.
+    pub fn invalidate(&self, name: &str) {
+        if let Some((_, read_at)) = self.cache.lock().get_mut(name) {
+            *read_at = None;
+        }
+    }
+
+    async fn read(&self, name: &str) -> RResult<Secret, AnyErr> {
+        let mut missing = vec![];
+        for store in &self.stores {
+            match store.get(name).await {
+                Ok(secret) => {
+                    info!(secret = name, store = ?store, "Read secret");
+                    return Ok(secret);
+                }
+                Err(report)
+                    if crate::errors::error_class(&report) == Some(ErrorClass::NotFound) =>
+                {
+                    missing.push(format!("{:?}", store));
+                }
+                Err(report) => {
+                    return Err(report.attach_printable(format!("Secret: {}", name)));
+                }
+            }
+        }
+        Err(err!(AnyErr, "Secret {} not found", name)
+            .attach_printable(format!("Looked in: {}", missing.join(", ")))
+            .classify(ErrorClass::NotFound))
+    }
+}
+
+#[async_trait]
+impl SecretStore for Secrets {
+    async fn get(&self, name: &str) -> RResult<Secret, AnyErr> {
+        let now = self.clock.now();
+        let previous = self.cache.lock().get(name).cloned();
+        if let Some((secret, Some(read_at))) = &previous {
+            if now.duration_since(*read_at) < self.ttl {
+                return Ok(secret.clone());
+            }
+        }
+        let secret = self.read(name).await?;
+        self.cache
+            .lock()
+            .insert(name.to_string(), (secret.clone(), Some(now)));
+        if previous.is_some_and(|(old, _)| old != secret) {
+            info!(secret = name, "Secret rotated");
+            for hook in &self.on_rotate {
+                hook(name);
+            }
+        }
+        Ok(secret)
+    }
+}
+
+/// The secret items, also part of [`crate::prelude`].
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{EnvSecrets, FileSecrets, SecretStore, Secrets};
+
+    #[cfg(feature = "k8s")]
+    #[allow(unused_imports)]
+    pub use super::KubeSecrets;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::error_class;
+    use crate::files::TempWorkspace;
+    use crate::testing::prelude::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[rstest]
+    #[tokio::test]
+    async fn falls_back_between_stores(temp_dir: TempWorkspace) {
+        temp_dir.write("github_token", "from-file\n").unwrap();
+        temp_dir.write("npm_token", "npm\n").unwrap();
+        std::env::set_var("RUTILS_TEST_SECRETS_GITHUB_TOKEN", "from-env");
+
+        let secrets = Secrets::new(EnvSecrets::new().prefix("RUTILS_TEST_SECRETS_"))
+            .fallback(FileSecrets::new(temp_dir.path()));
+        assert_eq!(
+            secrets.get("github_token").await.unwrap().expose(),
+            "from-env"
+        );
+        assert_eq!(secrets.get("npm_token").await.unwrap().expose(), "npm");
+        let report = secrets.get("missing").await.unwrap_err();
+        assert_eq!(error_class(&report), Some(ErrorClass::NotFound));
+        assert!(FileSecrets::new(temp_dir.path())
+            .get("../npm_token")
+            .await
+            .is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn caches_until_rotated(temp_dir: TempWorkspace, mock_clock: MockClock) {
+        temp_dir.write("api_key", "v1").unwrap();
+        let rotations = Arc::new(AtomicUsize::new(0));
+        let rotated = rotations.clone();
+        let secrets = Secrets::new(FileSecrets::new(temp_dir.path()))
+            .ttl(Duration::from_secs(60))
+            .clock(mock_clock.shared())
+            .on_rotate(move |name| {
+                assert_eq!(name, "api_key");
+                rotated.fetch_add(1, Ordering::SeqCst);
+            });
+        assert_eq!(secrets.get("api_key").await.unwrap().expose(), "v1");
+
+        temp_dir.write("api_key", "v2").unwrap();
+        assert_eq!(secrets.get("api_key").await.unwrap().expose(), "v1");
+        mock_clock.advance(Duration::from_secs(60));
+        assert_eq!(secrets.get("api_key").await.unwrap().expose(), "v2");
+        assert_eq!(rotations.load(Ordering::SeqCst), 1);
+
+        temp_dir.write("api_key", "v3").unwrap();
+        secrets.invalidate("api_key");
+        assert_eq!(secrets.get("api_key").await.unwrap().expose(), "v3");
+        assert_eq!(rotations.load(Ordering::SeqCst), 2);
+    }
+
+    #[cfg(feature = "k8s")]
+    #[rstest]
+    #[tokio::test]
+    async fn reads_kube_secret_keys() {
+        let kube = crate::k8_manager::fake_api(|method, path, _| {
+            assert_eq!(
+                (method, path),
+                ("GET", "/api/v1/namespaces/test/secrets/api-keys")
+            );
+            let secret = serde_json::json!({
+                "apiVersion": "v1",
+                "kind": "Secret",
+                "metadata": {"name": "api-keys"},
+                "data": {"github_token": "Z2hwXzEyMw=="},
+            });
+            (200, secret.to_string())
+        })
+        .await;
+        let secrets = KubeSecrets::new(kube, "api-keys");
+        assert_eq!(
+            secrets.get("github_token").await.unwrap().expose(),
+            "ghp_123"
+        );
+        let report = secrets.get("npm_token").await.unwrap_err();
+        assert_eq!(error_class(&report), Some(ErrorClass::NotFound));
+    }
+}
@@ -0,0 +1,223 @@
+use parking_lot::{Mutex, MutexGuard};
+use std::sync::Arc;
+
+use crate::cmd::{set_executor, CommandExecutor};
+use crate::testing::prelude::*;
+
+/// A command [`CmdHarness`] was asked to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RanCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl std::fmt::Display for RanCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Recorder {
+    ran: Mutex<Vec<RanCommand>>,
+    /// Commands to fail, with how many more times.
+    failures: Mutex<Vec<(RanCommand, usize)>>,
+}
+
+impl CommandExecutor for Recorder {
+    fn execute(&self, command: &str, args: &[&str]) -> RResult<(), AnyErr> {
+        let ran = RanCommand {
+            program: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        };
+        self.ran.lock().push(ran.clone());
+        let mut failures = self.failures.lock();
+        match failures
+            .iter_mut()
+            .find(|(failing, times)| *failing == ran && *times > 0)
+        {
+            Some((_, times)) => {
+                *times -= 1;
+                Err(err!(AnyErr, "Command '{}' failed with status: 1", command))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Held by the installed [`CmdHarness`], as its executor runs the commands of every thread.
+static INSTALLED: Mutex<()> = Mutex::new(());
+
+/// Records the commands [`crate::cmd::run_command`] and [`crate::cmd::run_async_command`] run on
+/// any thread instead of spawning them, see [`cmd_harness`]. They succeed unless told to
+/// [`CmdHarness::fail`]. Spawning is back to normal when dropped.
+pub struct CmdHarness {
+    recorder: Arc<Recorder>,
+    _installed: MutexGuard<'static, ()>,
+}
+
+impl CmdHarness {
+    /// Waits for the harness of other tests to be dropped first, so only one is installed at a
+    /// time. Installing a second one on the same thread deadlocks.
+    pub fn install() -> Self {
+        let installed = INSTALLED.lock();
+        let recorder = Arc::new(Recorder::default());
+        set_executor(Some(recorder.clone()));
+        CmdHarness {
+            recorder,
+            _installed: installed,
+        }
+    }
+
+    /// Makes the next `times` runs of exactly this command fail.
+    pub fn fail<'a>(&self, program: &str, args: impl IntoIterator<Item = &'a str>, times: usize) {
+        self.recorder
+            .failures
+            .lock()
+            .push((ran_command(program, args), times));
+    }
+
+    /// Everything run so far, in order.
+    pub fn ran(&self) -> Vec<RanCommand> {
+        self.recorder.ran.lock().clone()
+    }
+
+    /// Panics unless `program` was run with exactly `args`.
+    #[track_caller]
+    pub fn assert_ran<'a>(&self, program: &str, args: impl IntoIterator<Item = &'a str>) {
+        let expected = ran_command(program, args);
+        let ran = self.ran();
+        assert!(
+            ran.contains(&expected),
+            "`{}` wasn't run, only:\n{}",
+            expected,
+            describe(&ran)
+        );
+    }
+
+    /// Panics if `program` was run at all.
+    #[track_caller]
+    pub fn assert_not_ran(&self, program: &str) {
+        let ran = self.ran();
+        assert!(
+            ran.iter().all(|command| command.program != program),
+            "`{}` was run:\n{}",
+            program,
+            describe(&ran)
+        );
+    }
+}
+
+impl Drop for CmdHarness {
+    fn drop(&mut self) {
+        set_executor(None);
+    }
+}
+
+fn ran_command<'a>(program: &str, args: impl IntoIterator<Item = &'a str>) -> RanCommand {
+    RanCommand {
+        program: program.to_string(),
+        args: args.into_iter().map(|arg| arg.to_string()).collect(),
+    }
+}
+
+fn describe(ran: &[RanCommand]) -> String {
+    ran.iter()
+        .map(|command| format!("  {}", command))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Records commands instead of running them, for testing install flows:
+///
+/// ```ignore
+/// #[rstest]
+/// fn starts_colima(cmd_harness: CmdHarness) {
+///     start_docker().unwrap();
+///     cmd_harness.assert_ran("colima", ["start"]);
+/// }
+/// ```
+///
+/// Commands run from other threads and `spawn_blocking` are recorded too, tests using the
+/// harness run one at a time. Tests really running commands meanwhile take [`real_commands`].
+#[fixture]
+pub fn cmd_harness() -> CmdHarness {
+    CmdHarness::install()
+}
+
+/// Held by tests that really spawn commands through [`crate::cmd::run_command`], so no
+/// [`CmdHarness`] of another test records them meanwhile, see [`real_commands`].
+pub struct RealCommands {
+    _installed: MutexGuard<'static, ()>,
+}
+
+/// Waits for the installed [`CmdHarness`] to be dropped and keeps others from being installed.
+#[fixture]
+pub fn real_commands() -> RealCommands {
+    RealCommands {
+        _installed: INSTALLED.lock(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{run_async_command, run_command};
+
+    #[rstest]
+    #[tokio::test]
+    async fn records_instead_of_spawning(cmd_harness: CmdHarness) {
+        cmd_harness.fail("docker", ["push", "app:1"], 1);
+        run_command("docker", &["build", "-t", "app:1", "."]).unwrap();
+        assert!(run_async_command("docker", &["push", "app:1"])
+            .await
+            .is_err());
+        run_async_command("docker", &["push", "app:1"])
+            .await
+            .unwrap();
+
+        cmd_harness.assert_ran("docker", ["build", "-t", "app:1", "."]);
+        cmd_harness.assert_not_ran("kubectl");
+        let pushes = cmd_harness
+            .ran()
+            .into_iter()
+            .filter(|command| command.args.first().is_some_and(|arg| arg == "push"))
+            .count();
+        assert_eq!(pushes, 2);
+    }
+
+    #[rstest]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn records_other_threads(cmd_harness: CmdHarness) {
+        tokio::task::spawn_blocking(|| run_command("kubectl", &["apply"]))
+            .await
+            .unwrap()
+            .unwrap();
+        std::thread::spawn(|| run_command("helm", &["install"]))
+            .join()
+            .unwrap()
+            .unwrap();
+        tokio::spawn(async { run_async_command("docker", &["push"]).await })
+            .await
+            .unwrap()
+            .unwrap();
+
+        cmd_harness.assert_ran("kubectl", ["apply"]);
+        cmd_harness.assert_ran("helm", ["install"]);
+        cmd_harness.assert_ran("docker", ["push"]);
+    }
+
+    #[rstest]
+    fn restores_spawning() {
+        {
+            let _harness = CmdHarness::install();
+            run_command("definitely-not-installed-rutils", &[]).unwrap();
+        }
+        let _real = real_commands();
+        assert!(run_command("definitely-not-installed-rutils", &[]).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::files::TempWorkspace;
+use crate::testing::prelude::*;
+
+/// Env vars are shared by the whole process, so only one test at a time gets a fake home.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// The env vars [`fake_home`] points into its directory, relative to it.
+const FAKE_ENV: [(&str, &str); 7] = [
+    ("HOME", "home"),
+    ("XDG_CONFIG_HOME", "home/.config"),
+    ("XDG_DATA_HOME", "home/.local/share"),
+    ("XDG_STATE_HOME", "home/.local/state"),
+    ("XDG_CACHE_HOME", "home/.cache"),
+    ("XDG_RUNTIME_DIR", "run"),
+    ("TMPDIR", "tmp"),
+];
+
+/// A directory of the test's own, removed when dropped.
+#[fixture]
+pub fn temp_dir() -> TempWorkspace {
+    panic_on_err!({ TempWorkspace::new("rutils-test-") })
+}
+
+/// A temp dir that `HOME`, the `XDG_*` dirs and `TMPDIR` point into while alive, see
+/// [`fake_home`]. The previous values are restored when dropped.
+#[derive(Debug)]
+pub struct FakeHome {
+    workspace: TempWorkspace,
+    saved: Vec<(&'static str, Option<OsString>)>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl FakeHome {
+    /// The directory holding the home, runtime and tmp dirs.
+    pub fn root(&self) -> &Path {
+        self.workspace.path()
+    }
+
+    /// What `HOME` is set to.
+    pub fn home(&self) -> PathBuf {
+        self.root().join("home")
+    }
+
+    /// Files for setting up the test, relative to the root, e.g. `home/.config/app.toml`.
+    pub fn workspace(&self) -> &TempWorkspace {
+        &self.workspace
+    }
+}
+
+impl Drop for FakeHome {
+    fn drop(&mut self) {
+        for (key, value) in &self.saved {
+            match value {
+                Some(value) => std::env::set_var(key, value),
+                None => std::env::remove_var(key),
+            }
+        }
+    }
+}
+
+/// Makes the test hermetic for code reading or writing files in the user's dirs.
+///
+/// Tests using this run one at a time, but others running meanwhile see the fake env too.
+#[fixture]
+pub fn fake_home() -> FakeHome {
+    // A test that panicked while holding the lock restored the env when unwinding:
+    let lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let workspace = panic_on_err!({ TempWorkspace::new("rutils-home-") });
+    let saved = FAKE_ENV
+        .iter()
+        .map(|(key, _)| (*key, std::env::var_os(key)))
+        .collect();
+    for (key, dir) in FAKE_ENV {
+        let path = workspace.path().join(dir);
+        std::fs::create_dir_all(&path).unwrap();
+        std::env::set_var(key, path);
+    }
+    FakeHome {
+        workspace,
+        saved,
+        _lock: lock,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest]
+    fn fakes_and_restores_env() {
+        let home = std::env::var_os("HOME");
+        {
+            let fake = fake_home();
+            assert_eq!(std::env::var_os("HOME"), Some(fake.home().into()));
+            assert_eq!(std::env::temp_dir(), fake.root().join("tmp"));
+            let config = fake
+                .workspace()
+                .write("home/.config/app.toml", "debug = true")
+                .unwrap();
+            assert!(config.starts_with(std::env::var("XDG_CONFIG_HOME").unwrap()));
+        }
+        assert_eq!(std::env::var_os("HOME"), home);
+    }
+}
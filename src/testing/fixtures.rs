@@ -1,8 +1,14 @@
 // use crate::logger::GlobalLog;
-use tracing::Level;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::subscriber::DefaultGuard;
+use tracing::{Event, Level, Subscriber};
 use tracing_subscriber::fmt::format::FmtSpan;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{EnvFilter, Layer};
 
+use crate::clock::MockClock;
 use crate::testing::prelude::*;
 
 /// Include this in a test to turn on logging globally.
@@ -17,3 +23,157 @@ pub fn logging(#[default(Level::TRACE)] level: Level) {
         Ok::<(), error_stack::Report<AnyErr>>(())
     })
 }
+
+/// An event recorded by [`captured_logs`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedEvent {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    /// The other fields, formatted with Debug.
+    pub fields: Vec<(String, String)>,
+    /// The names of the spans it was logged in, outermost first.
+    pub spans: Vec<String>,
+}
+
+/// The events logged on this thread while alive, see [`captured_logs`].
+#[derive(Debug)]
+pub struct CapturedLogs {
+    events: Arc<Mutex<Vec<CapturedEvent>>>,
+    _guard: DefaultGuard,
+}
+
+impl CapturedLogs {
+    pub fn events(&self) -> Vec<CapturedEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// The events logged inside the span named `span`, directly or in a child span.
+    pub fn events_for_span(&self, span: &str) -> Vec<CapturedEvent> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event.spans.iter().any(|name| name == span))
+            .cloned()
+            .collect()
+    }
+
+    /// Panics unless an event of `level` has `substring` in its message.
+    #[track_caller]
+    pub fn assert_contains(&self, level: Level, substring: &str) {
+        let events = self.events.lock().unwrap();
+        assert!(
+            events
+                .iter()
+                .any(|event| event.level == level && event.message.contains(substring)),
+            "No {} event containing {:?} in:\n{}",
+            level,
+            substring,
+            events
+                .iter()
+                .map(|event| format!("{} {}", event.level, event.message))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+}
+
+/// Records the events logged on this thread, at all levels, until dropped. Events of spawned
+/// threads aren't, nor of other tokio workers, `#[tokio::test]` runs everything on this one.
+#[fixture]
+pub fn captured_logs() -> CapturedLogs {
+    let events = Arc::new(Mutex::new(vec![]));
+    let subscriber = tracing_subscriber::registry().with(CaptureLayer {
+        events: events.clone(),
+    });
+    CapturedLogs {
+        events,
+        _guard: tracing::subscriber::set_default(subscriber),
+    }
+}
+
+/// A [`MockClock`] to pass to the retry, cache and rate limit utilities with
+/// [`MockClock::shared`], so their waits take no time.
+#[fixture]
+pub fn mock_clock() -> MockClock {
+    MockClock::new()
+}
+
+struct CaptureLayer {
+    events: Arc<Mutex<Vec<CapturedEvent>>>,
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for CaptureLayer {
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let spans = ctx
+            .event_scope(event)
+            .map(|scope| {
+                scope
+                    .from_root()
+                    .map(|span| span.name().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.events.lock().unwrap().push(CapturedEvent {
+            level: *event.metadata().level(),
+            target: event.metadata().target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+            spans,
+        });
+    }
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: Vec<(String, String)>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            name => self.fields.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{:?}", value),
+            name => self.fields.push((name.to_string(), format!("{:?}", value))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest]
+    fn captures_events(captured_logs: CapturedLogs) {
+        tracing::info_span!("request", id = 7).in_scope(|| {
+            tracing::warn!(user = "ada", "Slow response after {}ms", 900);
+        });
+        tracing::debug!("Outside");
+
+        captured_logs.assert_contains(Level::WARN, "Slow response after 900ms");
+        let in_request = captured_logs.events_for_span("request");
+        assert_eq!(in_request.len(), 1);
+        assert_eq!(
+            in_request[0].fields,
+            [("user".to_string(), "ada".to_string())]
+        );
+        assert_eq!(captured_logs.events().len(), 2);
+    }
+
+    #[rstest]
+    #[should_panic(expected = "No ERROR event")]
+    fn fails_without_match(captured_logs: CapturedLogs) {
+        tracing::info!("Fine");
+        captured_logs.assert_contains(Level::ERROR, "Fine");
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+
+use crate::testing::prelude::*;
+use crate::testing::snapshot::UPDATE_SNAPSHOTS_ENV;
+
+/// Checks `actual` is the text stored at `path`, relative to the crate root, e.g. the output of
+/// a cli command or `LogViewer` formatting. Stores it when the file doesn't exist yet or
+/// [`UPDATE_SNAPSHOTS_ENV`] is set, otherwise panics with a line diff when they differ.
+/// Line endings are normalized, so files checked out on windows still match.
+#[track_caller]
+pub fn assert_matches_golden(path: impl AsRef<Path>, actual: &str) {
+    let path = golden_path(path.as_ref());
+    let actual = actual.replace("\r\n", "\n");
+    let update = std::env::var_os(UPDATE_SNAPSHOTS_ENV).is_some();
+    if path.exists() && !update {
+        let expected = panic_on_err!({
+            std::fs::read_to_string(&path)
+                .change_context(AnyErr)
+                .attach_printable_lazy(|| format!("Golden file: {}", path.display()))
+        })
+        .replace("\r\n", "\n");
+        assert!(
+            expected == actual,
+            "Output doesn't match {}, rerun with {}=1 to update it.\n{}",
+            path.display(),
+            UPDATE_SNAPSHOTS_ENV,
+            line_diff(&expected, &actual)
+        );
+    } else {
+        panic_on_err!({
+            path.parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::write(&path, &actual))
+                .change_context(AnyErr)
+                .attach_printable_lazy(|| format!("Golden file: {}", path.display()))
+        });
+    }
+}
+
+fn golden_path(path: &Path) -> PathBuf {
+    match std::env::var_os("CARGO_MANIFEST_DIR") {
+        Some(root) if path.is_relative() => PathBuf::from(root).join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// The lines of `expected` missing from `actual` prefixed with `-`, the added ones with `+`
+/// and unchanged ones with a space.
+pub fn line_diff(expected: &str, actual: &str) -> String {
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+    // Longest common subsequences of the suffixes:
+    let mut lcs = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut diff = vec![];
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            diff.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j < actual.len() && (i == expected.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            diff.push(format!("+ {}", actual[j]));
+            j += 1;
+        } else {
+            diff.push(format!("- {}", expected[i]));
+            i += 1;
+        }
+    }
+    diff.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempWorkspace;
+
+    #[rstest]
+    fn diffs_lines() {
+        assert_eq!(line_diff("a\nb\nc\n", "a\nc\nd\n"), "  a\n- b\n  c\n+ d");
+    }
+
+    #[rstest]
+    fn stores_then_compares(temp_dir: TempWorkspace) {
+        let path = temp_dir.path().join("out.txt");
+        assert_matches_golden(&path, "line 1\r\nline 2\n");
+        assert_matches_golden(&path, "line 1\nline 2\n");
+        let changed = std::panic::catch_unwind(|| assert_matches_golden(&path, "line 1\n"));
+        assert!(changed.is_err());
+    }
+}
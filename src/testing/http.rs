@@ -0,0 +1,366 @@
+use reqwest::{Method, Url};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::testing::prelude::*;
+
+/// A request a [`MockHttp`] received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedRequest {
+    pub method: Method,
+    /// Without the query.
+    pub path: String,
+    pub query: BTreeMap<String, String>,
+    /// With lowercase names.
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_str(&self.body).ok()
+    }
+}
+
+#[derive(Debug)]
+struct Mock {
+    method: Method,
+    path: String,
+    query: BTreeMap<String, String>,
+    headers: BTreeMap<String, String>,
+    json_body: Option<Value>,
+    times: Option<usize>,
+    status: u16,
+    content_type: &'static str,
+    body: String,
+    calls: usize,
+}
+
+impl Mock {
+    fn matches(&self, request: &RecordedRequest) -> bool {
+        self.method == request.method
+            && self.path == request.path
+            && self
+                .query
+                .iter()
+                .all(|(key, value)| request.query.get(key) == Some(value))
+            && self
+                .headers
+                .iter()
+                .all(|(key, value)| request.headers.get(key) == Some(value))
+            && self
+                .json_body
+                .as_ref()
+                .is_none_or(|body| request.json().as_ref() == Some(body))
+            && self.times.is_none_or(|times| self.calls < times)
+    }
+
+    fn describe(&self) -> String {
+        format!("{} {}", self.method, self.path)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    mocks: Vec<Mock>,
+    requests: Vec<RecordedRequest>,
+    unmatched: Vec<RecordedRequest>,
+}
+
+/// An http server on localhost answering with the responses set up with
+/// [`MockHttp::expect`], see [`mock_http`]. Stops when dropped.
+#[derive(Debug)]
+pub struct MockHttp {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    stopped: Arc<AtomicBool>,
+}
+
+/// A response being set up, see [`MockHttp::expect`].
+#[must_use = "the mock is only added once responded with"]
+pub struct MockExpectation<'a> {
+    server: &'a MockHttp,
+    mock: Mock,
+}
+
+impl MockExpectation<'_> {
+    /// Only matches requests with this query param.
+    pub fn query(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.mock.query.insert(key.into(), value.into());
+        self
+    }
+
+    /// Only matches requests with this header.
+    pub fn header(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.mock.headers.insert(key.to_lowercase(), value.into());
+        self
+    }
+
+    /// Only matches requests with a json body equal to this.
+    pub fn json_body(mut self, body: Value) -> Self {
+        self.mock.json_body = Some(body);
+        self
+    }
+
+    /// How often it's expected to be called, at least once by default. After that it no
+    /// longer matches.
+    pub fn times(mut self, times: usize) -> Self {
+        self.mock.times = Some(times);
+        self
+    }
+
+    pub fn respond_json(self, status: u16, body: Value) {
+        self.respond_with(status, "application/json", body.to_string())
+    }
+
+    pub fn respond(self, status: u16, body: impl Into<String>) {
+        self.respond_with(status, "text/plain", body.into())
+    }
+
+    fn respond_with(mut self, status: u16, content_type: &'static str, body: String) {
+        self.mock.status = status;
+        self.mock.content_type = content_type;
+        self.mock.body = body;
+        self.server.state.lock().unwrap().mocks.push(self.mock);
+    }
+}
+
+impl MockHttp {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (served, stop) = (state.clone(), stopped.clone());
+        std::thread::spawn(move || {
+            for conn in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(conn) = conn else {
+                    continue;
+                };
+                let state = served.clone();
+                std::thread::spawn(move || serve(conn, &state));
+            }
+        });
+        MockHttp {
+            addr,
+            state,
+            stopped,
+        }
+    }
+
+    /// The base url, e.g. for `Endpoint::builder().base_url(&server.url())`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Sets up the response to requests with `method` to `path`, e.g.
+    /// `server.expect(Method::GET, "/users/7").respond_json(200, json!({"name": "ada"}))`.
+    /// Requests matching no expectation get a 501.
+    pub fn expect(&self, method: Method, path: impl Into<String>) -> MockExpectation<'_> {
+        MockExpectation {
+            server: self,
+            mock: Mock {
+                method,
+                path: path.into(),
+                query: BTreeMap::new(),
+                headers: BTreeMap::new(),
+                json_body: None,
+                times: None,
+                status: 200,
+                content_type: "application/json",
+                body: String::new(),
+                calls: 0,
+            },
+        }
+    }
+
+    /// All requests received so far, in order.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Panics when an expectation wasn't called as often as it should, or a request matched
+    /// none.
+    pub fn verify(&self) {
+        let state = self.state.lock().unwrap();
+        let mut problems = state
+            .mocks
+            .iter()
+            .filter_map(|mock| match mock.times {
+                Some(times) if mock.calls != times => Some(format!(
+                    "{} was called {} times instead of {}",
+                    mock.describe(),
+                    mock.calls,
+                    times
+                )),
+                None if mock.calls == 0 => Some(format!("{} wasn't called", mock.describe())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        problems.extend(
+            state
+                .unmatched
+                .iter()
+                .map(|request| format!("Unexpected {:?}", request)),
+        );
+        assert!(problems.is_empty(), "{}", problems.join("\n"));
+    }
+}
+
+impl Drop for MockHttp {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wakes the accepting thread up to see it stopped:
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+/// A [`MockHttp`] server for a test.
+///
+/// ```ignore
+/// #[rstest]
+/// #[tokio::test]
+/// async fn creates_user(mock_http: MockHttp) {
+///     mock_http
+///         .expect(Method::POST, "/users")
+///         .json_body(json!({"name": "ada"}))
+///         .respond_json(201, json!({"id": 7}));
+///     ...
+///     mock_http.verify();
+/// }
+/// ```
+#[fixture]
+pub fn mock_http() -> MockHttp {
+    MockHttp::start()
+}
+
+/// Answers one request, bodies need a content-length.
+fn serve(conn: TcpStream, state: &Mutex<State>) {
+    let Ok(request) = read_request(&conn) else {
+        return;
+    };
+    let response = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(request.clone());
+        match state.mocks.iter_mut().find(|mock| mock.matches(&request)) {
+            Some(mock) => {
+                mock.calls += 1;
+                (mock.status, mock.content_type, mock.body.clone())
+            }
+            None => {
+                state.unmatched.push(request.clone());
+                let body = format!("No mock for {} {}", request.method, request.path);
+                (501, "text/plain", body)
+            }
+        }
+    };
+    let (status, content_type, body) = response;
+    let mut conn = conn;
+    let _ = write!(
+        conn,
+        "HTTP/1.1 {} Mock\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+}
+
+fn read_request(conn: &TcpStream) -> std::io::Result<RecordedRequest> {
+    let mut reader = BufReader::new(conn);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts
+        .next()
+        .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+        .ok_or_else(|| std::io::Error::other("No method"))?;
+    let target = parts.next().unwrap_or("/");
+    // Proxied requests have absolute targets:
+    let url = match target.starts_with('/') {
+        true => Url::parse(&format!("http://mock{}", target)),
+        false => Url::parse(target),
+    }
+    .map_err(std::io::Error::other)?;
+
+    let mut headers = BTreeMap::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    let length = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(RecordedRequest {
+        method,
+        path: url.path().to_string(),
+        query: url.query_pairs().into_owned().collect(),
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::Endpoint;
+    use serde_json::json;
+
+    #[rstest]
+    #[tokio::test]
+    async fn responds_and_verifies(mock_http: MockHttp) {
+        mock_http
+            .expect(Method::POST, "/users")
+            .json_body(json!({"name": "ada"}))
+            .times(1)
+            .respond_json(201, json!({"id": 7}));
+
+        let created = Endpoint::builder()
+            .base_url(&mock_http.url())
+            .endpoint("/users")
+            .method(Method::POST)
+            .query_param("invite", "true")
+            .json_body(json!({"name": "ada"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(created, json!({"id": 7}));
+        mock_http.verify();
+        let request = &mock_http.requests()[0];
+        assert_eq!(request.query["invite"], "true");
+        assert_eq!(request.headers["content-type"], "application/json");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    #[should_panic(expected = "GET /users wasn't called")]
+    async fn reports_unmet_expectations(mock_http: MockHttp) {
+        mock_http
+            .expect(Method::GET, "/users")
+            .respond_json(200, json!([]));
+        let response = reqwest::get(format!("{}/groups", mock_http.url()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 501);
+        mock_http.verify();
+    }
+}
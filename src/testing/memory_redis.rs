@@ -0,0 +1,318 @@
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
+use parking_lot::Mutex;
+use redis::RedisError;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use crate::clock::{system_clock, Clock};
+use crate::redis_manager::RedisLike;
+use crate::testing::prelude::*;
+
+#[derive(Debug, Default)]
+struct Store {
+    strings: HashMap<String, (String, Option<Instant>)>,
+    sorted_sets: HashMap<String, Vec<(f64, String)>>,
+    hashes: HashMap<String, HashMap<String, String>>,
+    channels: HashMap<String, broadcast::Sender<String>>,
+}
+
+/// An in-process stand in for a [`crate::redis_manager::RedisManager`], implementing
+/// [`RedisLike`] so redis backed utilities can be tested without a server. Clones share the
+/// same data.
+#[derive(Debug, Clone)]
+pub struct MemoryRedis {
+    store: Arc<Mutex<Store>>,
+    clock: Arc<dyn Clock>,
+    unreachable: Arc<AtomicBool>,
+}
+
+impl Default for MemoryRedis {
+    fn default() -> Self {
+        MemoryRedis::new()
+    }
+}
+
+impl MemoryRedis {
+    pub fn new() -> Self {
+        MemoryRedis {
+            store: Arc::new(Mutex::new(Store::default())),
+            clock: system_clock(),
+            unreachable: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Where key expiry gets the time from, e.g. a [`MockClock`] to expire keys instantly.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Makes every command fail like a server that can't be reached, until set back. Open
+    /// subscriptions keep receiving.
+    pub fn set_unreachable(&self, unreachable: bool) {
+        self.unreachable.store(unreachable, Ordering::SeqCst);
+    }
+
+    fn reachable(&self) -> Result<(), RedisError> {
+        if self.unreachable.load(Ordering::SeqCst) {
+            return Err(RedisError::from((
+                redis::ErrorKind::IoError,
+                "Connection refused",
+            )));
+        }
+        Ok(())
+    }
+
+    /// The keys currently set, of any type, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        let now = self.clock.now();
+        let store = self.store.lock();
+        let mut keys = store
+            .strings
+            .iter()
+            .filter(|(_, (_, expires))| expires.is_none_or(|expires| expires > now))
+            .map(|(key, _)| key.clone())
+            .chain(store.sorted_sets.keys().cloned())
+            .chain(store.hashes.keys().cloned())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    fn channel(&self, channel: &str) -> broadcast::Sender<String> {
+        self.store
+            .lock()
+            .channels
+            .entry(channel.to_string())
+            .or_insert_with(|| broadcast::channel(1024).0)
+            .clone()
+    }
+}
+
+#[async_trait]
+impl RedisLike for MemoryRedis {
+    async fn get(&self, key: &str) -> Result<Option<String>, RedisError> {
+        self.reachable()?;
+        let now = self.clock.now();
+        let mut store = self.store.lock();
+        match store.strings.get(key) {
+            Some((_, Some(expires))) if *expires <= now => {
+                store.strings.remove(key);
+                Ok(None)
+            }
+            Some((value, _)) => Ok(Some(value.clone())),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), RedisError> {
+        self.reachable()?;
+        let expires = ttl.map(|ttl| self.clock.now() + ttl);
+        self.store
+            .lock()
+            .strings
+            .insert(key.to_string(), (value.to_string(), expires));
+        Ok(())
+    }
+
+    async fn set_nx(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, RedisError> {
+        self.reachable()?;
+        let now = self.clock.now();
+        let mut store = self.store.lock();
+        if let Some((_, expires)) = store.strings.get(key) {
+            if expires.is_none_or(|expires| expires > now) {
+                return Ok(false);
+            }
+        }
+        store
+            .strings
+            .insert(key.to_string(), (value.to_string(), Some(now + ttl)));
+        Ok(true)
+    }
+
+    async fn zadd(&self, key: &str, member: &str, score: f64) -> Result<(), RedisError> {
+        self.reachable()?;
+        let mut store = self.store.lock();
+        let members = store.sorted_sets.entry(key.to_string()).or_default();
+        members.retain(|(_, existing)| existing != member);
+        members.push((score, member.to_string()));
+        // Like redis, ties are ordered by member:
+        members.sort_by(|(a_score, a), (b_score, b)| a_score.total_cmp(b_score).then(a.cmp(b)));
+        Ok(())
+    }
+
+    async fn zrangebyscore(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<String>, RedisError> {
+        self.reachable()?;
+        Ok(self
+            .store
+            .lock()
+            .sorted_sets
+            .get(key)
+            .map(|members| {
+                members
+                    .iter()
+                    .filter(|(score, _)| *score >= min && *score <= max)
+                    .map(|(_, member)| member.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn hset(&self, key: &str, fields: &[(String, String)]) -> Result<(), RedisError> {
+        self.reachable()?;
+        if fields.is_empty() {
+            return Ok(());
+        }
+        self.store
+            .lock()
+            .hashes
+            .entry(key.to_string())
+            .or_default()
+            .extend(fields.iter().cloned());
+        Ok(())
+    }
+
+    async fn hgetall(&self, key: &str) -> Result<HashMap<String, String>, RedisError> {
+        self.reachable()?;
+        Ok(self
+            .store
+            .lock()
+            .hashes
+            .get(key)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> Result<(), RedisError> {
+        self.reachable()?;
+        // Nobody subscribed isn't an error, the message is just dropped:
+        let _ = self.channel(channel).send(message.to_string());
+        Ok(())
+    }
+
+    async fn subscribe_and_wait_for_response(
+        &self,
+        channel: &str,
+        timeout: Duration,
+    ) -> Result<String, RedisError> {
+        self.reachable()?;
+        let mut receiver = self.channel(channel).subscribe();
+        match tokio::time::timeout(timeout, receiver.recv()).await {
+            Ok(Ok(message)) => Ok(message),
+            Ok(Err(_)) => Err(RedisError::from((
+                redis::ErrorKind::IoError,
+                "No message received",
+            ))),
+            Err(_) => Err(RedisError::from((
+                redis::ErrorKind::IoError,
+                "Timeout waiting for response",
+            ))),
+        }
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<BoxStream<'static, String>, RedisError> {
+        self.reachable()?;
+        let receiver = self.channel(channel).subscribe();
+        let messages = futures::stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => return Some((message, receiver)),
+                    // Like a subscriber redis drops for being slow, what it missed is gone:
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(messages.boxed())
+    }
+}
+
+/// An empty [`MemoryRedis`] for a test.
+#[fixture]
+pub fn memory_redis() -> MemoryRedis {
+    MemoryRedis::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn behaves_like_redis(mock_clock: MockClock) {
+        let redis = MemoryRedis::new().clock(mock_clock.shared());
+        redis
+            .set("session", "ada", Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        redis.zadd("traces", "b", 2.0).await.unwrap();
+        redis.zadd("traces", "a", 1.0).await.unwrap();
+        redis.zadd("traces", "c", 3.0).await.unwrap();
+        redis.zadd("traces", "a", 4.0).await.unwrap();
+        redis
+            .hset("stats", &[("runs".to_string(), "1".to_string())])
+            .await
+            .unwrap();
+        redis
+            .hset("stats", &[("runs".to_string(), "2".to_string())])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            redis
+                .zrangebyscore("traces", f64::NEG_INFINITY, 3.0)
+                .await
+                .unwrap(),
+            vec!["b", "c"]
+        );
+        assert_eq!(redis.get("session").await.unwrap().as_deref(), Some("ada"));
+        assert!(!redis
+            .set_nx("session", "bob", Duration::from_secs(60))
+            .await
+            .unwrap());
+        mock_clock.advance(Duration::from_secs(60));
+        assert_eq!(redis.get("session").await.unwrap(), None);
+        assert_eq!(redis.hgetall("stats").await.unwrap()["runs"], "2");
+        assert_eq!(redis.keys(), vec!["stats", "traces"]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn delivers_published_messages(memory_redis: MemoryRedis) {
+        let subscriber = memory_redis.clone();
+        let response = tokio::spawn(async move {
+            subscriber
+                .subscribe_and_wait_for_response("jobs:7", Duration::from_secs(5))
+                .await
+        });
+        // Only subscribers at the time of publishing get the message, like with redis:
+        while memory_redis.channel("jobs:7").receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+        memory_redis.publish("jobs:7", "done").await.unwrap();
+        assert_eq!(response.await.unwrap().unwrap(), "done");
+
+        // Subscriptions receive every message until dropped:
+        let mut messages = memory_redis.subscribe("jobs:8").await.unwrap();
+        for message in ["started", "done"] {
+            memory_redis.publish("jobs:8", message).await.unwrap();
+        }
+        assert_eq!(messages.next().await.as_deref(), Some("started"));
+        assert_eq!(messages.next().await.as_deref(), Some("done"));
+        drop(messages);
+        assert_eq!(memory_redis.channel("jobs:8").receiver_count(), 0);
+
+        memory_redis.set_unreachable(true);
+        assert!(memory_redis.get("jobs:8").await.is_err());
+        assert!(memory_redis.subscribe("jobs:8").await.is_err());
+    }
+}
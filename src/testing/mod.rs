@@ -1,4 +1,16 @@
+pub mod cmd;
+#[cfg(feature = "docker")]
+pub mod containers;
+pub mod dirs;
 pub mod fixtures;
+pub mod golden;
+pub mod http;
+pub mod memory_redis;
+pub mod net;
+pub mod redis_db;
+pub mod runner;
+pub mod snapshot;
+pub mod traces;
 
 pub mod prelude {
     #[allow(unused_imports)]
@@ -7,6 +19,47 @@ pub mod prelude {
     #[allow(unused_imports)]
     pub use crate::prelude::*;
 
+    #[allow(unused_imports)]
+    pub use crate::testing::dirs::{fake_home, temp_dir, FakeHome};
+
     #[allow(unused_imports)]
     pub use crate::testing::fixtures::*;
+
+    #[allow(unused_imports)]
+    pub use crate::testing::cmd::{
+        cmd_harness, real_commands, CmdHarness, RanCommand, RealCommands,
+    };
+
+    #[allow(unused_imports)]
+    pub use crate::clock::{Clock, MockClock};
+
+    #[cfg(feature = "docker")]
+    #[allow(unused_imports)]
+    pub use crate::testing::containers::{
+        container, postgres_container, redis_container, ServiceContainer,
+    };
+
+    #[allow(unused_imports)]
+    pub use crate::testing::golden::assert_matches_golden;
+
+    #[allow(unused_imports)]
+    pub use crate::testing::http::{mock_http, MockHttp, RecordedRequest};
+
+    #[allow(unused_imports)]
+    pub use crate::testing::memory_redis::{memory_redis, MemoryRedis};
+
+    #[allow(unused_imports)]
+    pub use crate::testing::net::{free_port, wait_for_port};
+
+    #[allow(unused_imports)]
+    pub use crate::testing::redis_db::{redis_test, RedisTest, LOCAL_REDIS};
+
+    #[allow(unused_imports)]
+    pub use crate::testing::runner::{rutils_test, RutilsTest};
+
+    #[allow(unused_imports)]
+    pub use crate::testing::snapshot::{assert_json_snapshot, JsonSnapshot};
+
+    #[allow(unused_imports)]
+    pub use crate::testing::traces::{job_trace, seed_traces};
 }
@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+use std::net::TcpListener;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::testing::prelude::*;
+
+/// How often [`wait_for_port`] tries to connect.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Ports already handed out by [`free_port`] in this process.
+static HANDED_OUT: Mutex<Option<HashSet<u16>>> = Mutex::new(None);
+
+/// A localhost port nothing listens on, for a server a test starts itself. Each call in the
+/// process gets a different one, so tests running at the same time don't collide.
+#[fixture]
+pub fn free_port() -> u16 {
+    let mut handed_out = HANDED_OUT.lock().unwrap_or_else(|e| e.into_inner());
+    let handed_out = handed_out.get_or_insert_with(HashSet::new);
+    loop {
+        // The os picks a free ephemeral port, it's free again once the listener is dropped:
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .map(|addr| addr.port())
+            .unwrap();
+        if handed_out.insert(port) {
+            return port;
+        }
+    }
+}
+
+/// Waits until `addr`, e.g. `127.0.0.1:8080`, accepts tcp connections, failing with
+/// [`ErrorClass::Timeout`] after `timeout`.
+pub async fn wait_for_port(addr: &str, timeout: Duration) -> RResult<(), AnyErr> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let error =
+            match tokio::time::timeout(POLL_INTERVAL, tokio::net::TcpStream::connect(addr)).await {
+                Ok(Ok(_)) => return Ok(()),
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => "Connecting timed out".to_string(),
+            };
+        if Instant::now() + POLL_INTERVAL > deadline {
+            return Err(err!(
+                AnyErr,
+                "Timed out waiting for {} to accept connections",
+                addr
+            ))
+            .attach_printable(format!("Timeout: {:?}", timeout))
+            .attach_printable(format!("Last error: {}", error))
+            .classify(ErrorClass::Timeout);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::error_class;
+
+    #[rstest]
+    fn hands_out_distinct_ports() {
+        let ports = (0..20).map(|_| free_port()).collect::<HashSet<_>>();
+        assert_eq!(ports.len(), 20);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn waits_for_listener(free_port: u16) {
+        let addr = format!("127.0.0.1:{}", free_port);
+        let report = wait_for_port(&addr, Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert_eq!(error_class(&report), Some(ErrorClass::Timeout));
+
+        let listen = addr.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let listener = tokio::net::TcpListener::bind(listen).await.unwrap();
+            let _ = listener.accept().await;
+        });
+        wait_for_port(&addr, Duration::from_secs(5)).await.unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use std::time::{Duration, Instant};
+
+use crate::redis_manager::RedisManager;
+use crate::testing::prelude::*;
+
+/// Where [`redis_test`] connects to by default.
+pub const LOCAL_REDIS: &str = "redis://127.0.0.1/";
+
+/// Databases 1-15 of the default 16 are handed out, 0 is left to whatever else uses the server.
+const TEST_DBS: std::ops::RangeInclusive<i64> = 1..=15;
+
+/// Claims of databases expire in case a test process died holding one.
+const CLAIM_TTL_SECS: u64 = 10 * 60;
+
+/// How long to wait for a database when all are claimed by other tests.
+const CLAIM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A logical redis database of a test's own, see [`redis_test`]. Derefs to its manager,
+/// flushed before the test and when dropped.
+pub struct RedisTest {
+    manager: RedisManager,
+    claims: RedisManager,
+    db: i64,
+}
+
+impl RedisTest {
+    /// The logical database handed to this test.
+    pub fn db(&self) -> i64 {
+        self.db
+    }
+}
+
+impl std::ops::Deref for RedisTest {
+    type Target = RedisManager;
+
+    fn deref(&self) -> &Self::Target {
+        &self.manager
+    }
+}
+
+impl Drop for RedisTest {
+    fn drop(&mut self) {
+        let flushed = self
+            .manager
+            .get_sync_connection()
+            .and_then(|mut conn| redis::cmd("FLUSHDB").query::<()>(&mut conn));
+        if let Err(e) = flushed {
+            eprintln!("Failed to flush redis db {}: {}", self.db, e);
+        }
+        let released = self.claims.get_sync_connection().and_then(|mut conn| {
+            redis::cmd("DEL")
+                .arg(claim_key(self.db))
+                .query::<()>(&mut conn)
+        });
+        if let Err(e) = released {
+            eprintln!("Failed to release redis db {}: {}", self.db, e);
+        }
+    }
+}
+
+/// A [`RedisManager`] on an empty logical database no other test uses at the same time, or
+/// None when redis isn't reachable at `url`, so tests can skip instead of failing:
+///
+/// ```ignore
+/// #[rstest]
+/// fn caches(redis_test: Option<RedisTest>) {
+///     let Some(redis) = redis_test else { return };
+///     ...
+/// }
+/// ```
+///
+/// Databases are claimed with keys in database 0, so tests of separate processes don't share
+/// one either.
+#[fixture]
+pub fn redis_test(#[default(LOCAL_REDIS)] url: &str) -> Option<RedisTest> {
+    let claims = RedisManager::new(&db_url(url, 0)).ok()?;
+    let mut conn = match claims.get_sync_connection() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Skipping, redis isn't reachable at {}: {}", url, e);
+            return None;
+        }
+    };
+    let started = Instant::now();
+    let db = loop {
+        let claimed = TEST_DBS.clone().find(|db| {
+            redis::cmd("SET")
+                .arg(claim_key(*db))
+                .arg(std::process::id())
+                .arg("NX")
+                .arg("EX")
+                .arg(CLAIM_TTL_SECS)
+                .query::<Option<String>>(&mut conn)
+                .is_ok_and(|reply| reply.is_some())
+        });
+        match claimed {
+            Some(db) => break db,
+            None if started.elapsed() >= CLAIM_TIMEOUT => {
+                panic!("All redis test dbs of {} stayed claimed", url)
+            }
+            None => std::thread::sleep(Duration::from_millis(100)),
+        }
+    };
+    let manager = RedisManager::new(&db_url(url, db)).ok()?;
+    let test = RedisTest {
+        manager,
+        claims,
+        db,
+    };
+    let flushed = test
+        .get_sync_connection()
+        .and_then(|mut conn| redis::cmd("FLUSHDB").query::<()>(&mut conn));
+    if let Err(e) = flushed {
+        panic!("Failed to flush redis db {}: {}", db, e);
+    }
+    Some(test)
+}
+
+fn claim_key(db: i64) -> String {
+    format!("rutils:test-db:{}", db)
+}
+
+/// `url` selecting `db` instead of the one it had, if any.
+fn db_url(url: &str, db: i64) -> String {
+    let base = url.trim_end_matches('/');
+    let base = match base.rsplit_once('/') {
+        Some((base, selected))
+            if !selected.is_empty() && selected.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            base
+        }
+        _ => base,
+    };
+    format!("{}/{}", base, db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::Commands;
+
+    #[rstest]
+    #[case("redis://127.0.0.1/", "redis://127.0.0.1/4")]
+    #[case("redis://127.0.0.1", "redis://127.0.0.1/4")]
+    #[case("redis://:pass@cache:6380/0", "redis://:pass@cache:6380/4")]
+    fn selects_db(#[case] url: &str, #[case] expected: &str) {
+        assert_eq!(db_url(url, 4), expected);
+    }
+
+    #[rstest]
+    fn isolates_tests(redis_test: Option<RedisTest>, #[from(redis_test)] other: Option<RedisTest>) {
+        let (Some(redis), Some(other)) = (redis_test, other) else {
+            return;
+        };
+        assert_ne!(redis.db(), other.db());
+        let mut conn = redis.get_sync_connection().unwrap();
+        conn.set::<_, _, ()>("key", "value").unwrap();
+        let mut other_conn = other.get_sync_connection().unwrap();
+        assert_eq!(other_conn.get::<_, Option<String>>("key").unwrap(), None);
+    }
+}
@@ -0,0 +1,156 @@
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+use crate::testing::prelude::*;
+
+/// How long a test gets by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Runs an async test, see [`rutils_test`].
+#[derive(Debug, Clone)]
+pub struct RutilsTest {
+    timeout: Duration,
+    retries: usize,
+    logging: bool,
+}
+
+impl RutilsTest {
+    /// How long each attempt gets before failing, a minute by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How often to rerun a failed attempt, none by default. Only for integration tests known
+    /// to be flaky.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Whether to turn on the [`logging`] fixture, on by default.
+    pub fn logging(mut self, logging: bool) -> Self {
+        self.logging = logging;
+        self
+    }
+
+    /// Runs the future `test` makes on a fresh runtime per attempt, like `#[tokio::test]`.
+    /// Panics with the last attempt's failure.
+    pub fn run<F, Fut>(&self, test: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        if self.logging {
+            logging::default();
+        }
+        let attempts = self.retries + 1;
+        for attempt in 1..=attempts {
+            let failure = match self.attempt(&test) {
+                Ok(()) => return,
+                Err(failure) => failure,
+            };
+            if attempt == attempts {
+                match failure {
+                    Failure::Panicked(payload) => std::panic::resume_unwind(payload),
+                    Failure::TimedOut => panic!("Test timed out after {:?}", self.timeout),
+                }
+            }
+            warn!(
+                "Test attempt {} of {} failed, retrying: {}",
+                attempt,
+                attempts,
+                failure.describe()
+            );
+        }
+    }
+
+    fn attempt<F, Fut>(&self, test: &F) -> Result<(), Failure>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let outcome = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            runtime.block_on(async { tokio::time::timeout(self.timeout, test()).await })
+        }));
+        // Tasks the test spawned may be stuck, so don't wait for them:
+        runtime.shutdown_background();
+        match outcome {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(Failure::TimedOut),
+            Err(payload) => Err(Failure::Panicked(payload)),
+        }
+    }
+}
+
+enum Failure {
+    Panicked(Box<dyn Any + Send>),
+    TimedOut,
+}
+
+impl Failure {
+    fn describe(&self) -> String {
+        match self {
+            Failure::Panicked(payload) => payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panicked".to_string()),
+            Failure::TimedOut => "timed out".to_string(),
+        }
+    }
+}
+
+/// Runs async tests with a timeout, retries for flaky ones and logging turned on:
+///
+/// ```ignore
+/// #[test]
+/// fn syncs_jobs() {
+///     rutils_test()
+///         .timeout(Duration::from_secs(30))
+///         .retries(2)
+///         .run(|| async {
+///             ...
+///         });
+/// }
+/// ```
+pub fn rutils_test() -> RutilsTest {
+    RutilsTest {
+        timeout: DEFAULT_TIMEOUT,
+        retries: 0,
+        logging: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[rstest]
+    fn retries_until_passing() {
+        let attempts = AtomicUsize::new(0);
+        rutils_test().retries(2).logging(false).run(|| async {
+            tokio::task::yield_now().await;
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            assert_eq!(attempt, 3, "flaked");
+        });
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[rstest]
+    #[should_panic(expected = "Test timed out after 50ms")]
+    fn times_out() {
+        rutils_test()
+            .timeout(Duration::from_millis(50))
+            .retries(1)
+            .logging(false)
+            .run(|| tokio::time::sleep(Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,180 @@
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::testing::prelude::*;
+
+/// Set to rewrite snapshots that don't match instead of failing.
+pub const UPDATE_SNAPSHOTS_ENV: &str = "UPDATE_SNAPSHOTS";
+
+/// Compares a json value to the one stored for it by an earlier run, see
+/// [`assert_json_snapshot`].
+#[derive(Debug, Clone)]
+pub struct JsonSnapshot {
+    name: String,
+    dir: PathBuf,
+    keys: Vec<Regex>,
+    values: Vec<(Regex, String)>,
+}
+
+impl JsonSnapshot {
+    /// Stored in a `snapshots` dir next to the calling file, with ids and timestamps redacted.
+    #[track_caller]
+    pub fn new(name: impl Into<String>) -> Self {
+        let caller = Path::new(std::panic::Location::caller().file());
+        let root = std::env::var_os("CARGO_MANIFEST_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_default();
+        JsonSnapshot {
+            name: name.into(),
+            dir: root
+                .join(caller.parent().unwrap_or(Path::new("")))
+                .join("snapshots"),
+            keys: vec![],
+            values: vec![],
+        }
+        .redact_keys(r"^(id|.+_id|timestamp|.+_at)$")
+        .redact_values(
+            r"^\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:?\d{2})?$",
+            "[timestamp]",
+        )
+    }
+
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    /// Replaces the values of keys fully matching `pattern` with `[key]`, at any depth.
+    pub fn redact_keys(mut self, pattern: &str) -> Self {
+        self.keys.push(Regex::new(pattern).unwrap());
+        self
+    }
+
+    /// Replaces the matches of `pattern` in string values with `replacement`.
+    pub fn redact_values(mut self, pattern: &str, replacement: impl Into<String>) -> Self {
+        self.values
+            .push((Regex::new(pattern).unwrap(), replacement.into()));
+        self
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(format!("{}.json", self.name))
+    }
+
+    /// Stores `value` when there's no snapshot yet or [`UPDATE_SNAPSHOTS_ENV`] is set,
+    /// otherwise panics showing both when they differ.
+    pub fn assert(&self, value: &impl Serialize) {
+        let mut actual = panic_on_err!({ serde_json::to_value(value).change_context(AnyErr) });
+        self.redact(&mut actual);
+        let path = self.path();
+        let update = std::env::var_os(UPDATE_SNAPSHOTS_ENV).is_some();
+        if path.exists() && !update {
+            let stored = panic_on_err!({
+                std::fs::read_to_string(&path)
+                    .change_context(AnyErr)
+                    .and_then(|stored| {
+                        serde_json::from_str::<Value>(&stored).change_context(AnyErr)
+                    })
+                    .attach_printable_lazy(|| format!("Snapshot: {}", path.display()))
+            });
+            assert!(
+                stored == actual,
+                "Snapshot {} doesn't match, rerun with {}=1 to update it.\nStored:\n{}\nActual:\n{}",
+                path.display(),
+                UPDATE_SNAPSHOTS_ENV,
+                pretty(&stored),
+                pretty(&actual)
+            );
+        } else {
+            panic_on_err!({
+                std::fs::create_dir_all(&self.dir)
+                    .and_then(|_| std::fs::write(&path, pretty(&actual) + "\n"))
+                    .change_context(AnyErr)
+                    .attach_printable_lazy(|| format!("Snapshot: {}", path.display()))
+            });
+        }
+    }
+
+    fn redact(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.keys.iter().any(|pattern| pattern.is_match(key)) {
+                        if !value.is_null() {
+                            *value = Value::String(format!("[{}]", key));
+                        }
+                    } else {
+                        self.redact(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.redact(value)),
+            Value::String(string) => {
+                for (pattern, replacement) in &self.values {
+                    *string = pattern
+                        .replace_all(string, replacement.as_str())
+                        .into_owned();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default()
+}
+
+/// Checks `value` still serializes to what it did when the snapshot `name` was stored, e.g. a
+/// response from `Endpoint::send` or a `LogData` dump. Ids, `..._id` and `..._at` keys,
+/// `timestamp` and timestamp strings are redacted, use [`JsonSnapshot`] for other rules.
+#[track_caller]
+pub fn assert_json_snapshot(name: &str, value: &impl Serialize) {
+    JsonSnapshot::new(name).assert(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempWorkspace;
+    use serde_json::json;
+
+    #[rstest]
+    fn redacts_and_compares(temp_dir: TempWorkspace) {
+        let snapshot = JsonSnapshot::new("user")
+            .dir(temp_dir.path())
+            .redact_values(r"tok_\w+", "[token]");
+        snapshot.assert(&json!({
+            "id": 7,
+            "name": "ada",
+            "created_at": "2024-05-01T10:00:00Z",
+            "sessions": [{"started": "2024-05-01 10:00:00.123+02:00", "token": "tok_ab12"}],
+            "deleted_at": null,
+        }));
+        let stored = std::fs::read_to_string(snapshot.path()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&stored).unwrap(),
+            json!({
+                "id": "[id]",
+                "name": "ada",
+                "created_at": "[created_at]",
+                "sessions": [{"started": "[timestamp]", "token": "[token]"}],
+                "deleted_at": null,
+            })
+        );
+
+        snapshot.assert(&json!({
+            "id": 8,
+            "name": "ada",
+            "created_at": "2024-06-01T10:00:00Z",
+            "sessions": [{"started": "2024-06-01T08:00:00Z", "token": "tok_cd34"}],
+            "deleted_at": null,
+        }));
+        let changed = std::panic::catch_unwind(|| {
+            snapshot.assert(&json!({"id": 8, "name": "grace"}));
+        });
+        assert!(changed.is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use tracing::Level;
+
+use crate::redis_manager::RedisLike;
+use crate::redis_tracing::{store_log, LogData};
+use crate::testing::prelude::*;
+
+/// Stores `entries` as logs of `app`, where [`crate::redis_tracing::LogViewer`] reads them
+/// from. Works on a [`crate::redis_manager::RedisManager`] for demo environments and on a
+/// [`MemoryRedis`] in unit tests.
+pub async fn seed_traces(
+    manager: &dyn RedisLike,
+    app: &str,
+    entries: &[LogData],
+) -> RResult<(), AnyErr> {
+    for entry in entries {
+        store_log(manager, app, entry).await?;
+    }
+    Ok(())
+}
+
+/// A job handled by `service` as a trace of `messages` logged `spacing` apart from `start`,
+/// all in one span.
+pub fn job_trace(
+    job_id: &str,
+    service: &str,
+    messages: &[&str],
+    start: DateTime<Utc>,
+    spacing: Duration,
+) -> Vec<LogData> {
+    let spacing = chrono::Duration::from_std(spacing).unwrap_or_default();
+    messages
+        .iter()
+        .zip(0..)
+        .map(|(message, index)| {
+            LogData::new(Level::INFO, *message)
+                .timestamp(start + spacing * index)
+                .trace_id(job_id)
+                .span(job_id, "job")
+                .job_id(job_id)
+                .service_name(service)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis_tracing::LogViewer;
+    use std::sync::Arc;
+
+    #[rstest]
+    #[tokio::test]
+    async fn seeds_viewable_traces(memory_redis: MemoryRedis) {
+        let start = Utc::now();
+        let mut entries = job_trace(
+            "job-1",
+            "billing",
+            &["Started", "Charged card"],
+            start,
+            Duration::from_secs(1),
+        );
+        entries.push(
+            LogData::new(Level::ERROR, "Card declined")
+                .timestamp(start - chrono::Duration::seconds(5))
+                .job_id("job-0")
+                .suggestion("Ask for another card"),
+        );
+        seed_traces(&memory_redis, "shop", &entries).await.unwrap();
+
+        let viewer = LogViewer::new(Arc::new(memory_redis));
+        let logs = viewer.view_logs_by_app_name("shop").await.unwrap();
+        assert_eq!(
+            logs.iter().map(LogData::get_message).collect::<Vec<_>>(),
+            vec!["Card declined", "Started", "Charged card"]
+        );
+        let job_logs = viewer.view_logs_by_job_id("shop", "job-1").await.unwrap();
+        assert_eq!(job_logs.len(), 2);
+        assert_eq!(job_logs[0].get_service_name(), Some("billing"));
+    }
+}